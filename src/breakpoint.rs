@@ -0,0 +1,248 @@
+use anyhow::{Result, Context};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+
+use crate::ptrace;
+use crate::syscall;
+
+const INT3: u8 = 0xcc;
+
+// ユーザーが設定したブレークポイント
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    id: usize,
+    // インフェリアごとに解決したアドレス. (インフェリアの番号, アドレス)
+    locations: Vec<(usize, u64)>,
+    // break ... inferior N で作ったものはそのインフェリアでだけ止まる
+    inferior: Option<usize>,
+    // tbreak で作ったものは一度止まったら消す
+    temporary: bool,
+    // 止まった時に評価して, 真の時だけ止まる式
+    condition: Option<String>,
+    // 条件が真でもあと何回は止まらずに続けるか
+    ignore_count: usize,
+    // 条件が真になった回数. 無視した回も数える
+    hit_count: usize,
+    // 止まった時に順に実行するコマンド
+    commands: Vec<String>,
+    // dprintf の "書式",式... の部分. あれば表示するだけで止まらない
+    dprintf: Option<String>,
+    // execした後や他のインフェリアで解決し直す場所
+    location: String,
+    // catch で作ったキャッチポイントなら捕まえるイベント
+    catch: Option<CatchKind>,
+}
+
+// キャッチポイントで捕まえるイベント
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatchKind {
+    // Rustのパニック. 巻き戻しを始める直前に止まる
+    Panic,
+    // システムコールの入り口と出口. 番号が空なら全てのシステムコール
+    Syscall(Vec<u64>),
+    // PTRACE_O_TRACE* のイベント. 子プロセスやスレッドを作った時, execした時, スレッドが終わる時
+    Fork,
+    Vfork,
+    Clone,
+    Exec,
+    Exit,
+}
+
+impl CatchKind {
+    // info breakpoints や止まった時の表示
+    pub fn describe(&self) -> String {
+        match self {
+            CatchKind::Panic => "panic".to_owned(),
+            CatchKind::Fork => "fork".to_owned(),
+            CatchKind::Vfork => "vfork".to_owned(),
+            CatchKind::Clone => "clone".to_owned(),
+            CatchKind::Exec => "exec".to_owned(),
+            CatchKind::Exit => "exit".to_owned(),
+            CatchKind::Syscall(nrs) if nrs.is_empty() => "syscall \"<any syscall>\"".to_owned(),
+            CatchKind::Syscall(nrs) => {
+                let names: Vec<String> = nrs.iter().map(|nr| syscall::display_name(*nr)).collect();
+                let plural = if nrs.len() > 1 { "s" } else { "" };
+                format!("syscall{} \"{}\"", plural, names.join(", "))
+            },
+        }
+    }
+
+    // 作った時の表示. システムコールは番号も表示する
+    pub fn describe_new(&self) -> String {
+        match self {
+            CatchKind::Syscall(nrs) if nrs.is_empty() => "any syscall".to_owned(),
+            CatchKind::Syscall(nrs) => {
+                let names: Vec<String> = nrs.iter().map(|nr| match syscall::name(*nr) {
+                    Some(name) => format!("'{}' [{}]", name, nr),
+                    None => nr.to_string(),
+                }).collect();
+                let plural = if nrs.len() > 1 { "s" } else { "" };
+                format!("syscall{} {}", plural, names.join(" "))
+            },
+            _ => self.describe(),
+        }
+    }
+
+    // 関数にint3を入れて止めるもの. システムコールはPTRACE_SYSCALLで止めるので場所は無い
+    pub fn has_location(&self) -> bool {
+        matches!(self, CatchKind::Panic)
+    }
+
+    pub fn catches_syscall(&self, nr: u64) -> bool {
+        match self {
+            CatchKind::Syscall(nrs) => nrs.is_empty() || nrs.contains(&nr),
+            _ => false,
+        }
+    }
+}
+
+impl Breakpoint {
+    // inferiorで解決したaddrに作る
+    pub fn new(id: usize, inferior: usize, addr: u64, temporary: bool) -> Self {
+        let location = format!("*0x{:x}", addr);
+        Breakpoint { id, locations: vec![(inferior, addr)], inferior: None, temporary, condition: None, ignore_count: 0, hit_count: 0, commands: vec![], dprintf: None, location, catch: None }
+    }
+
+    // 場所を持たないキャッチポイント. 場所のあるものは作った後にresolveする
+    pub fn new_catchpoint(id: usize, catch: CatchKind) -> Self {
+        Breakpoint { id, locations: vec![], inferior: None, temporary: false, condition: None, ignore_count: 0, hit_count: 0, commands: vec![], dprintf: None, location: String::new(), catch: Some(catch) }
+    }
+
+    pub fn get_id(&self) -> usize { self.id }
+    pub fn get_addr(&self, inferior: usize) -> Option<u64> {
+        self.locations.iter().find(|(num, _)| *num == inferior).map(|(_, addr)| *addr)
+    }
+    pub fn get_locations(&self) -> &Vec<(usize, u64)> { &self.locations }
+    pub fn get_inferior(&self) -> Option<usize> { self.inferior }
+    pub fn is_temporary(&self) -> bool { self.temporary }
+    pub fn get_condition(&self) -> Option<&String> { self.condition.as_ref() }
+    pub fn get_ignore_count(&self) -> usize { self.ignore_count }
+    pub fn get_hit_count(&self) -> usize { self.hit_count }
+    pub fn get_commands(&self) -> &Vec<String> { &self.commands }
+    pub fn get_dprintf(&self) -> Option<&String> { self.dprintf.as_ref() }
+    pub fn get_location(&self) -> &String { &self.location }
+    pub fn get_catch(&self) -> Option<&CatchKind> { self.catch.as_ref() }
+    pub fn applies_to(&self, inferior: usize) -> bool {
+        self.inferior.is_none_or(|num| num == inferior)
+    }
+
+    pub fn set_inferior(&mut self, inferior: Option<usize>) {
+        self.inferior = inferior;
+    }
+
+    pub fn set_condition(&mut self, condition: Option<String>) {
+        self.condition = condition;
+    }
+
+    pub fn set_ignore_count(&mut self, count: usize) {
+        self.ignore_count = count;
+    }
+
+    pub fn set_dprintf(&mut self, dprintf: String) {
+        self.dprintf = Some(dprintf);
+    }
+
+    pub fn set_location(&mut self, location: String) {
+        self.location = location;
+    }
+
+    // inferiorで解決し直した場所. Noneならそのインフェリアでは止まらない
+    pub fn resolve(&mut self, inferior: usize, addr: Option<u64>) {
+        self.locations.retain(|(num, _)| *num != inferior);
+        if let Some(addr) = addr {
+            self.locations.push((inferior, addr));
+            self.locations.sort_by_key(|(num, _)| *num);
+        }
+    }
+
+    // forkした子はメモリが同じなので, 親と同じアドレスで止まる
+    pub fn copy_location(&mut self, from: usize, to: usize) {
+        if self.applies_to(to) {
+            let addr = self.get_addr(from);
+            self.resolve(to, addr);
+        }
+    }
+
+    pub fn set_commands(&mut self, commands: Vec<String>) {
+        self.commands = commands;
+    }
+
+    // 条件が真だった時に呼ぶ. 止まるべきならtrue
+    pub fn hit(&mut self) -> bool {
+        self.hit_count += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return false;
+        }
+        true
+    }
+}
+
+// 実際にint3を書き込んだアドレスと元の1バイト.
+// 同じアドレスに複数のブレークポイント(ユーザーのものとfinish用の一時的なもの)があっても1回だけ書き込む
+#[derive(Debug, Default, Clone)]
+pub struct BreakpointSites {
+    inserted: HashMap<u64, u8>,
+}
+
+impl BreakpointSites {
+    pub fn is_inserted(&self, addr: u64) -> bool {
+        self.inserted.contains_key(&addr)
+    }
+
+    // wantedのアドレスにだけint3が入っている状態にする
+    pub fn sync(&mut self, pid: Pid, wanted: &[u64]) -> Result<()> {
+        let stale: Vec<u64> = self.inserted.keys().filter(|addr| !wanted.contains(addr)).cloned().collect();
+        for addr in stale.into_iter() {
+            self.remove(pid, addr)?;
+        }
+        for addr in wanted.iter() {
+            if !self.is_inserted(*addr) {
+                self.insert(pid, *addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, pid: Pid, addr: u64) -> Result<()> {
+        let word = ptrace::peek_text(pid, addr).with_context(|| format!("Cannot insert breakpoint at 0x{:x}.", addr))?;
+        ptrace::poke_text(pid, addr, (word & !0xff) as u64 | INT3 as u64)?;
+        self.inserted.insert(addr, (word & 0xff) as u8);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, pid: Pid, addr: u64) -> Result<()> {
+        if let Some(original) = self.inserted.remove(&addr) {
+            let word = ptrace::peek_text(pid, addr).with_context(|| format!("Cannot remove breakpoint at 0x{:x}.", addr))?;
+            ptrace::poke_text(pid, addr, (word & !0xff) as u64 | original as u64)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_all(&mut self, pid: Pid) -> Result<()> {
+        self.sync(pid, &[])
+    }
+
+    // forkした子プロセスにもint3がコピーされるので, 元に戻す. こちらの記録は変えない
+    pub fn remove_from(&self, pid: Pid) -> Result<()> {
+        for (addr, original) in self.inserted.iter() {
+            let word = ptrace::peek_text(pid, *addr).with_context(|| format!("Cannot remove breakpoint at 0x{:x}.", addr))?;
+            ptrace::poke_text(pid, *addr, (word & !0xff) as u64 | *original as u64)?;
+        }
+        Ok(())
+    }
+
+    // execしたか, 他のプロセスから外したので, メモリには書き込まずに記録だけ消す
+    pub fn forget(&mut self) {
+        self.inserted.clear();
+    }
+
+    // int3が入ったままのメモリを読んだ時に, 元の命令に戻す
+    pub fn restore_original(&self, addr: u64, bytes: &mut [u8]) {
+        for (site, original) in self.inserted.iter() {
+            if *site >= addr && *site < addr + bytes.len() as u64 {
+                bytes[(*site - addr) as usize] = *original;
+            }
+        }
+    }
+}
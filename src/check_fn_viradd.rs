@@ -86,6 +86,7 @@ pub fn get_fn_vir_address_maps(file_path: &str) -> Vec<SymMap> {
     return var_addr_map;
 }
 
+#[repr(C)]
 #[derive(Debug)]
 struct EIdent {
     magic_num1: u8,
@@ -133,11 +134,12 @@ struct ElfEhdr {
 //     pub fn
 // }
 
+#[repr(C)]
 #[derive(Debug)]
 struct ElfEPhdr {
     p_type: u32,
     p_flags: u32,
-    p_offset: u32,
+    p_offset: u64,
     p_vaddr: u64,
     p_paddr: u64,
     p_filesz: u64,
@@ -182,7 +184,21 @@ impl ElfEShdr {
     }
 
     pub fn get_type(&self) -> SHType {
-        unsafe { transmute(self.sh_type) }
+        // SHT_GNU_HASHなど列挙していない値をtransmuteすると未定義動作になるので, 知らない値はSHT_NULL扱いにする
+        match self.sh_type {
+            1 => SHType::SHT_PROGBITS,
+            2 => SHType::SHT_SYMTAB,
+            3 => SHType::SHT_STRTAB,
+            4 => SHType::SHT_RELA,
+            5 => SHType::SHT_HASH,
+            6 => SHType::SHT_DYNAMIC,
+            7 => SHType::SHT_NOTE,
+            8 => SHType::SHT_NOBITS,
+            9 => SHType::SHT_REL,
+            10 => SHType::SHT_SHLIB,
+            11 => SHType::SHT_DYNSYM,
+            _ => SHType::SHT_NULL,
+        }
     }
 }
 
@@ -248,6 +264,7 @@ fn lookup_sym_name(file: &Vec<u8>, elf_eshdr_list: &Vec<ElfEShdr>, sym_name: &st
                 result.push(SymMap {
                     fn_name: fn_origin_name,
                     vir_addr: symtab.st_value,
+                    size: symtab.st_size,
                 })
             }
         }
@@ -280,19 +297,149 @@ fn get_all_sym_name_vir_addr_map(file: &Vec<u8>, elf_eshdr_list: &Vec<ElfEShdr>)
             result.push(SymMap {
                 fn_name: fn_origin_name,
                 vir_addr: symtab.st_value,
+                size: symtab.st_size,
             });
         }
     }
     return Ok(result);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SymMap {
     fn_name: String,
     vir_addr: u64,
+    size: u64,
 }
 
 impl SymMap {
     pub fn get_fn_name(&self) -> &String { &self.fn_name }
     pub fn get_vir_addr(&self) -> u64 { self.vir_addr }
+    pub fn get_size(&self) -> u64 { self.size }
+}
+
+const STT_FUNC: u8 = 2;
+const STT_GNU_IFUNC: u8 = 10;
+const PT_LOAD: u32 = 1;
+
+fn load_elf(file_path: &str) -> Result<(Vec<u8>, ElfEhdr, Vec<ElfEShdr>), String> {
+    let file = read(file_path).map_err(|e| format!("{:?}: {:?}", file_path, e))?;
+    if file.len() < size_of::<ElfEhdr>() { return Err(format!("{:?} is too small to be elf format.", file_path)); }
+    let mut array: [u8; size_of::<ElfEhdr>()] = [0x0; size_of::<ElfEhdr>()];
+    array.copy_from_slice(&file[..size_of::<ElfEhdr>()]);
+    let elf_ehdr: ElfEhdr = unsafe { transmute::<[u8; size_of::<ElfEhdr>()], ElfEhdr>(array) };
+    if !elf_ehdr.e_ident.check_elf_format()? { return Err(format!("{:?} is not elf format!!", file_path)); }
+
+    let mut elf_eshdr_list: Vec<ElfEShdr> = vec![];
+    for idx in 0..elf_ehdr.e_shnum {
+        let end = elf_ehdr.e_shoff as usize + size_of::<ElfEShdr>() * (idx as usize + 1);
+        if end > file.len() { return Err(format!("section header is out of {:?}.", file_path)); }
+        elf_eshdr_list.push(ElfEShdr::parse_unit(&file, &elf_ehdr, idx as usize));
+    }
+    Ok((file, elf_ehdr, elf_eshdr_list))
+}
+
+fn get_section_name(file: &[u8], elf_ehdr: &ElfEhdr, elf_eshdr_list: &[ElfEShdr], elf_eshdr: &ElfEShdr) -> Option<String> {
+    let shstrtab = elf_eshdr_list.get(elf_ehdr.e_shstrndx as usize)?;
+    crate::dwarf_reader::read_cstr_at(file, shstrtab.sh_offset as usize + elf_eshdr.sh_name as usize)
+}
+
+// .eh_frame や .debug_line などのセクションをまとめて取り出す
+#[derive(Debug)]
+pub struct SectionData {
+    name: String,
+    vir_addr: u64,
+    data: Vec<u8>,
+}
+
+impl SectionData {
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_vir_addr(&self) -> u64 { self.vir_addr }
+    pub fn get_data(&self) -> &[u8] { &self.data }
+}
+
+pub fn get_sections(file_path: &str, section_names: &[&str]) -> Result<Vec<SectionData>, String> {
+    let (file, elf_ehdr, elf_eshdr_list) = load_elf(file_path)?;
+    let mut result: Vec<SectionData> = vec![];
+    for elf_eshdr in elf_eshdr_list.iter() {
+        let name = match get_section_name(&file, &elf_ehdr, &elf_eshdr_list, elf_eshdr) {
+            Some(name) => name,
+            None => continue,
+        };
+        if !section_names.contains(&name.as_str()) { continue; }
+        let data = match elf_eshdr.get_type() {
+            SHType::SHT_NOBITS => vec![],
+            _ => {
+                let start = elf_eshdr.sh_offset as usize;
+                let end = start + elf_eshdr.sh_size as usize;
+                if end > file.len() { return Err(format!("section {:?} is out of {:?}.", name, file_path)); }
+                file[start..end].to_vec()
+            },
+        };
+        result.push(SectionData { name, vir_addr: elf_eshdr.sh_addr, data });
+    }
+    Ok(result)
+}
+
+// PT_LOADの中で一番小さい仮想アドレス. PIEなら0になる.
+pub fn get_load_vir_addr(file_path: &str) -> Result<u64, String> {
+    let (file, elf_ehdr, _) = load_elf(file_path)?;
+    let mut min_vaddr: Option<u64> = None;
+    for idx in 0..elf_ehdr.e_phnum {
+        let end = elf_ehdr.e_phoff as usize + size_of::<ElfEPhdr>() * (idx as usize + 1);
+        if end > file.len() { break; }
+        let elf_ephdr: ElfEPhdr = ElfEPhdr::parse_unit(&file, &elf_ehdr, idx as usize);
+        if elf_ephdr.p_type != PT_LOAD { continue; }
+        let vaddr = elf_ephdr.p_vaddr & !(elf_ephdr.p_align.max(1) - 1);
+        min_vaddr = Some(min_vaddr.map_or(vaddr, |min| min.min(vaddr)));
+    }
+    Ok(min_vaddr.unwrap_or(0))
+}
+
+// .symtab と .dynsym から関数シンボルだけを集めてアドレス順に並べる
+pub fn get_fn_sym_maps(file_path: &str) -> Result<Vec<SymMap>, String> {
+    let (file, _, elf_eshdr_list) = load_elf(file_path)?;
+    let mut result: Vec<SymMap> = vec![];
+    for elf_eshdr in elf_eshdr_list.iter() {
+        match elf_eshdr.get_type() {
+            SHType::SHT_SYMTAB | SHType::SHT_DYNSYM => {},
+            _ => continue,
+        };
+        let strtab = match elf_eshdr_list.get(elf_eshdr.sh_link as usize) {
+            Some(strtab) => strtab,
+            None => continue,
+        };
+        for idx in 0..elf_eshdr.sh_size as usize / size_of::<ElfESym>() {
+            if elf_eshdr.sh_offset as usize + size_of::<ElfESym>() * (idx + 1) > file.len() { break; }
+            let symtab: ElfESym = ElfESym::parse(&file, elf_eshdr, idx);
+            let sym_type = symtab.st_info & 0xf;
+            if (sym_type != STT_FUNC && sym_type != STT_GNU_IFUNC) || symtab.st_value == 0 { continue; }
+            let fn_name = match crate::dwarf_reader::read_cstr_at(&file, strtab.sh_offset as usize + symtab.st_name as usize) {
+                Some(fn_name) => fn_name,
+                None => continue,
+            };
+            let fn_origin_name = match try_demangle(&fn_name) {
+                Ok(demangled_fn_name) => format!("{:#}", demangled_fn_name),
+                Err(_) => fn_name,
+            };
+            result.push(SymMap {
+                fn_name: fn_origin_name,
+                vir_addr: symtab.st_value,
+                size: symtab.st_size,
+            });
+        }
+    }
+    result.sort_by(|a, b| a.vir_addr.cmp(&b.vir_addr).then(b.size.cmp(&a.size)));
+    result.dedup_by(|a, b| a.vir_addr == b.vir_addr);
+    Ok(result)
+}
+
+// get_fn_sym_mapsの結果(アドレス順)からvir_addrを含む関数を探す
+pub fn lookup_sym_by_addr(sym_map_list: &[SymMap], vir_addr: u64) -> Option<&SymMap> {
+    let idx = match sym_map_list.binary_search_by(|sym_map| sym_map.vir_addr.cmp(&vir_addr)) {
+        Ok(idx) => idx,
+        Err(0) => return None,
+        Err(idx) => idx - 1,
+    };
+    let sym_map = &sym_map_list[idx];
+    if sym_map.size == 0 || vir_addr < sym_map.vir_addr + sym_map.size { Some(sym_map) } else { None }
 }
\ No newline at end of file
@@ -0,0 +1,417 @@
+use anyhow::{Result, bail};
+use std::io::{BufRead, Write, stdout};
+
+use crate::breakpoint::CatchKind;
+use crate::watchpoint::WatchKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // continue [-a]. non-stopで-aなら止まっている全てのスレッドを再開する
+    Continue(bool),
+    Backtrace(Option<usize>),
+    Frame(Option<usize>),
+    Up(usize),
+    Down(usize),
+    InfoRegisters(Vec<String>),
+    InfoAllRegisters,
+    InfoLocals,
+    InfoArgs,
+    Print(String),
+    Set(String),
+    List(Option<String>),
+    Stepi(usize),
+    Nexti(usize),
+    Step(usize),
+    Next(usize),
+    Finish,
+    Until(Option<String>),
+    Advance(String),
+    Disassemble(Option<String>),
+    // x/NFU addr
+    Examine(ExamineFormat, Option<String>),
+    // break [場所] [inferior N] [if 条件]. 最初は tbreak ならtrue
+    Break(bool, Option<String>, Option<usize>, Option<String>),
+    // condition id [条件]. 条件が無ければ外す
+    Condition(usize, Option<String>),
+    Ignore(usize, usize),
+    // commands [id] の後に end までの行. idが無ければ最後に作ったブレークポイント
+    Commands(Option<usize>, Vec<String>),
+    // dprintf 場所,"書式",式...
+    Dprintf(String),
+    Printf(String),
+    // set dprintf-log [ファイル]. ファイルが無ければ標準出力に戻す
+    SetDprintfLog(Option<String>),
+    Watch(WatchKind, String),
+    // catch イベント
+    Catch(CatchKind),
+    // catch syscall [名前|番号|group:グループ]...
+    CatchSyscall(Vec<String>),
+    // 空なら全て消す
+    Delete(Vec<usize>),
+    InfoBreakpoints,
+    InfoThreads,
+    // thread [番号]. 番号が無ければ今のスレッドを表示する
+    Thread(Option<usize>),
+    // interrupt [-a]. -aなら動いている全てのスレッドを止める
+    Interrupt(bool),
+    InfoInferiors,
+    // inferior [番号]. 番号が無ければ今のインフェリアを表示する
+    Inferior(Option<usize>),
+    // add-inferior [-exec ファイル]
+    AddInferior(Option<String>),
+    Attach(i32),
+    // handle シグナル [動作]...
+    Handle(String, Vec<String>),
+    // info signals [シグナル]
+    InfoSignals(Option<String>),
+    // signal シグナル. 0ならシグナルを渡さずに再開する
+    Signal(String),
+    SetNonStop(bool),
+    ShowNonStop,
+    SetFollowForkMode(FollowForkMode),
+    ShowFollowForkMode,
+    SetDetachOnFork(bool),
+    ShowDetachOnFork,
+    Start,
+    Quit,
+    Unknown(String),
+}
+
+// forkした時にどちらのプロセスを追うか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowForkMode {
+    Parent,
+    Child,
+}
+
+// xコマンドの /NFU の部分. 省略された所は前回の値を使う
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExamineFormat {
+    pub count: Option<usize>,
+    pub format: Option<char>,
+    pub size: Option<char>,
+}
+
+pub fn parse_command(line: &str) -> Command {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        // 何も入力せずEnterだけなら今まで通り再開する
+        None => return Command::Continue(false),
+    };
+    let args: Vec<&str> = words.collect();
+    match name {
+        "c" | "continue" => Command::Continue(args.first() == Some(&"-a")),
+        "interrupt" => Command::Interrupt(args.first() == Some(&"-a")),
+        "bt" | "backtrace" | "where" => {
+            match args.first() {
+                Some(limit) => match limit.parse::<usize>() {
+                    Ok(limit) => Command::Backtrace(Some(limit)),
+                    Err(_) => Command::Unknown(line.to_owned()),
+                },
+                None => Command::Backtrace(None),
+            }
+        },
+        "f" | "frame" => match args.first() {
+            Some(level) => match level.parse::<usize>() {
+                Ok(level) => Command::Frame(Some(level)),
+                Err(_) => Command::Unknown(line.to_owned()),
+            },
+            None => Command::Frame(None),
+        },
+        "up" | "down" | "si" | "stepi" | "ni" | "nexti" | "s" | "step" | "n" | "next" => {
+            let count = match args.first() {
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) => count,
+                    Err(_) => return Command::Unknown(line.to_owned()),
+                },
+                None => 1,
+            };
+            match name {
+                "up" => Command::Up(count),
+                "down" => Command::Down(count),
+                "si" | "stepi" => Command::Stepi(count),
+                "ni" | "nexti" => Command::Nexti(count),
+                "s" | "step" => Command::Step(count),
+                _ => Command::Next(count),
+            }
+        },
+        "fin" | "finish" => Command::Finish,
+        "u" | "until" => {
+            let location = rest_of(line, name);
+            Command::Until(if location.is_empty() { None } else { Some(location) })
+        },
+        "advance" => Command::Advance(rest_of(line, name)),
+        "disas" | "disassemble" => {
+            let arg = rest_of(line, name);
+            Command::Disassemble(if arg.is_empty() { None } else { Some(arg) })
+        },
+        _ if name == "x" || name.starts_with("x/") => {
+            let format = match parse_examine_format(&name[1..]) {
+                Some(format) => format,
+                None => return Command::Unknown(line.to_owned()),
+            };
+            let addr = rest_of(line, name);
+            Command::Examine(format, if addr.is_empty() { None } else { Some(addr) })
+        },
+        "b" | "br" | "break" | "tb" | "tbreak" => {
+            let arg = rest_of(line, name);
+            let (location, condition) = if arg == "if" || arg.starts_with("if ") {
+                ("", Some(arg[2..].trim()))
+            } else {
+                match arg.split_once(" if ") {
+                    Some((location, condition)) => (location.trim(), Some(condition.trim())),
+                    None => (arg.as_str(), None),
+                }
+            };
+            // 場所の後ろの inferior N
+            let (location, inferior) = match location.rsplit_once("inferior ") {
+                Some((rest, num)) if rest.is_empty() || rest.ends_with(' ') => match num.trim().parse::<usize>() {
+                    Ok(num) => (rest.trim(), Some(num)),
+                    Err(_) => (location, None),
+                },
+                _ => (location, None),
+            };
+            let location = if location.is_empty() { None } else { Some(location.to_owned()) };
+            Command::Break(name.starts_with('t'), location, inferior, condition.map(|condition| condition.to_owned()))
+        },
+        "condition" => {
+            let arg = rest_of(line, name);
+            let (id, condition) = arg.split_once(char::is_whitespace).unwrap_or((&arg, ""));
+            match id.parse::<usize>() {
+                Ok(id) => Command::Condition(id, if condition.trim().is_empty() { None } else { Some(condition.trim().to_owned()) }),
+                Err(_) => Command::Unknown(line.to_owned()),
+            }
+        },
+        "ignore" => match (args.first().and_then(|id| id.parse::<usize>().ok()), args.get(1).and_then(|count| count.parse::<usize>().ok())) {
+            (Some(id), Some(count)) if args.len() == 2 => Command::Ignore(id, count),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "commands" => match args.first() {
+            Some(id) => match id.parse::<usize>() {
+                Ok(id) if args.len() == 1 => Command::Commands(Some(id), vec![]),
+                _ => Command::Unknown(line.to_owned()),
+            },
+            None => Command::Commands(None, vec![]),
+        },
+        "dprintf" => Command::Dprintf(rest_of(line, name)),
+        "printf" => Command::Printf(rest_of(line, name)),
+        "watch" | "rwatch" | "awatch" => {
+            let kind = match name {
+                "watch" => WatchKind::Write,
+                "rwatch" => WatchKind::Read,
+                _ => WatchKind::Access,
+            };
+            // 常に評価したアドレスを見張るので -location は付けても付けなくても同じ
+            let expr = rest_of(line, name);
+            let expr = match expr.split_once(char::is_whitespace) {
+                Some(("-l", rest)) | Some(("-location", rest)) => rest.trim().to_owned(),
+                _ => expr,
+            };
+            Command::Watch(kind, expr)
+        },
+        "catch" => match args.as_slice() {
+            ["panic"] => Command::Catch(CatchKind::Panic),
+            ["fork"] => Command::Catch(CatchKind::Fork),
+            ["vfork"] => Command::Catch(CatchKind::Vfork),
+            ["clone"] => Command::Catch(CatchKind::Clone),
+            ["exec"] => Command::Catch(CatchKind::Exec),
+            ["exit"] => Command::Catch(CatchKind::Exit),
+            ["syscall", names @ ..] => Command::CatchSyscall(names.iter().map(|name| name.to_string()).collect()),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "d" | "delete" => {
+            let ids: Option<Vec<usize>> = args.iter().map(|id| id.parse::<usize>().ok()).collect();
+            match ids {
+                Some(ids) => Command::Delete(ids),
+                None => Command::Unknown(line.to_owned()),
+            }
+        },
+        "start" => Command::Start,
+        "thread" => match args.first() {
+            Some(num) => match num.parse() {
+                Ok(num) => Command::Thread(Some(num)),
+                Err(_) => Command::Unknown(line.to_owned()),
+            },
+            None => Command::Thread(None),
+        },
+        "inferior" => match args.first() {
+            Some(num) => match num.parse() {
+                Ok(num) => Command::Inferior(Some(num)),
+                Err(_) => Command::Unknown(line.to_owned()),
+            },
+            None => Command::Inferior(None),
+        },
+        "add-inferior" => match args.as_slice() {
+            [] => Command::AddInferior(None),
+            ["-exec", file] => Command::AddInferior(Some(file.to_string())),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "attach" => match args.first().map(|pid| pid.parse::<i32>()) {
+            Some(Ok(pid)) if args.len() == 1 => Command::Attach(pid),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "handle" => match args.split_first() {
+            Some((name, actions)) => Command::Handle(name.to_string(), actions.iter().map(|action| action.to_string()).collect()),
+            None => Command::Unknown(line.to_owned()),
+        },
+        "signal" => match args.as_slice() {
+            [name] => Command::Signal(name.to_string()),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "q" | "quit" => Command::Quit,
+        "i" | "info" => match args.first() {
+            Some(&"r") | Some(&"reg") | Some(&"registers") => {
+                Command::InfoRegisters(args[1..].iter().map(|name| name.trim_start_matches('$').to_owned()).collect())
+            },
+            Some(&"all-registers") => Command::InfoAllRegisters,
+            Some(&"b") | Some(&"break") | Some(&"breakpoints") | Some(&"watchpoints") => Command::InfoBreakpoints,
+            Some(&"threads") => Command::InfoThreads,
+            Some(&"inferiors") => Command::InfoInferiors,
+            Some(&"signals") | Some(&"handle") => Command::InfoSignals(args.get(1).map(|name| name.to_string())),
+            Some(&"locals") => Command::InfoLocals,
+            Some(&"args") => Command::InfoArgs,
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "p" | "print" => Command::Print(rest_of(line, name)),
+        "set" if args.first() == Some(&"dprintf-log") => {
+            let file = rest_of(line, name)["dprintf-log".len()..].trim().to_owned();
+            Command::SetDprintfLog(if file.is_empty() { None } else { Some(file) })
+        },
+        "set" if args.first() == Some(&"non-stop") => match args.get(1) {
+            Some(&"on") | None => Command::SetNonStop(true),
+            Some(&"off") => Command::SetNonStop(false),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "set" if args.first() == Some(&"follow-fork-mode") => match args.get(1) {
+            Some(&"parent") => Command::SetFollowForkMode(FollowForkMode::Parent),
+            Some(&"child") => Command::SetFollowForkMode(FollowForkMode::Child),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "set" if args.first() == Some(&"detach-on-fork") => match args.get(1) {
+            Some(&"on") | None => Command::SetDetachOnFork(true),
+            Some(&"off") => Command::SetDetachOnFork(false),
+            _ => Command::Unknown(line.to_owned()),
+        },
+        "set" => Command::Set(rest_of(line, name)),
+        "show" if args.first() == Some(&"non-stop") => Command::ShowNonStop,
+        "show" if args.first() == Some(&"follow-fork-mode") => Command::ShowFollowForkMode,
+        "show" if args.first() == Some(&"detach-on-fork") => Command::ShowDetachOnFork,
+        "l" | "list" => {
+            let arg = rest_of(line, name);
+            Command::List(if arg.is_empty() { None } else { Some(arg) })
+        },
+        _ => Command::Unknown(line.to_owned()),
+    }
+}
+
+// "/10xg" のような指定を読む. 数, フォーマット, サイズの順だが, フォーマットとサイズは逆でもよい
+fn parse_examine_format(spec: &str) -> Option<ExamineFormat> {
+    let mut format = ExamineFormat::default();
+    let spec = match spec.strip_prefix('/') {
+        Some(spec) => spec,
+        None if spec.is_empty() => return Some(format),
+        None => return None,
+    };
+    let digits: String = spec.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if !digits.is_empty() {
+        format.count = Some(digits.parse().ok()?);
+    }
+    for c in spec[digits.len()..].chars() {
+        match c {
+            'b' | 'h' | 'w' | 'g' if format.size.is_none() => format.size = Some(c),
+            'x' | 'd' | 'u' | 'o' | 't' | 'c' | 's' | 'i' | 'a' if format.format.is_none() => format.format = Some(c),
+            _ => return None,
+        }
+    }
+    Some(format)
+}
+
+// コマンド名より後ろをそのまま返す
+fn rest_of(line: &str, name: &str) -> String {
+    line.trim_start()[name.len()..].trim().to_owned()
+}
+
+pub fn read_command() -> Command {
+    loop {
+        print!("(toy-gdb) ");
+        stdout().flush().unwrap_or(());
+        let mut s = String::new();
+        if std::io::stdin().read_line(&mut s).unwrap_or(0) == 0 {
+            // EOFなら終了する
+            return Command::Quit;
+        }
+        match parse_command(s.trim()) {
+            // 読み取りに失敗したらブレークポイントのコマンドは変えずに次のコマンドを読む
+            Command::Commands(id, _) => match read_command_list(&mut std::io::stdin().lock(), id) {
+                Ok(commands) => return Command::Commands(id, commands),
+                Err(e) => println!("{}", e),
+            },
+            command => return command,
+        }
+    }
+}
+
+// end だけの行までを読む. gdbと同じく中で commands は使えない.
+// 入れ子の commands があれば, 残りの行がトップレベルで実行されないよう対応する end まで読み捨ててからエラーにする
+fn read_command_list(input: &mut dyn BufRead, id: Option<usize>) -> Result<Vec<String>> {
+    match id {
+        Some(id) => println!("Type commands for breakpoint(s) {}, one per line.", id),
+        None => println!("Type commands for breakpoint(s), one per line."),
+    }
+    println!("End with a line saying just \"end\".");
+    let mut commands = vec![];
+    let mut nested = false;
+    // 読み捨て中の入れ子の深さ
+    let mut depth = 0usize;
+    loop {
+        print!(">");
+        stdout().flush().unwrap_or(());
+        let mut s = String::new();
+        if input.read_line(&mut s).unwrap_or(0) == 0 { break; }
+        let line = s.trim();
+        if line == "end" {
+            if depth == 0 { break; }
+            depth -= 1;
+            continue;
+        }
+        if let Command::Commands(..) = parse_command(line) {
+            nested = true;
+            depth += 1;
+        }
+        if !nested && !line.is_empty() {
+            commands.push(line.to_owned());
+        }
+    }
+    if nested { bail!("The \"commands\" command cannot be used inside a breakpoint command list."); }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_list() {
+        let mut input = "print 1\n\n  silent  \nend\ncontinue\n".as_bytes();
+        assert_eq!(read_command_list(&mut input, Some(1)).unwrap(), vec!["print 1", "silent"]);
+        // end の後ろは読まない
+        assert_eq!(input, b"continue\n");
+    }
+
+    #[test]
+    fn nested_command_list() {
+        let mut input = "print 1\ncommands 2\ncontinue\ncommands\nkill\nend\nend\nprint 2\nend\ninfo breakpoints\n".as_bytes();
+        assert!(read_command_list(&mut input, None).is_err());
+        // 入れ子のブロックと外側の end までは読み捨てる
+        assert_eq!(input, b"info breakpoints\n");
+    }
+
+    #[test]
+    fn command_list_at_eof() {
+        let mut input = "print 1\ncommands\nprint 2\n".as_bytes();
+        assert!(read_command_list(&mut input, None).is_err());
+        let mut input = "print 1\n".as_bytes();
+        assert_eq!(read_command_list(&mut input, None).unwrap(), vec!["print 1"]);
+    }
+}
@@ -0,0 +1,3154 @@
+use anyhow::{Result, bail, Context};
+use nix::unistd::Pid;
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::libc::{self, user_regs_struct};
+use std::fs::{File, OpenOptions, read_link, read_to_string};
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use crate::breakpoint::{Breakpoint, BreakpointSites, CatchKind};
+use crate::command::{self, Command, ExamineFormat, FollowForkMode};
+use crate::disasm::{self, Instruction};
+use crate::dwarf_expr::{self, ExprContext, ExprResult};
+use crate::dwarf_info::{DebugInfo, Function, Location, Variable};
+use crate::expression::{self, EvalContext};
+use crate::panic;
+use crate::printf;
+use crate::ptrace;
+use crate::registers::{self, FpKind, FpRegisters};
+use crate::signals::{self, SignalTable};
+use crate::syscall;
+use crate::target::{Module, Target};
+use crate::thread::{self, Thread};
+use crate::unwind::{self, Frame};
+use crate::value::{self, ReturnClass, Scalar, Value, ValueType};
+use crate::watchpoint::{self, DebugRegisters, WatchKind, Watchpoint};
+
+const LIST_LINES: u64 = 10;
+const NOT_RUNNING: &str = "The program is not being run.";
+// int3で止まった時のsi_code (SI_KERNEL, TRAP_BRKPT)
+const SI_KERNEL: i32 = 0x80;
+const TRAP_BRKPT: i32 = 1;
+
+// 再開した後にプロセスが止まった理由
+#[derive(Debug, Clone, PartialEq)]
+enum StopEvent {
+    // int3で止まった. ripはブレークポイントのアドレスに戻してある
+    Breakpoint(u64),
+    // シングルステップが終わった
+    Trap,
+    // ウォッチポイントの値が変わった (読まれた). 報告するものの (id, 前の値)
+    Watchpoint(Vec<(usize, Vec<u8>)>),
+    Signal(Signal),
+    // catch で捕まえるイベント
+    Catch(CatchEvent),
+    Exited(i32),
+    Killed(Signal),
+}
+
+// キャッチポイントで止まるイベント. どれもイベントが終わる前に止まる
+#[derive(Debug, Clone, PartialEq)]
+enum CatchEvent {
+    // システムコールの入り口と出口. 番号はorig_raxのもの
+    SyscallEntry(u64),
+    SyscallExit(u64),
+    // 作った子プロセスやスレッド
+    Fork(Pid),
+    Vfork(Pid),
+    Clone(Pid),
+    // execした新しいプログラムのパス
+    Exec(String),
+    // PTRACE_GETEVENTMSGで受け取った, waitと同じ形の終了ステータス
+    Exit(i32),
+}
+
+impl CatchEvent {
+    fn caught_by(&self, catch: &CatchKind) -> bool {
+        match self {
+            CatchEvent::SyscallEntry(nr) | CatchEvent::SyscallExit(nr) => catch.catches_syscall(*nr),
+            CatchEvent::Fork(_) => *catch == CatchKind::Fork,
+            CatchEvent::Vfork(_) => *catch == CatchKind::Vfork,
+            CatchEvent::Clone(_) => *catch == CatchKind::Clone,
+            CatchEvent::Exec(_) => *catch == CatchKind::Exec,
+            CatchEvent::Exit(_) => *catch == CatchKind::Exit,
+        }
+    }
+
+    // 止まった時の "Catchpoint 1 (forked process 1234)" の括弧の中
+    fn describe(&self) -> String {
+        match self {
+            CatchEvent::SyscallEntry(nr) => format!("call to syscall {}", syscall::display_name(*nr)),
+            CatchEvent::SyscallExit(nr) => format!("returned from syscall {}", syscall::display_name(*nr)),
+            CatchEvent::Fork(child) => format!("forked process {}", child),
+            CatchEvent::Vfork(child) => format!("vforked process {}", child),
+            CatchEvent::Clone(tid) => format!("cloned LWP {}", tid),
+            CatchEvent::Exec(path) => format!("exec'd {}", path),
+            CatchEvent::Exit(status) if libc::WIFSIGNALED(*status) => match Signal::try_from(libc::WTERMSIG(*status)) {
+                Ok(sig) => format!("exiting on signal {}", sig.as_str()),
+                Err(_) => format!("exiting on signal {}", libc::WTERMSIG(*status)),
+            },
+            CatchEvent::Exit(status) => format!("exiting with status {}", libc::WEXITSTATUS(*status)),
+        }
+    }
+
+    // info threads に表示する止まった理由
+    fn stop_reason(&self) -> String {
+        match self {
+            CatchEvent::SyscallEntry(nr) | CatchEvent::SyscallExit(nr) => format!("syscall {}", syscall::display_name(*nr)),
+            CatchEvent::Fork(_) => "fork".to_owned(),
+            CatchEvent::Vfork(_) => "vfork".to_owned(),
+            CatchEvent::Clone(_) => "clone".to_owned(),
+            CatchEvent::Exec(_) => "exec".to_owned(),
+            CatchEvent::Exit(_) => "exit".to_owned(),
+        }
+    }
+}
+
+// waitで受け取った状態を処理した結果
+#[derive(Debug, Clone, PartialEq)]
+enum ThreadStatus {
+    // 報告するイベントで止まった
+    Stop(StopEvent),
+    // デバッガの都合で止まっただけなので, 同じように再開すればよい
+    Internal,
+    // スレッドが終わった
+    Gone,
+    // まだ知らないスレッドの停止. クローンのイベントが届くまで止めておく
+    Ignore,
+}
+
+// info breakpoints の1行. (番号, 種類, 止まった後どうするか, アドレス, 場所, 次の行からの詳細, 複数のインフェリアにある時の (アドレス, 場所))
+type BreakpointRow = (usize, &'static str, &'static str, String, String, Vec<String>, Vec<(String, String)>);
+
+// インフェリア1つ分のプロセスの状態. 今選んでいるものはDebuggerのinferiorに, 他はinferiorsに置く
+struct Inferior {
+    num: usize,
+    pid: Pid,
+    // プロセスID (スレッドグループのリーダーのtid). pidは今選んでいるスレッド
+    tgid: Pid,
+    target: Target,
+    // 起動したプログラムと引数. アタッチした時はNone
+    program: Option<Vec<String>>,
+    // 起動してから一度も再開していない
+    just_launched: bool,
+    regs: Option<user_regs_struct>,
+    frames: Vec<Frame>,
+    selected_frame: usize,
+    threads: Vec<Thread>,
+    next_thread_num: usize,
+    // 他のスレッドを止めた時に起きていたイベント. 次に再開する時に, 再開せずに報告する
+    pending_events: Vec<(Pid, StopEvent)>,
+    sites: BreakpointSites,
+    debug_registers: DebugRegisters,
+    watchpoints: Vec<Watchpoint>,
+    // クローンのイベントより先に届いた, 新しいスレッドの最初の停止
+    early_stops: Vec<Pid>,
+    // 最後に止まったと表示したスレッド. 違うスレッドで止まったら切り替えたことを表示する
+    announced_thread: Pid,
+    // finishやnextが使う一時的なブレークポイントのアドレス
+    internal_breakpoints: Vec<u64>,
+    // vforkした子を追っている時の親の (インフェリアの番号, プロセスID, スレッド).
+    // 子がexecするか終わるまではメモリを共有しているので止めておく
+    vfork_parent: Option<(usize, Pid, Vec<Pid>)>,
+    // 親を追っていてvforkした子を切り離した時の子. 子がexecするか終わるまではメモリを共有しているので,
+    // catch vfork で親が止まっていてもint3を入れない
+    vfork_child: Option<Pid>,
+}
+
+impl Inferior {
+    // 止まっているプロセスpidのインフェリア. pidが0ならプロセスはまだ無い
+    fn new(num: usize, pid: Pid, target: Target, program: Option<Vec<String>>) -> Self {
+        let threads = if pid.as_raw() == 0 { vec![] } else { vec![Thread::new(1, pid)] };
+        let regs = if threads.is_empty() { None } else { ptrace::getregs(pid).ok() };
+        Inferior {
+            num,
+            pid,
+            tgid: pid,
+            target,
+            program,
+            just_launched: false,
+            regs,
+            frames: vec![],
+            selected_frame: 0,
+            threads,
+            next_thread_num: 2,
+            pending_events: vec![],
+            sites: BreakpointSites::default(),
+            debug_registers: DebugRegisters::default(),
+            watchpoints: vec![],
+            early_stops: vec![],
+            announced_thread: pid,
+            internal_breakpoints: vec![],
+            vfork_parent: None,
+            vfork_child: None,
+        }
+    }
+
+    fn empty(num: usize, program: Option<Vec<String>>) -> Self {
+        Inferior::new(num, Pid::from_raw(0), Target::empty(), program)
+    }
+}
+
+pub struct Debugger {
+    // 今選んでいるインフェリアと, 他のインフェリア (番号順)
+    inferior: Inferior,
+    inferiors: Vec<Inferior>,
+    next_inferior_num: usize,
+    value_history: Vec<Value>,
+    // 次にlistで表示する (ファイル名, 行番号)
+    list_position: Option<(String, u64)>,
+    breakpoints: Vec<Breakpoint>,
+    // dprintfの出力先. Noneなら標準出力
+    dprintf_log: Option<File>,
+    // 最後に止まった時に, 条件を満たして止まったブレークポイントのid
+    stopped_breakpoints: Vec<usize>,
+    // ブレークポイントで止まった時に, 入力の代わりに実行するコマンド. 再開したら残りは捨てる
+    breakpoint_commands: VecDeque<String>,
+    // ブレークポイントとウォッチポイントで共通の番号
+    next_breakpoint_id: usize,
+    // non-stop: 止まったスレッドだけを止めて, 他のスレッドは動かし続ける
+    non_stop: bool,
+    follow_fork_mode: FollowForkMode,
+    // forkした時に追わない方のプロセスを切り離すか. offなら止めたままにしておく
+    detach_on_fork: bool,
+    // handle コマンドで決めた, シグナルを受け取った時に止めるか, 表示するか, プログラムに渡すか
+    signal_table: SignalTable,
+    // 前回のxコマンドのフォーマットと, 次に表示するアドレス
+    examine_format: ExamineFormat,
+    next_examine_addr: Option<u64>,
+}
+
+impl Debugger {
+    pub fn new(pid: Pid, target: Target) -> Self {
+        let mut inferior = Inferior::new(1, pid, target, None);
+        // まだ止まっていないかもしれない. レジスタは止まった時に読む
+        inferior.regs = None;
+        Debugger {
+            inferior,
+            inferiors: vec![],
+            next_inferior_num: 2,
+            value_history: vec![],
+            list_position: None,
+            breakpoints: vec![],
+            dprintf_log: None,
+            stopped_breakpoints: vec![],
+            breakpoint_commands: VecDeque::new(),
+            next_breakpoint_id: 1,
+            non_stop: false,
+            follow_fork_mode: FollowForkMode::Parent,
+            detach_on_fork: true,
+            signal_table: SignalTable::default(),
+            examine_format: ExamineFormat { count: Some(1), format: Some('x'), size: Some('w') },
+            next_examine_addr: None,
+        }
+    }
+
+    // programを起動して, execした所で止めておく
+    pub fn launch(program: Vec<String>) -> Result<Self> {
+        let pid = ptrace::spawn(&program)?;
+        let target = Target::load(pid)?;
+        let mut debugger = Debugger::new(pid, target);
+        debugger.inferior.program = Some(program);
+        debugger.inferior.just_launched = true;
+        debugger.stopped(ptrace::getregs(pid)?);
+        Ok(debugger)
+    }
+
+    // 止まるたびに呼ぶ. 巻き戻したフレームと選択中のフレームを捨てる
+    pub fn stopped(&mut self, regs: user_regs_struct) {
+        self.inferior.regs = Some(regs);
+        self.inferior.frames = vec![];
+        self.inferior.selected_frame = 0;
+        self.list_position = None;
+        self.stopped_breakpoints = vec![];
+        self.breakpoint_commands.clear();
+    }
+
+    fn ensure_frames(&mut self) -> Result<()> {
+        if !self.inferior.frames.is_empty() { return Ok(()); }
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        self.inferior.frames = unwind::backtrace(&self.inferior.target, self.inferior.pid, &regs, None);
+        Ok(())
+    }
+
+    fn get_selected_frame(&mut self) -> Result<Frame> {
+        self.ensure_frames()?;
+        self.inferior.frames.get(self.inferior.selected_frame).cloned().context("No stack.")
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u64, temporary: bool) -> usize {
+        self.new_breakpoint(addr, temporary, if temporary { "Temporary breakpoint" } else { "Breakpoint" })
+    }
+
+    // kindは作った時の表示に使う
+    fn new_breakpoint(&mut self, addr: u64, temporary: bool, kind: &str) -> usize {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        match self.inferior.target.find_source_line(addr) {
+            Some((file_name, line)) => println!("{} {} at 0x{:x}: file {}, line {}.", kind, id, addr, file_name, line),
+            None => println!("{} {} at 0x{:x}", kind, id, addr),
+        }
+        self.breakpoints.push(Breakpoint::new(id, self.inferior.num, addr, temporary));
+        id
+    }
+
+    // 最初のブレークポイントまで進めてから, 終了するまでコマンドを受け付ける
+    pub fn run(&mut self) {
+        self.execute(Debugger::continue_execution);
+        self.prompt();
+    }
+
+    // 終了するまでコマンドを受け付ける
+    pub fn prompt(&mut self) {
+        loop {
+            // non-stopでは, 動いているスレッドで起きたことをコマンドを読む前に報告する
+            if self.non_stop && self.breakpoint_commands.is_empty() {
+                self.execute(Debugger::poll_events);
+            }
+            self.arm_interrupt();
+            let command = match self.breakpoint_commands.pop_front() {
+                Some(line) => command::parse_command(&line),
+                None => command::read_command(),
+            };
+            match command {
+                Command::Continue(false) => self.execute(Debugger::continue_execution),
+                Command::Continue(true) => self.execute(Debugger::continue_all),
+                Command::Backtrace(limit) => self.execute(|debugger| debugger.backtrace(limit)),
+                Command::Frame(level) => self.execute(|debugger| debugger.select_frame(level)),
+                Command::Up(count) => self.execute(|debugger| {
+                    let level = debugger.inferior.selected_frame + count;
+                    debugger.move_frame(level, "Initial frame selected; you cannot go up.")
+                }),
+                Command::Down(count) => self.execute(|debugger| {
+                    if debugger.inferior.selected_frame == 0 {
+                        bail!("Bottom (innermost) frame selected; you cannot go down.");
+                    }
+                    let level = debugger.inferior.selected_frame.saturating_sub(count);
+                    debugger.move_frame(level, "")
+                }),
+                Command::InfoRegisters(names) => self.execute(|debugger| debugger.info_registers(&names)),
+                Command::InfoAllRegisters => self.execute(|debugger| debugger.info_all_registers()),
+                Command::InfoLocals => self.execute(|debugger| debugger.info_variables(false)),
+                Command::InfoArgs => self.execute(|debugger| debugger.info_variables(true)),
+                Command::Print(expr) => self.execute(|debugger| debugger.print(&expr)),
+                Command::List(arg) => self.execute(|debugger| debugger.list(arg.as_deref())),
+                Command::Stepi(count) => self.execute(|debugger| debugger.step_instruction(count, false)),
+                Command::Nexti(count) => self.execute(|debugger| debugger.step_instruction(count, true)),
+                Command::Step(count) => self.execute(|debugger| debugger.step_line(count, false)),
+                Command::Next(count) => self.execute(|debugger| debugger.step_line(count, true)),
+                Command::Finish => self.execute(Debugger::finish),
+                Command::Until(None) => self.execute(Debugger::until_next_line),
+                Command::Until(Some(location)) => self.execute(|debugger| debugger.run_to_location(&location, true)),
+                Command::Advance(location) => self.execute(|debugger| debugger.run_to_location(&location, false)),
+                Command::Set(expr) => self.execute(|debugger| debugger.set(&expr)),
+                Command::Disassemble(arg) => self.execute(|debugger| debugger.disassemble(arg.as_deref())),
+                Command::Examine(format, addr) => self.execute(|debugger| debugger.examine(format, addr.as_deref())),
+                Command::Break(temporary, location, inferior, condition) => self.execute(|debugger| debugger.set_breakpoint(location.as_deref(), inferior, condition.as_deref(), temporary)),
+                Command::Condition(id, condition) => self.execute(|debugger| debugger.condition(id, condition)),
+                Command::Ignore(id, count) => self.execute(|debugger| debugger.ignore(id, count)),
+                Command::Commands(id, commands) => self.execute(|debugger| debugger.set_commands(id, commands)),
+                Command::Dprintf(arg) => self.execute(|debugger| debugger.dprintf(&arg)),
+                Command::Printf(arg) => self.execute(|debugger| debugger.printf(&arg)),
+                Command::SetDprintfLog(path) => self.execute(|debugger| debugger.set_dprintf_log(path.as_deref())),
+                Command::Watch(kind, expr) => self.execute(|debugger| debugger.watch(kind, &expr)),
+                Command::Catch(kind) => self.execute(|debugger| debugger.catch(kind)),
+                Command::CatchSyscall(names) => self.execute(|debugger| debugger.catch_syscall(&names)),
+                Command::Delete(ids) => self.execute(|debugger| debugger.delete(&ids)),
+                Command::InfoBreakpoints => self.execute(Debugger::info_breakpoints),
+                Command::InfoThreads => self.execute(Debugger::info_threads),
+                Command::Thread(num) => self.execute(|debugger| debugger.thread(num)),
+                Command::InfoInferiors => self.execute(Debugger::info_inferiors),
+                Command::Inferior(num) => self.execute(|debugger| debugger.inferior(num)),
+                Command::AddInferior(exec) => self.execute(|debugger| debugger.add_inferior(exec)),
+                Command::Attach(pid) => self.execute(|debugger| debugger.attach(pid)),
+                Command::Interrupt(all) => self.execute(|debugger| debugger.interrupt(all)),
+                Command::SetNonStop(non_stop) => self.execute(|debugger| debugger.set_non_stop(non_stop)),
+                Command::ShowNonStop => println!("Controlling the inferior in non-stop mode is {}.", if self.non_stop { "on" } else { "off" }),
+                Command::SetFollowForkMode(mode) => self.follow_fork_mode = mode,
+                Command::ShowFollowForkMode => {
+                    let mode = if self.follow_fork_mode == FollowForkMode::Parent { "parent" } else { "child" };
+                    println!("Debugger response to a program call of fork or vfork is \"{}\".", mode);
+                },
+                Command::SetDetachOnFork(detach) => self.detach_on_fork = detach,
+                Command::ShowDetachOnFork => println!("Whether gdb will detach the child of a fork is {}.", if self.detach_on_fork { "on" } else { "off" }),
+                Command::Handle(name, actions) => self.execute(|debugger| debugger.handle(&name, &actions)),
+                Command::InfoSignals(name) => self.execute(|debugger| debugger.info_signals(name.as_deref())),
+                Command::Signal(name) => self.execute(|debugger| debugger.signal(&name)),
+                Command::Start => self.execute(Debugger::start),
+                Command::Quit => {
+                    self.execute(Debugger::quit);
+                    break;
+                },
+                Command::Unknown(line) => println!("Undefined command: \"{}\".", line),
+            }
+        }
+    }
+
+    fn execute<F: FnOnce(&mut Debugger) -> Result<()>>(&mut self, f: F) {
+        if let Err(e) = f(self) {
+            // non-stopで選択中のスレッドが動いている時は, 止まっていないことを伝える
+            if e.to_string() == NOT_RUNNING && self.is_thread_running(self.inferior.pid) {
+                println!("Selected thread is running.");
+            } else {
+                println!("{}", e);
+            }
+        }
+    }
+
+    // 全てのインフェリアのプロセスを終わらせるか切り離す
+    fn quit(&mut self) -> Result<()> {
+        self.discard_vfork_parent()?;
+        self.release_process()?;
+        while let Some(inferior) = self.inferiors.pop() {
+            self.inferior = inferior;
+            self.discard_vfork_parent()?;
+            self.release_process()?;
+        }
+        Ok(())
+    }
+
+    fn release_process(&mut self) -> Result<()> {
+        if self.inferior.threads.is_empty() { return Ok(()); }
+        // 起動したプログラムはgdbと同じく終わらせる
+        if self.inferior.program.is_some() {
+            return ptrace::kill(self.inferior.tgid);
+        }
+        // non-stopで動いているスレッドも, 止めてからint3を外す
+        self.stop_all_threads()?;
+        self.inferior.sites.remove_all(self.inferior.pid)?;
+        // デバッグレジスタが残っているとデタッチした後にSIGTRAPで落ちる
+        let tids = self.thread_tids();
+        self.inferior.debug_registers.sync(&tids, &[])?;
+        self.detach_threads(&tids)
+    }
+
+    // スレッドを全て切り離す. まだ受け取っていないPTRACE_INTERRUPTの停止は切り離すと消えるので, 待たなくてよい
+    fn detach_threads(&mut self, tids: &[Pid]) -> Result<()> {
+        for tid in tids.iter() {
+            ptrace::detach(*tid)?;
+        }
+        Ok(())
+    }
+
+    // vforkした子を追うのをやめる時に, 止めておいた親を起動したものなら終わらせ, アタッチしたものなら切り離す
+    fn discard_vfork_parent(&mut self) -> Result<()> {
+        if let Some((_, pid, tids)) = self.inferior.vfork_parent.take() {
+            if self.inferior.program.is_some() {
+                ptrace::kill(pid)?;
+            } else {
+                for tid in tids.iter() {
+                    ptrace::detach(*tid)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 何も表示せずにnumのインフェリアを選ぶ
+    fn switch_inferior(&mut self, num: usize) -> Result<()> {
+        if num == self.inferior.num { return Ok(()); }
+        let idx = self.inferiors.iter().position(|inferior| inferior.num == num).with_context(|| format!("Inferior ID {} not known.", num))?;
+        let inferior = self.inferiors.remove(idx);
+        let previous = std::mem::replace(&mut self.inferior, inferior);
+        self.push_inferior(previous);
+        self.list_position = None;
+        self.stopped_breakpoints = vec![];
+        Ok(())
+    }
+
+    // 一時的にnumのインフェリアを選んでfを呼び, 元のインフェリアに戻す
+    fn with_inferior<T, F: FnOnce(&mut Debugger) -> T>(&mut self, num: usize, f: F) -> Result<T> {
+        let current = self.inferior.num;
+        self.switch_inferior(num)?;
+        let result = f(self);
+        self.switch_inferior(current)?;
+        Ok(result)
+    }
+
+    // 新しいインフェリアを加える. 表示はしない
+    fn push_inferior(&mut self, inferior: Inferior) {
+        self.inferiors.push(inferior);
+        self.inferiors.sort_by_key(|inferior| inferior.num);
+    }
+
+    fn new_inferior_num(&mut self) -> usize {
+        let num = self.next_inferior_num;
+        self.next_inferior_num += 1;
+        num
+    }
+
+    // 止まったままのforkの子をインフェリアにする. 親のブレークポイントはそのまま子でも止まる
+    fn add_forked_inferior(&mut self, num: usize, pid: Pid, sites: BreakpointSites) -> Result<()> {
+        let mut inferior = Inferior::new(num, pid, Target::load(pid)?, self.inferior.program.clone());
+        inferior.sites = sites;
+        let parent = self.inferior.num;
+        for breakpoint in self.breakpoints.iter_mut() {
+            breakpoint.copy_location(parent, num);
+        }
+        self.push_inferior(inferior);
+        Ok(())
+    }
+
+    // forkやvforkで作られた子プロセスを, follow-fork-modeとdetach-on-forkに従って扱う. 続けて動かすスレッドを返す
+    fn handle_fork(&mut self, parent: Pid, child: Pid, vfork: bool) -> Result<Pid> {
+        self.wait_new_task(child)?;
+        let kind = if vfork { "vfork" } else { "fork" };
+        if self.follow_fork_mode == FollowForkMode::Parent {
+            if self.detach_on_fork {
+                // 子プロセスにコピーされたint3を外す. vforkならメモリを共有しているので親のint3も外れ, vforkが終わった所で入れ直す
+                self.inferior.sites.remove_from(child)?;
+                if vfork {
+                    self.inferior.sites.forget();
+                    self.inferior.vfork_child = Some(child);
+                }
+                ptrace::detach(child)?;
+                println!("[Detaching after {} from child process {}]", kind, child);
+            } else {
+                // 子は止めたまま新しいインフェリアにする. vforkならメモリを共有しているのでint3もそのまま
+                let sites = if vfork { self.inferior.sites.clone() } else {
+                    self.inferior.sites.remove_from(child)?;
+                    BreakpointSites::default()
+                };
+                let num = self.new_inferior_num();
+                self.add_forked_inferior(num, child, sites)?;
+                println!("[New inferior {} (process {})]", num, child);
+            }
+            return Ok(parent);
+        }
+        // 子プロセスを追う. 親の他のスレッドも止めてから切り離す
+        let parent_pid = self.inferior.tgid;
+        let parent_num = self.inferior.num;
+        let label = self.thread_label(parent);
+        self.stop_all_threads()?;
+        self.inferior.pending_events.clear();
+        let parent_tids = self.thread_tids();
+        self.inferior.debug_registers.sync(&parent_tids, &[])?;
+        let child_num = self.new_inferior_num();
+        println!("[Attaching after {} {} to child process {}]", label, kind, child);
+        println!("[New inferior {} (process {})]", child_num, child);
+        if vfork {
+            // 子がexecするか終わるまではメモリを共有しているので, int3を外して親を止めておく
+            self.inferior.sites.remove_all(parent)?;
+            self.inferior.vfork_parent = Some((parent_num, parent_pid, parent_tids));
+        } else if self.detach_on_fork {
+            self.inferior.sites.remove_from(parent)?;
+            self.detach_threads(&parent_tids)?;
+            println!("[Detaching after fork from parent process {}]", parent_pid);
+            println!("[Inferior {} (process {}) detached]", parent_num, parent_pid);
+            // プロセスは無くなってもブレークポイントの場所を表示できるように, シンボルは残しておく
+            let mut inferior = Inferior::empty(parent_num, self.inferior.program.clone());
+            inferior.target = std::mem::replace(&mut self.inferior.target, Target::empty());
+            self.push_inferior(inferior);
+        } else {
+            // 親は止めたまま今の状態でインフェリアとして残し, 子のインフェリアを選ぶ
+            let child_sites = self.inferior.sites.clone();
+            self.inferior.sites.remove_all(parent)?;
+            self.inferior.pid = parent;
+            self.stopped(ptrace::getregs(parent)?);
+            self.add_forked_inferior(child_num, child, child_sites)?;
+            self.switch_inferior(child_num)?;
+            return Ok(child);
+        }
+        for breakpoint in self.breakpoints.iter_mut() {
+            breakpoint.copy_location(parent_num, child_num);
+        }
+        self.inferior.num = child_num;
+        self.reset_threads(child);
+        self.inferior.debug_registers = DebugRegisters::default();
+        self.inferior.target = Target::load(child)?;
+        Ok(child)
+    }
+
+    // vforkした子を追っている時に, 子がexecするか終わったら止めておいた親を切り離す
+    fn release_vfork_parent(&mut self, reason: &str) -> Result<()> {
+        let (num, parent, tids) = match self.inferior.vfork_parent.take() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        if !self.detach_on_fork {
+            // 親は止めたままインフェリアとして残す
+            let mut inferior = Inferior::new(num, parent, Target::load(parent)?, self.inferior.program.clone());
+            inferior.threads = tids.iter().enumerate().map(|(idx, tid)| Thread::new(idx + 1, *tid)).collect();
+            inferior.next_thread_num = tids.len() + 1;
+            self.push_inferior(inferior);
+            return Ok(());
+        }
+        for tid in tids.iter() {
+            ptrace::detach(*tid)?;
+        }
+        println!("[Detaching vfork parent process {} after child {}]", parent, reason);
+        self.push_inferior(Inferior::empty(num, self.inferior.program.clone()));
+        Ok(())
+    }
+
+    // execした. 新しいプログラムのシンボルを読み直して, ブレークポイントの場所を解決し直す
+    fn handle_exec(&mut self, tid: Pid) -> Result<()> {
+        // 他のスレッドは終わり, execしたスレッドはリーダーのtidになる
+        self.inferior.threads.retain(|thread| thread.get_tid() == tid);
+        self.inferior.pending_events.clear();
+        self.inferior.pid = tid;
+        // 新しいプログラムにはint3もデバッグレジスタも残っていない
+        self.inferior.sites.forget();
+        self.inferior.debug_registers = DebugRegisters::default();
+        self.inferior.internal_breakpoints.clear();
+        self.inferior.target = Target::load(tid)?;
+        let path = read_link(format!("/proc/{}/exe", tid)).map(|path| path.display().to_string()).unwrap_or_default();
+        println!("process {} is executing new program: {}", tid, path);
+        self.release_vfork_parent("exec")?;
+        for watchpoint in self.inferior.watchpoints.drain(..) {
+            println!("Watchpoint {} deleted because the program has left the block in", watchpoint.get_id());
+            println!("which its expression is valid.");
+        }
+        self.stopped(ptrace::getregs(tid)?);
+        self.reresolve_breakpoints(true);
+        // まだ再開する途中なので, 止まったことにはしない
+        self.inferior.regs = None;
+        self.inferior.frames = vec![];
+        let wanted = self.wanted_addresses();
+        self.inferior.sites.sync(tid, &wanted)
+    }
+
+    // 新しいプログラムでブレークポイントの場所を解決し直す. 見つからないものはこのインフェリアでは止まらない.
+    // reportなら見つからなかったことを表示する
+    fn reresolve_breakpoints(&mut self, report: bool) {
+        let inferior = self.inferior.num;
+        let locations: Vec<(usize, String, Option<CatchKind>)> = self.breakpoints.iter()
+            .filter(|breakpoint| breakpoint.applies_to(inferior) && breakpoint.get_catch().is_none_or(|catch| catch.has_location()))
+            .map(|breakpoint| (breakpoint.get_id(), breakpoint.get_location().clone(), breakpoint.get_catch().cloned()))
+            .collect();
+        for (id, location, catch) in locations.into_iter() {
+            let addr = match self.locate_breakpoint(&location, catch.as_ref()) {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    if report { println!("Error in re-setting breakpoint {}: {}", id, e); }
+                    None
+                },
+            };
+            if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+                breakpoint.resolve(inferior, addr);
+            }
+        }
+    }
+
+    // アタッチした時に, リーダー以外の既にあるスレッドにもアタッチする. この後に作られるスレッドはクローンのイベントで追う
+    pub fn attach_threads(&mut self) -> Result<()> {
+        let mut tried = self.thread_tids();
+        loop {
+            let tids: Vec<Pid> = thread::list_tasks(self.inferior.tgid)?.into_iter().filter(|tid| !tried.contains(tid)).collect();
+            if tids.is_empty() { return Ok(()); }
+            for tid in tids.into_iter() {
+                tried.push(tid);
+                // アタッチする前に終わったスレッドは無視する
+                if ptrace::attach(tid).is_err() { continue; }
+                let status = ptrace::wait_thread(tid)?;
+                self.push_thread(tid);
+                // 他のシグナルで先に止まった時は, PTRACE_INTERRUPTの停止がまだ残っている
+                if !matches!(status, WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_STOP)) {
+                    if let Some(thread) = self.inferior.threads.last_mut() { thread.set_pending_stop(true); }
+                }
+            }
+        }
+    }
+
+    fn push_thread(&mut self, tid: Pid) {
+        self.inferior.threads.push(Thread::new(self.inferior.next_thread_num, tid));
+        self.inferior.next_thread_num += 1;
+    }
+
+    // クローンのイベントで知った新しいスレッドを加える
+    fn add_thread(&mut self, tid: Pid) -> Result<()> {
+        if self.inferior.threads.iter().any(|thread| thread.get_tid() == tid) { return Ok(()); }
+        self.wait_new_task(tid)?;
+        self.push_thread(tid);
+        println!("[New {}]", self.thread_label(tid));
+        // non-stopでは新しいスレッドはそのまま動かす
+        if self.non_stop {
+            self.inferior.debug_registers.sync(&[tid], &self.inferior.watchpoints)?;
+            self.resume_quietly(tid)?;
+        }
+        Ok(())
+    }
+
+    // 新しいスレッドやプロセスは最初にPTRACE_EVENT_STOPで止まる. まだ受け取っていなければ待つ
+    fn wait_new_task(&mut self, tid: Pid) -> Result<()> {
+        match self.inferior.early_stops.iter().position(|early| *early == tid) {
+            Some(idx) => { self.inferior.early_stops.remove(idx); },
+            None => { ptrace::wait_thread(tid)?; },
+        }
+        Ok(())
+    }
+
+    // 起動し直したプロセスのスレッドだけにする
+    fn reset_threads(&mut self, pid: Pid) {
+        self.inferior.pid = pid;
+        self.inferior.tgid = pid;
+        self.inferior.threads = vec![Thread::new(1, pid)];
+        self.inferior.next_thread_num = 2;
+        self.inferior.pending_events.clear();
+        self.inferior.early_stops.clear();
+        self.inferior.announced_thread = pid;
+    }
+
+    fn thread_tids(&self) -> Vec<Pid> {
+        self.inferior.threads.iter().map(|thread| thread.get_tid()).collect()
+    }
+
+    // non-stopで動いているスレッドのレジスタは読み書きできないので, 止まっているものだけ
+    fn stopped_tids(&self) -> Vec<Pid> {
+        self.inferior.threads.iter().filter(|thread| !thread.is_running()).map(|thread| thread.get_tid()).collect()
+    }
+
+    fn is_thread_running(&self, tid: Pid) -> bool {
+        self.inferior.threads.iter().any(|thread| thread.get_tid() == tid && thread.is_running())
+    }
+
+    // gdbと同じく "Thread 0x7ffff7d89780 (LWP 1234)" の形. 0x... はpthread_tで, スレッドのfs_baseと同じ
+    fn thread_label(&mut self, tid: Pid) -> String {
+        let fs_base = ptrace::getregs(tid).map(|regs| regs.fs_base).unwrap_or(0);
+        let pthread = match self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+            Some(thread) => {
+                if fs_base != 0 { thread.set_pthread(fs_base); }
+                thread.get_pthread()
+            },
+            None => fs_base,
+        };
+        if pthread == 0 { format!("LWP {}", tid) } else { format!("Thread 0x{:x} (LWP {})", pthread, tid) }
+    }
+
+    fn current_thread_num(&self) -> usize {
+        self.inferior.threads.iter().find(|thread| thread.get_tid() == self.inferior.pid).map(|thread| thread.get_num()).unwrap_or(1)
+    }
+
+    // 複数のインフェリアがある時は, gdbと同じく "インフェリア.スレッド" の形にする
+    fn thread_id(&self, num: usize) -> String {
+        if self.inferiors.is_empty() { num.to_string() } else { format!("{}.{}", self.inferior.num, num) }
+    }
+
+    // 複数のスレッドがある時は, gdbと同じく止まったスレッドを書く
+    fn thread_prefix(&self, verb: &str) -> Option<String> {
+        self.thread_prefix_of(self.inferior.pid, verb)
+    }
+
+    fn thread_prefix_of(&self, tid: Pid, verb: &str) -> Option<String> {
+        if self.inferior.threads.len() < 2 && self.inferiors.is_empty() { return None; }
+        let num = self.inferior.threads.iter().find(|thread| thread.get_tid() == tid).map(|thread| thread.get_num()).unwrap_or(1);
+        let name = thread::thread_name(self.inferior.tgid, tid).unwrap_or_default();
+        Some(format!("Thread {} \"{}\" {}", self.thread_id(num), name, verb))
+    }
+
+    fn set_stop_reason(&mut self, reason: String) {
+        let pid = self.inferior.pid;
+        if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == pid) {
+            thread.set_stop_reason(reason);
+        }
+    }
+
+    fn info_threads(&mut self) -> Result<()> {
+        if self.inferior.threads.is_empty() {
+            println!("No threads.");
+            return Ok(());
+        }
+        let mut rows = vec![];
+        for (num, tid, reason) in self.inferior.threads.iter().map(|thread| (thread.get_num(), thread.get_tid(), thread.get_stop_reason().clone())).collect::<Vec<_>>() {
+            let name = thread::thread_name(self.inferior.tgid, tid).unwrap_or_default();
+            let target_id = format!("{} \"{}\"", self.thread_label(tid), name);
+            let target_id = if reason.is_empty() { target_id } else { format!("{} ({})", target_id, reason) };
+            if self.is_thread_running(tid) {
+                rows.push((tid == self.inferior.pid, self.thread_id(num), target_id, "(running)".to_owned()));
+                continue;
+            }
+            let regs = ptrace::getregs(tid)?;
+            let frame = match unwind::backtrace(&self.inferior.target, tid, &regs, Some(1)).into_iter().next() {
+                Some(frame) => {
+                    let at_line_start = self.inferior.target.is_statement_start(frame.get_pc());
+                    self.format_location(&frame, !at_line_start)
+                },
+                None => format!("0x{:016x} in ?? ()", regs.rip),
+            };
+            rows.push((tid == self.inferior.pid, self.thread_id(num), target_id, frame));
+        }
+        let width = rows.iter().map(|(_, _, target_id, _)| target_id.len()).max().unwrap_or(0).max("Target Id".len());
+        println!("  Id   {:<width$} Frame ", "Target Id", width = width);
+        for (current, num, target_id, frame) in rows.iter() {
+            println!("{} {:<4} {:<width$} {}", if *current { '*' } else { ' ' }, num, target_id, frame, width = width);
+        }
+        Ok(())
+    }
+
+    // numが無ければ今のスレッドを表示する
+    fn thread(&mut self, num: Option<usize>) -> Result<()> {
+        if self.inferior.threads.is_empty() { bail!(NOT_RUNNING); }
+        let num = match num {
+            Some(num) => num,
+            None => {
+                let label = self.thread_label(self.inferior.pid);
+                println!("[Current thread is {} ({})]", self.thread_id(self.current_thread_num()), label);
+                return Ok(());
+            },
+        };
+        let tid = self.inferior.threads.iter().find(|thread| thread.get_num() == num).map(|thread| thread.get_tid())
+            .with_context(|| format!("Invalid thread ID: {}", num))?;
+        // non-stopで動いているスレッドも選べるが, 止まるまで調べられない
+        if self.is_thread_running(tid) {
+            self.inferior.pid = tid;
+            self.inferior.regs = None;
+            self.inferior.frames = vec![];
+            println!("[Switching to thread {} ({})](running)", self.thread_id(num), self.thread_label(tid));
+            return Ok(());
+        }
+        let regs = ptrace::getregs(tid)?;
+        self.inferior.pid = tid;
+        self.inferior.announced_thread = tid;
+        self.stopped(regs);
+        println!("[Switching to thread {} ({})]", self.thread_id(num), self.thread_label(tid));
+        self.print_selected_frame()
+    }
+
+    // waitで受け取った状態を処理する. スレッドの出入りやデバッガが止めた停止はここで片付ける
+    fn classify(&mut self, status: WaitStatus) -> Result<(Pid, ThreadStatus)> {
+        let tid = match status.pid() {
+            Some(tid) => tid,
+            None => return Ok((self.inferior.pid, ThreadStatus::Ignore)),
+        };
+        // non-stopで動いている他のインフェリアのイベントなら, そのインフェリアを選ぶ.
+        // まだ知らないスレッドの最初の停止は, 作ったプロセスのインフェリアで覚えておく
+        if !self.inferior.threads.iter().any(|thread| thread.get_tid() == tid) {
+            let owner = self.inferiors.iter().find(|inferior| inferior.threads.iter().any(|thread| thread.get_tid() == tid)).map(|inferior| inferior.num)
+                .or_else(|| self.new_task_owner(tid));
+            if let Some(num) = owner { self.switch_inferior(num)?; }
+        }
+        match status {
+            WaitStatus::Exited(_, code) if tid == self.inferior.tgid => {
+                self.inferior.threads.clear();
+                return Ok((tid, ThreadStatus::Stop(StopEvent::Exited(code))));
+            },
+            WaitStatus::Signaled(_, sig, _) if tid == self.inferior.tgid => {
+                self.inferior.threads.clear();
+                return Ok((tid, ThreadStatus::Stop(StopEvent::Killed(sig))));
+            },
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                if self.inferior.threads.iter().any(|thread| thread.get_tid() == tid) {
+                    println!("[{} exited]", self.thread_label(tid));
+                    self.inferior.threads.retain(|thread| thread.get_tid() != tid);
+                    self.inferior.pending_events.retain(|(pending, _)| *pending != tid);
+                }
+                return Ok((tid, ThreadStatus::Gone));
+            },
+            _ => {},
+        }
+        let thread = match self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+            Some(thread) => thread,
+            None => {
+                // クローンのイベントより先に新しいスレッドの最初の停止が届いた
+                self.inferior.early_stops.push(tid);
+                return Ok((tid, ThreadStatus::Ignore));
+            },
+        };
+        thread.set_running(false);
+        let status = match status {
+            WaitStatus::PtraceEvent(_, sig, libc::PTRACE_EVENT_STOP) => {
+                // SIGTRAP以外ならジョブ制御のシグナルによるグループストップ. デバッガが止めたのとは区別する
+                thread.set_group_stop(sig != Signal::SIGTRAP);
+                if thread.has_pending_stop() {
+                    thread.set_pending_stop(false);
+                    ThreadStatus::Internal
+                } else if ptrace::take_interrupted(tid) {
+                    ThreadStatus::Stop(StopEvent::Signal(Signal::SIGINT))
+                } else {
+                    ThreadStatus::Internal
+                }
+            },
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE) => {
+                let new_tid = Pid::from_raw(ptrace::get_event(tid)? as libc::pid_t);
+                self.add_thread(new_tid)?;
+                self.catch_event(CatchEvent::Clone(new_tid))
+            },
+            WaitStatus::PtraceEvent(_, _, event) if event == libc::PTRACE_EVENT_FORK || event == libc::PTRACE_EVENT_VFORK => {
+                let child = Pid::from_raw(ptrace::get_event(tid)? as libc::pid_t);
+                let vfork = event == libc::PTRACE_EVENT_VFORK;
+                let resumed = self.handle_fork(tid, child, vfork)?;
+                // 子を追うことにしたなら, 子のインフェリアで子が止まったことにする
+                let caught = if vfork { CatchEvent::Vfork(child) } else { CatchEvent::Fork(child) };
+                return Ok((resumed, self.catch_event(caught)));
+            },
+            // vforkした子がexecするか終わって, 親とメモリを共有しなくなった
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_VFORK_DONE) => {
+                self.inferior.vfork_child = None;
+                let wanted = self.wanted_addresses();
+                self.inferior.sites.sync(tid, &wanted)?;
+                ThreadStatus::Internal
+            },
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => {
+                self.handle_exec(tid)?;
+                self.catch_event(CatchEvent::Exec(executable_path(tid, true, None)))
+            },
+            // スレッドが終わる直前. まだレジスタもメモリも読める
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXIT) => {
+                let status = ptrace::get_event(tid)? as i32;
+                self.catch_event(CatchEvent::Exit(status))
+            },
+            WaitStatus::PtraceSyscall(_) => self.classify_syscall(tid)?,
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => ThreadStatus::Stop(self.classify_trap(tid)?),
+            WaitStatus::Stopped(_, sig) if !self.signal_table.get_policy(sig).stop => {
+                self.pass_signal(tid, sig);
+                ThreadStatus::Internal
+            },
+            WaitStatus::Stopped(_, sig) => ThreadStatus::Stop(StopEvent::Signal(sig)),
+            _ => ThreadStatus::Internal,
+        };
+        Ok((tid, status))
+    }
+
+    // まだ知らないスレッドかforkした子プロセスを作った, 選んでいない方のインフェリア
+    fn new_task_owner(&self, tid: Pid) -> Option<usize> {
+        let (tgid, ppid) = thread::task_owner(tid)?;
+        let creator = if tgid == tid { ppid } else { tgid };
+        self.inferiors.iter().find(|inferior| !inferior.threads.is_empty() && inferior.tgid == creator).map(|inferior| inferior.num)
+    }
+
+    // 止めないシグナルを受け取った. 表示するなら表示して, 渡すなら次に再開する時に渡す
+    fn pass_signal(&mut self, tid: Pid, sig: Signal) {
+        let policy = self.signal_table.get_policy(sig);
+        if policy.print {
+            println!();
+            let prefix = self.thread_prefix_of(tid, "received").unwrap_or_else(|| "Program received".to_owned());
+            println!("{} signal {}, {}.", prefix, sig.as_str(), signals::description(sig));
+        }
+        if policy.pass {
+            if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+                thread.set_pending_signal(Some(sig));
+            }
+        }
+    }
+
+    fn classify_trap(&mut self, tid: Pid) -> Result<StopEvent> {
+        let mut regs = ptrace::getregs(tid)?;
+        let si_code = ptrace::get_siginfo(tid).map(|siginfo| siginfo.si_code).unwrap_or(0);
+        let addr = regs.rip.wrapping_sub(1);
+        if (si_code == SI_KERNEL || si_code == TRAP_BRKPT) && self.inferior.sites.is_inserted(addr) {
+            regs.rip = addr;
+            ptrace::setregs(tid, regs)?;
+            return Ok(StopEvent::Breakpoint(addr));
+        }
+        let hits = self.inferior.debug_registers.take_hits(tid)?;
+        if hits.is_empty() { Ok(StopEvent::Trap) } else { Ok(StopEvent::Watchpoint(self.check_watchpoints(&hits))) }
+    }
+
+    // tidで起きたeventを報告する前に, プロセス全体を止まった状態にする
+    fn finish_stop(&mut self, tid: Pid, event: StopEvent) -> Result<StopEvent> {
+        // Ctrl-Cで止めたスレッドが先に他の理由で止まった. 残っているPTRACE_INTERRUPTの停止は報告しない
+        ptrace::set_interrupt_target(None);
+        if let Some(interrupted) = ptrace::take_any_interrupted() {
+            if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == interrupted) {
+                thread.set_pending_stop(true);
+            }
+        }
+        match event {
+            StopEvent::Exited(_) | StopEvent::Killed(_) => {
+                self.release_vfork_parent("exit")?;
+                self.inferior.vfork_child = None;
+                self.inferior.regs = None;
+                self.inferior.frames = vec![];
+                self.inferior.sites = BreakpointSites::default();
+                self.inferior.debug_registers = DebugRegisters::default();
+                self.inferior.threads.clear();
+                self.inferior.pending_events.clear();
+                self.inferior.pid = self.inferior.tgid;
+            },
+            _ => {
+                if !self.non_stop { self.stop_all_threads()?; }
+                if let StopEvent::Breakpoint(addr) = event {
+                    if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+                        thread.set_reported_breakpoint(Some(addr));
+                    }
+                }
+                self.inferior.pid = tid;
+                // 止まっている間はint3を書き込まない. non-stopで他のスレッドが動いている間は残しておく
+                if !self.inferior.threads.iter().any(|thread| thread.is_running()) {
+                    self.inferior.sites.remove_all(tid)?;
+                }
+                let regs = ptrace::getregs(tid)?;
+                // 動き出した後もスレッドを表示できるように, pthread_tを覚えておく
+                if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+                    if regs.fs_base != 0 { thread.set_pthread(regs.fs_base); }
+                }
+                self.stopped(regs);
+            },
+        }
+        Ok(event)
+    }
+
+    // all-stop: 1つのスレッドが止まったら, 動いている他のスレッドもPTRACE_INTERRUPTで止める.
+    // 止まるまでの間に起きたイベントは次に再開する時に報告する
+    fn stop_all_threads(&mut self) -> Result<()> {
+        let running: Vec<Pid> = self.inferior.threads.iter().filter(|thread| thread.is_running()).map(|thread| thread.get_tid()).collect();
+        for thread in self.inferior.threads.iter_mut().filter(|thread| thread.is_running()) {
+            thread.set_pending_stop(true);
+            // 既に終わっていれば止められないが, 終わったことはwaitで分かる
+            let _ = ptrace::interrupt(thread.get_tid());
+        }
+        for tid in running.into_iter() {
+            while self.inferior.threads.iter().any(|thread| thread.get_tid() == tid && thread.is_running()) {
+                let status = ptrace::wait_thread(tid)?;
+                if let (tid, ThreadStatus::Stop(event)) = self.classify(status)? {
+                    self.inferior.pending_events.push((tid, event));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 止まっているスレッドを全て再開する
+    fn resume_all_threads(&mut self) -> Result<()> {
+        let syscalls = self.catching_syscalls();
+        for thread in self.inferior.threads.iter_mut().filter(|thread| !thread.is_running()) {
+            // 終わりかけのスレッドには再開できないが, 終わったことはwaitで分かる
+            let _ = restart_thread(thread, syscalls);
+        }
+        Ok(())
+    }
+
+    // tidだけを1命令進める. スレッドが終わったらNone
+    fn step_thread(&mut self, tid: Pid) -> Result<Option<StopEvent>> {
+        // non-stopで他のスレッドのために残しているint3は, 進める間だけ外す.
+        // その間に他のスレッドが通るとそのブレークポイントでは止まらない
+        let pc = ptrace::getregs(tid)?.rip;
+        let lifted = self.inferior.sites.is_inserted(pc);
+        if lifted { self.inferior.sites.remove(tid, pc)?; }
+        let result = self.step_thread_once(tid);
+        if lifted && matches!(result, Ok(Some(_))) { self.inferior.sites.insert(tid, pc)?; }
+        result
+    }
+
+    fn step_thread_once(&mut self, tid: Pid) -> Result<Option<StopEvent>> {
+        loop {
+            let sig = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid).and_then(|thread| thread.take_pending_signal());
+            ptrace::single_step(tid, sig)?;
+            if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+                thread.set_running(true);
+            }
+            let status = ptrace::wait_thread(tid)?;
+            match self.classify(status)? {
+                // forkした子を追うことにしたので, このスレッドは切り離した
+                (_, ThreadStatus::Internal) if !self.inferior.threads.iter().any(|thread| thread.get_tid() == tid) => return Ok(None),
+                // デバッガが止めた停止やクローンで止まった時は, まだ進んでいない
+                (_, ThreadStatus::Internal) | (_, ThreadStatus::Ignore) => continue,
+                (_, ThreadStatus::Gone) => return Ok(None),
+                (_, ThreadStatus::Stop(event)) => return Ok(Some(event)),
+            }
+        }
+    }
+
+    fn single_step(&mut self) -> Result<StopEvent> {
+        self.inferior.regs.context(NOT_RUNNING)?;
+        self.inferior.just_launched = false;
+        let tid = self.inferior.pid;
+        let event = match self.step_thread(tid)? {
+            Some(event) => self.finish_stop(tid, event)?,
+            None => return self.current_thread_exited(),
+        };
+        match event {
+            // 報告しないウォッチポイントは普通のシングルステップと同じ
+            StopEvent::Watchpoint(hits) if hits.is_empty() => Ok(self.check_software_watchpoints()),
+            StopEvent::Trap => Ok(self.check_software_watchpoints()),
+            event => Ok(event),
+        }
+    }
+
+    // 進めている途中で今のスレッドが終わった. 他のスレッドに切り替えて中断する
+    fn current_thread_exited(&mut self) -> Result<StopEvent> {
+        let next = self.inferior.threads.first().map(|thread| thread.get_tid());
+        if let Some(tid) = next {
+            if let Ok(regs) = ptrace::getregs(tid) {
+                self.inferior.pid = tid;
+                self.stopped(regs);
+                bail!("Command aborted, thread exited.");
+            }
+        }
+        // リーダーも終わるところなので, 他のスレッドの終了を受け取りながらプロセスの終了を待つ
+        loop {
+            let status = ptrace::wait_all()?;
+            if let (tid, ThreadStatus::Stop(event)) = self.classify(status)? {
+                if tid == self.inferior.tgid { return self.finish_stop(tid, event); }
+            }
+        }
+    }
+
+    // 1命令進めるたびに呼ぶ. 値が変わったソフトウェアウォッチポイントがあれば報告する
+    fn check_software_watchpoints(&mut self) -> StopEvent {
+        let ids: Vec<usize> = self.inferior.watchpoints.iter().filter(|watchpoint| !watchpoint.is_hardware()).map(|watchpoint| watchpoint.get_id()).collect();
+        if ids.is_empty() { return StopEvent::Trap; }
+        let hits = self.check_watchpoints(&ids);
+        if hits.is_empty() { StopEvent::Trap } else { StopEvent::Watchpoint(hits) }
+    }
+
+    fn has_software_watchpoints(&self) -> bool {
+        self.inferior.watchpoints.iter().any(|watchpoint| !watchpoint.is_hardware())
+    }
+
+    // 条件を満たさないブレークポイントでは止まらずに続ける. finishなどの一時的なブレークポイントは再開したスレッドでだけ止まる
+    fn resume(&mut self) -> Result<StopEvent> {
+        let thread = self.inferior.pid;
+        loop {
+            let event = self.resume_once()?;
+            // 共有ライブラリが読み込まれているかもしれないので, モジュール一覧を作り直す
+            if self.inferior.regs.is_some() {
+                self.inferior.target.reload()?;
+                self.inferior.frames = vec![];
+            }
+            if let StopEvent::Breakpoint(addr) = event {
+                let internal = self.inferior.internal_breakpoints.contains(&addr) && self.inferior.pid == thread;
+                if !self.breakpoint_triggered(addr) && !internal { continue; }
+            }
+            if let StopEvent::Catch(caught) = &event {
+                if !self.catchpoint_triggered(caught) { continue; }
+            }
+            return Ok(event);
+        }
+    }
+
+    // addrにあるブレークポイントの条件と無視する回数を調べる. 止まるものがあればtrue
+    fn breakpoint_triggered(&mut self, addr: u64) -> bool {
+        if self.panic_handler_address() == Some(addr) { self.record_panic_location(); }
+        let ids: Vec<usize> = self.breakpoints.iter()
+            .filter(|breakpoint| breakpoint.get_addr(self.inferior.num) == Some(addr))
+            .map(|breakpoint| breakpoint.get_id())
+            .collect();
+        self.check_triggered(&ids)
+    }
+
+    // caughtを捕まえるキャッチポイントの条件と無視する回数を調べる
+    fn catchpoint_triggered(&mut self, caught: &CatchEvent) -> bool {
+        let ids = self.catchpoint_ids(|catch| caught.caught_by(catch));
+        self.check_triggered(&ids)
+    }
+
+    // 今のインフェリアのキャッチポイントのうち, matchesを満たすもの
+    fn catchpoint_ids<F: Fn(&CatchKind) -> bool>(&self, matches: F) -> Vec<usize> {
+        let inferior = self.inferior.num;
+        self.breakpoints.iter()
+            .filter(|breakpoint| breakpoint.applies_to(inferior) && breakpoint.get_catch().is_some_and(&matches))
+            .map(|breakpoint| breakpoint.get_id())
+            .collect()
+    }
+
+    // イベントを捕まえるキャッチポイントがあれば止まる. 無ければデバッガの都合で止まっただけにする
+    fn catch_event(&self, caught: CatchEvent) -> ThreadStatus {
+        if self.catchpoint_ids(|catch| caught.caught_by(catch)).is_empty() {
+            ThreadStatus::Internal
+        } else {
+            ThreadStatus::Stop(StopEvent::Catch(caught))
+        }
+    }
+
+    // idsのブレークポイントのうち止まるものをstopped_breakpointsにする. dprintfは表示だけする
+    fn check_triggered(&mut self, ids: &[usize]) -> bool {
+        let candidates: Vec<(usize, Option<String>, Option<String>)> = self.breakpoints.iter()
+            .filter(|breakpoint| ids.contains(&breakpoint.get_id()))
+            .map(|breakpoint| (breakpoint.get_id(), breakpoint.get_condition().cloned(), breakpoint.get_dprintf().cloned()))
+            .collect();
+        let mut triggered = vec![];
+        for (id, condition, dprintf) in candidates.into_iter() {
+            let matched = match condition {
+                Some(condition) => match self.evaluate_condition(&condition) {
+                    Ok(matched) => matched,
+                    Err(e) => {
+                        // gdbと同じく, 評価できない時は止まる
+                        println!("Error in testing condition for breakpoint {}:", id);
+                        println!("{}", e);
+                        true
+                    },
+                },
+                None => true,
+            };
+            let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id);
+            if matched && breakpoint.is_some_and(|breakpoint| breakpoint.hit()) {
+                match dprintf {
+                    // dprintfは表示するだけで止まらない
+                    Some(dprintf) => self.print_dprintf(id, &dprintf),
+                    None => triggered.push(id),
+                }
+            }
+        }
+        self.stopped_breakpoints = triggered;
+        !self.stopped_breakpoints.is_empty()
+    }
+
+    fn print_dprintf(&mut self, id: usize, dprintf: &str) {
+        let output = match self.format_printf(dprintf) {
+            Ok(output) => output,
+            Err(e) => {
+                println!("Error in dprintf {}: {}", id, e);
+                return;
+            },
+        };
+        let result = match self.dprintf_log.as_mut() {
+            Some(file) => file.write_all(output.as_bytes()).and_then(|_| file.flush()),
+            None => {
+                print!("{}", output);
+                stdout().flush()
+            },
+        };
+        if let Err(e) = result {
+            println!("Error in dprintf {}: {}", id, e);
+        }
+    }
+
+    // "書式",式... を選択中のフレームで評価して書式に当てはめる
+    fn format_printf(&mut self, arg: &str) -> Result<String> {
+        let (format, args) = printf::split_arguments(arg)?;
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let values = args.iter().map(|arg| expression::evaluate_str(&ctx, arg)).collect::<Result<Vec<Value>>>()?;
+        let pid = self.inferior.pid;
+        printf::format(ctx.get_debug_info(), &format, &values, &|addr, len| ptrace::read_bytes(pid, addr, len))
+    }
+
+    fn printf(&mut self, arg: &str) -> Result<()> {
+        let output = self.format_printf(arg)?;
+        print!("{}", output);
+        stdout().flush()?;
+        Ok(())
+    }
+
+    fn set_dprintf_log(&mut self, path: Option<&str>) -> Result<()> {
+        self.dprintf_log = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("{}: cannot open.", path))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn evaluate_condition(&mut self, condition: &str) -> Result<bool> {
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let value = expression::evaluate_str(&ctx, condition)?;
+        expression::is_true(ctx.get_debug_info(), &value)
+    }
+
+    fn resume_once(&mut self) -> Result<StopEvent> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        self.inferior.just_launched = false;
+        // 前に他のスレッドを止めた時に起きていたイベントがあれば, 再開せずにそれを報告する
+        if !self.inferior.pending_events.is_empty() {
+            let (tid, event) = self.inferior.pending_events.remove(0);
+            return self.finish_stop(tid, event);
+        }
+        let wanted = self.wanted_addresses();
+        if self.has_software_watchpoints() {
+            return self.resume_stepping(&wanted);
+        }
+        if self.non_stop {
+            if let Some(event) = self.resume_selected_thread()? { return Ok(event); }
+            return self.wait_event();
+        }
+        // 今いるアドレスのブレークポイントは, 1命令進めてから書き込む
+        if wanted.contains(&regs.rip) {
+            let event = self.single_step()?;
+            if event != StopEvent::Trap { return Ok(event); }
+        }
+        // 他のスレッドも, 報告したブレークポイントから1命令進めておく
+        let reported: Vec<Pid> = self.inferior.threads.iter()
+            .filter(|thread| thread.get_tid() != self.inferior.pid && thread.get_reported_breakpoint().is_some())
+            .map(|thread| thread.get_tid())
+            .collect();
+        for tid in reported.into_iter() {
+            match self.step_thread(tid)? {
+                Some(StopEvent::Trap) | None => {},
+                Some(event) => self.inferior.pending_events.push((tid, event)),
+            }
+        }
+        if !self.inferior.pending_events.is_empty() {
+            let (tid, event) = self.inferior.pending_events.remove(0);
+            return self.finish_stop(tid, event);
+        }
+        self.inferior.sites.sync(self.inferior.pid, &wanted)?;
+        loop {
+            // 新しいスレッドのデバッグレジスタは空なので, 再開する前に毎回書く
+            let tids: Vec<Pid> = self.inferior.threads.iter().map(|thread| thread.get_tid()).collect();
+            self.inferior.debug_registers.sync(&tids, &self.inferior.watchpoints)?;
+            self.resume_all_threads()?;
+            self.arm_interrupt();
+            let status = ptrace::wait_all()?;
+            match self.classify(status)? {
+                // 同じ値を書き込んだだけなどで報告しないウォッチポイントなら, そのまま続ける
+                (_, ThreadStatus::Stop(StopEvent::Watchpoint(hits))) if hits.is_empty() => {},
+                (tid, ThreadStatus::Stop(event)) => return self.finish_stop(tid, event),
+                _ => {},
+            }
+        }
+    }
+
+    // int3を入れておくアドレス
+    fn wanted_addresses(&self) -> Vec<u64> {
+        if self.inferior.vfork_child.is_some() { return vec![]; }
+        let mut wanted: Vec<u64> = self.breakpoints.iter().filter_map(|breakpoint| breakpoint.get_addr(self.inferior.num)).collect();
+        wanted.extend(self.inferior.internal_breakpoints.iter());
+        wanted.extend(self.panic_handler_address());
+        wanted
+    }
+
+    // non-stop: 選択中のスレッドだけを再開する. ブレークポイントから1命令進めた所で何か起きればそれを返す
+    fn resume_selected_thread(&mut self) -> Result<Option<StopEvent>> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let wanted = self.wanted_addresses();
+        if wanted.contains(&regs.rip) {
+            let event = self.single_step()?;
+            if event != StopEvent::Trap { return Ok(Some(event)); }
+        }
+        self.inferior.sites.sync(self.inferior.pid, &wanted)?;
+        let stopped = self.stopped_tids();
+        self.inferior.debug_registers.sync(&stopped, &self.inferior.watchpoints)?;
+        self.resume_quietly(self.inferior.pid)?;
+        self.inferior.regs = None;
+        self.inferior.frames = vec![];
+        Ok(None)
+    }
+
+    // デバッガの都合で止まったスレッドを, 報告せずにそのまま再開する
+    fn resume_quietly(&mut self, tid: Pid) -> Result<()> {
+        let syscalls = self.catching_syscalls();
+        if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid) {
+            restart_thread(thread, syscalls)?;
+        }
+        Ok(())
+    }
+
+    // non-stop: どれかのスレッドで報告するイベントが起きるまで待つ
+    fn wait_event(&mut self) -> Result<StopEvent> {
+        loop {
+            self.arm_interrupt();
+            let status = ptrace::wait_all()?;
+            if let Some(event) = self.handle_status(status)? { return Ok(event); }
+        }
+    }
+
+    // non-stop: waitで受け取った状態を処理する. 報告するイベントならそのスレッドを選ぶ
+    fn handle_status(&mut self, status: WaitStatus) -> Result<Option<StopEvent>> {
+        match self.classify(status)? {
+            (tid, ThreadStatus::Internal) => self.resume_quietly(tid)?,
+            (tid, ThreadStatus::Stop(StopEvent::Watchpoint(hits))) if hits.is_empty() => self.resume_quietly(tid)?,
+            (tid, ThreadStatus::Stop(event)) => return self.finish_stop(tid, event).map(Some),
+            _ => {},
+        }
+        Ok(None)
+    }
+
+    // non-stop: 動いているスレッドで起きたイベントを報告する. 何も起きていなければすぐに戻る
+    fn poll_events(&mut self) -> Result<()> {
+        while self.has_running_threads() {
+            let status = ptrace::wait_all_nohang()?;
+            if status == WaitStatus::StillAlive { break; }
+            if let Some(event) = self.handle_status(status)? {
+                self.report_async(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    // non-stop: コマンドを待たずに受け取ったイベントを報告する. 条件を満たさないブレークポイントならそのスレッドを再開する
+    fn report_async(&mut self, event: StopEvent) -> Result<()> {
+        let mut event = event;
+        loop {
+            if self.inferior.regs.is_some() {
+                self.inferior.target.reload()?;
+                self.inferior.frames = vec![];
+            }
+            match event {
+                StopEvent::Breakpoint(addr) if !self.breakpoint_triggered(addr) => match self.resume_selected_thread()? {
+                    Some(next) => event = next,
+                    None => return Ok(()),
+                },
+                StopEvent::Catch(ref caught) if !self.catchpoint_triggered(caught) => match self.resume_selected_thread()? {
+                    Some(next) => event = next,
+                    None => return Ok(()),
+                },
+                _ => return self.report_stop(event),
+            }
+        }
+    }
+
+    // Ctrl-Cで止めるスレッドを決める. 選んでいるスレッドが動いていればそれを, 止まっていれば他の動いているスレッドを止める
+    fn arm_interrupt(&self) {
+        let tid = if self.is_thread_running(self.inferior.pid) {
+            Some(self.inferior.pid)
+        } else {
+            self.inferior.threads.iter().find(|thread| thread.is_running()).map(|thread| thread.get_tid())
+        };
+        ptrace::set_interrupt_target(tid);
+    }
+
+    // どれかのインフェリアに動いているスレッドがある
+    fn has_running_threads(&self) -> bool {
+        self.inferior.threads.iter().chain(self.inferiors.iter().flat_map(|inferior| inferior.threads.iter())).any(|thread| thread.is_running())
+    }
+
+    // non-stop: 動いているスレッドを止める. allでなければ選択中のスレッドだけ
+    fn interrupt(&mut self, all: bool) -> Result<()> {
+        let targets: Vec<Pid> = self.inferior.threads.iter()
+            .filter(|thread| thread.is_running() && (all || thread.get_tid() == self.inferior.pid))
+            .map(|thread| thread.get_tid())
+            .collect();
+        for thread in self.inferior.threads.iter_mut().filter(|thread| targets.contains(&thread.get_tid())) {
+            thread.set_pending_stop(true);
+            let _ = ptrace::interrupt(thread.get_tid());
+        }
+        for tid in targets.into_iter() {
+            while self.is_thread_running(tid) {
+                let status = ptrace::wait_thread(tid)?;
+                match self.classify(status)? {
+                    // PTRACE_INTERRUPTで止まった
+                    (_, ThreadStatus::Internal) if !self.inferior.threads.iter().any(|thread| thread.get_tid() == tid && thread.has_pending_stop()) => {
+                        self.finish_stop(tid, StopEvent::Trap)?;
+                        self.inferior.announced_thread = tid;
+                        let name = thread::thread_name(self.inferior.tgid, tid).unwrap_or_default();
+                        println!();
+                        println!("Thread {} \"{}\" stopped.", self.thread_id(self.current_thread_num()), name);
+                        self.print_stop_location(true)?;
+                    },
+                    (_, ThreadStatus::Internal) => self.resume_quietly(tid)?,
+                    // 止まる前に他のことが起きた. PTRACE_INTERRUPTの停止は次に再開した時に受け取る
+                    (_, ThreadStatus::Stop(event)) => {
+                        let event = self.finish_stop(tid, event)?;
+                        self.report_async(event)?;
+                    },
+                    _ => {},
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_non_stop(&mut self, non_stop: bool) -> Result<()> {
+        // all-stopに戻す時は, 動いているスレッドを止めておく
+        if self.non_stop && !non_stop && !self.inferior.threads.is_empty() {
+            self.stop_all_threads()?;
+            self.inferior.sites.remove_all(self.inferior.pid)?;
+            if self.inferior.regs.is_none() {
+                let regs = ptrace::getregs(self.inferior.pid)?;
+                self.stopped(regs);
+            }
+        }
+        self.non_stop = non_stop;
+        Ok(())
+    }
+
+    // handle シグナル [stop|nostop|print|noprint|pass|nopass]... all なら SIGINT と SIGTRAP 以外の全て
+    fn handle(&mut self, name: &str, actions: &[String]) -> Result<()> {
+        let sigs: Vec<Signal> = if name == "all" {
+            self.signal_table.get_policies().iter().map(|(sig, _)| *sig)
+                .filter(|sig| *sig != Signal::SIGINT && *sig != Signal::SIGTRAP)
+                .collect()
+        } else {
+            vec![signals::parse_signal(name).with_context(|| format!("Unrecognized or ambiguous flag word: \"{}\".", name))?]
+        };
+        // 途中で分からない言葉があれば何も変えない
+        let mut table = self.signal_table.clone();
+        for sig in sigs.iter() {
+            table.apply(*sig, actions)?;
+        }
+        self.signal_table = table;
+        println!("{}", signals::POLICY_HEADER);
+        for sig in sigs.into_iter() {
+            println!("{}", signals::format_policy(sig, &self.signal_table.get_policy(sig)));
+        }
+        Ok(())
+    }
+
+    fn info_signals(&self, name: Option<&str>) -> Result<()> {
+        if let Some(name) = name {
+            let sig = signals::parse_signal(name).with_context(|| format!("Only signals 1-15 are valid as numeric signals, or \"{}\" is not a signal name.", name))?;
+            println!("{}", signals::POLICY_HEADER);
+            println!("{}", signals::format_policy(sig, &self.signal_table.get_policy(sig)));
+            return Ok(());
+        }
+        println!("{}", signals::POLICY_HEADER);
+        println!();
+        for (sig, policy) in self.signal_table.get_policies().iter() {
+            println!("{}", signals::format_policy(*sig, policy));
+        }
+        println!();
+        println!("Use the \"handle\" command to change these tables.");
+        Ok(())
+    }
+
+    // signal シグナル. 止まった時に受け取ったシグナルの代わりにこのシグナルを渡して再開する. 0なら何も渡さない
+    fn signal(&mut self, name: &str) -> Result<()> {
+        self.inferior.regs.context(NOT_RUNNING)?;
+        let sig = match name {
+            "0" => None,
+            _ => Some(signals::parse_signal(name).with_context(|| format!("Only signals 1-15 are valid as numeric signals, or \"{}\" is not a signal name.", name))?),
+        };
+        let pid = self.inferior.pid;
+        if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == pid) {
+            thread.set_pending_signal(sig);
+        }
+        match sig {
+            Some(sig) => println!("Continuing with signal {}.", sig.as_str()),
+            None => println!("Continuing with no signal."),
+        }
+        self.continue_execution()
+    }
+
+    // ソフトウェアウォッチポイントがある間は, 1命令ずつ進めて値を比べる. int3は使わずpcでブレークポイントを見る
+    fn resume_stepping(&mut self, wanted: &[u64]) -> Result<StopEvent> {
+        loop {
+            let event = self.single_step()?;
+            if event != StopEvent::Trap { return Ok(event); }
+            let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+            if wanted.contains(&pc) { return Ok(StopEvent::Breakpoint(pc)); }
+        }
+    }
+
+    // 引っかかったウォッチポイントの値を読み直して, 報告するものだけを返す
+    fn check_watchpoints(&mut self, ids: &[usize]) -> Vec<(usize, Vec<u8>)> {
+        let pid = self.inferior.pid;
+        let mut hits = vec![];
+        for watchpoint in self.inferior.watchpoints.iter_mut().filter(|watchpoint| ids.contains(&watchpoint.get_id())) {
+            let old = watchpoint.get_value().clone();
+            let new = ptrace::read_bytes(pid, watchpoint.get_addr(), watchpoint.get_len()).unwrap_or_else(|_| old.clone());
+            let changed = new != old;
+            watchpoint.set_value(new);
+            let report = match watchpoint.get_kind() {
+                WatchKind::Write => changed,
+                WatchKind::Read => !changed,
+                WatchKind::Access => true,
+            };
+            if report { hits.push((watchpoint.get_id(), old)); }
+        }
+        hits
+    }
+
+    // addrに着くまで再開する. min_spがあれば, 再帰呼び出しの中で着いた時は無視する
+    fn run_to(&mut self, addr: u64, min_sp: Option<u64>) -> Result<StopEvent> {
+        self.inferior.internal_breakpoints.push(addr);
+        let result = loop {
+            match self.resume() {
+                Ok(StopEvent::Breakpoint(hit)) if hit == addr => {
+                    let sp = self.inferior.regs.map(|regs| regs.rsp).unwrap_or(0);
+                    if min_sp.is_none_or(|min_sp| sp >= min_sp) { break Ok(StopEvent::Trap); }
+                    if !self.stopped_breakpoints.is_empty() { break Ok(StopEvent::Breakpoint(hit)); }
+                },
+                other => break other,
+            }
+        };
+        if let Some(idx) = self.inferior.internal_breakpoints.iter().position(|internal| *internal == addr) {
+            self.inferior.internal_breakpoints.remove(idx);
+        }
+        result
+    }
+
+    fn read_instruction(&self, addr: u64) -> Result<Instruction> {
+        // 命令がページの終わりにある時は, 読めるだけ読む
+        let mut bytes = ptrace::read_bytes_partial(self.inferior.pid, addr, disasm::MAX_INSTRUCTION_LENGTH);
+        if bytes.is_empty() { bail!("Cannot access memory at address 0x{:x}", addr); }
+        // non-stopで他のスレッドが動いている間はint3が残っている
+        self.inferior.sites.restore_original(addr, &mut bytes);
+        Ok(disasm::decode(&bytes, addr))
+    }
+
+    // pcの命令がcallなら, 戻り先のアドレスを返す
+    fn call_return_address(&self, pc: u64) -> Option<u64> {
+        let instruction = self.read_instruction(pc).ok()?;
+        if instruction.is_call() { Some(pc + instruction.get_length() as u64) } else { None }
+    }
+
+    // 1命令進める. callで関数に入った時は, その戻り先も返す
+    fn step_into(&mut self) -> Result<(StopEvent, Option<u64>)> {
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        let return_addr = self.call_return_address(pc);
+        let event = self.single_step()?;
+        let entered = match (&event, return_addr, self.inferior.regs) {
+            (StopEvent::Trap, Some(return_addr), Some(regs)) if regs.rip != return_addr => Some(return_addr),
+            _ => None,
+        };
+        Ok((event, entered))
+    }
+
+    // callで入った関数から戻るまで進める. 入った先にブレークポイントがあればそこで止まる
+    fn step_over_call(&mut self, return_addr: u64, before: &user_regs_struct) -> Result<StopEvent> {
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        if self.breakpoint_triggered(pc) {
+            return Ok(StopEvent::Breakpoint(pc));
+        }
+        self.run_to(return_addr, Some(before.rsp))
+    }
+
+    fn function_start(&self, pc: u64) -> Option<u64> {
+        self.inferior.target.symbolize(pc).map(|(_, offset)| pc - offset)
+    }
+
+    // 今のフレームの中で, 今の行より後ろの行まで進める. ループの先頭には戻らない
+    fn until_next_line(&mut self) -> Result<()> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let start_function = self.function_start(regs.rip);
+        loop {
+            if let Some(event) = self.step_one_line(true)? {
+                return self.report_stop(event);
+            }
+            let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+            if pc > regs.rip || self.function_start(pc) != start_function { break; }
+        }
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        self.print_stop_location(self.function_start(pc) != start_function)
+    }
+
+    // locationに着くか, 今のフレームから戻るまで進める.
+    // in_current_frameなら (until) 今のフレームより内側の呼び出しの中で着いても止まらない
+    fn run_to_location(&mut self, location: &str, in_current_frame: bool) -> Result<()> {
+        if location.is_empty() { bail!("Argument required (a location)."); }
+        let addr = self.resolve_location(location)?;
+        self.ensure_frames()?;
+        let frame = self.inferior.frames.first().cloned().context("No stack.")?;
+        let start_cfa = frame.get_cfa().unwrap_or(frame.get_regs().rsp);
+        let return_addr = self.inferior.frames.get(1).map(|caller| caller.get_pc());
+        let start_function = self.function_start(frame.get_pc());
+
+        let internal_count = self.inferior.internal_breakpoints.len();
+        self.inferior.internal_breakpoints.push(addr);
+        self.inferior.internal_breakpoints.extend(return_addr.iter());
+        let result = loop {
+            let event = match self.resume() {
+                Ok(event) => event,
+                Err(e) => break Err(e),
+            };
+            let hit = match event {
+                StopEvent::Breakpoint(hit) => hit,
+                _ => break Ok(event),
+            };
+            let sp = self.inferior.regs.map(|regs| regs.rsp).unwrap_or(0);
+            if hit == addr {
+                if !in_current_frame { break Ok(StopEvent::Trap); }
+                let _ = self.ensure_frames();
+                let cfa = self.inferior.frames.first().and_then(|frame| frame.get_cfa()).unwrap_or(sp);
+                if cfa >= start_cfa { break Ok(StopEvent::Trap); }
+            }
+            if Some(hit) == return_addr && sp >= start_cfa { break Ok(StopEvent::Trap); }
+            if !self.stopped_breakpoints.is_empty() { break Ok(event); }
+        };
+        self.inferior.internal_breakpoints.truncate(internal_count);
+        match result? {
+            StopEvent::Trap => {
+                let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+                self.print_stop_location(self.function_start(pc) != start_function)
+            },
+            event => self.report_stop(event),
+        }
+    }
+
+    // non-stop: 止まっている他のスレッドも再開してから, 選択中のスレッドを再開する
+    fn continue_all(&mut self) -> Result<()> {
+        if !self.non_stop { return self.continue_execution(); }
+        let current = self.inferior.pid;
+        let others: Vec<Pid> = self.inferior.threads.iter()
+            .filter(|thread| !thread.is_running() && thread.get_tid() != current)
+            .map(|thread| thread.get_tid())
+            .collect();
+        for tid in others.into_iter() {
+            self.inferior.pid = tid;
+            self.stopped(ptrace::getregs(tid)?);
+            if let Some(event) = self.resume_selected_thread()? {
+                return self.report_async(event);
+            }
+        }
+        self.inferior.pid = current;
+        if self.is_thread_running(current) {
+            let event = self.wait_event()?;
+            return self.report_async(event);
+        }
+        self.stopped(ptrace::getregs(current)?);
+        self.continue_execution()
+    }
+
+    fn continue_execution(&mut self) -> Result<()> {
+        if self.has_software_watchpoints() {
+            println!("Single-stepping to check software watchpoints (slow)...");
+        }
+        let event = self.resume()?;
+        self.report_stop(event)
+    }
+
+    fn step_instruction(&mut self, count: usize, over_calls: bool) -> Result<()> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let start_function = self.function_start(regs.rip);
+        for _ in 0..count {
+            let before = self.inferior.regs.context(NOT_RUNNING)?;
+            let (mut event, entered) = self.step_into()?;
+            if over_calls {
+                if let Some(return_addr) = entered {
+                    event = self.step_over_call(return_addr, &before)?;
+                }
+            }
+            if event != StopEvent::Trap { return self.report_stop(event); }
+        }
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        self.print_stop_location(self.function_start(pc) != start_function)
+    }
+
+    fn step_line(&mut self, count: usize, over_calls: bool) -> Result<()> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let start_function = self.function_start(regs.rip);
+        for _ in 0..count {
+            if let Some(event) = self.step_one_line(over_calls)? {
+                return self.report_stop(event);
+            }
+        }
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        self.print_stop_location(self.function_start(pc) != start_function)
+    }
+
+    // 次の行の先頭まで進める. 途中でブレークポイントやシグナルで止まったらそのイベントを返す
+    fn step_one_line(&mut self, over_calls: bool) -> Result<Option<StopEvent>> {
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let mut current_line = match self.inferior.target.find_source_line(regs.rip) {
+            Some(location) => location,
+            None => {
+                let name = self.inferior.target.symbolize(regs.rip).map(|(name, _)| name).unwrap_or_else(|| "??".to_owned());
+                println!("Single stepping until exit from function {},\nwhich has no line number information.", name);
+                return self.step_out();
+            },
+        };
+        let mut current_function = self.function_start(regs.rip);
+        loop {
+            let before = self.inferior.regs.context(NOT_RUNNING)?;
+            let (event, entered) = self.step_into()?;
+            if event != StopEvent::Trap { return Ok(Some(event)); }
+
+            if let Some(return_addr) = entered {
+                let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+                if !over_calls && self.inferior.target.find_source_line(pc).is_some() {
+                    // 呼ばれた関数のプロローグの後で止まる
+                    return match self.inferior.target.find_prologue_end(pc) {
+                        Some(addr) if addr != pc => {
+                            let event = self.run_to(addr, None)?;
+                            Ok(if event == StopEvent::Trap { None } else { Some(event) })
+                        },
+                        _ => Ok(None),
+                    };
+                }
+                let event = self.step_over_call(return_addr, &before)?;
+                if event != StopEvent::Trap { return Ok(Some(event)); }
+            }
+
+            let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+            let location = match self.inferior.target.find_source_line(pc) {
+                Some(location) => location,
+                // 行情報の無いところに戻ってきた
+                None => return Ok(None),
+            };
+            let function = self.function_start(pc);
+            if self.inferior.target.is_statement_start(pc) && (location != current_line || function != current_function) {
+                return Ok(None);
+            }
+            if function != current_function {
+                // 呼び出し元の行の途中に戻ってきたので, その行の終わりまで進める
+                current_line = location;
+                current_function = function;
+            }
+        }
+    }
+
+    // 今の関数から戻るまで進める
+    fn step_out(&mut self) -> Result<Option<StopEvent>> {
+        self.ensure_frames()?;
+        let frame = self.inferior.frames.first().cloned().context("No stack.")?;
+        let caller = self.inferior.frames.get(1).cloned().context("\"finish\" not meaningful in the outermost frame.")?;
+        let event = self.run_to(caller.get_pc(), frame.get_cfa())?;
+        Ok(if event == StopEvent::Trap { None } else { Some(event) })
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.ensure_frames()?;
+        let frame = self.get_selected_frame()?;
+        let caller = self.inferior.frames.get(self.inferior.selected_frame + 1).cloned().context("\"finish\" not meaningful in the outermost frame.")?;
+        let with_addr = self.inferior.selected_frame > 0 || !self.inferior.target.is_statement_start(frame.get_pc());
+        println!("Run till exit from #{:<3}{}", self.inferior.selected_frame, self.format_location(&frame, with_addr));
+        let callee_pc = frame.get_lookup_pc();
+        let event = self.run_to(caller.get_pc(), frame.get_cfa())?;
+        if event != StopEvent::Trap { return self.report_stop(event); }
+        self.print_stop_location(true)?;
+
+        let (module, function) = match self.inferior.target.find_function(callee_pc) {
+            Some(function) => function,
+            None => return Ok(()),
+        };
+        let return_type = match function.get_return_type() {
+            Some(return_type) => ValueType::Dwarf(return_type),
+            None => return Ok(()),
+        };
+        let debug_info = module.get_debug_info();
+        let value = match self.read_return_value(debug_info, &return_type)? {
+            Some(value) => value,
+            None => {
+                println!("Value returned has type: {}. Cannot determine contents", value::type_name(debug_info, &return_type));
+                return Ok(());
+            },
+        };
+        let pid = self.inferior.pid;
+        let formatted = value::format_value(debug_info, &value, &|addr, len| ptrace::read_bytes(pid, addr, len));
+        self.value_history.push(value);
+        println!("Value returned is ${} = {}", self.value_history.len(), formatted);
+        Ok(())
+    }
+
+    // System V ABI: 8バイトごとにINTEGERはrax, rdxの順, SSEはxmm0, xmm1の順に入る
+    fn read_return_value(&self, debug_info: Option<&DebugInfo>, ty: &ValueType) -> Result<Option<Value>> {
+        let classes = match value::classify_return(debug_info, ty) {
+            Some(classes) => classes,
+            None => return Ok(None),
+        };
+        let regs = self.inferior.regs.context(NOT_RUNNING)?;
+        let mut integer_regs = vec![regs.rax, regs.rdx].into_iter();
+        let mut sse_regs = Vec::new().into_iter();
+        if classes.contains(&ReturnClass::Sse) {
+            let fpregs = ptrace::getfpregs(self.inferior.pid)?;
+            // xmm0, xmm1の下位8バイト
+            let low = |index: usize| fpregs.xmm_space[index * 4] as u64 | (fpregs.xmm_space[index * 4 + 1] as u64) << 32;
+            sse_regs = vec![low(0), low(1)].into_iter();
+        }
+        let mut bytes = Vec::new();
+        for class in classes {
+            let word = match class {
+                ReturnClass::Integer => integer_regs.next(),
+                ReturnClass::Sse => sse_regs.next(),
+            };
+            bytes.extend(word.context("Return value does not fit in registers.")?.to_le_bytes().iter());
+        }
+        bytes.truncate(value::type_size(debug_info, ty) as usize);
+        Ok(Some(Value::new(ty.clone(), bytes, None)))
+    }
+
+    fn report_stop(&mut self, event: StopEvent) -> Result<()> {
+        // 前に止まった時と違うスレッドで止まった
+        if self.inferior.regs.is_some() && self.inferior.pid != self.inferior.announced_thread {
+            println!();
+            println!("[Switching to {}]", self.thread_label(self.inferior.pid));
+        }
+        self.inferior.announced_thread = self.inferior.pid;
+        let reason = match &event {
+            StopEvent::Breakpoint(_) => self.stopped_breakpoints.first().map(|id| format!("breakpoint {}", id)),
+            StopEvent::Watchpoint(hits) => hits.first().map(|(id, _)| format!("watchpoint {}", id)),
+            StopEvent::Trap => Some("step".to_owned()),
+            StopEvent::Signal(sig) => Some(sig.as_str().to_owned()),
+            StopEvent::Catch(caught) => Some(caught.stop_reason()),
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            self.set_stop_reason(reason);
+        }
+        match event {
+            StopEvent::Breakpoint(_) => {
+                match self.stopped_breakpoints.first() {
+                    Some(id) => {
+                        let id = *id;
+                        let temporary = self.breakpoints.iter().any(|breakpoint| breakpoint.get_id() == id && breakpoint.is_temporary());
+                        let catch = self.breakpoints.iter().find(|breakpoint| breakpoint.get_id() == id).and_then(|breakpoint| breakpoint.get_catch().cloned());
+                        if self.take_breakpoint_commands() { return Ok(()); }
+                        let frame = self.get_selected_frame()?;
+                        println!();
+                        let kind = match (&catch, temporary) {
+                            (Some(_), _) => "Catchpoint",
+                            (None, true) => "Temporary breakpoint",
+                            (None, false) => "Breakpoint",
+                        };
+                        let kind = match self.thread_prefix("hit") {
+                            Some(prefix) => format!("{} {}", prefix, kind),
+                            None => kind.to_owned(),
+                        };
+                        match &catch {
+                            Some(catch) => println!("{} {} ({}), {}", kind, id, catch.describe(), self.format_location(&frame, false)),
+                            None => println!("{} {}, {}", kind, id, self.format_location(&frame, false)),
+                        }
+                        self.print_source_line(&frame, false);
+                        if catch == Some(CatchKind::Panic) { self.print_panic(); }
+                        Ok(())
+                    },
+                    None => self.print_stop_location(true),
+                }
+            },
+            StopEvent::Catch(caught) => {
+                let id = match self.stopped_breakpoints.first() {
+                    Some(id) => *id,
+                    None => return self.print_stop_location(true),
+                };
+                if self.take_breakpoint_commands() { return Ok(()); }
+                let regs = self.inferior.regs.context(NOT_RUNNING)?;
+                let frame = self.get_selected_frame()?;
+                // システムコールの命令の後などで止まるので, 行の途中ならアドレスも表示する
+                let with_addr = !self.inferior.target.is_statement_start(frame.get_pc());
+                println!();
+                let kind = match self.thread_prefix("hit") {
+                    Some(prefix) => format!("{} Catchpoint", prefix),
+                    None => "Catchpoint".to_owned(),
+                };
+                println!("{} {} ({}), {}", kind, id, caught.describe(), self.format_location(&frame, with_addr));
+                match caught {
+                    CatchEvent::SyscallEntry(nr) => {
+                        let args = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+                        let args: Vec<String> = args[..syscall::argument_count(nr)].iter().map(|arg| format!("0x{:x}", arg)).collect();
+                        println!("{}({})", syscall::display_name(nr), args.join(", "));
+                    },
+                    CatchEvent::SyscallExit(_) => {
+                        // -4095から-1まではエラー番号
+                        let ret = regs.rax as i64;
+                        if (-4095..0).contains(&ret) {
+                            println!("= {} ({})", ret, Errno::from_i32(-ret as i32).desc());
+                        } else {
+                            println!("= {}", ret);
+                        }
+                    },
+                    _ => {},
+                }
+                self.print_source_line(&frame, with_addr);
+                Ok(())
+            },
+            StopEvent::Trap => self.print_stop_location(true),
+            StopEvent::Watchpoint(hits) => {
+                for (id, old) in hits.iter() {
+                    let watchpoint = match self.inferior.watchpoints.iter().find(|watchpoint| watchpoint.get_id() == *id) {
+                        Some(watchpoint) => watchpoint,
+                        None => continue,
+                    };
+                    println!();
+                    match self.thread_prefix("hit") {
+                        Some(prefix) => println!("{} {} {}: {}", prefix, watchpoint.describe(), id, watchpoint.get_expr()),
+                        None => println!("{} {}: {}", watchpoint.describe(), id, watchpoint.get_expr()),
+                    }
+                    println!();
+                    let new = watchpoint.get_value();
+                    if watchpoint.get_kind() == WatchKind::Read || new == old {
+                        println!("Value = {}", self.format_watch_value(watchpoint, new));
+                    } else {
+                        println!("Old value = {}", self.format_watch_value(watchpoint, old));
+                        println!("New value = {}", self.format_watch_value(watchpoint, new));
+                    }
+                }
+                self.print_stop_location(true)
+            },
+            StopEvent::Signal(sig) => {
+                // 渡すかどうかは handle で決める. SIGINTはCtrl-Cで止めたものなので初めは渡さない.
+                // SIGSTOPは渡すとグループストップする
+                let pid = self.inferior.pid;
+                let siginfo = ptrace::get_siginfo(pid).ok().filter(|siginfo| siginfo.si_signo == sig as i32);
+                if self.signal_table.get_policy(sig).pass {
+                    if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == pid) {
+                        thread.set_pending_signal(Some(sig));
+                    }
+                }
+                println!();
+                let prefix = self.thread_prefix("received").unwrap_or_else(|| "Program received".to_owned());
+                println!("{} signal {}, {}.", prefix, sig.as_str(), signals::description(sig));
+                // Ctrl-Cで止めた時はシグナルを受け取っていないのでsiginfoは無い
+                if let Some(siginfo) = siginfo.as_ref() {
+                    println!("Signal code: {}.", signals::describe_siginfo(sig, siginfo));
+                }
+                self.print_stop_location(true)?;
+                if signals::is_crash(sig) { self.print_crash_report(sig, siginfo.as_ref())?; }
+                Ok(())
+            },
+            StopEvent::Exited(0) => {
+                println!("[Inferior {} (process {}) exited normally]", self.inferior.num, self.inferior.tgid);
+                Ok(())
+            },
+            StopEvent::Exited(code) => {
+                println!("[Inferior {} (process {}) exited with code {:02o}]", self.inferior.num, self.inferior.tgid, code);
+                Ok(())
+            },
+            StopEvent::Killed(sig) => {
+                println!();
+                println!("Program terminated with signal {}, {}.", sig.as_str(), signals::description(sig));
+                println!("The program no longer exists.");
+                Ok(())
+            },
+        }
+    }
+
+    // 止まったブレークポイントのコマンドを実行するように並べ, 一時的なものを消す.
+    // 全てのコマンドが silent で始まっていれば止まった場所を表示しないのでtrue
+    fn take_breakpoint_commands(&mut self) -> bool {
+        let mut silent = true;
+        let mut commands = VecDeque::new();
+        for breakpoint in self.breakpoints.iter().filter(|breakpoint| self.stopped_breakpoints.contains(&breakpoint.get_id())) {
+            let list = breakpoint.get_commands();
+            match list.first() {
+                Some(first) if first == "silent" => commands.extend(list[1..].iter().cloned()),
+                _ => {
+                    silent = false;
+                    commands.extend(list.iter().cloned());
+                },
+            }
+        }
+        self.breakpoint_commands = commands;
+        let stopped_breakpoints = &self.stopped_breakpoints;
+        self.breakpoints.retain(|breakpoint| !(breakpoint.is_temporary() && stopped_breakpoints.contains(&breakpoint.get_id())));
+        silent
+    }
+
+    // プログラムが落ちた時の詳しい報告. 原因のアドレスのマッピング, 止まった命令, レジスタ, バックトレース
+    fn print_crash_report(&mut self, sig: Signal, siginfo: Option<&libc::siginfo_t>) -> Result<()> {
+        println!();
+        if let Some(siginfo) = siginfo.filter(|_| signals::is_fault(sig)) {
+            let addr = signals::fault_address(siginfo);
+            match self.inferior.target.find_mapping(addr) {
+                Some((start, end, perms, path)) if path.is_empty() => println!("Fault address 0x{:x} is in an anonymous mapping 0x{:x}-0x{:x} ({}).", addr, start, end, perms),
+                Some((start, end, perms, path)) => println!("Fault address 0x{:x} is in 0x{:x}-0x{:x} ({}) {}.", addr, start, end, perms, path),
+                None => println!("Fault address 0x{:x} is not in any mapping.", addr),
+            }
+        }
+        let pc = self.inferior.regs.context(NOT_RUNNING)?.rip;
+        println!("{}", if signals::is_fault(sig) { "Faulting instruction:" } else { "Current instruction:" });
+        match self.read_instruction(pc) {
+            Ok(instruction) => {
+                let symbolize = |addr: u64| self.symbolize_offset(addr);
+                let sym = symbolize(pc).map(|sym| format!(" <{}>", sym)).unwrap_or_default();
+                println!("=> 0x{:016x}{}:\t{}", pc, sym, instruction.format(&symbolize));
+            },
+            Err(e) => println!("=> 0x{:016x}:\t{}", pc, e),
+        }
+        println!();
+        println!("Registers:");
+        self.info_registers(&[])?;
+        println!();
+        println!("Backtrace:");
+        self.backtrace(None)
+    }
+
+    // 型は設定した時のフレームのモジュールのDWARFで調べる
+    fn format_watch_value(&self, watchpoint: &Watchpoint, bytes: &[u8]) -> String {
+        let debug_info = self.inferior.target.find_module(watchpoint.get_scope_pc()).and_then(|module| module.get_debug_info());
+        let value = Value::new(watchpoint.get_type().clone(), bytes.to_vec(), Some(watchpoint.get_addr()));
+        let pid = self.inferior.pid;
+        value::format_value(debug_info, &value, &|addr, len| ptrace::read_bytes(pid, addr, len))
+    }
+
+    // 起動したプログラムをmainまで進める. 既に動いていれば起動し直す
+    fn start(&mut self) -> Result<()> {
+        let program = match self.inferior.program.clone() {
+            Some(program) => program,
+            None if self.inferior.threads.is_empty() => bail!("No executable file specified."),
+            None => bail!("The program was attached to, so it cannot be started again."),
+        };
+        if self.inferior.regs.is_some() && !self.inferior.just_launched {
+            self.discard_vfork_parent()?;
+            ptrace::kill(self.inferior.tgid)?;
+            self.inferior.regs = None;
+        }
+        if self.inferior.regs.is_none() {
+            let pid = ptrace::spawn(&program)?;
+            self.reset_threads(pid);
+            self.inferior.target = Target::load(pid)?;
+            self.inferior.sites = BreakpointSites::default();
+            self.inferior.debug_registers = DebugRegisters::default();
+            self.inferior.just_launched = true;
+            self.stopped(ptrace::getregs(self.inferior.pid)?);
+            // 他のインフェリアで作ったブレークポイントも, このプログラムで見つかれば止まる
+            self.reresolve_breakpoints(false);
+        }
+        let main = self.inferior.target.find_main().context("No symbol table loaded.  Use the \"file\" command.")?;
+        let addr = self.inferior.target.find_prologue_end(main).unwrap_or(main);
+        let id = self.add_breakpoint(addr, true);
+        let inferior = self.inferior.num;
+        if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+            breakpoint.set_inferior(Some(inferior));
+        }
+        self.continue_execution()
+    }
+
+    // プロセスの無いインフェリアで, 動いているプロセスにアタッチする
+    fn attach(&mut self, pid: i32) -> Result<()> {
+        if !self.inferior.threads.is_empty() { bail!("A program is being debugged already."); }
+        let pid = Pid::from_raw(pid);
+        ptrace::attach(pid).with_context(|| format!("Cannot attach to process {}", pid))?;
+        ptrace::wait_thread(pid)?;
+        println!("Attaching to process {}", pid);
+        self.reset_threads(pid);
+        self.inferior.target = Target::load(pid)?;
+        self.inferior.program = None;
+        self.inferior.just_launched = false;
+        self.inferior.sites = BreakpointSites::default();
+        self.inferior.debug_registers = DebugRegisters::default();
+        self.stopped(ptrace::getregs(pid)?);
+        self.attach_threads()?;
+        self.reresolve_breakpoints(false);
+        self.print_stop_location(true)
+    }
+
+    // "process 1234" か, プロセスが無ければ "<null>"
+    fn process_description(&self) -> String {
+        if self.inferior.threads.is_empty() { "<null>".to_owned() } else { format!("process {}", self.inferior.tgid) }
+    }
+
+    fn info_inferiors(&mut self) -> Result<()> {
+        let mut rows = vec![(true, self.inferior.num, self.process_description(), executable_path(self.inferior.tgid, !self.inferior.threads.is_empty(), self.inferior.program.as_ref()))];
+        for inferior in self.inferiors.iter() {
+            let alive = !inferior.threads.is_empty();
+            let description = if alive { format!("process {}", inferior.tgid) } else { "<null>".to_owned() };
+            rows.push((false, inferior.num, description, executable_path(inferior.tgid, alive, inferior.program.as_ref())));
+        }
+        rows.sort_by_key(|row| row.1);
+        println!("  Num  Description       Connection           Executable        ");
+        for (current, num, description, executable) in rows.iter() {
+            let connection = if description == "<null>" { "" } else { "1 (native)" };
+            println!("{} {:<4} {:<17} {:<20} {}", if *current { '*' } else { ' ' }, num, description, connection, executable);
+        }
+        Ok(())
+    }
+
+    // numが無ければ今のインフェリアを表示する
+    fn inferior(&mut self, num: Option<usize>) -> Result<()> {
+        let num = match num {
+            Some(num) => num,
+            None => {
+                let executable = executable_path(self.inferior.tgid, !self.inferior.threads.is_empty(), self.inferior.program.as_ref());
+                println!("[Current inferior is {} [{}] ({})]", self.inferior.num, self.process_description(), executable);
+                return Ok(());
+            },
+        };
+        self.switch_inferior(num)?;
+        let executable = executable_path(self.inferior.tgid, !self.inferior.threads.is_empty(), self.inferior.program.as_ref());
+        println!("[Switching to inferior {} [{}] ({})]", self.inferior.num, self.process_description(), executable);
+        if self.inferior.threads.is_empty() { return Ok(()); }
+        self.inferior.announced_thread = self.inferior.pid;
+        let id = self.thread_id(self.current_thread_num());
+        let label = self.thread_label(self.inferior.pid);
+        if self.is_thread_running(self.inferior.pid) {
+            println!("[Switching to thread {} ({})](running)", id, label);
+            return Ok(());
+        }
+        println!("[Switching to thread {} ({})]", id, label);
+        self.print_selected_frame()
+    }
+
+    fn add_inferior(&mut self, exec: Option<String>) -> Result<()> {
+        let num = self.new_inferior_num();
+        self.push_inferior(Inferior::empty(num, exec.map(|file| vec![file])));
+        println!("[New inferior {}]", num);
+        println!("Added inferior {}", num);
+        Ok(())
+    }
+
+    // locationが無ければ選択中のフレームのpcに置く. inferiorがあればそのインフェリアでだけ止まる
+    fn set_breakpoint(&mut self, location: Option<&str>, inferior: Option<usize>, condition: Option<&str>, temporary: bool) -> Result<()> {
+        if let Some(condition) = condition {
+            if condition.is_empty() { bail!("Argument required (boolean expression)."); }
+            expression::parse(condition)?;
+        }
+        let id = match inferior {
+            Some(num) if num != self.inferior.num => {
+                if !self.inferiors.iter().any(|inferior| inferior.num == num) { bail!("Unknown inferior number {}.", num); }
+                self.with_inferior(num, |debugger| debugger.create_breakpoint(location, temporary))??
+            },
+            _ => self.create_breakpoint(location, temporary)?,
+        };
+        if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+            breakpoint.set_condition(condition.map(|condition| condition.to_owned()));
+            breakpoint.set_inferior(inferior);
+        }
+        if inferior.is_none() { self.resolve_in_other_inferiors(id)?; }
+        Ok(())
+    }
+
+    fn create_breakpoint(&mut self, location: Option<&str>, temporary: bool) -> Result<usize> {
+        let addr = match location {
+            Some(location) => self.resolve_location(location)?,
+            None => self.get_selected_frame()?.get_pc(),
+        };
+        let id = self.add_breakpoint(addr, temporary);
+        let location = location.map(|location| self.canonical_location(location, addr));
+        if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+            if let Some(location) = location { breakpoint.set_location(location); }
+        }
+        Ok(id)
+    }
+
+    // プロセスのある他のインフェリアでも場所を探す. 見つからなければそのインフェリアでは止まらない
+    fn resolve_in_other_inferiors(&mut self, id: usize) -> Result<()> {
+        let (location, catch) = match self.breakpoints.iter().find(|breakpoint| breakpoint.get_id() == id) {
+            Some(breakpoint) if breakpoint.get_catch().is_none_or(|catch| catch.has_location()) => (breakpoint.get_location().clone(), breakpoint.get_catch().cloned()),
+            _ => return Ok(()),
+        };
+        let nums: Vec<usize> = self.inferiors.iter().filter(|inferior| !inferior.threads.is_empty()).map(|inferior| inferior.num).collect();
+        for num in nums.into_iter() {
+            let addr = self.with_inferior(num, |debugger| debugger.locate_breakpoint(&location, catch.as_ref()).ok())?;
+            if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+                breakpoint.resolve(num, addr);
+            }
+        }
+        Ok(())
+    }
+
+    // dprintf 場所,"書式",式...
+    fn dprintf(&mut self, arg: &str) -> Result<()> {
+        let (location, dprintf) = match arg.split_once(',') {
+            Some((location, dprintf)) if !location.trim().is_empty() => (location.trim(), dprintf.trim()),
+            _ => bail!("Format string required"),
+        };
+        let (format, args) = printf::split_arguments(dprintf)?;
+        printf::check(&format, &args)?;
+        for arg in args.iter() {
+            expression::parse(arg)?;
+        }
+        let addr = self.resolve_location(location)?;
+        let id = self.new_breakpoint(addr, false, "Dprintf");
+        let location = self.canonical_location(location, addr);
+        if let Some(breakpoint) = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id) {
+            breakpoint.set_dprintf(dprintf.to_owned());
+            breakpoint.set_location(location);
+        }
+        self.resolve_in_other_inferiors(id)
+    }
+
+    fn condition(&mut self, id: usize, condition: Option<String>) -> Result<()> {
+        if let Some(condition) = condition.as_ref() {
+            expression::parse(condition)?;
+        }
+        let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id)
+            .with_context(|| format!("No breakpoint number {}.", id))?;
+        if condition.is_none() {
+            println!("Breakpoint {} now unconditional.", id);
+        }
+        breakpoint.set_condition(condition);
+        Ok(())
+    }
+
+    fn set_commands(&mut self, id: Option<usize>, commands: Vec<String>) -> Result<()> {
+        let id = match id {
+            Some(id) => id,
+            None => self.breakpoints.iter().map(|breakpoint| breakpoint.get_id()).max().context("No breakpoints specified.")?,
+        };
+        let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id)
+            .with_context(|| format!("No breakpoint number {}.", id))?;
+        breakpoint.set_commands(commands);
+        Ok(())
+    }
+
+    fn ignore(&mut self, id: usize, count: usize) -> Result<()> {
+        let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| breakpoint.get_id() == id)
+            .with_context(|| format!("No breakpoint number {}.", id))?;
+        breakpoint.set_ignore_count(count);
+        match count {
+            0 => println!("Will stop next time breakpoint {} is reached.", id),
+            1 => println!("Will ignore next crossing of breakpoint {}.", id),
+            _ => println!("Will ignore next {} crossings of breakpoint {}.", count, id),
+        }
+        Ok(())
+    }
+
+    // catch イベント. ブレークポイントと同じ番号を使う
+    fn catch(&mut self, kind: CatchKind) -> Result<()> {
+        let addr = if kind.has_location() { Some(self.locate_breakpoint("", Some(&kind))?) } else { None };
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let mut breakpoint = Breakpoint::new_catchpoint(id, kind.clone());
+        if addr.is_some() { breakpoint.resolve(self.inferior.num, addr); }
+        self.breakpoints.push(breakpoint);
+        println!("Catchpoint {} ({})", id, kind.describe_new());
+        self.resolve_in_other_inferiors(id)
+    }
+
+    // catch syscall [名前|番号|group:グループ]... 何も無ければ全てのシステムコール
+    fn catch_syscall(&mut self, names: &[String]) -> Result<()> {
+        let nrs = syscall::parse(names)?;
+        self.catch(CatchKind::Syscall(nrs))
+    }
+
+    // 今のインフェリアにシステムコールのキャッチポイントがあれば, PTRACE_SYSCALLで再開する
+    fn catching_syscalls(&self) -> bool {
+        !self.catchpoint_ids(|catch| matches!(catch, CatchKind::Syscall(_))).is_empty()
+    }
+
+    // システムコールの停止を報告するか. 入り口か出口かはPTRACE_GET_SYSCALL_INFOで分かる.
+    // 使えないカーネルでは入り口でraxが-ENOSYSになるのを見て, -ENOSYSを返した出口とはスレッドの状態で区別する
+    fn classify_syscall(&mut self, tid: Pid) -> Result<ThreadStatus> {
+        let regs = ptrace::getregs(tid)?;
+        let nr = regs.orig_rax;
+        let thread = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == tid);
+        let in_syscall = thread.as_ref().is_some_and(|thread| thread.is_in_syscall());
+        let is_entry = match ptrace::is_syscall_entry(tid)? {
+            Some(is_entry) => is_entry,
+            None => regs.rax as i64 == -(libc::ENOSYS as i64) && !in_syscall,
+        };
+        if let Some(thread) = thread { thread.set_in_syscall(is_entry); }
+        if self.catchpoint_ids(|catch| catch.catches_syscall(nr)).is_empty() { return Ok(ThreadStatus::Internal); }
+        let caught = if is_entry { CatchEvent::SyscallEntry(nr) } else { CatchEvent::SyscallExit(nr) };
+        Ok(ThreadStatus::Stop(StopEvent::Catch(caught)))
+    }
+
+    // ブレークポイントの場所を探す. キャッチポイントはイベントが起きた時に呼ばれる関数
+    fn locate_breakpoint(&mut self, location: &str, catch: Option<&CatchKind>) -> Result<u64> {
+        match catch {
+            Some(CatchKind::Panic) => self.find_first_function(&panic::RAISE_FUNCTIONS).context("No symbol \"rust_panic\" in current program."),
+            // 場所を持たないキャッチポイントは解決しない
+            Some(catch) => bail!("Catchpoint ({}) has no location.", catch.describe()),
+            None => self.resolve_location(location),
+        }
+    }
+
+    // 名前の順に探して, 最初に見つかった関数の先頭. 引数を読むのでプロローグの前で止める
+    fn find_first_function(&self, names: &[&str]) -> Option<u64> {
+        names.iter().find_map(|name| self.inferior.target.find_function_by_name(name))
+    }
+
+    // catch panic がある時にパニックの場所を読むために止まるパニックハンドラ
+    fn panic_handler_address(&self) -> Option<u64> {
+        let inferior = self.inferior.num;
+        let catching = self.breakpoints.iter().any(|breakpoint| breakpoint.get_catch() == Some(&CatchKind::Panic) && breakpoint.applies_to(inferior));
+        if catching { self.find_first_function(&panic::HANDLER_FUNCTIONS) } else { None }
+    }
+
+    // パニックハンドラの入り口で, 引数の &PanicInfo から場所を読んでおく. メッセージはまだ組み立てられていない
+    fn record_panic_location(&mut self) {
+        let regs = match self.inferior.regs {
+            Some(regs) => regs,
+            None => return,
+        };
+        let pid = self.inferior.pid;
+        let location = panic::read_location(&|addr, len| ptrace::read_bytes(pid, addr, len), regs.rdi);
+        if let Some(thread) = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == pid) {
+            thread.set_panic_location(location);
+        }
+    }
+
+    // catch panic で止まった時に, rust_panic の引数の &mut dyn PanicPayload からメッセージを読んで表示する
+    fn print_panic(&mut self) {
+        let regs = match self.inferior.regs {
+            Some(regs) => regs,
+            None => return,
+        };
+        let pid = self.inferior.pid;
+        let message = panic::read_message(&|addr, len| ptrace::read_bytes(pid, addr, len), regs.rdi, regs.rsi);
+        let location = self.inferior.threads.iter_mut().find(|thread| thread.get_tid() == pid).and_then(|thread| thread.take_panic_location());
+        match location {
+            Some(location) => println!("panicked at {}:", location),
+            None => println!("panicked:"),
+        }
+        println!("{}", message.unwrap_or_else(|| "<message unavailable>".to_owned()));
+    }
+
+    // 式が指しているメモリを見張る. デバッグレジスタが使えなければソフトウェアウォッチポイントにする
+    fn watch(&mut self, kind: WatchKind, expr: &str) -> Result<()> {
+        if expr.is_empty() { bail!("Argument required (expression to compute)."); }
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let value = expression::evaluate_str(&ctx, expr)?;
+        let addr = value.get_address().with_context(|| format!("Cannot watch constant value `{}'.", expr))?;
+        let len = value.get_bytes().len();
+        let used = self.inferior.watchpoints.iter().filter(|watchpoint| watchpoint.is_hardware()).count();
+        let software_reason = if !watchpoint::fits_debug_register(addr, len) {
+            Some(format!("{} bytes at 0x{:x} do not fit a debug register", len, addr))
+        } else if used >= watchpoint::DEBUG_REGISTER_COUNT {
+            Some(format!("all {} debug registers are in use", watchpoint::DEBUG_REGISTER_COUNT))
+        } else {
+            None
+        };
+        // 読み込みはソフトウェアでは分からない
+        if software_reason.is_some() && kind != WatchKind::Write {
+            bail!("Expression cannot be implemented with read/access watchpoint.");
+        }
+        let id = self.next_breakpoint_id;
+        let watchpoint = Watchpoint::new(id, kind, software_reason.is_none(), expr.to_owned(), addr, frame.get_lookup_pc(), &value);
+        let mut watchpoints = self.inferior.watchpoints.clone();
+        watchpoints.push(watchpoint.clone());
+        let tids = self.stopped_tids();
+        self.inferior.debug_registers.sync(&tids, &watchpoints)?;
+        self.inferior.watchpoints = watchpoints;
+        self.next_breakpoint_id += 1;
+        println!("{} {}: {}", watchpoint.describe(), id, expr);
+        if let Some(reason) = software_reason {
+            println!("Software watchpoint because {}; the program will be single-stepped, which is very slow.", reason);
+        }
+        Ok(())
+    }
+
+    // idsが空なら全て消す
+    fn delete(&mut self, ids: &[usize]) -> Result<()> {
+        if ids.is_empty() {
+            self.breakpoints.clear();
+            self.inferior.watchpoints.clear();
+        }
+        for id in ids.iter() {
+            let breakpoint_count = self.breakpoints.len();
+            let watchpoint_count = self.inferior.watchpoints.len();
+            self.breakpoints.retain(|breakpoint| breakpoint.get_id() != *id);
+            self.inferior.watchpoints.retain(|watchpoint| watchpoint.get_id() != *id);
+            if self.breakpoints.len() == breakpoint_count && self.inferior.watchpoints.len() == watchpoint_count {
+                println!("No breakpoint number {}.", id);
+            }
+        }
+        if self.inferior.regs.is_some() {
+            let tids = self.stopped_tids();
+            self.inferior.debug_registers.sync(&tids, &self.inferior.watchpoints)?;
+        }
+        Ok(())
+    }
+
+    fn info_breakpoints(&mut self) -> Result<()> {
+        if self.breakpoints.is_empty() && self.inferior.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+            return Ok(());
+        }
+        let mut rows: Vec<BreakpointRow> = vec![];
+        let breakpoints = self.breakpoints.clone();
+        for breakpoint in breakpoints.iter() {
+            // 複数のインフェリアにある時は, インフェリアごとの場所を次の行から書く
+            let mut locations = vec![];
+            for (num, addr) in breakpoint.get_locations().iter() {
+                let mut what = self.with_inferior(*num, |debugger| debugger.describe_address(*addr))?;
+                if !self.inferiors.is_empty() { what = format!("{} inf {}", what, num); }
+                locations.push((format!("0x{:016x}", addr), what));
+            }
+            // キャッチポイントは場所の代わりにイベントを表示する
+            if let Some(catch) = breakpoint.get_catch() {
+                locations = vec![("".to_owned(), catch.describe())];
+            }
+            // どのプログラムでも見つからなかったものは, 場所だけを表示する
+            let (addr_column, what) = match locations.len() {
+                0 => ("<PENDING>".to_owned(), breakpoint.get_location().clone()),
+                1 => locations.remove(0),
+                _ => ("<MULTIPLE>".to_owned(), "".to_owned()),
+            };
+            let mut details = vec![];
+            if let Some(condition) = breakpoint.get_condition() {
+                details.push(format!("stop only if {}", condition));
+            }
+            let type_name = if breakpoint.get_catch().is_some() {
+                "catchpoint"
+            } else if breakpoint.get_dprintf().is_some() {
+                "dprintf"
+            } else {
+                "breakpoint"
+            };
+            let counted = if breakpoint.get_catch().is_some() { "catchpoint" } else { "breakpoint" };
+            match breakpoint.get_hit_count() {
+                0 => {},
+                1 => details.push(format!("{} already hit 1 time", counted)),
+                count => details.push(format!("{} already hit {} times", counted, count)),
+            }
+            if breakpoint.get_ignore_count() > 0 {
+                details.push(format!("Will ignore next {} crossings of breakpoint.", breakpoint.get_ignore_count()));
+            }
+            if let Some(dprintf) = breakpoint.get_dprintf() {
+                details.push(format!("printf {}", dprintf));
+            }
+            for command in breakpoint.get_commands().iter() {
+                details.push(command.clone());
+            }
+            let disposition = if breakpoint.is_temporary() { "del" } else { "keep" };
+            if let Some(num) = breakpoint.get_inferior() {
+                details.insert(0, format!("stop only in inferior {}", num));
+            }
+            rows.push((breakpoint.get_id(), type_name, disposition, addr_column, what, details, locations));
+        }
+        for watchpoint in self.inferior.watchpoints.iter() {
+            rows.push((watchpoint.get_id(), watchpoint.type_name(), "keep", "".to_owned(), watchpoint.get_expr().clone(), vec![], vec![]));
+        }
+        rows.sort_by_key(|row| row.0);
+        println!("Num     Type            Disp Enb Address            What");
+        for (id, type_name, disposition, addr, what, details, locations) in rows.iter() {
+            println!("{:<8}{:<16}{:<5}y   {:<19}{}", id, type_name, disposition, addr, what);
+            for detail in details.iter() {
+                println!("\t{}", detail);
+            }
+            for (idx, (addr, what)) in locations.iter().enumerate() {
+                println!("{:<29}y   {:<19}{}", format!("{}.{}", id, idx + 1), addr, what);
+            }
+        }
+        Ok(())
+    }
+
+    // info breakpoints の "in main at main.rs:10" の部分
+    fn describe_address(&self, addr: u64) -> String {
+        let name = self.inferior.target.symbolize(addr).map(|(name, _)| name).unwrap_or_else(|| "??".to_owned());
+        match self.inferior.target.find_source_line(addr) {
+            Some((file_name, line)) => format!("in {} at {}:{}", name, file_name, line),
+            None => self.symbolize_offset(addr).map(|sym| format!("<{}>", sym)).unwrap_or_default(),
+        }
+    }
+
+    // print_frameならフレームの情報を, そうでなければソースの行だけを出す
+    fn print_stop_location(&mut self, print_frame: bool) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        let at_line_start = self.inferior.target.is_statement_start(frame.get_pc());
+        if print_frame {
+            println!("{}", self.format_location(&frame, !at_line_start));
+        } else if self.inferior.target.find_source_line(frame.get_pc()).is_none() {
+            println!("{}", unwind::format_frame(&self.inferior.target, &frame));
+            return Ok(());
+        }
+        self.print_source_line(&frame, !print_frame && !at_line_start);
+        Ok(())
+    }
+
+    // 行の途中で止まっている時は先頭にアドレスを付ける
+    fn print_source_line(&self, frame: &Frame, with_addr: bool) {
+        let (file_name, line) = match self.inferior.target.find_source_line(frame.get_lookup_pc()) {
+            Some(location) => location,
+            None => return,
+        };
+        let prefix = if with_addr { format!("0x{:016x}\t", frame.get_pc()) } else { "".to_owned() };
+        match self.read_source(&file_name).and_then(|lines| (line as usize).checked_sub(1).and_then(|idx| lines.get(idx)).cloned()) {
+            Some(text) => println!("{}{}\t{}", prefix, line, text),
+            None => println!("{}{}\t{}: No such file or directory.", prefix, line, file_name),
+        }
+    }
+
+    // gdbの "0x... in main::main2 (idx=3) at src/main.rs:16" の形
+    fn format_location(&self, frame: &Frame, with_addr: bool) -> String {
+        let pc = frame.get_lookup_pc();
+        let addr = if with_addr { format!("0x{:016x} in ", frame.get_pc()) } else { "".to_owned() };
+        let name = self.inferior.target.symbolize(pc).map(|(name, _)| name).unwrap_or_else(|| "??".to_owned());
+        let ctx = FrameContext::new(self, frame);
+        let args: Vec<String> = match ctx.function {
+            Some((module, function)) => function.get_visible_variables(pc.wrapping_sub(module.get_base())).into_iter()
+                .filter(|variable| variable.is_param())
+                .map(|variable| {
+                    let formatted = match ctx.read_variable(Some(function), variable) {
+                        Ok(value) => ctx.format(&value),
+                        Err(e) => format!("<{}>", e),
+                    };
+                    format!("{}={}", variable.get_name(), formatted)
+                })
+                .collect(),
+            None => vec![],
+        };
+        match self.inferior.target.find_source_line(pc) {
+            Some((file_name, line)) => format!("{}{} ({}) at {}:{}", addr, name, args.join(", "), file_name, line),
+            None => match self.inferior.target.find_module(pc) {
+                Some(module) => format!("{}{} () from {}", addr, name, module.get_path()),
+                None => format!("{}{} ()", addr, name),
+            },
+        }
+    }
+
+    fn backtrace(&mut self, limit: Option<usize>) -> Result<()> {
+        self.ensure_frames()?;
+        let shown = limit.unwrap_or(self.inferior.frames.len()).min(self.inferior.frames.len());
+        unwind::print_backtrace(&self.inferior.target, &self.inferior.frames[..shown], shown < self.inferior.frames.len());
+        Ok(())
+    }
+
+    fn select_frame(&mut self, level: Option<usize>) -> Result<()> {
+        match level {
+            Some(level) => self.move_frame(level, ""),
+            None => self.print_selected_frame(),
+        }
+    }
+
+    fn move_frame(&mut self, level: usize, top_message: &str) -> Result<()> {
+        self.ensure_frames()?;
+        if level >= self.inferior.frames.len() {
+            if !top_message.is_empty() && self.inferior.selected_frame + 1 == self.inferior.frames.len() { bail!("{}", top_message); }
+            self.inferior.selected_frame = self.inferior.frames.len() - 1;
+        } else {
+            self.inferior.selected_frame = level;
+        }
+        self.list_position = None;
+        self.print_selected_frame()
+    }
+
+    fn print_selected_frame(&mut self) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        println!("{}", unwind::format_frame(&self.inferior.target, &frame));
+        if let Some((file_name, line)) = self.inferior.target.find_source_line(frame.get_lookup_pc()) {
+            if let Some(lines) = self.read_source(&file_name) {
+                if let Some(text) = (line as usize).checked_sub(1).and_then(|idx| lines.get(idx)) {
+                    println!("{}\t{}", line, text);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // namesが空なら全ての汎用レジスタ
+    // XSAVE領域が読めなければFXSAVEの範囲だけにする
+    fn read_fp_registers(&self) -> Result<FpRegisters> {
+        match ptrace::getregset_xstate(self.inferior.pid) {
+            Ok(area) => Ok(FpRegisters::from_xstate(area)),
+            Err(_) => Ok(FpRegisters::from_fpregs(&ptrace::getfpregs(self.inferior.pid)?)),
+        }
+    }
+
+    // 名前の代わりに general, float, vector, all のグループも指定できる
+    fn info_registers(&mut self, names: &[String]) -> Result<()> {
+        let fp = self.read_fp_registers().ok();
+        let general = || registers::GENERAL_REGISTERS.iter().chain(registers::BASE_REGISTERS.iter()).map(|name| name.to_string());
+        let mut expanded: Vec<String> = vec![];
+        if names.is_empty() {
+            expanded.extend(general());
+        }
+        for name in names.iter() {
+            match (name.as_str(), &fp) {
+                ("general", _) => expanded.extend(general()),
+                ("float", _) => expanded.extend(registers::X87_REGISTERS.iter().map(|name| name.to_string())),
+                ("vector", Some(fp)) => expanded.extend(fp.get_vector_names()),
+                ("all", Some(fp)) => {
+                    expanded.extend(general());
+                    expanded.extend(fp.get_names());
+                },
+                _ => expanded.push(name.clone()),
+            }
+        }
+        self.print_registers(&expanded, fp.as_ref())
+    }
+
+    fn info_all_registers(&mut self) -> Result<()> {
+        self.info_registers(&["all".to_owned()])
+    }
+
+    fn print_registers(&mut self, names: &[String], fp: Option<&FpRegisters>) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let regs = frame.get_regs();
+        for name in names.iter().map(|name| name.as_str()) {
+            if let Some((value, size)) = registers::read(regs, name) {
+                let natural = match name {
+                    "rip" | "pc" => match self.symbolize_offset(value) {
+                        Some(sym) => format!("0x{:x} <{}>", value, sym),
+                        None => format!("0x{:x}", value),
+                    },
+                    "rbp" | "rsp" | "fp" | "sp" | "fs_base" | "gs_base" => format!("0x{:x}", value),
+                    "eflags" => registers::format_eflags(value),
+                    _ => {
+                        let bits = size as u32 * 8;
+                        if bits == 64 { (value as i64).to_string() } else { (((value << (64 - bits)) as i64) >> (64 - bits)).to_string() }
+                    },
+                };
+                println!("{:<15}0x{:<18x}{}", name, value, natural);
+                continue;
+            }
+            let (kind, bytes) = fp.and_then(|fp| fp.read(name)).with_context(|| format!("Invalid register `{}'", name))?;
+            match kind {
+                FpKind::X87 => {
+                    let raw: String = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+                    let natural = ctx.format(&Value::from_f64(registers::extended_to_f64(&bytes)));
+                    println!("{:<15}{:<20}(raw 0x{})", name, natural, raw);
+                },
+                FpKind::Control | FpKind::Mxcsr => {
+                    let mut array = [0u8; 8];
+                    array[..bytes.len()].copy_from_slice(&bytes);
+                    let value = u64::from_le_bytes(array);
+                    let natural = if kind == FpKind::Mxcsr { registers::format_mxcsr(value) } else { value.to_string() };
+                    println!("{:<15}0x{:<18x}{}", name, value, natural);
+                },
+                FpKind::Vector => {
+                    let value = fp.and_then(|fp| fp.read_value(name)).with_context(|| format!("Invalid register `{}'", name))?;
+                    println!("{:<15}{}", name, ctx.format(&value));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn info_variables(&mut self, is_param: bool) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let (module, function) = match ctx.function {
+            Some(function) => function,
+            None => bail!("No symbol table info available."),
+        };
+        let relative_pc = frame.get_lookup_pc().wrapping_sub(module.get_base());
+        let variables: Vec<&Variable> = function.get_visible_variables(relative_pc).into_iter()
+            .filter(|variable| variable.is_param() == is_param)
+            .collect();
+        if variables.is_empty() {
+            println!("{}", if is_param { "No arguments." } else { "No locals." });
+            return Ok(());
+        }
+        for variable in variables.iter() {
+            let formatted = match ctx.read_variable(Some(function), variable) {
+                Ok(value) => ctx.format(&value),
+                Err(e) => format!("<{}>", e),
+            };
+            println!("{} = {}", variable.get_name(), formatted);
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, expr: &str) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        let value = expression::evaluate_str(&ctx, expr)?;
+        let formatted = ctx.format(&value);
+        self.value_history.push(value);
+        println!("${} = {}", self.value_history.len(), formatted);
+        // print x = 1 のように書き換えることもある
+        self.reload_state()
+    }
+
+    // set var x = 1, set {i32}0x1234 = 1. gdbと同じく値は表示しない
+    fn set(&mut self, arg: &str) -> Result<()> {
+        let expr = match arg.split_once(char::is_whitespace) {
+            Some(("var", expr)) | Some(("variable", expr)) => expr,
+            _ if arg == "var" || arg == "variable" => "",
+            _ => arg,
+        };
+        if expr.trim().is_empty() { bail!("Argument required (expression to compute)."); }
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        expression::evaluate_str(&ctx, expr)?;
+        self.reload_state()
+    }
+
+    // 書き換えたメモリに退避されたレジスタや戻りアドレスがあるかもしれないので, レジスタを読み直してフレームを作り直す
+    fn reload_state(&mut self) -> Result<()> {
+        // 書き換えでウォッチポイントが報告しないように, 見張っている値も読み直す
+        let pid = self.inferior.pid;
+        for watchpoint in self.inferior.watchpoints.iter_mut() {
+            if let Ok(value) = ptrace::read_bytes(pid, watchpoint.get_addr(), watchpoint.get_len()) {
+                watchpoint.set_value(value);
+            }
+        }
+        if self.inferior.regs.is_some() {
+            self.inferior.regs = Some(ptrace::getregs(self.inferior.pid)?);
+        }
+        self.inferior.frames.clear();
+        Ok(())
+    }
+
+    // 命令の表示で使う "main::main2+4" の形
+    fn symbolize_offset(&self, addr: u64) -> Option<String> {
+        let (name, start, end) = self.inferior.target.find_symbol_range(addr)?;
+        // サイズの分からないシンボルの後ろのアドレスには名前を付けない
+        if end == start && addr != start { return None; }
+        let offset = addr - start;
+        Some(if offset == 0 { name } else { format!("{}+{}", name, offset) })
+    }
+
+    // 式として評価できなければ関数名として探す
+    fn evaluate_address(&mut self, expr: &str) -> Result<u64> {
+        let frame = self.get_selected_frame()?;
+        let ctx = FrameContext::new(self, &frame);
+        match expression::evaluate_str(&ctx, expr) {
+            Ok(value) => Ok(value.raw_u64()),
+            Err(e) => self.inferior.target.find_function_by_name(expr).ok_or(e),
+        }
+    }
+
+    fn disassemble(&mut self, arg: Option<&str>) -> Result<()> {
+        let pc = self.get_selected_frame().ok().map(|frame| frame.get_pc());
+        let addr = match arg {
+            None => pc.context("No frame selected.")?,
+            Some(arg) => self.evaluate_address(arg)?,
+        };
+        let (name, start, end) = match self.inferior.target.find_symbol_range(addr) {
+            Some(range) if range.2 > range.1 => range,
+            _ => bail!("No function contains specified address."),
+        };
+        println!("Dump of assembler code for function {}:", name);
+        let symbolize = |addr: u64| self.symbolize_offset(addr);
+        let mut addr = start;
+        while addr < end {
+            let instruction = self.read_instruction(addr)?;
+            let marker = if Some(addr) == pc { "=> " } else { "   " };
+            println!("{}0x{:016x} <+{}>:\t{}", marker, addr, addr - start, instruction.format(&symbolize));
+            addr += instruction.get_length() as u64;
+        }
+        println!("End of assembler dump.");
+        Ok(())
+    }
+
+    // x/NFU addr. addrを省略すると前回の続きから表示する
+    fn examine(&mut self, format: ExamineFormat, expr: Option<&str>) -> Result<()> {
+        let letter = format.format.or(self.examine_format.format).unwrap_or('x');
+        // aはアドレスなのでgiant, cは1文字ずつなのでbyteが既定
+        let default_size = match letter {
+            'a' => Some('g'),
+            'c' => Some('b'),
+            _ => self.examine_format.size,
+        };
+        let format = ExamineFormat { count: format.count.or(Some(1)), format: Some(letter), size: format.size.or(default_size) };
+        let mut addr = match expr {
+            Some(expr) => self.evaluate_address(expr)?,
+            None => self.next_examine_addr.context("Argument required (starting display address).")?,
+        };
+        self.examine_format = format.clone();
+        let count = format.count.unwrap_or(1);
+        match letter {
+            'i' => {
+                let pc = self.inferior.regs.map(|regs| regs.rip);
+                let symbolize = |addr: u64| self.symbolize_offset(addr);
+                for _ in 0..count {
+                    let instruction = self.read_instruction(addr)?;
+                    let marker = if Some(addr) == pc { "=> " } else { "   " };
+                    println!("{}{}:\t{}", marker, self.format_address(addr), instruction.format(&symbolize));
+                    addr = instruction.get_addr() + instruction.get_length() as u64;
+                }
+            },
+            's' => {
+                for _ in 0..count {
+                    // 長すぎる文字列は途中で切る
+                    let bytes = ptrace::read_bytes_partial(self.inferior.pid, addr, value::MAX_STRING_LENGTH as usize + 1);
+                    let (len, next, suffix) = match bytes.iter().position(|b| *b == 0) {
+                        Some(len) => (len, addr + len as u64 + 1, ""),
+                        None if bytes.len() > value::MAX_STRING_LENGTH as usize => (bytes.len() - 1, addr + bytes.len() as u64 - 1, "..."),
+                        None => bail!("Cannot access memory at address 0x{:x}", addr + bytes.len() as u64),
+                    };
+                    println!("{}:\t{:?}{}", self.format_address(addr), String::from_utf8_lossy(&bytes[..len]), suffix);
+                    addr = next;
+                }
+            },
+            _ => {
+                let unit: usize = match format.size { Some('b') => 1, Some('h') => 2, Some('g') => 8, _ => 4 };
+                let per_line = match (letter, unit) {
+                    ('c', _) | (_, 1) | (_, 2) => 8,
+                    (_, 4) => 4,
+                    _ => 2,
+                };
+                // まとめて読んで, 読めなかったところでエラーにする
+                let bytes = ptrace::read_bytes_partial(self.inferior.pid, addr, count * unit);
+                for (idx, chunk) in bytes.chunks_exact(unit).enumerate() {
+                    let item_addr = addr + (idx * unit) as u64;
+                    if idx % per_line == 0 {
+                        if idx != 0 { println!(); }
+                        print!("{}:", self.format_address(item_addr));
+                    }
+                    print!("\t{}", self.format_unit(letter, chunk));
+                }
+                if bytes.len() >= unit { println!(); }
+                let read = bytes.len() / unit * unit;
+                if read < count * unit {
+                    self.next_examine_addr = Some(addr + read as u64);
+                    bail!("Cannot access memory at address 0x{:x}", addr + read as u64);
+                }
+                addr += read as u64;
+            },
+        }
+        self.next_examine_addr = Some(addr);
+        Ok(())
+    }
+
+    // xの各行の先頭の "0x555555558734 <main::main2+4>"
+    fn format_address(&self, addr: u64) -> String {
+        match self.symbolize_offset(addr) {
+            Some(sym) => format!("0x{:x} <{}>", addr, sym),
+            None => format!("0x{:x}", addr),
+        }
+    }
+
+    fn format_unit(&self, letter: char, bytes: &[u8]) -> String {
+        let mut raw_bytes = [0u8; 8];
+        raw_bytes[..bytes.len()].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(raw_bytes);
+        let bits = bytes.len() * 8;
+        let signed = if bits == 64 { raw as i64 } else { ((raw << (64 - bits)) as i64) >> (64 - bits) };
+        match letter {
+            'd' => signed.to_string(),
+            'u' => raw.to_string(),
+            'o' => if raw == 0 { "0".to_owned() } else { format!("0{:o}", raw) },
+            't' => format!("{:0width$b}", raw, width = bits),
+            'a' => self.format_address(raw),
+            'c' => format!("{} {:?}", signed, raw as u8 as char),
+            _ => format!("0x{:0width$x}", raw, width = bytes.len() * 2),
+        }
+    }
+
+    fn list(&mut self, arg: Option<&str>) -> Result<()> {
+        let (file_name, center) = match arg {
+            None => match self.list_position.clone() {
+                Some((file_name, next)) => (file_name, next + LIST_LINES / 2),
+                None => {
+                    let frame = self.get_selected_frame()?;
+                    self.inferior.target.find_source_line(frame.get_lookup_pc()).context("No line number information available.")?
+                },
+            },
+            Some(arg) => match arg.parse::<u64>() {
+                Ok(line) => {
+                    let file_name = match &self.list_position {
+                        Some((file_name, _)) => file_name.clone(),
+                        None => {
+                            let frame = self.get_selected_frame()?;
+                            self.inferior.target.find_source_line(frame.get_lookup_pc()).context("No line number information available.")?.0
+                        },
+                    };
+                    (file_name, line)
+                },
+                Err(_) => {
+                    let addr = self.inferior.target.find_function_by_name(arg).with_context(|| format!("Function \"{}\" not defined.", arg))?;
+                    self.inferior.target.find_source_line(addr).context("No line number information available.")?
+                },
+            },
+        };
+        let lines = self.read_source(&file_name).with_context(|| format!("Cannot read source file \"{}\".", file_name))?;
+        let start = center.saturating_sub(LIST_LINES / 2).max(1);
+        let end = (start + LIST_LINES).min(lines.len() as u64 + 1);
+        if start > lines.len() as u64 {
+            bail!("Line number {} out of range; \"{}\" has {} lines.", start, file_name, lines.len());
+        }
+        for line in start..end {
+            println!("{}\t{}", line, lines[line as usize - 1]);
+        }
+        self.list_position = Some((file_name, end));
+        Ok(())
+    }
+
+    // "関数名", "行番号", "ファイル:行番号", "*アドレスの式" をアドレスにする
+    fn resolve_location(&mut self, location: &str) -> Result<u64> {
+        let location = location.trim();
+        if let Some(expr) = location.strip_prefix('*') {
+            let frame = self.get_selected_frame()?;
+            let ctx = FrameContext::new(self, &frame);
+            return Ok(expression::evaluate_str(&ctx, expr)?.raw_u64());
+        }
+        if let Ok(line) = location.parse::<u64>() {
+            let frame = self.get_selected_frame()?;
+            let (file_name, _) = self.inferior.target.find_source_line(frame.get_lookup_pc()).context("No line number information available.")?;
+            let (addr, _) = self.inferior.target.find_line_address(Some(&file_name), line).with_context(|| format!("No line {} in the current file.", line))?;
+            return Ok(addr);
+        }
+        if let Some((file_name, line)) = location.rsplit_once(':') {
+            if let Ok(line) = line.parse::<u64>() {
+                let (addr, _) = self.inferior.target.find_line_address(Some(file_name), line).with_context(|| format!("No line {} in file \"{}\".", line, file_name))?;
+                return Ok(addr);
+            }
+        }
+        let addr = self.inferior.target.find_function_by_name(location).with_context(|| format!("Function \"{}\" not defined.", location))?;
+        // gdbと同じく関数のプロローグの後で止める
+        Ok(self.inferior.target.find_prologue_end(addr).unwrap_or(addr))
+    }
+
+    // 行番号だけの場所は今のファイルによるので, execした後も同じ所を指すようにファイル名を付ける
+    fn canonical_location(&self, location: &str, addr: u64) -> String {
+        let location = location.trim();
+        if location.parse::<u64>().is_ok() {
+            if let Some((file_name, line)) = self.inferior.target.find_source_line(addr) {
+                return format!("{}:{}", file_name, line);
+            }
+        }
+        location.to_owned()
+    }
+
+    fn read_source(&self, file_name: &str) -> Option<Vec<String>> {
+        let mut candidates: Vec<String> = vec![file_name.to_owned()];
+        if !Path::new(file_name).is_absolute() {
+            for module in self.inferior.target.get_modules().iter() {
+                if let Some(debug_info) = module.get_debug_info() {
+                    for comp_dir in debug_info.get_comp_dirs().iter() {
+                        candidates.push(format!("{}/{}", comp_dir, file_name));
+                    }
+                }
+            }
+        }
+        candidates.iter()
+            .find_map(|path| read_to_string(path).ok())
+            .map(|source| source.lines().map(|line| line.to_owned()).collect())
+    }
+}
+
+// 選択中のフレームで式を評価するためのコンテキスト
+struct FrameContext<'a> {
+    debugger: &'a Debugger,
+    frame: &'a Frame,
+    function: Option<(&'a Module, &'a Function)>,
+}
+
+impl<'a> FrameContext<'a> {
+    fn new(debugger: &'a Debugger, frame: &'a Frame) -> Self {
+        let function = debugger.inferior.target.find_function(frame.get_lookup_pc());
+        FrameContext { debugger, frame, function }
+    }
+
+    fn get_module(&self) -> Option<&'a Module> {
+        match self.function {
+            Some((module, _)) => Some(module),
+            None => self.debugger.inferior.target.find_module(self.frame.get_lookup_pc()),
+        }
+    }
+
+    fn format(&self, value: &Value) -> String {
+        let pid = self.debugger.inferior.pid;
+        value::format_value(self.get_debug_info(), value, &|addr, len| ptrace::read_bytes(pid, addr, len))
+    }
+
+    fn read_variable(&self, function: Option<&Function>, variable: &Variable) -> Result<Value> {
+        let module = self.get_module().context("No symbol table info available.")?;
+        let relative_pc = self.frame.get_lookup_pc().wrapping_sub(module.get_base());
+        let ty = match variable.get_type_offset() {
+            Some(type_offset) => ValueType::Dwarf(type_offset),
+            None => bail!("unknown type"),
+        };
+        let size = value::type_size(module.get_debug_info(), &ty) as usize;
+        let expr = match variable.get_location() {
+            Location::Expr(expr) => expr.clone(),
+            Location::LocList(entries) => {
+                match entries.iter().find(|(begin, end, _)| *begin <= relative_pc && relative_pc < *end) {
+                    Some((_, _, expr)) => expr.clone(),
+                    None => bail!("optimized out"),
+                }
+            },
+            Location::ConstValue(bytes) => return Ok(Value::new(ty, bytes.clone(), None)),
+            Location::None => bail!("optimized out"),
+        };
+        let expr_ctx = VariableExprContext {
+            pid: self.debugger.inferior.pid,
+            regs: self.frame.get_regs(),
+            frame_base: function.and_then(|function| function.get_frame_base()).cloned(),
+            cfa: self.frame.get_cfa(),
+            base: module.get_base(),
+        };
+        match dwarf_expr::evaluate(&expr, &expr_ctx, &[])? {
+            ExprResult::Address(addr) => {
+                let bytes = ptrace::read_bytes(self.debugger.inferior.pid, addr, size).with_context(|| format!("Cannot access memory at address 0x{:x}", addr))?;
+                Ok(Value::new(ty, bytes, Some(addr)))
+            },
+            ExprResult::Register(reg) => {
+                let raw = unwind::get_dwarf_reg(self.frame.get_regs(), reg).with_context(|| format!("unknown register {}", reg))?;
+                let mut bytes = raw.to_le_bytes().to_vec();
+                bytes.resize(size, 0);
+                Ok(Value::new(ty, bytes, None))
+            },
+            ExprResult::Value(raw) => {
+                let mut bytes = raw.to_le_bytes().to_vec();
+                bytes.resize(size, 0);
+                Ok(Value::new(ty, bytes, None))
+            },
+            ExprResult::ImplicitValue(bytes) => Ok(Value::new(ty, bytes, None)),
+        }
+    }
+}
+
+impl<'a> EvalContext for FrameContext<'a> {
+    fn get_debug_info(&self) -> Option<&DebugInfo> {
+        self.get_module().and_then(|module| module.get_debug_info())
+    }
+
+    fn lookup_variable(&self, name: &str) -> Result<Option<Value>> {
+        if let Some((module, function)) = self.function {
+            let relative_pc = self.frame.get_lookup_pc().wrapping_sub(module.get_base());
+            if let Some(variable) = function.get_visible_variables(relative_pc).into_iter().find(|variable| variable.get_name() == name) {
+                return self.read_variable(Some(function), variable).map(Some);
+            }
+        }
+        if let Some(module) = self.get_module() {
+            if let Some(variable) = module.get_debug_info().and_then(|debug_info| debug_info.find_global(name)) {
+                return self.read_variable(None, variable).map(Some);
+            }
+        }
+        // 関数名はその先頭アドレス
+        if let Some(addr) = self.debugger.inferior.target.find_function_by_name(name) {
+            return Ok(Some(Value::pointer(ValueType::Int { size: 1, signed: false }, addr)));
+        }
+        Ok(None)
+    }
+
+    fn read_register(&self, name: &str) -> Result<Option<Value>> {
+        // $1, $2 ... と $ は値の履歴
+        if name.is_empty() {
+            return Ok(self.debugger.value_history.last().cloned());
+        }
+        if let Ok(idx) = name.parse::<usize>() {
+            return match self.debugger.value_history.get(idx.wrapping_sub(1)) {
+                Some(value) => Ok(Some(value.clone())),
+                None => bail!("History has not yet reached ${}.", idx),
+            };
+        }
+        let (value, size) = match registers::read(self.frame.get_regs(), name) {
+            Some(register) => register,
+            // 浮動小数点・ベクトルレジスタはフレームごとに保存されないので, 今の値を読む
+            None if registers::is_fp_register(name) => return Ok(self.debugger.read_fp_registers()?.read_value(name)),
+            None => return Ok(None),
+        };
+        let ty = match name {
+            "pc" | "rip" | "sp" | "rsp" | "fp" | "rbp" => ValueType::Pointer(Box::new(ValueType::Int { size: 1, signed: false })),
+            _ => ValueType::Int { size, signed: true },
+        };
+        Ok(Some(Value::new(ty, value.to_le_bytes()[..size as usize].to_vec(), None)))
+    }
+
+    fn write_register(&self, name: &str, value: &Value) -> Result<bool> {
+        if registers::is_fp_register(name) {
+            bail!("Writing floating point and vector registers is not supported.");
+        }
+        if !registers::is_register(name) { return Ok(false); }
+        if self.debugger.inferior.selected_frame != 0 {
+            bail!("Cannot write registers of an outer frame.");
+        }
+        let value = match value::to_scalar(self.get_debug_info(), value)? {
+            Scalar::Int(value) => value as u64,
+            Scalar::Float(value) => value as i64 as u64,
+        };
+        let mut regs = ptrace::getregs(self.debugger.inferior.pid)?;
+        registers::write(&mut regs, name, value);
+        ptrace::setregs(self.debugger.inferior.pid, regs)?;
+        Ok(true)
+    }
+
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        ptrace::read_bytes(self.debugger.inferior.pid, addr, len)
+    }
+
+    fn write_memory(&self, addr: u64, bytes: &[u8]) -> Result<()> {
+        ptrace::write_bytes(self.debugger.inferior.pid, addr, bytes)
+    }
+
+    fn lookup_type(&self, name: &str) -> Option<ValueType> {
+        let debug_info = self.get_debug_info()?;
+        debug_info.find_type_by_name(name).map(ValueType::Dwarf)
+    }
+}
+
+struct VariableExprContext<'a> {
+    pid: Pid,
+    regs: &'a user_regs_struct,
+    frame_base: Option<Vec<u8>>,
+    cfa: Option<u64>,
+    base: u64,
+}
+
+impl<'a> ExprContext for VariableExprContext<'a> {
+    fn read_register(&self, dwarf_reg: u16) -> Result<u64> {
+        unwind::get_dwarf_reg(self.regs, dwarf_reg).with_context(|| format!("unknown DWARF register: {}", dwarf_reg))
+    }
+
+    fn read_memory(&self, addr: u64, size: u8) -> Result<u64> {
+        let bytes = ptrace::read_bytes(self.pid, addr, size as usize)?;
+        let mut array = [0u8; 8];
+        array[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+        Ok(u64::from_le_bytes(array))
+    }
+
+    fn get_frame_base(&self) -> Result<u64> {
+        let frame_base = self.frame_base.as_ref().context("frame base is unknown.")?;
+        match dwarf_expr::evaluate(frame_base, self, &[])? {
+            ExprResult::Register(reg) => self.read_register(reg),
+            ExprResult::Address(addr) | ExprResult::Value(addr) => Ok(addr),
+            ExprResult::ImplicitValue(_) => bail!("unsupported frame base."),
+        }
+    }
+
+    fn get_call_frame_cfa(&self) -> Result<u64> {
+        self.cfa.context("CFA is unknown.")
+    }
+
+    fn relocate_address(&self, addr: u64) -> u64 {
+        self.base.wrapping_add(addr)
+    }
+}
+
+// 止まっていたスレッドを再開する. グループストップしているものはSIGCONTが来るまで止めておく.
+// syscallsならシステムコールの入り口と出口でも止まる
+fn restart_thread(thread: &mut Thread, syscalls: bool) -> Result<()> {
+    let tid = thread.get_tid();
+    let result = if thread.take_group_stop() {
+        ptrace::listen(tid)
+    } else if syscalls {
+        ptrace::syscall(tid, thread.take_pending_signal())
+    } else {
+        // システムコールの出口では止まらないので, 次の停止は入り口から数え直す
+        thread.set_in_syscall(false);
+        ptrace::cont(tid, thread.take_pending_signal())
+    };
+    thread.set_running(true);
+    result
+}
+
+// インフェリアの実行ファイル. プロセスがあれば /proc/<pid>/exe から, 無ければ起動するプログラム
+fn executable_path(tgid: Pid, alive: bool, program: Option<&Vec<String>>) -> String {
+    if alive {
+        if let Ok(path) = read_link(format!("/proc/{}/exe", tgid)) { return path.display().to_string(); }
+    }
+    program.and_then(|program| program.first()).cloned().unwrap_or_default()
+}
@@ -0,0 +1,1177 @@
+// x86-64の命令デコーダ. gdbやobjdumpと同じAT&T記法で表示する
+
+// x86-64の命令は最大15バイト
+pub const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+const REG64: [&str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+const REG32: [&str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+const REG16: [&str; 16] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+const REG8_REX: [&str; 16] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"];
+const REG8_LEGACY: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+const SEGMENTS: [&str; 8] = ["es", "cs", "ss", "ds", "fs", "gs", "?", "?"];
+const CONDITIONS: [&str; 16] = ["o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g"];
+const GROUP1: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const GROUP2: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "shl", "sar"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstructionKind {
+    Call,
+    Jump,
+    ConditionalJump,
+    Return,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Reg(String),
+    Imm(u64),
+    // 分岐先の絶対アドレス
+    Target(u64),
+    Mem {
+        segment: Option<&'static str>,
+        base: Option<String>,
+        index: Option<(String, u8)>,
+        disp: i64,
+        // dispのバイトがあるか. 0でも "0x0(%rax)" のように表示する
+        explicit_disp: bool,
+        // RIP相対ならtrue. 実際のアドレスは命令の末尾 + disp
+        rip_relative: bool,
+    },
+    Str(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    addr: u64,
+    bytes: Vec<u8>,
+    prefix: String,
+    mnemonic: String,
+    // Intel記法の順 (書き込み先が先頭)
+    operands: Vec<Operand>,
+    indirect: bool,
+    kind: InstructionKind,
+}
+
+impl Instruction {
+    pub fn get_addr(&self) -> u64 { self.addr }
+    pub fn get_length(&self) -> usize { self.bytes.len() }
+
+    pub fn is_call(&self) -> bool { self.kind == InstructionKind::Call }
+
+    // RIP相対のメモリ参照があれば, その絶対アドレス
+    fn get_rip_relative_addr(&self) -> Option<u64> {
+        let end = self.addr + self.bytes.len() as u64;
+        self.operands.iter().find_map(|operand| match operand {
+            Operand::Mem { rip_relative: true, disp, .. } => Some(end.wrapping_add(*disp as u64)),
+            _ => None,
+        })
+    }
+
+    // symbolizeはアドレスを "<main::main2+4>" の中身に変換する
+    pub fn format(&self, symbolize: &dyn Fn(u64) -> Option<String>) -> String {
+        let mut operands: Vec<String> = self.operands.iter().rev().map(|operand| match operand {
+            Operand::Reg(name) => format!("%{}", name),
+            Operand::Imm(value) => format!("$0x{:x}", value),
+            Operand::Target(target) => match symbolize(*target) {
+                Some(sym) => format!("0x{:x} <{}>", target, sym),
+                None => format!("0x{:x}", target),
+            },
+            Operand::Mem { segment, base, index, disp, explicit_disp, rip_relative } => {
+                let mut s = String::new();
+                if let Some(segment) = segment { s.push_str(&format!("%{}:", segment)); }
+                let has_register = *rip_relative || base.is_some() || index.is_some();
+                if *disp < 0 && has_register {
+                    s.push_str(&format!("-0x{:x}", disp.unsigned_abs()));
+                } else if *disp != 0 || *explicit_disp || !has_register {
+                    s.push_str(&format!("0x{:x}", disp));
+                }
+                if has_register {
+                    s.push('(');
+                    if *rip_relative { s.push_str("%rip"); }
+                    if let Some(base) = base { s.push_str(&format!("%{}", base)); }
+                    if let Some((index, scale)) = index { s.push_str(&format!(",%{},{}", index, scale)); }
+                    s.push(')');
+                }
+                s
+            },
+            Operand::Str(s) => s.clone(),
+        }).collect();
+        if self.indirect {
+            if let Some(first) = operands.first_mut() { *first = format!("*{}", first); }
+        }
+        let mnemonic = format!("{}{}", self.prefix, self.mnemonic);
+        let mut text = if operands.is_empty() { mnemonic } else { format!("{:<6} {}", mnemonic, operands.join(",")) };
+        if let Some(addr) = self.get_rip_relative_addr() {
+            match symbolize(addr) {
+                Some(sym) => text.push_str(&format!("        # 0x{:x} <{}>", addr, sym)),
+                None => text.push_str(&format!("        # 0x{:x}", addr)),
+            }
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vex {
+    // 1: 0F, 2: 0F38, 3: 0F3A
+    map: u8,
+    // 0: なし, 1: 66, 2: F3, 3: F2
+    pp: u8,
+    l: bool,
+    w: bool,
+    vvvv: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModRm {
+    md: u8,
+    reg: u8,
+    rm: u8,
+}
+
+// オペランドの指定. Intelのマニュアルのopcode mapの記法に合わせている
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Spec {
+    // r/m (b: 8bit, v: オペランドサイズ, w: 16bit, d: 32bit, q: 64bit, y: 32/64bit(REX.W))
+    Eb, Ev, Ew, Ed, Eq, Ey,
+    // ModRMのreg
+    Gb, Gv, Gd, Gy,
+    // 即値 (bs: 8bitを符号拡張, z: 16/32bitを符号拡張, v: 64bitまで)
+    Ib, Ibs, Iw, Iz, Iv,
+    // 相対分岐
+    Jb, Jz,
+    // メモリのみ (lea)
+    M,
+    // opcodeの下位3bitのレジスタ
+    Zb, Zv, Zq,
+    Al, Rax, Cl, Dx,
+    // moffs
+    Ob, Ov,
+    Sw,
+    // xmm/ymm (V: reg, H: VEXのvvvv, W: r/m. dq/ss/sdはymmでもxmmのまま)
+    Vx, Hx, Wx, Wdq, Wss, Wsd,
+    // 汎用レジスタからxmmへ (movd/movq)
+    Ed64,
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    addr: u64,
+    opsize16: bool,
+    addrsize32: bool,
+    rep: bool,
+    repne: bool,
+    lock: bool,
+    segment: Option<&'static str>,
+    rex: u8,
+    // REXが付いていればspl/bpl/sil/dilになる (REX.WRXBが全部0でも)
+    rex_present: bool,
+    // 最後に読んだopcodeのバイト (0Fの後ろならその次のバイト)
+    opcode: u8,
+    // 2個目以降の66は "data16" として表示する
+    extra_opsize: usize,
+    vex: Option<Vex>,
+    modrm: Option<ModRm>,
+    // 読み終わったメモリオペランド. 同じr/mを2回参照する命令がある
+    memory_cache: Option<Operand>,
+    // オペランドサイズ(バイト). suffixを付けるかの判定にも使う
+    size: u8,
+}
+
+// 失敗したら "(bad)" の1バイト命令にする
+pub fn decode(bytes: &[u8], addr: u64) -> Instruction {
+    let bytes = &bytes[..bytes.len().min(MAX_INSTRUCTION_LENGTH)];
+    let mut decoder = Decoder {
+        bytes, pos: 0, addr,
+        opsize16: false, addrsize32: false, rep: false, repne: false, lock: false, segment: None,
+        rex: 0, vex: None, modrm: None, rex_present: false, opcode: 0, extra_opsize: 0, memory_cache: None, size: 4,
+    };
+    match decoder.decode() {
+        Some(instruction) => instruction,
+        None => Instruction {
+            addr,
+            bytes: bytes.iter().take(1).cloned().collect(),
+            prefix: "".to_owned(),
+            mnemonic: "(bad)".to_owned(),
+            operands: vec![],
+            indirect: false,
+            kind: InstructionKind::Other,
+        },
+    }
+}
+
+impl<'a> Decoder<'a> {
+    fn next(&mut self) -> Option<u8> {
+        let b = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn read_le(&mut self, len: usize) -> Option<u64> {
+        let mut value: u64 = 0;
+        for i in 0..len {
+            value |= (self.next()? as u64) << (i * 8);
+        }
+        Some(value)
+    }
+
+    fn read_signed(&mut self, len: usize) -> Option<i64> {
+        let value = self.read_le(len)?;
+        let shift = 64 - len * 8;
+        Some(((value << shift) as i64) >> shift)
+    }
+
+    fn rex_w(&self) -> bool { self.rex & 8 != 0 || self.vex.is_some_and(|vex| vex.w) }
+    fn rex_r(&self) -> u8 { (self.rex >> 2) & 1 }
+    fn rex_x(&self) -> u8 { (self.rex >> 1) & 1 }
+    fn rex_b(&self) -> u8 { self.rex & 1 }
+
+    fn modrm(&mut self) -> Option<ModRm> {
+        if let Some(modrm) = self.modrm { return Some(modrm); }
+        let b = self.next()?;
+        let modrm = ModRm { md: b >> 6, reg: (b >> 3) & 7, rm: b & 7 };
+        self.modrm = Some(modrm);
+        Some(modrm)
+    }
+
+    fn decode(&mut self) -> Option<Instruction> {
+        // レガシープレフィックス
+        loop {
+            match self.peek()? {
+                0x66 => {
+                    if self.opsize16 { self.extra_opsize += 1; }
+                    self.opsize16 = true;
+                },
+                0x67 => self.addrsize32 = true,
+                0xf3 => self.rep = true,
+                0xf2 => self.repne = true,
+                0xf0 => self.lock = true,
+                0x26 => self.segment = Some("es"),
+                0x2e => self.segment = Some("cs"),
+                0x36 => self.segment = Some("ss"),
+                0x3e => self.segment = Some("ds"),
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if let Some(b) = self.peek() {
+            if (0x40..=0x4f).contains(&b) {
+                self.rex = b & 0xf;
+                self.rex_present = true;
+                self.pos += 1;
+            }
+        }
+        let opcode = self.next()?;
+        let mut prefix = String::new();
+        if self.lock { prefix.push_str("lock "); }
+        prefix.push_str(&"data16 ".repeat(self.extra_opsize));
+        let (mnemonic, specs, kind) = match opcode {
+            0xc4 | 0xc5 if !self.rex_present => {
+                self.decode_vex(opcode)?;
+                self.opcode = self.next()?;
+                self.vex_opcode(self.opcode)?
+            },
+            0x0f => {
+                self.opcode = self.next()?;
+                self.two_byte(self.opcode)?
+            },
+            _ => {
+                self.opcode = opcode;
+                self.one_byte(opcode, &mut prefix)?
+            },
+        };
+        // REX.Wがあると66は意味が無い
+        if opcode != 0x0f && self.vex.is_none() && self.opsize16 && self.rex_w() {
+            prefix.push_str("data16 ");
+        }
+        let mut operands: Vec<Operand> = vec![];
+        let mut has_register = false;
+        let mut has_memory = false;
+        let mut has_vector = false;
+        for spec in specs.iter() {
+            let operand = self.operand(*spec)?;
+            match &operand {
+                // シフトの%clはサイズを決めないのでsuffixが要る
+                Operand::Reg(_) if *spec == Spec::Cl => {},
+                Operand::Reg(name) => {
+                    if name.starts_with("xmm") || name.starts_with("ymm") { has_vector = true; } else { has_register = true; }
+                },
+                Operand::Mem { .. } => has_memory = true,
+                _ => {},
+            }
+            operands.push(operand);
+        }
+        let bytes = self.bytes[..self.pos].to_vec();
+        let indirect = matches!(kind, InstructionKind::Call | InstructionKind::Jump) && self.modrm.is_some() && opcode == 0xff;
+        // CETのnotrack (間接分岐の3e)
+        if indirect && self.segment == Some("ds") {
+            prefix.push_str("notrack ");
+            self.segment = None;
+        }
+        let mut mnemonic = mnemonic;
+        // 64bitモードで無視されるセグメントはnopの前に付いているだけなので, objdumpと同じくプレフィックスとして表示する
+        if mnemonic == "nop" && matches!(self.segment, Some("cs") | Some("ds") | Some("es") | Some("ss")) {
+            prefix.push_str(&format!("{} ", self.segment.unwrap_or_default()));
+            if let Some(Operand::Mem { segment, .. }) = operands.first_mut() { *segment = None; }
+        }
+        let default_size = mnemonic.starts_with("set") || ((mnemonic == "push" || mnemonic == "pop") && self.size == 8);
+        let needs_suffix = has_memory && !has_register && !has_vector && !indirect
+            && !mnemonic.starts_with("lea") && !mnemonic.starts_with("prefetch")
+            && !mnemonic.contains(' ') && !default_size && self.vex.is_none() && !is_sse_mnemonic(&mnemonic);
+        if needs_suffix {
+            mnemonic.push(size_suffix(self.size));
+        }
+        Some(Instruction { addr: self.addr, bytes, prefix, mnemonic, operands, indirect, kind })
+    }
+
+    fn decode_vex(&mut self, first: u8) -> Option<()> {
+        let b1 = self.next()?;
+        let (r, x, b, map, w, vvvv, l, pp);
+        if first == 0xc5 {
+            r = (!b1 >> 7) & 1;
+            x = 0;
+            b = 0;
+            map = 1;
+            w = false;
+            vvvv = (!b1 >> 3) & 0xf;
+            l = b1 & 4 != 0;
+            pp = b1 & 3;
+        } else {
+            let b2 = self.next()?;
+            r = (!b1 >> 7) & 1;
+            x = (!b1 >> 6) & 1;
+            b = (!b1 >> 5) & 1;
+            map = b1 & 0x1f;
+            w = b2 & 0x80 != 0;
+            vvvv = (!b2 >> 3) & 0xf;
+            l = b2 & 4 != 0;
+            pp = b2 & 3;
+        }
+        self.rex = (r << 2) | (x << 1) | b;
+        self.vex = Some(Vex { map, pp, l, w, vvvv });
+        Some(())
+    }
+
+    fn op_size(&mut self) {
+        self.size = if self.rex_w() { 8 } else if self.opsize16 { 2 } else { 4 };
+    }
+
+    fn one_byte(&mut self, opcode: u8, prefix: &mut String) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        use Spec::*;
+        use InstructionKind::*;
+        self.op_size();
+        let simple = |mnemonic: &str, specs: &[Spec]| Some((mnemonic.to_owned(), specs.to_vec(), Other));
+        match opcode {
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let mnemonic = GROUP1[(opcode >> 3) as usize];
+                match opcode & 7 {
+                    0 => simple(mnemonic, &[Eb, Gb]),
+                    1 => simple(mnemonic, &[Ev, Gv]),
+                    2 => simple(mnemonic, &[Gb, Eb]),
+                    3 => simple(mnemonic, &[Gv, Ev]),
+                    4 => simple(mnemonic, &[Al, Ib]),
+                    _ => simple(mnemonic, &[Rax, Iz]),
+                }
+            },
+            0x50..=0x57 => { self.size = if self.opsize16 { 2 } else { 8 }; simple("push", &[Zq]) },
+            0x58..=0x5f => { self.size = if self.opsize16 { 2 } else { 8 }; simple("pop", &[Zq]) },
+            0x63 => {
+                let mnemonic = if self.rex_w() { "movslq" } else { "movsxd" };
+                simple(mnemonic, &[Gv, Ed])
+            },
+            0x68 => { self.size = 8; simple("push", &[Iz]) },
+            0x69 => simple("imul", &[Gv, Ev, Iz]),
+            0x6a => { self.size = 8; simple("push", &[Ibs]) },
+            0x6b => simple("imul", &[Gv, Ev, Ibs]),
+            0x70..=0x7f => Some((format!("j{}", CONDITIONS[(opcode & 0xf) as usize]), vec![Jb], ConditionalJump)),
+            0x80 => { let m = self.modrm()?; self.size = 1; simple(GROUP1[m.reg as usize], &[Eb, Ib]) },
+            0x81 => { let m = self.modrm()?; simple(GROUP1[m.reg as usize], &[Ev, Iz]) },
+            0x83 => { let m = self.modrm()?; simple(GROUP1[m.reg as usize], &[Ev, Ibs]) },
+            0x84 => simple("test", &[Eb, Gb]),
+            0x85 => simple("test", &[Ev, Gv]),
+            0x86 => simple("xchg", &[Eb, Gb]),
+            0x87 => simple("xchg", &[Ev, Gv]),
+            0x88 => simple("mov", &[Eb, Gb]),
+            0x89 => simple("mov", &[Ev, Gv]),
+            0x8a => simple("mov", &[Gb, Eb]),
+            0x8b => simple("mov", &[Gv, Ev]),
+            0x8c => simple("mov", &[Ev, Sw]),
+            0x8d => simple("lea", &[Gv, M]),
+            0x8e => simple("mov", &[Sw, Ew]),
+            0x8f => { self.size = 8; simple("pop", &[Eq]) },
+            0x90 if self.rex_b() == 0 && !self.opsize16 => if self.rep { simple("pause", &[]) } else { simple("nop", &[]) },
+            0x90..=0x97 => simple("xchg", &[Zv, Rax]),
+            0x98 => simple(if self.rex_w() { "cltq" } else if self.opsize16 { "cbtw" } else { "cwtl" }, &[]),
+            0x99 => simple(if self.rex_w() { "cqto" } else if self.opsize16 { "cwtd" } else { "cltd" }, &[]),
+            0x9b => simple("fwait", &[]),
+            0x9c => simple("pushf", &[]),
+            0x9d => simple("popf", &[]),
+            0x9e => simple("sahf", &[]),
+            0x9f => simple("lahf", &[]),
+            0xa0 => simple("movabs", &[Al, Ob]),
+            0xa1 => simple("movabs", &[Rax, Ov]),
+            0xa2 => simple("movabs", &[Ob, Al]),
+            0xa3 => simple("movabs", &[Ov, Rax]),
+            0xa4..=0xa7 | 0xaa..=0xaf => {
+                if opcode & 1 == 0 { self.size = 1; }
+                if self.rep { prefix.push_str(if opcode >= 0xa6 && opcode != 0xaa && opcode != 0xab && opcode != 0xac && opcode != 0xad { "repz " } else { "rep " }); }
+                if self.repne { prefix.push_str("repnz "); }
+                let reg = self.accumulator();
+                let (mnemonic, operands) = match opcode {
+                    0xa4 | 0xa5 => (format!("movs{}", size_suffix(self.size)), "%ds:(%rsi),%es:(%rdi)".to_owned()),
+                    0xa6 | 0xa7 => (format!("cmps{}", size_suffix(self.size)), "%es:(%rdi),%ds:(%rsi)".to_owned()),
+                    0xaa | 0xab => ("stos".to_owned(), format!("%{},%es:(%rdi)", reg)),
+                    0xac | 0xad => ("lods".to_owned(), format!("%ds:(%rsi),%{}", reg)),
+                    _ => ("scas".to_owned(), format!("%es:(%rdi),%{}", reg)),
+                };
+                // objdumpはプレフィックスも含めて6桁に揃える
+                let width = 6usize.saturating_sub(prefix.len());
+                Some((format!("{:<width$} {}", mnemonic, operands, width = width), vec![], Other))
+            },
+            0xa8 => simple("test", &[Al, Ib]),
+            0xa9 => simple("test", &[Rax, Iz]),
+            0xb0..=0xb7 => simple("mov", &[Zb, Ib]),
+            0xb8..=0xbf => simple(if self.rex_w() { "movabs" } else { "mov" }, &[Zv, Iv]),
+            0xc0 => { let m = self.modrm()?; self.size = 1; simple(GROUP2[m.reg as usize], &[Eb, Ib]) },
+            0xc1 => { let m = self.modrm()?; simple(GROUP2[m.reg as usize], &[Ev, Ib]) },
+            0xc2 => Some(("ret".to_owned(), vec![Iw], Return)),
+            0xc3 => {
+                if self.rep { prefix.push_str("repz "); }
+                Some(("ret".to_owned(), vec![], Return))
+            },
+            0xc6 if self.peek() == Some(0xf8) => { self.pos += 1; simple("xabort", &[Ib]) },
+            0xc7 if self.peek() == Some(0xf8) => { self.pos += 1; Some(("xbegin".to_owned(), vec![Jz], ConditionalJump)) },
+            0xc6 => { let m = self.modrm()?; if m.reg != 0 { return None; } self.size = 1; simple("mov", &[Eb, Ib]) },
+            0xc7 => { let m = self.modrm()?; if m.reg != 0 { return None; } simple("mov", &[Ev, Iz]) },
+            0xc8 => simple("enter", &[Iw, Ib]),
+            0xc9 => simple("leave", &[]),
+            0xca => Some(("lret".to_owned(), vec![Iw], Return)),
+            0xcb => Some(("lret".to_owned(), vec![], Return)),
+            0xcc => simple("int3", &[]),
+            0xcd => simple("int", &[Ib]),
+            0xcf => Some((if self.rex_w() { "iretq" } else { "iret" }.to_owned(), vec![], Return)),
+            0xd0 => { let m = self.modrm()?; self.size = 1; simple(GROUP2[m.reg as usize], &[Eb]) },
+            0xd1 => { let m = self.modrm()?; simple(GROUP2[m.reg as usize], &[Ev]) },
+            0xd2 => { let m = self.modrm()?; self.size = 1; simple(GROUP2[m.reg as usize], &[Eb, Cl]) },
+            0xd3 => { let m = self.modrm()?; simple(GROUP2[m.reg as usize], &[Ev, Cl]) },
+            0xd8..=0xdf => self.x87(opcode),
+            0xe0 => Some(("loopne".to_owned(), vec![Jb], ConditionalJump)),
+            0xe1 => Some(("loope".to_owned(), vec![Jb], ConditionalJump)),
+            0xe2 => Some(("loop".to_owned(), vec![Jb], ConditionalJump)),
+            0xe3 => Some(("jrcxz".to_owned(), vec![Jb], ConditionalJump)),
+            0xe4 => simple("in", &[Al, Ib]),
+            0xe5 => simple("in", &[Rax, Ib]),
+            0xe6 => simple("out", &[Ib, Al]),
+            0xe7 => simple("out", &[Ib, Rax]),
+            0xe8 => Some(("call".to_owned(), vec![Jz], Call)),
+            0xe9 => Some(("jmp".to_owned(), vec![Jz], Jump)),
+            0xeb => Some(("jmp".to_owned(), vec![Jb], Jump)),
+            0xec => simple("in", &[Al, Dx]),
+            0xed => simple("in", &[Rax, Dx]),
+            0xee => simple("out", &[Dx, Al]),
+            0xef => simple("out", &[Dx, Rax]),
+            0xf1 => simple("int1", &[]),
+            0xf4 => simple("hlt", &[]),
+            0xf5 => simple("cmc", &[]),
+            0xf6 | 0xf7 => {
+                let m = self.modrm()?;
+                if opcode == 0xf6 { self.size = 1; }
+                let e = if opcode == 0xf6 { Eb } else { Ev };
+                let i = if opcode == 0xf6 { Ib } else { Iz };
+                match m.reg {
+                    0 | 1 => simple("test", &[e, i]),
+                    2 => simple("not", &[e]),
+                    3 => simple("neg", &[e]),
+                    4 => simple("mul", &[e]),
+                    5 => simple("imul", &[e]),
+                    6 => simple("div", &[e]),
+                    _ => simple("idiv", &[e]),
+                }
+            },
+            0xf8 => simple("clc", &[]),
+            0xf9 => simple("stc", &[]),
+            0xfa => simple("cli", &[]),
+            0xfb => simple("sti", &[]),
+            0xfc => simple("cld", &[]),
+            0xfd => simple("std", &[]),
+            0xfe => {
+                let m = self.modrm()?;
+                self.size = 1;
+                match m.reg {
+                    0 => simple("inc", &[Eb]),
+                    1 => simple("dec", &[Eb]),
+                    _ => None,
+                }
+            },
+            0xff => {
+                let m = self.modrm()?;
+                match m.reg {
+                    0 => simple("inc", &[Ev]),
+                    1 => simple("dec", &[Ev]),
+                    2 => { self.size = 8; Some(("call".to_owned(), vec![Eq], Call)) },
+                    3 => Some(("lcall".to_owned(), vec![M], Call)),
+                    4 => { self.size = 8; Some(("jmp".to_owned(), vec![Eq], Jump)) },
+                    5 => Some(("ljmp".to_owned(), vec![M], Jump)),
+                    6 => { self.size = if self.opsize16 { 2 } else { 8 }; simple("push", &[Ev]) },
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn accumulator(&self) -> &'static str {
+        match self.size {
+            1 => "al",
+            2 => "ax",
+            8 => "rax",
+            _ => "eax",
+        }
+    }
+
+    fn x87(&mut self, opcode: u8) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        let m = self.modrm()?;
+        let mnemonic = if m.md != 3 {
+            let name = match (opcode, m.reg) {
+                (0xd8, r) => ["fadds", "fmuls", "fcoms", "fcomps", "fsubs", "fsubrs", "fdivs", "fdivrs"][r as usize],
+                (0xd9, 0) => "flds",
+                (0xd9, 2) => "fsts",
+                (0xd9, 3) => "fstps",
+                (0xd9, 4) => "fldenv",
+                (0xd9, 5) => "fldcw",
+                (0xd9, 6) => "fnstenv",
+                (0xd9, 7) => "fnstcw",
+                (0xda, r) => ["fiaddl", "fimull", "ficoml", "ficompl", "fisubl", "fisubrl", "fidivl", "fidivrl"][r as usize],
+                (0xdb, 0) => "fildl",
+                (0xdb, 1) => "fisttpl",
+                (0xdb, 2) => "fistl",
+                (0xdb, 3) => "fistpl",
+                (0xdb, 5) => "fldt",
+                (0xdb, 7) => "fstpt",
+                (0xdc, r) => ["faddl", "fmull", "fcoml", "fcompl", "fsubl", "fsubrl", "fdivl", "fdivrl"][r as usize],
+                (0xdd, 0) => "fldl",
+                (0xdd, 1) => "fisttpll",
+                (0xdd, 2) => "fstl",
+                (0xdd, 3) => "fstpl",
+                (0xdd, 4) => "frstor",
+                (0xdd, 6) => "fnsave",
+                (0xdd, 7) => "fnstsw",
+                (0xde, r) => ["fiadds", "fimuls", "ficoms", "ficomps", "fisubs", "fisubrs", "fidivs", "fidivrs"][r as usize],
+                (0xdf, 0) => "filds",
+                (0xdf, 1) => "fisttps",
+                (0xdf, 2) => "fists",
+                (0xdf, 3) => "fistps",
+                (0xdf, 5) => "fildll",
+                (0xdf, 7) => "fistpll",
+                _ => return None,
+            };
+            // x87はsuffixを名前に含めている
+            return Some((name.to_owned(), vec![Spec::M], InstructionKind::Other));
+        } else {
+            let st = format!("%st({})", m.rm);
+            match (opcode, m.reg) {
+                (0xd8, r) => format!("{:<6} {},%st", ["fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr"][r as usize], st),
+                (0xd9, 0) => format!("{:<6} {}", "fld", st),
+                (0xd9, 1) => format!("{:<6} {}", "fxch", st),
+                (0xd9, _) => match m.rm | (m.reg << 3) {
+                    0x10 => "fnop".to_owned(),
+                    0x20 => "fchs".to_owned(),
+                    0x21 => "fabs".to_owned(),
+                    0x24 => "ftst".to_owned(),
+                    0x25 => "fxam".to_owned(),
+                    0x28 => "fld1".to_owned(),
+                    0x29 => "fldl2t".to_owned(),
+                    0x2a => "fldl2e".to_owned(),
+                    0x2b => "fldpi".to_owned(),
+                    0x2c => "fldlg2".to_owned(),
+                    0x2d => "fldln2".to_owned(),
+                    0x2e => "fldz".to_owned(),
+                    0x30 => "f2xm1".to_owned(),
+                    0x31 => "fyl2x".to_owned(),
+                    0x32 => "fptan".to_owned(),
+                    0x33 => "fpatan".to_owned(),
+                    0x34 => "fxtract".to_owned(),
+                    0x35 => "fprem1".to_owned(),
+                    0x36 => "fdecstp".to_owned(),
+                    0x37 => "fincstp".to_owned(),
+                    0x38 => "fprem".to_owned(),
+                    0x39 => "fyl2xp1".to_owned(),
+                    0x3a => "fsqrt".to_owned(),
+                    0x3b => "fsincos".to_owned(),
+                    0x3c => "frndint".to_owned(),
+                    0x3d => "fscale".to_owned(),
+                    0x3e => "fsin".to_owned(),
+                    0x3f => "fcos".to_owned(),
+                    _ => return None,
+                },
+                (0xda, r) if r < 4 => format!("{:<6} {},%st", ["fcmovb", "fcmove", "fcmovbe", "fcmovu"][r as usize], st),
+                (0xda, 5) if m.rm == 1 => "fucompp".to_owned(),
+                (0xdb, r) if r < 4 => format!("{:<6} {},%st", ["fcmovnb", "fcmovne", "fcmovnbe", "fcmovnu"][r as usize], st),
+                (0xdb, 4) if m.rm == 2 => "fnclex".to_owned(),
+                (0xdb, 4) if m.rm == 3 => "fninit".to_owned(),
+                (0xdb, 5) => format!("{:<6} {},%st", "fucomi", st),
+                (0xdb, 6) => format!("{:<6} {},%st", "fcomi", st),
+                (0xdc, r) => format!("{:<6} %st,{}", ["fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr"][r as usize], st),
+                (0xdd, 0) => format!("{:<6} {}", "ffree", st),
+                (0xdd, 2) => format!("{:<6} {}", "fst", st),
+                (0xdd, 3) => format!("{:<6} {}", "fstp", st),
+                (0xdd, 4) => format!("{:<6} {}", "fucom", st),
+                (0xdd, 5) => format!("{:<6} {}", "fucomp", st),
+                (0xde, 3) if m.rm == 1 => "fcompp".to_owned(),
+                (0xde, r) => format!("{:<6} %st,{}", ["faddp", "fmulp", "?", "?", "fsubp", "fsubrp", "fdivp", "fdivrp"][r as usize], st),
+                (0xdf, 4) if m.rm == 0 => format!("{:<6} %ax", "fnstsw"),
+                (0xdf, 5) => format!("{:<6} {},%st", "fucomip", st),
+                (0xdf, 6) => format!("{:<6} {},%st", "fcomip", st),
+                _ => return None,
+            }
+        };
+        Some((mnemonic, vec![], InstructionKind::Other))
+    }
+
+    // SSEの命令はプレフィックスで種類が変わる (なし, 66, F3, F2)
+    fn sse_variant(&self) -> usize {
+        if self.rep { 2 } else if self.repne { 3 } else if self.opsize16 { 1 } else { 0 }
+    }
+
+    fn two_byte(&mut self, opcode: u8) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        use Spec::*;
+        use InstructionKind::*;
+        self.op_size();
+        let simple = |mnemonic: &str, specs: &[Spec]| Some((mnemonic.to_owned(), specs.to_vec(), Other));
+        let variant = self.sse_variant();
+        // ps/pd/ss/sd の4種類がある算術命令
+        let arith = |name: &str| -> Option<(String, Vec<Spec>, InstructionKind)> {
+            let suffix = ["ps", "pd", "ss", "sd"][variant];
+            let w = match variant { 2 => Wss, 3 => Wsd, _ => Wx };
+            Some((format!("{}{}", name, suffix), vec![Vx, w], Other))
+        };
+        match opcode {
+            0x01 => {
+                let name = match self.next()? {
+                    0xd0 => "xgetbv",
+                    0xd5 => "xend",
+                    0xd6 => "xtest",
+                    0xee => "rdpkru",
+                    0xef => "wrpkru",
+                    0xf9 => "rdtscp",
+                    _ => return None,
+                };
+                simple(name, &[])
+            },
+            0x05 => simple("syscall", &[]),
+            0x0b => simple("ud2", &[]),
+            0x0d => { self.modrm()?; simple("prefetchw", &[M]) },
+            0x10 | 0x11 => {
+                let name = ["movups", "movupd", "movss", "movsd"][variant];
+                let w = match variant { 2 => Wss, 3 => Wsd, _ => Wx };
+                if opcode == 0x10 { simple(name, &[Vx, w]) } else { simple(name, &[w, Vx]) }
+            },
+            0x12 if variant == 0 && self.modrm()?.md == 3 => simple("movhlps", &[Vx, Wx]),
+            0x16 if variant == 0 && self.modrm()?.md == 3 => simple("movlhps", &[Vx, Wx]),
+            0x12 | 0x13 => {
+                let name = if variant == 1 { "movlpd" } else { "movlps" };
+                if opcode == 0x12 { simple(name, &[Vx, Wsd]) } else { simple(name, &[Wsd, Vx]) }
+            },
+            0x14 => simple(if variant == 1 { "unpcklpd" } else { "unpcklps" }, &[Vx, Wx]),
+            0x15 => simple(if variant == 1 { "unpckhpd" } else { "unpckhps" }, &[Vx, Wx]),
+            0x16 | 0x17 => {
+                let name = if variant == 1 { "movhpd" } else { "movhps" };
+                if opcode == 0x16 { simple(name, &[Vx, Wsd]) } else { simple(name, &[Wsd, Vx]) }
+            },
+            0x18 => {
+                let m = self.modrm()?;
+                let name = ["prefetchnta", "prefetcht0", "prefetcht1", "prefetcht2"].get(m.reg as usize).cloned().unwrap_or("nop");
+                simple(name, &[M])
+            },
+            0x1e if self.rep && self.peek() == Some(0xfa) => { self.pos += 1; simple("endbr64", &[]) },
+            0x1e if self.rep && self.peek() == Some(0xfb) => { self.pos += 1; simple("endbr32", &[]) },
+            0x19..=0x1f => simple("nop", &[Ev]),
+            0x28 => simple(if variant == 1 { "movapd" } else { "movaps" }, &[Vx, Wx]),
+            0x29 => simple(if variant == 1 { "movapd" } else { "movaps" }, &[Wx, Vx]),
+            0x2a if variant >= 2 => {
+                // メモリから読む時だけ元のサイズを付ける
+                let suffix = if self.modrm()?.md == 3 { "" } else if self.rex_w() { "q" } else { "l" };
+                simple(&format!("{}{}", if variant == 2 { "cvtsi2ss" } else { "cvtsi2sd" }, suffix), &[Vx, Ey])
+            },
+            0x2b => simple(if variant == 1 { "movntpd" } else { "movntps" }, &[Wx, Vx]),
+            0x2c => match variant { 2 => simple("cvttss2si", &[Gy, Wss]), 3 => simple("cvttsd2si", &[Gy, Wsd]), _ => None },
+            0x2d => match variant { 2 => simple("cvtss2si", &[Gy, Wss]), 3 => simple("cvtsd2si", &[Gy, Wsd]), _ => None },
+            0x2e => simple(if variant == 1 { "ucomisd" } else { "ucomiss" }, &[Vx, if variant == 1 { Wsd } else { Wss }]),
+            0x2f => simple(if variant == 1 { "comisd" } else { "comiss" }, &[Vx, if variant == 1 { Wsd } else { Wss }]),
+            0x31 => simple("rdtsc", &[]),
+            0x38 => {
+                let opcode = self.next()?;
+                self.three_byte_38(opcode)
+            },
+            0x3a => {
+                let opcode = self.next()?;
+                self.three_byte_3a(opcode)
+            },
+            0x40..=0x4f => simple(&format!("cmov{}", CONDITIONS[(opcode & 0xf) as usize]), &[Gv, Ev]),
+            0x50 => simple(if variant == 1 { "movmskpd" } else { "movmskps" }, &[Gd, Wx]),
+            0x51 => arith("sqrt"),
+            0x54 => simple(if variant == 1 { "andpd" } else { "andps" }, &[Vx, Wx]),
+            0x55 => simple(if variant == 1 { "andnpd" } else { "andnps" }, &[Vx, Wx]),
+            0x56 => simple(if variant == 1 { "orpd" } else { "orps" }, &[Vx, Wx]),
+            0x57 => simple(if variant == 1 { "xorpd" } else { "xorps" }, &[Vx, Wx]),
+            0x58 => arith("add"),
+            0x59 => arith("mul"),
+            0x5a => {
+                let name = ["cvtps2pd", "cvtpd2ps", "cvtss2sd", "cvtsd2ss"][variant];
+                simple(name, &[Vx, match variant { 2 => Wss, 3 => Wsd, _ => Wx }])
+            },
+            0x5b => simple(["cvtdq2ps", "cvtps2dq", "cvttps2dq", "(bad)"][variant], &[Vx, Wx]),
+            0x5c => arith("sub"),
+            0x5d => arith("min"),
+            0x5e => arith("div"),
+            0x5f => arith("max"),
+            0x60..=0x6d if variant == 1 => {
+                let names = ["punpcklbw", "punpcklwd", "punpckldq", "packsswb", "pcmpgtb", "pcmpgtw", "pcmpgtd", "packuswb",
+                    "punpckhbw", "punpckhwd", "punpckhdq", "packssdw", "punpcklqdq", "punpckhqdq"];
+                simple(names[(opcode - 0x60) as usize], &[Vx, Wx])
+            },
+            0x6e if variant == 1 => simple(if self.rex_w() { "movq" } else { "movd" }, &[Vx, Ed64]),
+            0x6f => match variant { 1 => simple("movdqa", &[Vx, Wx]), 2 => simple("movdqu", &[Vx, Wx]), _ => None },
+            0x70 => simple(["pshufw", "pshufd", "pshufhw", "pshuflw"][variant], &[Vx, Wx, Ib]),
+            0x71..=0x73 if variant == 1 => {
+                let m = self.modrm()?;
+                let names: [&str; 8] = match opcode {
+                    0x71 => ["?", "?", "psrlw", "?", "psraw", "?", "psllw", "?"],
+                    0x72 => ["?", "?", "psrld", "?", "psrad", "?", "pslld", "?"],
+                    _ => ["?", "?", "psrlq", "psrldq", "?", "?", "psllq", "pslldq"],
+                };
+                if names[m.reg as usize] == "?" { return None; }
+                simple(names[m.reg as usize], &[Wx, Ib])
+            },
+            0x74 if variant == 1 => simple("pcmpeqb", &[Vx, Wx]),
+            0x75 if variant == 1 => simple("pcmpeqw", &[Vx, Wx]),
+            0x76 if variant == 1 => simple("pcmpeqd", &[Vx, Wx]),
+            0x7e => match variant {
+                1 => simple(if self.rex_w() { "movq" } else { "movd" }, &[Ed64, Vx]),
+                2 => simple("movq", &[Vx, Wsd]),
+                _ => None,
+            },
+            0x7f => match variant { 1 => simple("movdqa", &[Wx, Vx]), 2 => simple("movdqu", &[Wx, Vx]), _ => None },
+            0x80..=0x8f => Some((format!("j{}", CONDITIONS[(opcode & 0xf) as usize]), vec![Jz], ConditionalJump)),
+            0x90..=0x9f => { self.modrm()?; self.size = 1; simple(&format!("set{}", CONDITIONS[(opcode & 0xf) as usize]), &[Eb]) },
+            0xa2 => simple("cpuid", &[]),
+            0xa3 => simple("bt", &[Ev, Gv]),
+            0xa4 => simple("shld", &[Ev, Gv, Ib]),
+            0xa5 => simple("shld", &[Ev, Gv, Cl]),
+            0xab => simple("bts", &[Ev, Gv]),
+            0xac => simple("shrd", &[Ev, Gv, Ib]),
+            0xad => simple("shrd", &[Ev, Gv, Cl]),
+            0xae => {
+                let m = self.modrm()?;
+                if m.md == 3 {
+                    match m.reg {
+                        5 => simple("lfence", &[]),
+                        6 => simple("mfence", &[]),
+                        7 => simple("sfence", &[]),
+                        _ => None,
+                    }
+                } else {
+                    let name = ["fxsave", "fxrstor", "ldmxcsr", "stmxcsr", "xsave", "xrstor", "xsaveopt", "clflush"][m.reg as usize];
+                    simple(name, &[M])
+                }
+            },
+            0xaf => simple("imul", &[Gv, Ev]),
+            0xb0 => { self.size = 1; simple("cmpxchg", &[Eb, Gb]) },
+            0xb1 => simple("cmpxchg", &[Ev, Gv]),
+            0xb3 => simple("btr", &[Ev, Gv]),
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let from = if opcode & 1 == 0 { 'b' } else { 'w' };
+                let kind = if opcode < 0xb8 { "movz" } else { "movs" };
+                let name = format!("{}{}{}", kind, from, size_suffix(self.size));
+                simple(&name, &[Gv, if from == 'b' { Eb } else { Ew }])
+            },
+            0xb8 if self.rep => simple("popcnt", &[Gv, Ev]),
+            0xba => {
+                let m = self.modrm()?;
+                let name = match m.reg { 4 => "bt", 5 => "bts", 6 => "btr", 7 => "btc", _ => return None };
+                simple(name, &[Ev, Ib])
+            },
+            0xbb => simple("btc", &[Ev, Gv]),
+            0xbc => simple(if self.rep { "tzcnt" } else { "bsf" }, &[Gv, Ev]),
+            0xbd => simple(if self.rep { "lzcnt" } else { "bsr" }, &[Gv, Ev]),
+            0xc0 => { self.size = 1; simple("xadd", &[Eb, Gb]) },
+            0xc1 => simple("xadd", &[Ev, Gv]),
+            0xc2 => {
+                let suffix = ["ps", "pd", "ss", "sd"][variant];
+                simple(&format!("cmp{}", suffix), &[Vx, Wx, Ib])
+            },
+            0xc6 => simple(if variant == 1 { "shufpd" } else { "shufps" }, &[Vx, Wx, Ib]),
+            0xc7 => {
+                let m = self.modrm()?;
+                match (m.md, m.reg) {
+                    (md, 1) if md != 3 => simple(if self.rex_w() { "cmpxchg16b" } else { "cmpxchg8b" }, &[M]),
+                    (3, 6) => simple("rdrand", &[Ev]),
+                    (3, 7) => simple("rdseed", &[Ev]),
+                    _ => None,
+                }
+            },
+            0xc8..=0xcf => simple("bswap", &[Zv]),
+            0xc4 if variant == 1 => simple("pinsrw", &[Vx, Ed, Ib]),
+            0xc5 if variant == 1 => simple("pextrw", &[Gd, Wx, Ib]),
+            0xd6 if variant == 1 => simple("movq", &[Wsd, Vx]),
+            0xd7 if variant == 1 => simple("pmovmskb", &[Gd, Wx]),
+            0xd0..=0xff if variant == 1 => {
+                let name = match opcode {
+                    0xd1 => "psrlw", 0xd2 => "psrld", 0xd3 => "psrlq", 0xd4 => "paddq", 0xd5 => "pmullw",
+                    0xd8 => "psubusb", 0xd9 => "psubusw", 0xda => "pminub", 0xdb => "pand",
+                    0xdc => "paddusb", 0xdd => "paddusw", 0xde => "pmaxub", 0xdf => "pandn",
+                    0xe0 => "pavgb", 0xe1 => "psraw", 0xe2 => "psrad", 0xe3 => "pavgw", 0xe4 => "pmulhuw", 0xe5 => "pmulhw",
+                    0xe7 => "movntdq", 0xe8 => "psubsb", 0xe9 => "psubsw", 0xea => "pminsw", 0xeb => "por",
+                    0xec => "paddsb", 0xed => "paddsw", 0xee => "pmaxsw", 0xef => "pxor",
+                    0xf1 => "psllw", 0xf2 => "pslld", 0xf3 => "psllq", 0xf4 => "pmuludq", 0xf5 => "pmaddwd", 0xf6 => "psadbw",
+                    0xf8 => "psubb", 0xf9 => "psubw", 0xfa => "psubd", 0xfb => "psubq",
+                    0xfc => "paddb", 0xfd => "paddw", 0xfe => "paddd",
+                    _ => return None,
+                };
+                if opcode == 0xe7 { simple(name, &[Wx, Vx]) } else { simple(name, &[Vx, Wx]) }
+            },
+            _ => None,
+        }
+    }
+
+    fn three_byte_38(&mut self, opcode: u8) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        use Spec::*;
+        let name = match opcode {
+            0x00 => "pshufb",
+            0x17 => "ptest",
+            0x29 => "pcmpeqq",
+            0x37 => "pcmpgtq",
+            0x38 => "pminsb",
+            0x3a => "pminuw",
+            0x3b => "pminud",
+            0x3c => "pmaxsb",
+            0x3e => "pmaxuw",
+            0x3f => "pmaxud",
+            0xf0 | 0xf1 if self.repne => {
+                self.op_size();
+                if opcode == 0xf0 { self.size = 1; }
+                let name = format!("crc32{}", size_suffix(self.size));
+                return Some((name, vec![Gy, if opcode == 0xf0 { Eb } else { Ev }], InstructionKind::Other));
+            },
+            0xf0 => return Some(("movbe".to_owned(), vec![Gv, Ev], InstructionKind::Other)),
+            0xf1 => return Some(("movbe".to_owned(), vec![Ev, Gv], InstructionKind::Other)),
+            _ => return None,
+        };
+        if !self.opsize16 { return None; }
+        Some((name.to_owned(), vec![Vx, Wx], InstructionKind::Other))
+    }
+
+    fn three_byte_3a(&mut self, opcode: u8) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        use Spec::*;
+        if !self.opsize16 { return None; }
+        let (name, specs): (&str, Vec<Spec>) = match opcode {
+            0x0f => ("palignr", vec![Vx, Wx, Ib]),
+            0x16 => (if self.rex_w() { "pextrq" } else { "pextrd" }, vec![Ey, Vx, Ib]),
+            0x22 => (if self.rex_w() { "pinsrq" } else { "pinsrd" }, vec![Vx, Ey, Ib]),
+            0x63 => ("pcmpistri", vec![Vx, Wx, Ib]),
+            _ => return None,
+        };
+        Some((name.to_owned(), specs, InstructionKind::Other))
+    }
+
+    // VEXの命令. よく使うAVX/AVX2/BMIのものだけ
+    fn vex_opcode(&mut self, opcode: u8) -> Option<(String, Vec<Spec>, InstructionKind)> {
+        use Spec::*;
+        let vex = self.vex?;
+        self.size = if vex.w { 8 } else { 4 };
+        let simple = |mnemonic: &str, specs: &[Spec]| Some((mnemonic.to_owned(), specs.to_vec(), InstructionKind::Other));
+        let variant = vex.pp as usize;
+        match (vex.map, opcode) {
+            (1, 0x77) => simple(if vex.l { "vzeroall" } else { "vzeroupper" }, &[]),
+            (1, 0x10) | (1, 0x11) => {
+                let name = ["vmovups", "vmovupd", "vmovss", "vmovsd"][variant];
+                let w = match variant { 2 => Wss, 3 => Wsd, _ => Wx };
+                // レジスタ同士のmovss/movsdは3オペランド
+                let reg_form = variant >= 2 && self.modrm()?.md == 3;
+                match (opcode, reg_form) {
+                    (0x10, true) => simple(name, &[Vx, Hx, w]),
+                    (0x10, false) => simple(name, &[Vx, w]),
+                    (_, true) => simple(name, &[w, Hx, Vx]),
+                    (_, false) => simple(name, &[w, Vx]),
+                }
+            },
+            (1, 0x28) => simple(if variant == 1 { "vmovapd" } else { "vmovaps" }, &[Vx, Wx]),
+            (1, 0x29) => simple(if variant == 1 { "vmovapd" } else { "vmovaps" }, &[Wx, Vx]),
+            (1, 0x2e) => simple(if variant == 1 { "vucomisd" } else { "vucomiss" }, &[Vx, if variant == 1 { Wsd } else { Wss }]),
+            (1, 0x2f) => simple(if variant == 1 { "vcomisd" } else { "vcomiss" }, &[Vx, if variant == 1 { Wsd } else { Wss }]),
+            (1, 0x51) | (1, 0x58) | (1, 0x59) | (1, 0x5c) | (1, 0x5d) | (1, 0x5e) | (1, 0x5f) => {
+                let base = match opcode { 0x51 => "vsqrt", 0x58 => "vadd", 0x59 => "vmul", 0x5c => "vsub", 0x5d => "vmin", 0x5e => "vdiv", _ => "vmax" };
+                let w = match variant { 2 => Wss, 3 => Wsd, _ => Wx };
+                simple(&format!("{}{}", base, ["ps", "pd", "ss", "sd"][variant]), &[Vx, Hx, w])
+            },
+            (1, 0x54) => simple(if variant == 1 { "vandpd" } else { "vandps" }, &[Vx, Hx, Wx]),
+            (1, 0x55) => simple(if variant == 1 { "vandnpd" } else { "vandnps" }, &[Vx, Hx, Wx]),
+            (1, 0x56) => simple(if variant == 1 { "vorpd" } else { "vorps" }, &[Vx, Hx, Wx]),
+            (1, 0x57) => simple(if variant == 1 { "vxorpd" } else { "vxorps" }, &[Vx, Hx, Wx]),
+            (1, 0x6e) if variant == 1 => simple(if vex.w { "vmovq" } else { "vmovd" }, &[Vx, Ed64]),
+            (1, 0x6f) => match variant { 1 => simple("vmovdqa", &[Vx, Wx]), 2 => simple("vmovdqu", &[Vx, Wx]), _ => None },
+            (1, 0x7e) => match variant {
+                1 => simple(if vex.w { "vmovq" } else { "vmovd" }, &[Ed64, Vx]),
+                2 => simple("vmovq", &[Vx, Wsd]),
+                _ => None,
+            },
+            (1, 0x7f) => match variant { 1 => simple("vmovdqa", &[Wx, Vx]), 2 => simple("vmovdqu", &[Wx, Vx]), _ => None },
+            (1, 0x70) if variant == 1 => simple("vpshufd", &[Vx, Wx, Ib]),
+            (1, 0x64) if variant == 1 => simple("vpcmpgtb", &[Vx, Hx, Wx]),
+            (1, 0xd6) if variant == 1 => simple("vmovq", &[Wsd, Vx]),
+            (1, 0x74) if variant == 1 => simple("vpcmpeqb", &[Vx, Hx, Wx]),
+            (1, 0x75) if variant == 1 => simple("vpcmpeqw", &[Vx, Hx, Wx]),
+            (1, 0x76) if variant == 1 => simple("vpcmpeqd", &[Vx, Hx, Wx]),
+            (1, 0xd7) if variant == 1 => simple("vpmovmskb", &[Gd, Wx]),
+            (1, 0xda) if variant == 1 => simple("vpminub", &[Vx, Hx, Wx]),
+            (1, 0xdb) if variant == 1 => simple("vpand", &[Vx, Hx, Wx]),
+            (1, 0xde) if variant == 1 => simple("vpmaxub", &[Vx, Hx, Wx]),
+            (1, 0xdf) if variant == 1 => simple("vpandn", &[Vx, Hx, Wx]),
+            (1, 0xe7) if variant == 1 => simple("vmovntdq", &[Wx, Vx]),
+            (1, 0xeb) if variant == 1 => simple("vpor", &[Vx, Hx, Wx]),
+            (1, 0xef) if variant == 1 => simple("vpxor", &[Vx, Hx, Wx]),
+            (1, 0xf8) if variant == 1 => simple("vpsubb", &[Vx, Hx, Wx]),
+            (1, 0xfc) if variant == 1 => simple("vpaddb", &[Vx, Hx, Wx]),
+            (1, 0xfe) if variant == 1 => simple("vpaddd", &[Vx, Hx, Wx]),
+            (2, 0x00) if variant == 1 => simple("vpshufb", &[Vx, Hx, Wx]),
+            (2, 0x3b) if variant == 1 => simple("vpminud", &[Vx, Hx, Wx]),
+            (2, 0x17) if variant == 1 => simple("vptest", &[Vx, Wx]),
+            (2, 0x18) if variant == 1 => simple("vbroadcastss", &[Vx, Wss]),
+            (2, 0x58) if variant == 1 => simple("vpbroadcastd", &[Vx, Wss]),
+            (2, 0x59) if variant == 1 => simple("vpbroadcastq", &[Vx, Wsd]),
+            (2, 0x78) if variant == 1 => simple("vpbroadcastb", &[Vx, Wdq]),
+            (2, 0x79) if variant == 1 => simple("vpbroadcastw", &[Vx, Wdq]),
+            (2, 0xf3) if variant == 0 => {
+                let name = match self.modrm()?.reg { 1 => "blsr", 2 => "blsmsk", 3 => "blsi", _ => return None };
+                simple(name, &[Hx, Ey])
+            },
+            (2, 0xf2) if variant == 0 => simple("andn", &[Gy, Hx, Ey]),
+            (2, 0xf5) if variant == 0 => simple("bzhi", &[Gy, Ey, Hx]),
+            (2, 0xf7) => simple(["bextr", "shlx", "sarx", "shrx"][variant], &[Gy, Ey, Hx]),
+            (3, 0x0f) if variant == 1 => simple("vpalignr", &[Vx, Hx, Wx, Ib]),
+            (3, 0x46) if variant == 1 => simple("vperm2i128", &[Vx, Hx, Wx, Ib]),
+            (3, 0x38) if variant == 1 => simple("vinserti128", &[Vx, Hx, Wdq, Ib]),
+            (3, 0x39) if variant == 1 => simple("vextracti128", &[Wdq, Vx, Ib]),
+            _ => None,
+        }
+    }
+
+    fn gpr(&self, idx: u8, size: u8) -> String {
+        let idx = idx as usize & 0xf;
+        match size {
+            1 => if self.rex_present || self.vex.is_some() { REG8_REX[idx] } else { REG8_LEGACY[idx & 7] },
+            2 => REG16[idx],
+            8 => REG64[idx],
+            _ => REG32[idx],
+        }.to_owned()
+    }
+
+    fn vector_reg(&self, idx: u8, force_xmm: bool) -> String {
+        let wide = !force_xmm && self.vex.is_some_and(|vex| vex.l);
+        format!("{}{}", if wide { "ymm" } else { "xmm" }, idx & 0xf)
+    }
+
+    fn operand(&mut self, spec: Spec) -> Option<Operand> {
+        use Spec::*;
+        let size = self.size;
+        let operand = match spec {
+            Eb => self.rm_operand(1, false)?,
+            Ev => self.rm_operand(size, false)?,
+            Ew => self.rm_operand(2, false)?,
+            Ed => self.rm_operand(4, false)?,
+            Eq => self.rm_operand(8, false)?,
+            Ey | Ed64 => self.rm_operand(if self.rex_w() { 8 } else { 4 }, false)?,
+            M => {
+                let m = self.modrm()?;
+                if m.md == 3 { return None; }
+                self.rm_operand(size, false)?
+            },
+            Gb => { let m = self.modrm()?; Operand::Reg(self.gpr(m.reg | (self.rex_r() << 3), 1)) },
+            Gv => { let m = self.modrm()?; Operand::Reg(self.gpr(m.reg | (self.rex_r() << 3), size)) },
+            Gd => { let m = self.modrm()?; Operand::Reg(self.gpr(m.reg | (self.rex_r() << 3), 4)) },
+            Gy => { let m = self.modrm()?; Operand::Reg(self.gpr(m.reg | (self.rex_r() << 3), if self.rex_w() { 8 } else { 4 })) },
+            Ib => Operand::Imm(self.read_le(1)?),
+            Iw => Operand::Imm(self.read_le(2)?),
+            Ibs => Operand::Imm(mask(self.read_signed(1)? as u64, size)),
+            Iz => {
+                let len = if size == 2 { 2 } else { 4 };
+                Operand::Imm(mask(self.read_signed(len)? as u64, size))
+            },
+            Iv => Operand::Imm(self.read_le(size as usize)?),
+            Jb => {
+                let rel = self.read_signed(1)?;
+                Operand::Target(self.end_addr().wrapping_add(rel as u64))
+            },
+            Jz => {
+                let rel = self.read_signed(4)?;
+                Operand::Target(self.end_addr().wrapping_add(rel as u64))
+            },
+            Zb => Operand::Reg(self.gpr((self.opcode & 7) | (self.rex_b() << 3), 1)),
+            Zv => Operand::Reg(self.gpr((self.opcode & 7) | (self.rex_b() << 3), size)),
+            Zq => Operand::Reg(self.gpr((self.opcode & 7) | (self.rex_b() << 3), if self.opsize16 { 2 } else { 8 })),
+            Al => Operand::Reg("al".to_owned()),
+            Rax => Operand::Reg(self.gpr(0, size)),
+            Cl => Operand::Reg("cl".to_owned()),
+            Dx => Operand::Str("(%dx)".to_owned()),
+            Ob | Ov => {
+                if spec == Ob { self.size = 1; }
+                let addr = self.read_le(if self.addrsize32 { 4 } else { 8 })?;
+                Operand::Mem { segment: self.segment, base: None, index: None, disp: addr as i64, explicit_disp: true, rip_relative: false }
+            },
+            Sw => { let m = self.modrm()?; Operand::Reg(SEGMENTS[m.reg as usize].to_owned()) },
+            Vx => { let m = self.modrm()?; Operand::Reg(self.vector_reg(m.reg | (self.rex_r() << 3), false)) },
+            Hx => {
+                let vvvv = self.vex?.vvvv;
+                if matches!(self.opcode, 0xf2 | 0xf3 | 0xf5 | 0xf7) && self.vex?.map == 2 {
+                    Operand::Reg(self.gpr(vvvv, if self.rex_w() { 8 } else { 4 }))
+                } else {
+                    Operand::Reg(self.vector_reg(vvvv, false))
+                }
+            },
+            Wx => self.rm_operand(16, true)?,
+            Wdq | Wss | Wsd => {
+                let m = self.modrm()?;
+                if m.md == 3 {
+                    Operand::Reg(self.vector_reg(m.rm | (self.rex_b() << 3), true))
+                } else {
+                    self.rm_operand(16, true)?
+                }
+            },
+        };
+        Some(operand)
+    }
+
+    // 即値がまだ読まれていない場合もあるので, 命令末尾は最後にformatで計算する.
+    // 相対分岐の即値は常に最後のオペランドなので, 読んだ直後のposが命令末尾になる
+    fn end_addr(&self) -> u64 {
+        self.addr + self.pos as u64
+    }
+
+    fn rm_operand(&mut self, size: u8, vector: bool) -> Option<Operand> {
+        let m = self.modrm()?;
+        if m.md == 3 {
+            let idx = m.rm | (self.rex_b() << 3);
+            return Some(Operand::Reg(if vector { self.vector_reg(idx, false) } else { self.gpr(idx, size) }));
+        }
+        // ModRMが既に読まれていてもSIBとdispは1回だけ読む
+        if let Some(operand) = self.memory_cache.clone() { return Some(operand); }
+        let addr_regs: &[&str; 16] = if self.addrsize32 { &REG32 } else { &REG64 };
+        let mut base: Option<String> = None;
+        let mut index: Option<(String, u8)> = None;
+        let mut rip_relative = false;
+        let disp_len;
+        if m.rm == 4 {
+            let sib = self.next()?;
+            let scale = 1 << (sib >> 6);
+            let index_idx = ((sib >> 3) & 7) | (self.rex_x() << 3);
+            let base_idx = (sib & 7) | (self.rex_b() << 3);
+            if index_idx != 4 {
+                index = Some((addr_regs[index_idx as usize].to_owned(), scale));
+            }
+            if sib & 7 == 5 && m.md == 0 {
+                disp_len = 4;
+            } else {
+                base = Some(addr_regs[base_idx as usize].to_owned());
+                disp_len = match m.md { 1 => 1, 2 => 4, _ => 0 };
+            }
+        } else if m.rm == 5 && m.md == 0 {
+            rip_relative = true;
+            disp_len = 4;
+        } else {
+            base = Some(addr_regs[(m.rm | (self.rex_b() << 3)) as usize].to_owned());
+            disp_len = match m.md { 1 => 1, 2 => 4, _ => 0 };
+        }
+        let disp = if disp_len > 0 { self.read_signed(disp_len)? } else { 0 };
+        let operand = Operand::Mem { segment: self.segment, base, index, disp, explicit_disp: disp_len > 0, rip_relative };
+        self.memory_cache = Some(operand.clone());
+        Some(operand)
+    }
+}
+
+fn mask(value: u64, size: u8) -> u64 {
+    if size >= 8 { value } else { value & ((1u64 << (size as u32 * 8)) - 1) }
+}
+
+fn size_suffix(size: u8) -> char {
+    match size {
+        1 => 'b',
+        2 => 'w',
+        8 => 'q',
+        _ => 'l',
+    }
+}
+
+fn is_sse_mnemonic(mnemonic: &str) -> bool {
+    mnemonic.starts_with("cvt") || mnemonic.starts_with("fx") || mnemonic.starts_with("xsave") || mnemonic.starts_with("xrstor")
+        || mnemonic.ends_with("mxcsr") || mnemonic.starts_with("clflush") || mnemonic.starts_with("cmpxchg8b") || mnemonic.starts_with("cmpxchg16b")
+        || mnemonic.starts_with('f') || mnemonic.starts_with("lcall") || mnemonic.starts_with("ljmp") || mnemonic.starts_with("crc32")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (バイト列, 命令長, objdumpと同じAT&T記法). アドレスは0x1000に置く
+    const CASES: &[(&[u8], usize, &str)] = &[
+        // REX.W
+        (&[0x48, 0x89, 0xe5], 3, "mov    %rsp,%rbp"),
+        (&[0x48, 0x83, 0xec, 0x10], 4, "sub    $0x10,%rsp"),
+        (&[0x48, 0x8b, 0x45, 0xf8], 4, "mov    -0x8(%rbp),%rax"),
+        // レガシープレフィックス
+        (&[0x66, 0x89, 0x07], 3, "mov    %ax,(%rdi)"),
+        (&[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00], 6, "nopw   0x0(%rax,%rax,1)"),
+        (&[0xab], 1, "stos   %eax,%es:(%rdi)"),
+        (&[0xf3, 0x48, 0xab], 3, "rep stos %rax,%es:(%rdi)"),
+        (&[0xf3, 0xa6], 2, "repz cmpsb %es:(%rdi),%ds:(%rsi)"),
+        (&[0xf0, 0x48, 0x0f, 0xb1, 0x11], 5, "lock cmpxchg %rdx,(%rcx)"),
+        (&[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00], 9, "mov    %fs:0x28,%rax"),
+        // RIP相対
+        (&[0x48, 0x8d, 0x05, 0x10, 0x00, 0x00, 0x00], 7, "lea    0x10(%rip),%rax        # 0x1017"),
+        (&[0xff, 0x15, 0x02, 0x00, 0x00, 0x00], 6, "call   *0x2(%rip)        # 0x1008"),
+        (&[0xe8, 0x00, 0x00, 0x00, 0x00], 5, "call   0x1005"),
+        // ベースレジスタの無いSIB
+        (&[0x8b, 0x04, 0x25, 0x00, 0x10, 0x00, 0x00], 7, "mov    0x1000,%eax"),
+        (&[0x8b, 0x04, 0x8d, 0x00, 0x00, 0x00, 0x00], 7, "mov    0x0(,%rcx,4),%eax"),
+        (&[0x48, 0x8b, 0x04, 0xcd, 0x08, 0x00, 0x00, 0x00], 8, "mov    0x8(,%rcx,8),%rax"),
+        // VEX
+        (&[0xc5, 0xf9, 0x6f, 0xc1], 4, "vmovdqa %xmm1,%xmm0"),
+        (&[0xc5, 0xfc, 0x28, 0x0d, 0x00, 0x01, 0x00, 0x00], 8, "vmovaps 0x100(%rip),%ymm1        # 0x1108"),
+        (&[0xc4, 0xe2, 0x79, 0x18, 0x07], 5, "vbroadcastss (%rdi),%xmm0"),
+        (&[0xc5, 0xf3, 0x58, 0xc2], 4, "vaddsd %xmm2,%xmm1,%xmm0"),
+        // x87
+        (&[0xd9, 0xe8], 2, "fld1"),
+        (&[0xdd, 0x45, 0xf8], 3, "fldl   -0x8(%rbp)"),
+        (&[0xde, 0xc1], 2, "faddp  %st,%st(1)"),
+        (&[0xdb, 0x7d, 0xf0], 3, "fstpt  -0x10(%rbp)"),
+        (&[0xd9, 0xc9], 2, "fxch   %st(1)"),
+        // 64ビットモードで無効な命令と途中で切れた命令
+        (&[0x0f, 0x0b], 2, "ud2"),
+        (&[0x06], 1, "(bad)"),
+        (&[0x0f, 0xff], 1, "(bad)"),
+        (&[0x48, 0x8b], 1, "(bad)"),
+    ];
+
+    #[test]
+    fn decode_att() {
+        for (bytes, length, expected) in CASES {
+            let instruction = decode(bytes, 0x1000);
+            assert_eq!(instruction.format(&|_| None), *expected, "{:02x?}", bytes);
+            assert_eq!(instruction.get_length(), *length, "{:02x?}", bytes);
+        }
+    }
+
+    #[test]
+    fn symbolize_target() {
+        let instruction = decode(&[0xe8, 0x00, 0x00, 0x00, 0x00], 0x1000);
+        assert!(instruction.is_call());
+        assert_eq!(instruction.format(&|addr| Some(format!("main+{}", addr - 0x1000))), "call   0x1005 <main+5>");
+    }
+}
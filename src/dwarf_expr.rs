@@ -0,0 +1,316 @@
+use anyhow::{Result, bail, Context};
+use crate::dwarf_reader::DwarfReader;
+
+// 壊れた式の分岐で無限ループしないよう, 実行する命令の数を抑える
+const MAX_OPERATIONS: usize = 10000;
+
+// DWARF式を評価する時に必要になるレジスタ・メモリなどの取得元
+pub trait ExprContext {
+    fn read_register(&self, dwarf_reg: u16) -> Result<u64>;
+    fn read_memory(&self, addr: u64, size: u8) -> Result<u64>;
+    fn get_frame_base(&self) -> Result<u64> {
+        bail!("DW_OP_fbreg is not available in this context.")
+    }
+    fn get_call_frame_cfa(&self) -> Result<u64> {
+        bail!("DW_OP_call_frame_cfa is not available in this context.")
+    }
+    // DW_OP_addrはリンク時のアドレスなのでロードアドレスに直す
+    fn relocate_address(&self, addr: u64) -> u64 {
+        addr
+    }
+}
+
+// 評価結果. Addressはメモリ上の場所, Registerはレジスタそのもの, Valueは値そのもの.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprResult {
+    Address(u64),
+    Register(u16),
+    Value(u64),
+    ImplicitValue(Vec<u8>),
+}
+
+pub fn evaluate(expr: &[u8], ctx: &dyn ExprContext, initial_stack: &[u64]) -> Result<ExprResult> {
+    let mut stack: Vec<u64> = initial_stack.to_vec();
+    let mut reader = DwarfReader::new(expr);
+    let mut result: Option<ExprResult> = None;
+    // DW_OP_pieceでつないだ値. pieceが無い式ならNone
+    let mut pieces: Option<Vec<u8>> = None;
+    let mut operations = 0;
+    while !reader.is_empty() {
+        operations += 1;
+        if operations > MAX_OPERATIONS { bail!("DWARF expression executes too many operations."); }
+        let opcode = reader.read_u8()?;
+        match opcode {
+            // DW_OP_addr
+            0x03 => stack.push(ctx.relocate_address(reader.read_u64()?)),
+            // DW_OP_deref
+            0x06 => {
+                let addr = pop(&mut stack)?;
+                stack.push(ctx.read_memory(addr, 8)?);
+            },
+            // DW_OP_const1u .. DW_OP_const8s
+            0x08 => stack.push(reader.read_u8()? as u64),
+            0x09 => stack.push(reader.read_i8()? as i64 as u64),
+            0x0a => stack.push(reader.read_u16()? as u64),
+            0x0b => stack.push(reader.read_i16()? as i64 as u64),
+            0x0c => stack.push(reader.read_u32()? as u64),
+            0x0d => stack.push(reader.read_i32()? as i64 as u64),
+            0x0e => stack.push(reader.read_u64()?),
+            0x0f => stack.push(reader.read_i64()? as u64),
+            // DW_OP_constu, DW_OP_consts
+            0x10 => stack.push(reader.read_uleb128()?),
+            0x11 => stack.push(reader.read_sleb128()? as u64),
+            // DW_OP_dup
+            0x12 => {
+                let top = *stack.last().context("DW_OP_dup with empty stack.")?;
+                stack.push(top);
+            },
+            // DW_OP_drop
+            0x13 => { pop(&mut stack)?; },
+            // DW_OP_over
+            0x14 => {
+                if stack.len() < 2 { bail!("DW_OP_over with short stack."); }
+                stack.push(stack[stack.len() - 2]);
+            },
+            // DW_OP_pick
+            0x15 => {
+                let idx = reader.read_u8()? as usize;
+                if idx >= stack.len() { bail!("DW_OP_pick out of stack."); }
+                stack.push(stack[stack.len() - 1 - idx]);
+            },
+            // DW_OP_swap
+            0x16 => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(b);
+            },
+            // DW_OP_rot
+            0x17 => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                let c = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(c);
+                stack.push(b);
+            },
+            // DW_OP_abs
+            0x19 => {
+                let a = pop(&mut stack)? as i64;
+                stack.push(a.wrapping_abs() as u64);
+            },
+            // DW_OP_neg, DW_OP_not
+            0x1f => {
+                let a = pop(&mut stack)? as i64;
+                stack.push(a.wrapping_neg() as u64);
+            },
+            0x20 => {
+                let a = pop(&mut stack)?;
+                stack.push(!a);
+            },
+            // 二項演算
+            0x1a | 0x1b | 0x1c | 0x1d | 0x1e | 0x21 | 0x22 | 0x24 | 0x25 | 0x26 | 0x27 | 0x29..=0x2e => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                let value = match opcode {
+                    0x1a => a & b,
+                    0x1b => {
+                        if b == 0 { bail!("DW_OP_div by zero."); }
+                        (a as i64).wrapping_div(b as i64) as u64
+                    },
+                    0x1c => a.wrapping_sub(b),
+                    0x1d => {
+                        if b == 0 { bail!("DW_OP_mod by zero."); }
+                        a % b
+                    },
+                    0x1e => a.wrapping_mul(b),
+                    0x21 => a | b,
+                    0x22 => a.wrapping_add(b),
+                    0x24 => a.checked_shl(b as u32).unwrap_or(0),
+                    0x25 => a.checked_shr(b as u32).unwrap_or(0),
+                    0x26 => (a as i64).checked_shr(b as u32).unwrap_or(if (a as i64) < 0 { -1 } else { 0 }) as u64,
+                    0x27 => a ^ b,
+                    0x29 => (a == b) as u64,
+                    0x2a => ((a as i64) >= (b as i64)) as u64,
+                    0x2b => ((a as i64) > (b as i64)) as u64,
+                    0x2c => ((a as i64) <= (b as i64)) as u64,
+                    0x2d => ((a as i64) < (b as i64)) as u64,
+                    _ => (a != b) as u64,
+                };
+                stack.push(value);
+            },
+            // DW_OP_plus_uconst
+            0x23 => {
+                let a = pop(&mut stack)?;
+                stack.push(a.wrapping_add(reader.read_uleb128()?));
+            },
+            // DW_OP_bra
+            0x28 => {
+                let offset = reader.read_i16()?;
+                if pop(&mut stack)? != 0 {
+                    reader.set_offset(branch_target(&reader, expr.len(), offset)?);
+                }
+            },
+            // DW_OP_skip
+            0x2f => {
+                let offset = reader.read_i16()?;
+                reader.set_offset(branch_target(&reader, expr.len(), offset)?);
+            },
+            // DW_OP_lit0 .. DW_OP_lit31
+            0x30..=0x4f => stack.push((opcode - 0x30) as u64),
+            // DW_OP_reg0 .. DW_OP_reg31
+            0x50..=0x6f => result = Some(ExprResult::Register((opcode - 0x50) as u16)),
+            // DW_OP_breg0 .. DW_OP_breg31
+            0x70..=0x8f => {
+                let offset = reader.read_sleb128()?;
+                let value = ctx.read_register((opcode - 0x70) as u16)?;
+                stack.push(value.wrapping_add(offset as u64));
+            },
+            // DW_OP_regx
+            0x90 => result = Some(ExprResult::Register(reader.read_uleb128()? as u16)),
+            // DW_OP_fbreg
+            0x91 => {
+                let offset = reader.read_sleb128()?;
+                stack.push(ctx.get_frame_base()?.wrapping_add(offset as u64));
+            },
+            // DW_OP_bregx
+            0x92 => {
+                let reg = reader.read_uleb128()? as u16;
+                let offset = reader.read_sleb128()?;
+                stack.push(ctx.read_register(reg)?.wrapping_add(offset as u64));
+            },
+            // DW_OP_piece: 直前の場所からsizeバイト取り出して, 複数のレジスタやメモリにある値をつなげる
+            0x93 => {
+                let size = reader.read_uleb128()? as usize;
+                let location = match result.take() {
+                    Some(location) => location,
+                    // 場所の無いpieceは最適化で消えた部分
+                    None => ExprResult::Address(stack.pop().context("optimized out")?),
+                };
+                pieces.get_or_insert_with(Vec::new).extend(read_piece(ctx, location, size)?);
+            },
+            // DW_OP_deref_size
+            0x94 => {
+                let size = reader.read_u8()?;
+                let addr = pop(&mut stack)?;
+                stack.push(ctx.read_memory(addr, size)?);
+            },
+            // DW_OP_nop
+            0x96 => {},
+            // DW_OP_call_frame_cfa
+            0x9c => stack.push(ctx.get_call_frame_cfa()?),
+            // DW_OP_implicit_value
+            0x9e => {
+                let len = reader.read_uleb128()? as usize;
+                result = Some(ExprResult::ImplicitValue(reader.read_bytes(len)?.to_vec()));
+            },
+            // DW_OP_stack_value
+            0x9f => {
+                result = Some(ExprResult::Value(pop(&mut stack)?));
+            },
+            _ => bail!("unsupported DWARF expression opcode: 0x{:x}", opcode),
+        }
+    }
+    if let Some(pieces) = pieces { return Ok(ExprResult::ImplicitValue(pieces)); }
+    match result {
+        Some(result) => Ok(result),
+        None => Ok(ExprResult::Address(pop(&mut stack)?)),
+    }
+}
+
+// 分岐先は式の中 (末尾を含む) でなければならない
+fn branch_target(reader: &DwarfReader, len: usize, offset: i16) -> Result<usize> {
+    let target = reader.get_offset() as i64 + offset as i64;
+    if target < 0 || target > len as i64 { bail!("DWARF expression branches out of range. [offset: {}]", offset); }
+    Ok(target as usize)
+}
+
+fn read_piece(ctx: &dyn ExprContext, location: ExprResult, size: usize) -> Result<Vec<u8>> {
+    let mut bytes = match location {
+        ExprResult::Address(addr) => {
+            let mut bytes = vec![];
+            while bytes.len() < size {
+                let chunk = (size - bytes.len()).min(8);
+                let word = ctx.read_memory(addr.wrapping_add(bytes.len() as u64), chunk as u8)?;
+                bytes.extend_from_slice(&word.to_le_bytes()[..chunk]);
+            }
+            return Ok(bytes);
+        },
+        ExprResult::Register(reg) => {
+            if size > 8 { bail!("unsupported DW_OP_piece of {} bytes in register {}.", size, reg); }
+            ctx.read_register(reg)?.to_le_bytes().to_vec()
+        },
+        ExprResult::Value(value) => value.to_le_bytes().to_vec(),
+        ExprResult::ImplicitValue(bytes) => bytes,
+    };
+    bytes.resize(size, 0);
+    Ok(bytes)
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64> {
+    stack.pop().context("DWARF expression stack is empty.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // レジスタnの値は 0x1111111111111111 * n, メモリはアドレスの下位バイトが並ぶ
+    struct TestContext;
+
+    impl ExprContext for TestContext {
+        fn read_register(&self, dwarf_reg: u16) -> Result<u64> {
+            Ok(0x1111_1111_1111_1111 * dwarf_reg as u64)
+        }
+
+        fn read_memory(&self, addr: u64, size: u8) -> Result<u64> {
+            let mut bytes = [0u8; 8];
+            for (i, byte) in bytes.iter_mut().take(size as usize).enumerate() {
+                *byte = (addr as u8).wrapping_add(i as u8);
+            }
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+
+    #[test]
+    fn simple_locations() {
+        // DW_OP_breg7 8
+        assert_eq!(evaluate(&[0x77, 8], &TestContext, &[]).unwrap(), ExprResult::Address(0x7777_7777_7777_777f));
+        // DW_OP_reg3
+        assert_eq!(evaluate(&[0x53], &TestContext, &[]).unwrap(), ExprResult::Register(3));
+        // DW_OP_lit5 DW_OP_lit3 DW_OP_minus DW_OP_stack_value
+        assert_eq!(evaluate(&[0x35, 0x33, 0x1c, 0x9f], &TestContext, &[]).unwrap(), ExprResult::Value(2));
+    }
+
+    #[test]
+    fn branches() {
+        // DW_OP_lit1 DW_OP_bra +1 DW_OP_lit2 DW_OP_lit3 DW_OP_stack_value: lit2を飛ばす
+        assert_eq!(evaluate(&[0x31, 0x28, 1, 0, 0x32, 0x33, 0x9f], &TestContext, &[]).unwrap(), ExprResult::Value(3));
+        // DW_OP_lit0 DW_OP_bra +1 DW_OP_lit2 DW_OP_stack_value: 0なら分岐しない
+        assert_eq!(evaluate(&[0x30, 0x28, 1, 0, 0x32, 0x9f], &TestContext, &[]).unwrap(), ExprResult::Value(2));
+        // DW_OP_skip -3: 自分自身に戻り続ける
+        assert!(evaluate(&[0x2f, 0xfd, 0xff], &TestContext, &[]).is_err());
+        // DW_OP_skip 0 DW_OP_skip -6: 先頭に戻り続ける
+        assert!(evaluate(&[0x2f, 0, 0, 0x2f, 0xfa, 0xff], &TestContext, &[]).is_err());
+        // 範囲外への分岐
+        assert!(evaluate(&[0x2f, 0x10, 0], &TestContext, &[]).is_err());
+        assert!(evaluate(&[0x31, 0x28, 0x00, 0x80], &TestContext, &[]).is_err());
+    }
+
+    #[test]
+    fn pieces() {
+        // DW_OP_reg0 DW_OP_piece 8, DW_OP_reg1 DW_OP_piece 4
+        let mut expected = vec![0u8; 8];
+        expected.extend_from_slice(&[0x11; 4]);
+        assert_eq!(evaluate(&[0x50, 0x93, 8, 0x51, 0x93, 4], &TestContext, &[]).unwrap(), ExprResult::ImplicitValue(expected));
+
+        // DW_OP_addr 0x1000 DW_OP_piece 10, DW_OP_lit7 DW_OP_stack_value DW_OP_piece 2
+        let mut expr = vec![0x03];
+        expr.extend_from_slice(&0x1000u64.to_le_bytes());
+        expr.extend_from_slice(&[0x93, 10, 0x37, 0x9f, 0x93, 2]);
+        assert_eq!(evaluate(&expr, &TestContext, &[]).unwrap(), ExprResult::ImplicitValue(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 7, 0]));
+
+        // 場所の無いpieceは最適化で消えている
+        assert!(evaluate(&[0x50, 0x93, 8, 0x93, 8], &TestContext, &[]).is_err());
+    }
+}
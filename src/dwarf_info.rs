@@ -0,0 +1,958 @@
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use crate::dwarf_reader::{DwarfReader, read_cstr_at};
+
+// DW_TAG_*
+const DW_TAG_ARRAY_TYPE: u64 = 0x01;
+const DW_TAG_CLASS_TYPE: u64 = 0x02;
+const DW_TAG_ENUMERATION_TYPE: u64 = 0x04;
+const DW_TAG_FORMAL_PARAMETER: u64 = 0x05;
+const DW_TAG_LEXICAL_BLOCK: u64 = 0x0b;
+const DW_TAG_MEMBER: u64 = 0x0d;
+const DW_TAG_POINTER_TYPE: u64 = 0x0f;
+const DW_TAG_REFERENCE_TYPE: u64 = 0x10;
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_STRUCTURE_TYPE: u64 = 0x13;
+const DW_TAG_SUBROUTINE_TYPE: u64 = 0x15;
+const DW_TAG_TYPEDEF: u64 = 0x16;
+const DW_TAG_UNION_TYPE: u64 = 0x17;
+const DW_TAG_INLINED_SUBROUTINE: u64 = 0x1d;
+const DW_TAG_SUBRANGE_TYPE: u64 = 0x21;
+const DW_TAG_BASE_TYPE: u64 = 0x24;
+const DW_TAG_CONST_TYPE: u64 = 0x26;
+const DW_TAG_ENUMERATOR: u64 = 0x28;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VARIABLE: u64 = 0x34;
+const DW_TAG_VOLATILE_TYPE: u64 = 0x35;
+const DW_TAG_RESTRICT_TYPE: u64 = 0x37;
+const DW_TAG_NAMESPACE: u64 = 0x39;
+const DW_TAG_RVALUE_REFERENCE_TYPE: u64 = 0x42;
+
+// DW_AT_*
+const DW_AT_LOCATION: u64 = 0x02;
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_BYTE_SIZE: u64 = 0x0b;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_COMP_DIR: u64 = 0x1b;
+const DW_AT_CONST_VALUE: u64 = 0x1c;
+const DW_AT_UPPER_BOUND: u64 = 0x2f;
+const DW_AT_ABSTRACT_ORIGIN: u64 = 0x31;
+const DW_AT_COUNT: u64 = 0x37;
+const DW_AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const DW_AT_DECLARATION: u64 = 0x3c;
+const DW_AT_ENCODING: u64 = 0x3e;
+const DW_AT_FRAME_BASE: u64 = 0x40;
+const DW_AT_SPECIFICATION: u64 = 0x47;
+const DW_AT_TYPE: u64 = 0x49;
+const DW_AT_RANGES: u64 = 0x55;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+const DW_AT_ADDR_BASE: u64 = 0x73;
+const DW_AT_RNGLISTS_BASE: u64 = 0x74;
+const DW_AT_LOCLISTS_BASE: u64 = 0x8c;
+
+// DW_FORM_*
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_FLAG: u64 = 0x0c;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF_ADDR: u64 = 0x10;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_INDIRECT: u64 = 0x16;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_EXPRLOC: u64 = 0x18;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+const DW_FORM_STRX: u64 = 0x1a;
+const DW_FORM_ADDRX: u64 = 0x1b;
+const DW_FORM_REF_SUP4: u64 = 0x1c;
+const DW_FORM_STRP_SUP: u64 = 0x1d;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_REF_SIG8: u64 = 0x20;
+const DW_FORM_IMPLICIT_CONST: u64 = 0x21;
+const DW_FORM_LOCLISTX: u64 = 0x22;
+const DW_FORM_RNGLISTX: u64 = 0x23;
+const DW_FORM_REF_SUP8: u64 = 0x24;
+const DW_FORM_STRX1: u64 = 0x25;
+const DW_FORM_STRX2: u64 = 0x26;
+const DW_FORM_STRX3: u64 = 0x27;
+const DW_FORM_STRX4: u64 = 0x28;
+const DW_FORM_ADDRX1: u64 = 0x29;
+const DW_FORM_ADDRX2: u64 = 0x2a;
+const DW_FORM_ADDRX3: u64 = 0x2b;
+const DW_FORM_ADDRX4: u64 = 0x2c;
+
+// DW_ATE_*
+pub const DW_ATE_BOOLEAN: u8 = 0x02;
+pub const DW_ATE_FLOAT: u8 = 0x04;
+pub const DW_ATE_SIGNED: u8 = 0x05;
+pub const DW_ATE_SIGNED_CHAR: u8 = 0x06;
+pub const DW_ATE_UNSIGNED_CHAR: u8 = 0x08;
+pub const DW_ATE_UTF: u8 = 0x10;
+
+// .debug_info を読むのに必要なセクションの中身
+#[derive(Debug, Default)]
+pub struct DebugSections<'a> {
+    pub debug_info: &'a [u8],
+    pub debug_abbrev: &'a [u8],
+    pub debug_str: &'a [u8],
+    pub debug_line_str: &'a [u8],
+    pub debug_str_offsets: &'a [u8],
+    pub debug_addr: &'a [u8],
+    pub debug_ranges: &'a [u8],
+    pub debug_rnglists: &'a [u8],
+    pub debug_loc: &'a [u8],
+    pub debug_loclists: &'a [u8],
+}
+
+// 変数の場所. LocListは (開始アドレス, 終了アドレス, DWARF式) のリスト
+#[derive(Debug, Clone)]
+pub enum Location {
+    None,
+    Expr(Vec<u8>),
+    LocList(Vec<(u64, u64, Vec<u8>)>),
+    ConstValue(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    name: String,
+    type_offset: Option<usize>,
+    location: Location,
+    is_param: bool,
+    // 変数が見えるアドレス範囲. Noneなら関数全体
+    scope: Option<Vec<(u64, u64)>>,
+    abstract_origin: Option<usize>,
+}
+
+impl Variable {
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_type_offset(&self) -> Option<usize> { self.type_offset }
+    pub fn get_location(&self) -> &Location { &self.location }
+    pub fn is_param(&self) -> bool { self.is_param }
+
+    pub fn is_visible_at(&self, pc: u64) -> bool {
+        match &self.scope {
+            Some(ranges) => ranges.iter().any(|(low, high)| *low <= pc && pc < *high),
+            None => true,
+        }
+    }
+
+    // 内側のスコープの変数ほど小さい値になる
+    fn scope_size(&self) -> u64 {
+        match &self.scope {
+            Some(ranges) => ranges.iter().map(|(low, high)| high - low).sum(),
+            None => u64::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    name: String,
+    ranges: Vec<(u64, u64)>,
+    frame_base: Option<Vec<u8>>,
+    return_type: Option<usize>,
+    variables: Vec<Variable>,
+    name_origin: Option<usize>,
+}
+
+impl Function {
+    pub fn get_frame_base(&self) -> Option<&Vec<u8>> { self.frame_base.as_ref() }
+    pub fn get_return_type(&self) -> Option<usize> { self.return_type }
+    pub fn get_low_pc(&self) -> u64 { self.ranges.iter().map(|(low, _)| *low).min().unwrap_or(0) }
+
+    pub fn contains(&self, pc: u64) -> bool {
+        self.ranges.iter().any(|(low, high)| *low <= pc && pc < *high)
+    }
+
+    // pcで見える引数とローカル変数. 同じ名前なら内側のスコープのものを優先する
+    pub fn get_visible_variables(&self, pc: u64) -> Vec<&Variable> {
+        let mut visible: Vec<&Variable> = self.variables.iter().filter(|variable| variable.is_visible_at(pc)).collect();
+        visible.sort_by_key(|variable| variable.scope_size());
+        let mut result: Vec<&Variable> = vec![];
+        for variable in visible.into_iter() {
+            if result.iter().all(|shown| shown.name != variable.name) {
+                result.push(variable);
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    name: String,
+    type_offset: Option<usize>,
+    offset: u64,
+}
+
+impl Member {
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_type_offset(&self) -> Option<usize> { self.type_offset }
+    pub fn get_offset(&self) -> u64 { self.offset }
+}
+
+#[derive(Debug, Clone)]
+pub enum Type {
+    Base { name: String, size: u64, encoding: u8 },
+    Pointer { name: String, target: Option<usize> },
+    Struct { name: String, size: u64, members: Vec<Member> },
+    Array { target: Option<usize>, count: Option<u64> },
+    Enumeration { name: String, size: u64, enumerators: Vec<(String, i64)> },
+    Typedef { name: String, target: Option<usize> },
+    Modifier { target: Option<usize> },
+    Other { name: String, size: u64 },
+}
+
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    functions: Vec<Function>,
+    globals: Vec<(String, Variable)>,
+    types: HashMap<usize, Type>,
+    comp_dirs: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Abbrev {
+    tag: u64,
+    has_children: bool,
+    attributes: Vec<(u64, u64, i64)>,
+}
+
+#[derive(Debug, Clone)]
+enum AttrValue {
+    Addr(u64),
+    Udata(u64),
+    Sdata(i64),
+    Str(String),
+    Ref(usize),
+    Block(Vec<u8>),
+    Flag(bool),
+    SecOffset(u64),
+    StrIndex(u64),
+    AddrIndex(u64),
+    ListIndex(u64),
+    Other,
+}
+
+impl AttrValue {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            AttrValue::Addr(value) | AttrValue::Udata(value) | AttrValue::SecOffset(value) => Some(*value),
+            AttrValue::Sdata(value) => Some(*value as u64),
+            AttrValue::Flag(flag) => Some(*flag as u64),
+            _ => None,
+        }
+    }
+}
+
+// コンパイル単位ごとの情報
+struct Unit<'a> {
+    version: u16,
+    is_dwarf64: bool,
+    address_size: u8,
+    offset: usize,
+    base_address: u64,
+    str_offsets_base: u64,
+    addr_base: u64,
+    rnglists_base: u64,
+    loclists_base: u64,
+    sections: &'a DebugSections<'a>,
+}
+
+// DIEを親から順に辿る時のスタック
+enum Scope {
+    Namespace(String),
+    Function(usize),
+    Block(Vec<(u64, u64)>),
+    Type(usize),
+    Skip,
+}
+
+impl DebugInfo {
+    pub fn parse(sections: &DebugSections) -> Result<Self> {
+        let mut debug_info = DebugInfo::default();
+        let mut abbrev_cache: HashMap<u64, HashMap<u64, Abbrev>> = HashMap::new();
+        // abstract_originやspecificationで参照される名前と型
+        let mut die_names: HashMap<usize, (String, Option<usize>)> = HashMap::new();
+        let mut reader = DwarfReader::new(sections.debug_info);
+        while !reader.is_empty() {
+            let unit_offset = reader.get_offset();
+            let (unit_length, is_dwarf64) = reader.read_initial_length()?;
+            let unit_end = match reader.offset_after(unit_length) {
+                Ok(unit_end) if unit_length != 0 && unit_end <= sections.debug_info.len() => unit_end,
+                _ => break,
+            };
+            let version = reader.read_u16()?;
+            let (abbrev_offset, address_size) = if version >= 5 {
+                let unit_type = reader.read_u8()?;
+                let address_size = reader.read_u8()?;
+                let abbrev_offset = reader.read_offset(is_dwarf64)?;
+                match unit_type {
+                    // DW_UT_type, DW_UT_split_type
+                    0x02 | 0x06 => {
+                        reader.skip(8)?;
+                        reader.read_offset(is_dwarf64)?;
+                    },
+                    // DW_UT_skeleton, DW_UT_split_compile
+                    0x04 | 0x05 => reader.skip(8)?,
+                    _ => {},
+                }
+                (abbrev_offset, address_size)
+            } else {
+                let abbrev_offset = reader.read_offset(is_dwarf64)?;
+                (abbrev_offset, reader.read_u8()?)
+            };
+            if let Entry::Vacant(entry) = abbrev_cache.entry(abbrev_offset) {
+                entry.insert(parse_abbrevs(sections.debug_abbrev, abbrev_offset as usize)?);
+            }
+            let abbrevs = &abbrev_cache[&abbrev_offset];
+            let mut unit = Unit {
+                version,
+                is_dwarf64,
+                address_size,
+                offset: unit_offset,
+                base_address: 0,
+                str_offsets_base: 8,
+                addr_base: 8,
+                rnglists_base: 12,
+                loclists_base: 12,
+                sections,
+            };
+            if let Err(e) = debug_info.parse_unit(&mut reader, unit_end, &mut unit, abbrevs, &mut die_names) {
+                println!("skip broken compile unit at 0x{:x}: {:?}", unit_offset, e);
+            }
+            reader.set_offset(unit_end);
+        }
+
+        // 名前を別のDIEから引いている関数や変数を解決する
+        for function in debug_info.functions.iter_mut() {
+            if let Some((name, type_offset)) = function.name_origin.and_then(|origin| die_names.get(&origin)) {
+                if function.name.is_empty() { function.name = name.clone(); }
+                if function.return_type.is_none() { function.return_type = *type_offset; }
+            }
+            for variable in function.variables.iter_mut() {
+                if let Some((name, type_offset)) = variable.abstract_origin.and_then(|origin| die_names.get(&origin)) {
+                    if variable.name.is_empty() { variable.name = name.clone(); }
+                    if variable.type_offset.is_none() { variable.type_offset = *type_offset; }
+                }
+            }
+            function.variables.retain(|variable| !variable.name.is_empty());
+        }
+        debug_info.functions.retain(|function| !function.ranges.is_empty());
+        Ok(debug_info)
+    }
+
+    fn parse_unit(&mut self, reader: &mut DwarfReader, unit_end: usize, unit: &mut Unit, abbrevs: &HashMap<u64, Abbrev>, die_names: &mut HashMap<usize, (String, Option<usize>)>) -> Result<()> {
+        let mut scopes: Vec<Scope> = vec![];
+        while reader.get_offset() < unit_end {
+            let die_offset = reader.get_offset();
+            let code = reader.read_uleb128()?;
+            if code == 0 {
+                scopes.pop();
+                if scopes.is_empty() { break; }
+                continue;
+            }
+            let abbrev = match abbrevs.get(&code) {
+                Some(abbrev) => abbrev,
+                None => bail!("unknown abbrev code {} at 0x{:x}", code, die_offset),
+            };
+            let mut attrs: HashMap<u64, AttrValue> = HashMap::new();
+            for (name, form, implicit_const) in abbrev.attributes.iter() {
+                let value = read_attr_value(reader, unit, *form, *implicit_const)?;
+                attrs.insert(*name, value);
+            }
+            let scope = self.handle_die(die_offset, abbrev.tag, &attrs, unit, &scopes, die_names);
+            if abbrev.has_children {
+                scopes.push(scope);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_die(&mut self, die_offset: usize, tag: u64, attrs: &HashMap<u64, AttrValue>, unit: &mut Unit, scopes: &[Scope], die_names: &mut HashMap<usize, (String, Option<usize>)>) -> Scope {
+        let name = attrs.get(&DW_AT_NAME).and_then(|value| unit.get_str(value));
+        let type_offset = attrs.get(&DW_AT_TYPE).and_then(|value| match value { AttrValue::Ref(offset) => Some(*offset), _ => None });
+        if let Some(name) = &name {
+            die_names.insert(die_offset, (name.clone(), type_offset));
+        }
+        // inlined_subroutineの中の変数は別の関数のものなので読まない
+        if scopes.iter().any(|scope| matches!(scope, Scope::Skip)) {
+            return Scope::Skip;
+        }
+        let byte_size = attrs.get(&DW_AT_BYTE_SIZE).and_then(|value| value.as_u64()).unwrap_or(0);
+        let name_or_empty = name.clone().unwrap_or_default();
+
+        match tag {
+            DW_TAG_COMPILE_UNIT => {
+                unit.read_bases(attrs);
+                unit.base_address = attrs.get(&DW_AT_LOW_PC).and_then(|value| unit.get_address(value)).unwrap_or(0);
+                if let Some(comp_dir) = attrs.get(&DW_AT_COMP_DIR).and_then(|value| unit.get_str(value)) {
+                    if !self.comp_dirs.contains(&comp_dir) { self.comp_dirs.push(comp_dir); }
+                }
+                Scope::Namespace("".to_owned())
+            },
+            DW_TAG_NAMESPACE => Scope::Namespace(name_or_empty),
+            DW_TAG_SUBPROGRAM => {
+                let ranges = unit.get_ranges(attrs);
+                let name_origin = match attrs.get(&DW_AT_SPECIFICATION).or_else(|| attrs.get(&DW_AT_ABSTRACT_ORIGIN)) {
+                    Some(AttrValue::Ref(offset)) => Some(*offset),
+                    _ => None,
+                };
+                let qualified_name = match &name {
+                    Some(name) => qualify(scopes, name),
+                    None => "".to_owned(),
+                };
+                if let Some(name) = &name {
+                    die_names.insert(die_offset, (qualify(scopes, name), type_offset));
+                }
+                if ranges.is_empty() { return Scope::Skip; }
+                let frame_base = match attrs.get(&DW_AT_FRAME_BASE) {
+                    Some(AttrValue::Block(expr)) => Some(expr.clone()),
+                    _ => None,
+                };
+                self.functions.push(Function { name: qualified_name, ranges, frame_base, return_type: type_offset, variables: vec![], name_origin });
+                Scope::Function(self.functions.len() - 1)
+            },
+            DW_TAG_LEXICAL_BLOCK => {
+                let ranges = unit.get_ranges(attrs);
+                if ranges.is_empty() { Scope::Block(vec![]) } else { Scope::Block(ranges) }
+            },
+            DW_TAG_INLINED_SUBROUTINE => Scope::Skip,
+            DW_TAG_VARIABLE | DW_TAG_FORMAL_PARAMETER => {
+                if attrs.contains_key(&DW_AT_DECLARATION) { return Scope::Skip; }
+                let location = match (attrs.get(&DW_AT_LOCATION), attrs.get(&DW_AT_CONST_VALUE)) {
+                    (Some(AttrValue::Block(expr)), _) => Location::Expr(expr.clone()),
+                    (Some(AttrValue::SecOffset(offset)), _) => unit.get_loclist(*offset, false),
+                    (Some(AttrValue::ListIndex(idx)), _) => unit.get_loclist(*idx, true),
+                    (_, Some(AttrValue::Block(bytes))) => Location::ConstValue(bytes.clone()),
+                    (_, Some(value)) => match value.as_u64() {
+                        Some(value) => Location::ConstValue(value.to_le_bytes().to_vec()),
+                        None => Location::None,
+                    },
+                    _ => Location::None,
+                };
+                let abstract_origin = match attrs.get(&DW_AT_ABSTRACT_ORIGIN) {
+                    Some(AttrValue::Ref(offset)) => Some(*offset),
+                    _ => None,
+                };
+                let function_idx = scopes.iter().rev().find_map(|scope| match scope { Scope::Function(idx) => Some(*idx), _ => None });
+                match function_idx {
+                    Some(function_idx) => {
+                        let scope = scopes.iter().rev().find_map(|scope| match scope {
+                            Scope::Block(ranges) if !ranges.is_empty() => Some(ranges.clone()),
+                            _ => None,
+                        });
+                        self.functions[function_idx].variables.push(Variable {
+                            name: name_or_empty,
+                            type_offset,
+                            location,
+                            is_param: tag == DW_TAG_FORMAL_PARAMETER,
+                            scope,
+                            abstract_origin,
+                        });
+                    },
+                    None => {
+                        if !matches!(location, Location::None) && name.is_some() {
+                            let variable = Variable { name: name_or_empty.clone(), type_offset, location, is_param: false, scope: None, abstract_origin };
+                            self.globals.push((qualify(scopes, &name_or_empty), variable));
+                        }
+                    },
+                }
+                Scope::Skip
+            },
+            DW_TAG_BASE_TYPE => {
+                let encoding = attrs.get(&DW_AT_ENCODING).and_then(|value| value.as_u64()).unwrap_or(0) as u8;
+                self.types.insert(die_offset, Type::Base { name: name_or_empty, size: byte_size, encoding });
+                Scope::Skip
+            },
+            DW_TAG_POINTER_TYPE | DW_TAG_REFERENCE_TYPE | DW_TAG_RVALUE_REFERENCE_TYPE => {
+                self.types.insert(die_offset, Type::Pointer { name: name_or_empty, target: type_offset });
+                Scope::Skip
+            },
+            DW_TAG_STRUCTURE_TYPE | DW_TAG_CLASS_TYPE | DW_TAG_UNION_TYPE => {
+                self.types.insert(die_offset, Type::Struct { name: name_or_empty, size: byte_size, members: vec![] });
+                Scope::Type(die_offset)
+            },
+            DW_TAG_MEMBER => {
+                let offset = match attrs.get(&DW_AT_DATA_MEMBER_LOCATION) {
+                    Some(AttrValue::Block(expr)) => {
+                        // DW_OP_plus_uconst N の形だけ扱う
+                        let mut reader = DwarfReader::new(expr);
+                        match reader.read_u8() {
+                            Ok(0x23) => reader.read_uleb128().unwrap_or(0),
+                            _ => 0,
+                        }
+                    },
+                    Some(value) => value.as_u64().unwrap_or(0),
+                    None => 0,
+                };
+                if let Some(Scope::Type(parent)) = scopes.last() {
+                    if let Some(Type::Struct { members, .. }) = self.types.get_mut(parent) {
+                        members.push(Member { name: name_or_empty, type_offset, offset });
+                    }
+                }
+                Scope::Skip
+            },
+            DW_TAG_ARRAY_TYPE => {
+                self.types.insert(die_offset, Type::Array { target: type_offset, count: None });
+                Scope::Type(die_offset)
+            },
+            DW_TAG_SUBRANGE_TYPE => {
+                let count = attrs.get(&DW_AT_COUNT).and_then(|value| value.as_u64())
+                    .or_else(|| attrs.get(&DW_AT_UPPER_BOUND).and_then(|value| value.as_u64()).map(|upper| upper + 1));
+                if let Some(Scope::Type(parent)) = scopes.last() {
+                    if let Some(Type::Array { count: array_count, .. }) = self.types.get_mut(parent) {
+                        if array_count.is_none() { *array_count = count; }
+                    }
+                }
+                Scope::Skip
+            },
+            DW_TAG_ENUMERATION_TYPE => {
+                self.types.insert(die_offset, Type::Enumeration { name: name_or_empty, size: byte_size, enumerators: vec![] });
+                Scope::Type(die_offset)
+            },
+            DW_TAG_ENUMERATOR => {
+                let value = match attrs.get(&DW_AT_CONST_VALUE) {
+                    Some(AttrValue::Sdata(value)) => *value,
+                    Some(value) => value.as_u64().unwrap_or(0) as i64,
+                    None => 0,
+                };
+                if let Some(Scope::Type(parent)) = scopes.last() {
+                    if let Some(Type::Enumeration { enumerators, .. }) = self.types.get_mut(parent) {
+                        enumerators.push((name_or_empty, value));
+                    }
+                }
+                Scope::Skip
+            },
+            DW_TAG_TYPEDEF => {
+                self.types.insert(die_offset, Type::Typedef { name: name_or_empty, target: type_offset });
+                Scope::Skip
+            },
+            DW_TAG_CONST_TYPE | DW_TAG_VOLATILE_TYPE | DW_TAG_RESTRICT_TYPE => {
+                self.types.insert(die_offset, Type::Modifier { target: type_offset });
+                Scope::Skip
+            },
+            DW_TAG_SUBROUTINE_TYPE => {
+                self.types.insert(die_offset, Type::Other { name: "fn".to_owned(), size: 0 });
+                Scope::Skip
+            },
+            _ => Scope::Skip,
+        }
+    }
+
+    pub fn find_function(&self, pc: u64) -> Option<&Function> {
+        // 入れ子になった関数がある場合は範囲の狭い方を選ぶ
+        self.functions.iter()
+            .filter(|function| function.contains(pc))
+            .min_by_key(|function| function.ranges.iter().map(|(low, high)| high - low).sum::<u64>())
+    }
+
+    // 完全な名前か, 最後の要素(main::main2 なら main2)で探す
+    pub fn find_function_by_name(&self, name: &str) -> Option<&Function> {
+        let suffix = format!("::{}", name);
+        self.functions.iter()
+            .find(|function| function.name == name)
+            .or_else(|| self.functions.iter().find(|function| function.name.ends_with(&suffix)))
+    }
+
+    pub fn find_global(&self, name: &str) -> Option<&Variable> {
+        self.globals.iter()
+            .find(|(qualified_name, _)| qualified_name == name)
+            .or_else(|| self.globals.iter().find(|(_, variable)| variable.name == name))
+            .map(|(_, variable)| variable)
+    }
+
+    // 同じ名前の型が複数のコンパイル単位にある時は, 最初に出てきたものにする
+    pub fn find_type_by_name(&self, name: &str) -> Option<usize> {
+        self.types.iter()
+            .filter(|(_, ty)| match ty {
+                Type::Base { name: type_name, .. } | Type::Pointer { name: type_name, .. } | Type::Struct { name: type_name, .. }
+                | Type::Enumeration { name: type_name, .. } | Type::Typedef { name: type_name, .. } | Type::Other { name: type_name, .. } => type_name == name,
+                _ => false,
+            })
+            .map(|(offset, _)| *offset)
+            .min()
+    }
+
+    pub fn get_type(&self, type_offset: usize) -> Option<&Type> {
+        self.types.get(&type_offset)
+    }
+
+    pub fn get_comp_dirs(&self) -> &Vec<String> { &self.comp_dirs }
+}
+
+fn qualify(scopes: &[Scope], name: &str) -> String {
+    let mut names: Vec<&str> = scopes.iter().filter_map(|scope| match scope {
+        Scope::Namespace(namespace) if !namespace.is_empty() => Some(namespace.as_str()),
+        _ => None,
+    }).collect();
+    names.push(name);
+    names.join("::")
+}
+
+fn parse_abbrevs(debug_abbrev: &[u8], offset: usize) -> Result<HashMap<u64, Abbrev>> {
+    let mut abbrevs: HashMap<u64, Abbrev> = HashMap::new();
+    let mut reader = DwarfReader::at(debug_abbrev, offset);
+    loop {
+        let code = reader.read_uleb128()?;
+        if code == 0 { break; }
+        let tag = reader.read_uleb128()?;
+        let has_children = reader.read_u8()? != 0;
+        let mut attributes: Vec<(u64, u64, i64)> = vec![];
+        loop {
+            let name = reader.read_uleb128()?;
+            let form = reader.read_uleb128()?;
+            let implicit_const = if form == DW_FORM_IMPLICIT_CONST { reader.read_sleb128()? } else { 0 };
+            if name == 0 && form == 0 { break; }
+            attributes.push((name, form, implicit_const));
+        }
+        abbrevs.insert(code, Abbrev { tag, has_children, attributes });
+    }
+    Ok(abbrevs)
+}
+
+fn read_attr_value(reader: &mut DwarfReader, unit: &Unit, form: u64, implicit_const: i64) -> Result<AttrValue> {
+    let value = match form {
+        DW_FORM_ADDR => AttrValue::Addr(reader.read_address(unit.address_size)?),
+        DW_FORM_DATA1 => AttrValue::Udata(reader.read_u8()? as u64),
+        DW_FORM_DATA2 => AttrValue::Udata(reader.read_u16()? as u64),
+        DW_FORM_DATA4 => AttrValue::Udata(reader.read_u32()? as u64),
+        DW_FORM_DATA8 => AttrValue::Udata(reader.read_u64()?),
+        DW_FORM_DATA16 => AttrValue::Block(reader.read_bytes(16)?.to_vec()),
+        DW_FORM_SDATA => AttrValue::Sdata(reader.read_sleb128()?),
+        DW_FORM_UDATA => AttrValue::Udata(reader.read_uleb128()?),
+        DW_FORM_IMPLICIT_CONST => AttrValue::Sdata(implicit_const),
+        DW_FORM_STRING => AttrValue::Str(reader.read_cstr()?),
+        DW_FORM_STRP => {
+            let offset = reader.read_offset(unit.is_dwarf64)? as usize;
+            AttrValue::Str(read_cstr_at(unit.sections.debug_str, offset).unwrap_or_default())
+        },
+        DW_FORM_LINE_STRP => {
+            let offset = reader.read_offset(unit.is_dwarf64)? as usize;
+            AttrValue::Str(read_cstr_at(unit.sections.debug_line_str, offset).unwrap_or_default())
+        },
+        DW_FORM_STRP_SUP => {
+            reader.read_offset(unit.is_dwarf64)?;
+            AttrValue::Other
+        },
+        DW_FORM_STRX | DW_FORM_STRX1 | DW_FORM_STRX2 | DW_FORM_STRX3 | DW_FORM_STRX4 => {
+            AttrValue::StrIndex(read_index(reader, form)?)
+        },
+        DW_FORM_ADDRX | DW_FORM_ADDRX1 | DW_FORM_ADDRX2 | DW_FORM_ADDRX3 | DW_FORM_ADDRX4 => {
+            AttrValue::AddrIndex(read_index(reader, form)?)
+        },
+        DW_FORM_REF1 => AttrValue::Ref(unit.offset + reader.read_u8()? as usize),
+        DW_FORM_REF2 => AttrValue::Ref(unit.offset + reader.read_u16()? as usize),
+        DW_FORM_REF4 => AttrValue::Ref(unit.offset + reader.read_u32()? as usize),
+        DW_FORM_REF8 => AttrValue::Ref(unit.offset + reader.read_u64()? as usize),
+        DW_FORM_REF_UDATA => AttrValue::Ref(unit.offset + reader.read_uleb128()? as usize),
+        DW_FORM_REF_ADDR => {
+            // DWARF2だけはアドレスサイズ
+            let offset = if unit.version <= 2 { reader.read_address(unit.address_size)? } else { reader.read_offset(unit.is_dwarf64)? };
+            AttrValue::Ref(offset as usize)
+        },
+        DW_FORM_REF_SIG8 => {
+            reader.skip(8)?;
+            AttrValue::Other
+        },
+        DW_FORM_REF_SUP4 => {
+            reader.skip(4)?;
+            AttrValue::Other
+        },
+        DW_FORM_REF_SUP8 => {
+            reader.skip(8)?;
+            AttrValue::Other
+        },
+        DW_FORM_SEC_OFFSET => AttrValue::SecOffset(reader.read_offset(unit.is_dwarf64)?),
+        DW_FORM_LOCLISTX | DW_FORM_RNGLISTX => AttrValue::ListIndex(reader.read_uleb128()?),
+        DW_FORM_EXPRLOC | DW_FORM_BLOCK => {
+            let len = reader.read_uleb128()? as usize;
+            AttrValue::Block(reader.read_bytes(len)?.to_vec())
+        },
+        DW_FORM_BLOCK1 => {
+            let len = reader.read_u8()? as usize;
+            AttrValue::Block(reader.read_bytes(len)?.to_vec())
+        },
+        DW_FORM_BLOCK2 => {
+            let len = reader.read_u16()? as usize;
+            AttrValue::Block(reader.read_bytes(len)?.to_vec())
+        },
+        DW_FORM_BLOCK4 => {
+            let len = reader.read_u32()? as usize;
+            AttrValue::Block(reader.read_bytes(len)?.to_vec())
+        },
+        DW_FORM_FLAG => AttrValue::Flag(reader.read_u8()? != 0),
+        DW_FORM_FLAG_PRESENT => AttrValue::Flag(true),
+        DW_FORM_INDIRECT => {
+            let form = reader.read_uleb128()?;
+            return read_attr_value(reader, unit, form, implicit_const);
+        },
+        _ => bail!("unsupported attribute form: 0x{:x}", form),
+    };
+    Ok(value)
+}
+
+fn read_index(reader: &mut DwarfReader, form: u64) -> Result<u64> {
+    match form {
+        DW_FORM_STRX1 | DW_FORM_ADDRX1 => Ok(reader.read_u8()? as u64),
+        DW_FORM_STRX2 | DW_FORM_ADDRX2 => Ok(reader.read_u16()? as u64),
+        DW_FORM_STRX3 | DW_FORM_ADDRX3 => {
+            let bytes = reader.read_bytes(3)?;
+            Ok(bytes[0] as u64 | (bytes[1] as u64) << 8 | (bytes[2] as u64) << 16)
+        },
+        DW_FORM_STRX4 | DW_FORM_ADDRX4 => Ok(reader.read_u32()? as u64),
+        _ => reader.read_uleb128(),
+    }
+}
+
+impl<'a> Unit<'a> {
+    fn read_bases(&mut self, attrs: &HashMap<u64, AttrValue>) {
+        if let Some(value) = attrs.get(&DW_AT_STR_OFFSETS_BASE).and_then(|value| value.as_u64()) { self.str_offsets_base = value; }
+        if let Some(value) = attrs.get(&DW_AT_ADDR_BASE).and_then(|value| value.as_u64()) { self.addr_base = value; }
+        if let Some(value) = attrs.get(&DW_AT_RNGLISTS_BASE).and_then(|value| value.as_u64()) { self.rnglists_base = value; }
+        if let Some(value) = attrs.get(&DW_AT_LOCLISTS_BASE).and_then(|value| value.as_u64()) { self.loclists_base = value; }
+    }
+
+    fn offset_size(&self) -> usize { if self.is_dwarf64 { 8 } else { 4 } }
+
+    fn get_str(&self, value: &AttrValue) -> Option<String> {
+        match value {
+            AttrValue::Str(s) => Some(s.clone()),
+            AttrValue::StrIndex(idx) => {
+                let offset = self.str_offsets_base as usize + *idx as usize * self.offset_size();
+                let mut reader = DwarfReader::at(self.sections.debug_str_offsets, offset);
+                let str_offset = reader.read_offset(self.is_dwarf64).ok()?;
+                read_cstr_at(self.sections.debug_str, str_offset as usize)
+            },
+            _ => None,
+        }
+    }
+
+    fn get_address(&self, value: &AttrValue) -> Option<u64> {
+        match value {
+            AttrValue::Addr(addr) => Some(*addr),
+            AttrValue::AddrIndex(idx) => self.read_addr_index(*idx),
+            _ => None,
+        }
+    }
+
+    fn read_addr_index(&self, idx: u64) -> Option<u64> {
+        let offset = self.addr_base as usize + idx as usize * self.address_size as usize;
+        DwarfReader::at(self.sections.debug_addr, offset).read_address(self.address_size).ok()
+    }
+
+    fn get_ranges(&self, attrs: &HashMap<u64, AttrValue>) -> Vec<(u64, u64)> {
+        if let Some(low_pc) = attrs.get(&DW_AT_LOW_PC).and_then(|value| self.get_address(value)) {
+            let high_pc = match attrs.get(&DW_AT_HIGH_PC) {
+                // DWARF4以降はlow_pcからのオフセットで書かれることが多い
+                Some(AttrValue::Addr(addr)) => *addr,
+                Some(AttrValue::AddrIndex(idx)) => self.read_addr_index(*idx).unwrap_or(low_pc),
+                Some(value) => low_pc + value.as_u64().unwrap_or(0),
+                None => low_pc + 1,
+            };
+            if low_pc == 0 || high_pc <= low_pc { return vec![]; }
+            return vec![(low_pc, high_pc)];
+        }
+        match attrs.get(&DW_AT_RANGES) {
+            Some(AttrValue::SecOffset(offset)) => {
+                if self.version >= 5 { self.read_rnglist(*offset as usize) } else { self.read_ranges(*offset as usize) }
+            },
+            Some(AttrValue::ListIndex(idx)) => {
+                let offset = self.rnglists_base as usize + *idx as usize * self.offset_size();
+                let mut reader = DwarfReader::at(self.sections.debug_rnglists, offset);
+                match reader.read_offset(self.is_dwarf64) {
+                    Ok(list_offset) => self.read_rnglist(self.rnglists_base as usize + list_offset as usize),
+                    Err(_) => vec![],
+                }
+            },
+            _ => vec![],
+        }
+    }
+
+    // DWARF4の.debug_ranges
+    fn read_ranges(&self, offset: usize) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        let mut reader = DwarfReader::at(self.sections.debug_ranges, offset);
+        let mut base = self.base_address;
+        while let (Ok(begin), Ok(end)) = (reader.read_address(self.address_size), reader.read_address(self.address_size)) {
+            if begin == 0 && end == 0 { break; }
+            if begin == u64::MAX {
+                base = end;
+                continue;
+            }
+            if begin != end && base + begin != 0 { ranges.push((base + begin, base + end)); }
+        }
+        ranges
+    }
+
+    // DWARF5の.debug_rnglists
+    fn read_rnglist(&self, offset: usize) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        let mut reader = DwarfReader::at(self.sections.debug_rnglists, offset);
+        let mut base = self.base_address;
+        loop {
+            let kind = match reader.read_u8() { Ok(kind) => kind, Err(_) => break };
+            let res: Result<()> = (|| {
+                match kind {
+                    // DW_RLE_end_of_list
+                    0x00 => bail!("end"),
+                    // DW_RLE_base_addressx
+                    0x01 => base = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0),
+                    // DW_RLE_startx_endx
+                    0x02 => {
+                        let begin = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        let end = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        ranges.push((begin, end));
+                    },
+                    // DW_RLE_startx_length
+                    0x03 => {
+                        let begin = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        let len = reader.read_uleb128()?;
+                        ranges.push((begin, begin + len));
+                    },
+                    // DW_RLE_offset_pair
+                    0x04 => {
+                        let begin = reader.read_uleb128()?;
+                        let end = reader.read_uleb128()?;
+                        ranges.push((base + begin, base + end));
+                    },
+                    // DW_RLE_base_address
+                    0x05 => base = reader.read_address(self.address_size)?,
+                    // DW_RLE_start_end
+                    0x06 => {
+                        let begin = reader.read_address(self.address_size)?;
+                        let end = reader.read_address(self.address_size)?;
+                        ranges.push((begin, end));
+                    },
+                    // DW_RLE_start_length
+                    0x07 => {
+                        let begin = reader.read_address(self.address_size)?;
+                        let len = reader.read_uleb128()?;
+                        ranges.push((begin, begin + len));
+                    },
+                    _ => bail!("unknown DW_RLE: 0x{:x}", kind),
+                }
+                Ok(())
+            })();
+            if res.is_err() { break; }
+        }
+        ranges.retain(|(begin, end)| *begin != 0 && begin < end);
+        ranges
+    }
+
+    fn get_loclist(&self, value: u64, is_index: bool) -> Location {
+        if self.version < 5 {
+            return Location::LocList(self.read_loc(value as usize));
+        }
+        let offset = if is_index {
+            let mut reader = DwarfReader::at(self.sections.debug_loclists, self.loclists_base as usize + value as usize * self.offset_size());
+            match reader.read_offset(self.is_dwarf64) {
+                Ok(list_offset) => self.loclists_base as usize + list_offset as usize,
+                Err(_) => return Location::None,
+            }
+        } else {
+            value as usize
+        };
+        Location::LocList(self.read_loclist(offset))
+    }
+
+    // DWARF4の.debug_loc
+    fn read_loc(&self, offset: usize) -> Vec<(u64, u64, Vec<u8>)> {
+        let mut entries: Vec<(u64, u64, Vec<u8>)> = vec![];
+        let mut reader = DwarfReader::at(self.sections.debug_loc, offset);
+        let mut base = self.base_address;
+        while let (Ok(begin), Ok(end)) = (reader.read_address(self.address_size), reader.read_address(self.address_size)) {
+            if begin == 0 && end == 0 { break; }
+            if begin == u64::MAX {
+                base = end;
+                continue;
+            }
+            let len = match reader.read_u16() { Ok(len) => len as usize, Err(_) => break };
+            let expr = match reader.read_bytes(len) { Ok(expr) => expr.to_vec(), Err(_) => break };
+            entries.push((base + begin, base + end, expr));
+        }
+        entries
+    }
+
+    // DWARF5の.debug_loclists
+    fn read_loclist(&self, offset: usize) -> Vec<(u64, u64, Vec<u8>)> {
+        let mut entries: Vec<(u64, u64, Vec<u8>)> = vec![];
+        let mut reader = DwarfReader::at(self.sections.debug_loclists, offset);
+        let mut base = self.base_address;
+        loop {
+            let kind = match reader.read_u8() { Ok(kind) => kind, Err(_) => break };
+            let res: Result<Option<(u64, u64)>> = (|| {
+                let range = match kind {
+                    // DW_LLE_end_of_list
+                    0x00 => bail!("end"),
+                    // DW_LLE_base_addressx
+                    0x01 => {
+                        base = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        None
+                    },
+                    // DW_LLE_startx_endx
+                    0x02 => {
+                        let begin = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        let end = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        Some((begin, end))
+                    },
+                    // DW_LLE_startx_length
+                    0x03 => {
+                        let begin = self.read_addr_index(reader.read_uleb128()?).unwrap_or(0);
+                        Some((begin, begin + reader.read_uleb128()?))
+                    },
+                    // DW_LLE_offset_pair
+                    0x04 => {
+                        let begin = reader.read_uleb128()?;
+                        let end = reader.read_uleb128()?;
+                        Some((base + begin, base + end))
+                    },
+                    // DW_LLE_default_location
+                    0x05 => Some((0, u64::MAX)),
+                    // DW_LLE_base_address
+                    0x06 => {
+                        base = reader.read_address(self.address_size)?;
+                        None
+                    },
+                    // DW_LLE_start_end
+                    0x07 => Some((reader.read_address(self.address_size)?, reader.read_address(self.address_size)?)),
+                    // DW_LLE_start_length
+                    0x08 => {
+                        let begin = reader.read_address(self.address_size)?;
+                        Some((begin, begin + reader.read_uleb128()?))
+                    },
+                    _ => bail!("unknown DW_LLE: 0x{:x}", kind),
+                };
+                Ok(range)
+            })();
+            match res {
+                Ok(Some((begin, end))) => {
+                    let len = match reader.read_uleb128() { Ok(len) => len as usize, Err(_) => break };
+                    let expr = match reader.read_bytes(len) { Ok(expr) => expr.to_vec(), Err(_) => break };
+                    entries.push((begin, end, expr));
+                },
+                Ok(None) => {},
+                Err(_) => break,
+            }
+        }
+        entries
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{Result, bail, Context};
+use crate::dwarf_reader::{DwarfReader, read_cstr_at};
+
+// DW_LNS_*
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNS_NEGATE_STMT: u8 = 0x06;
+const DW_LNS_SET_BASIC_BLOCK: u8 = 0x07;
+const DW_LNS_CONST_ADD_PC: u8 = 0x08;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 0x09;
+const DW_LNS_SET_PROLOGUE_END: u8 = 0x0a;
+const DW_LNS_SET_EPILOGUE_BEGIN: u8 = 0x0b;
+const DW_LNS_SET_ISA: u8 = 0x0c;
+
+// DW_LNE_*
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+const DW_LNE_DEFINE_FILE: u8 = 0x03;
+const DW_LNE_SET_DISCRIMINATOR: u8 = 0x04;
+
+// DWARF5のファイルエントリで使うDW_LNCT_*とDW_FORM_*
+const DW_LNCT_PATH: u64 = 0x1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x2;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+#[derive(Debug, Clone)]
+pub struct LineRow {
+    address: u64,
+    file_idx: usize,
+    line: u64,
+    is_stmt: bool,
+}
+
+// end_sequenceまでの連続した行情報. end_addressは含まない.
+#[derive(Debug)]
+struct LineSequence {
+    start_address: u64,
+    end_address: u64,
+    rows: Vec<LineRow>,
+}
+
+#[derive(Debug, Default)]
+pub struct LineTable {
+    file_names: Vec<String>,
+    sequences: Vec<LineSequence>,
+}
+
+impl LineTable {
+    pub fn parse(debug_line: &[u8], debug_str: &[u8], debug_line_str: &[u8]) -> Result<Self> {
+        let mut line_table = LineTable::default();
+        let mut reader = DwarfReader::new(debug_line);
+        while !reader.is_empty() {
+            let (unit_length, is_dwarf64) = reader.read_initial_length()?;
+            let unit_end = match reader.offset_after(unit_length) {
+                Ok(unit_end) if unit_length != 0 && unit_end <= debug_line.len() => unit_end,
+                _ => break,
+            };
+            // 壊れた行番号プログラムはそのユニットだけ読み飛ばす
+            let _ = line_table.parse_unit(&mut reader, unit_end, is_dwarf64, debug_str, debug_line_str);
+            reader.set_offset(unit_end);
+        }
+        line_table.sequences.sort_by_key(|sequence| sequence.start_address);
+        Ok(line_table)
+    }
+
+    fn parse_unit(&mut self, reader: &mut DwarfReader, unit_end: usize, is_dwarf64: bool, debug_str: &[u8], debug_line_str: &[u8]) -> Result<()> {
+        let version = reader.read_u16()?;
+        if !(2..=5).contains(&version) { bail!("unsupported .debug_line version: {}", version); }
+        let mut address_size = 8;
+        if version >= 5 {
+            address_size = reader.read_u8()?;
+            let _segment_selector_size = reader.read_u8()?;
+        }
+        let header_length = reader.read_offset(is_dwarf64)?;
+        let program_start = reader.offset_after(header_length)?;
+        let minimum_instruction_length = reader.read_u8()? as u64;
+        if version >= 4 {
+            let _maximum_operations_per_instruction = reader.read_u8()?;
+        }
+        let default_is_stmt = reader.read_u8()? != 0;
+        let line_base = reader.read_i8()? as i64;
+        let line_range = reader.read_u8()? as u64;
+        let opcode_base = reader.read_u8()?;
+        let mut standard_opcode_lengths: Vec<u8> = vec![];
+        for _ in 1..opcode_base {
+            standard_opcode_lengths.push(reader.read_u8()?);
+        }
+        if line_range == 0 { bail!("line_range is 0."); }
+
+        // file_idxはself.file_namesへの通し番号に変換しておく
+        let mut file_idx_map: Vec<usize> = vec![];
+        if version >= 5 {
+            let directories = parse_entry_formats(reader, is_dwarf64, debug_str, debug_line_str)?;
+            let files = parse_entry_formats(reader, is_dwarf64, debug_str, debug_line_str)?;
+            for (path, dir_idx) in files.into_iter() {
+                let dir = directories.get(dir_idx as usize).map(|(dir, _)| dir.as_str()).unwrap_or("");
+                file_idx_map.push(self.push_file_name(dir, &path));
+            }
+        } else {
+            let mut directories: Vec<String> = vec!["".to_owned()];
+            loop {
+                let dir = reader.read_cstr()?;
+                if dir.is_empty() { break; }
+                directories.push(dir);
+            }
+            // DWARF4以前のファイル番号は1始まり
+            file_idx_map.push(self.push_file_name("", "<unknown>"));
+            loop {
+                let path = reader.read_cstr()?;
+                if path.is_empty() { break; }
+                let dir_idx = reader.read_uleb128()?;
+                let _mtime = reader.read_uleb128()?;
+                let _length = reader.read_uleb128()?;
+                let dir = directories.get(dir_idx as usize).cloned().unwrap_or_default();
+                file_idx_map.push(self.push_file_name(&dir, &path));
+            }
+        }
+
+        reader.set_offset(program_start);
+        let initial_file = if version >= 5 { 0 } else { 1 };
+        let mut address: u64 = 0;
+        let mut file: u64 = initial_file;
+        let mut line: u64 = 1;
+        let mut is_stmt = default_is_stmt;
+        let mut rows: Vec<LineRow> = vec![];
+
+        while reader.get_offset() < unit_end {
+            let opcode = reader.read_u8()?;
+            if opcode >= opcode_base {
+                let adjusted = (opcode - opcode_base) as u64;
+                address = advance_address(address, adjusted / line_range, minimum_instruction_length)?;
+                line = (line as i64 + line_base + (adjusted % line_range) as i64) as u64;
+                rows.push(LineRow { address, file_idx: map_file_idx(&file_idx_map, file), line, is_stmt });
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = reader.read_uleb128()?;
+                    let sub_end = reader.offset_after(len)?;
+                    if len == 0 { continue; }
+                    let sub_opcode = reader.read_u8()?;
+                    match sub_opcode {
+                        DW_LNE_END_SEQUENCE => {
+                            rows.push(LineRow { address, file_idx: map_file_idx(&file_idx_map, file), line, is_stmt });
+                            self.push_sequence(std::mem::take(&mut rows));
+                            address = 0;
+                            file = initial_file;
+                            line = 1;
+                            is_stmt = default_is_stmt;
+                        },
+                        DW_LNE_SET_ADDRESS => {
+                            address = reader.read_address(address_size)?;
+                        },
+                        DW_LNE_DEFINE_FILE => {
+                            let path = reader.read_cstr()?;
+                            file_idx_map.push(self.push_file_name("", &path));
+                        },
+                        DW_LNE_SET_DISCRIMINATOR => {},
+                        _ => {},
+                    }
+                    reader.set_offset(sub_end);
+                },
+                DW_LNS_COPY => {
+                    rows.push(LineRow { address, file_idx: map_file_idx(&file_idx_map, file), line, is_stmt });
+                },
+                DW_LNS_ADVANCE_PC => {
+                    address = advance_address(address, reader.read_uleb128()?, minimum_instruction_length)?;
+                },
+                DW_LNS_ADVANCE_LINE => {
+                    line = (line as i64 + reader.read_sleb128()?) as u64;
+                },
+                DW_LNS_SET_FILE => {
+                    file = reader.read_uleb128()?;
+                },
+                DW_LNS_SET_COLUMN => {
+                    reader.read_uleb128()?;
+                },
+                DW_LNS_NEGATE_STMT => {
+                    is_stmt = !is_stmt;
+                },
+                DW_LNS_SET_BASIC_BLOCK | DW_LNS_SET_PROLOGUE_END | DW_LNS_SET_EPILOGUE_BEGIN => {},
+                DW_LNS_CONST_ADD_PC => {
+                    address = advance_address(address, (255 - opcode_base as u64) / line_range, minimum_instruction_length)?;
+                },
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    address = advance_address(address, reader.read_u16()? as u64, 1)?;
+                },
+                DW_LNS_SET_ISA => {
+                    reader.read_uleb128()?;
+                },
+                _ => {
+                    // 知らない標準オペコードは引数の数だけ読み飛ばす
+                    for _ in 0..standard_opcode_lengths[opcode as usize - 1] {
+                        reader.read_uleb128()?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn push_file_name(&mut self, dir: &str, path: &str) -> usize {
+        let file_name = if path.starts_with('/') || dir.is_empty() { path.to_owned() } else { format!("{}/{}", dir, path) };
+        if let Some(idx) = self.file_names.iter().position(|name| name == &file_name) {
+            return idx;
+        }
+        self.file_names.push(file_name);
+        self.file_names.len() - 1
+    }
+
+    fn push_sequence(&mut self, rows: Vec<LineRow>) {
+        if rows.len() < 2 { return; }
+        let start_address = rows[0].address;
+        let end_address = rows[rows.len() - 1].address;
+        // リンカに捨てられた関数はアドレス0から始まる
+        if start_address == 0 || start_address >= end_address { return; }
+        self.sequences.push(LineSequence { start_address, end_address, rows });
+    }
+
+    pub fn get_file_name(&self, file_idx: usize) -> &str {
+        self.file_names.get(file_idx).map(|name| name.as_str()).unwrap_or("<unknown>")
+    }
+
+    // addrを含む行(addr以下で最大のアドレスを持つ行)を返す
+    pub fn find_row(&self, addr: u64) -> Option<&LineRow> {
+        let sequence = self.find_sequence(addr)?;
+        let idx = match sequence.rows.binary_search_by(|row| row.address.cmp(&addr)) {
+            Ok(mut idx) => {
+                // 同じアドレスの行が続く場合は最後のものを使う
+                while idx + 1 < sequence.rows.len() && sequence.rows[idx + 1].address == addr { idx += 1; }
+                idx
+            },
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        sequence.rows.get(idx)
+    }
+
+    fn find_sequence(&self, addr: u64) -> Option<&LineSequence> {
+        let idx = match self.sequences.binary_search_by(|sequence| sequence.start_address.cmp(&addr)) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let sequence = &self.sequences[idx];
+        if addr < sequence.end_address { Some(sequence) } else { None }
+    }
+
+    pub fn find_location(&self, addr: u64) -> Option<(&str, u64)> {
+        let row = self.find_row(addr)?;
+        Some((self.get_file_name(row.file_idx), row.line))
+    }
+
+    // file:line の最初の命令のアドレス. その行に命令が無ければ, 命令のある次の行を使う.
+    // fileがNoneなら全てのファイルから探す. 戻り値は (アドレス, 実際の行番号)
+    pub fn find_line_address(&self, file: Option<&str>, line: u64) -> Option<(u64, u64)> {
+        let file_matches = |file_idx: usize| match file {
+            Some(file) => {
+                let name = self.get_file_name(file_idx);
+                name == file || name.ends_with(&format!("/{}", file))
+            },
+            None => true,
+        };
+        let candidates = self.sequences.iter()
+            .flat_map(|sequence| sequence.rows.iter())
+            .filter(|row| row.is_stmt && row.line >= line && file_matches(row.file_idx));
+        let best_line = candidates.clone().map(|row| row.line).min()?;
+        candidates.filter(|row| row.line == best_line)
+            .map(|row| row.address)
+            .min()
+            .map(|addr| (addr, best_line))
+    }
+
+    // addrがある行の先頭の命令かどうか. stepはここで止まる
+    pub fn is_statement_start(&self, addr: u64) -> bool {
+        match self.find_sequence(addr) {
+            Some(sequence) => sequence.rows.iter().any(|row| row.address == addr && row.is_stmt && row.line != 0),
+            None => false,
+        }
+    }
+
+    // 関数の先頭アドレスから, プロローグの後の最初の行のアドレスを探す
+    pub fn find_prologue_end(&self, low_pc: u64) -> Option<u64> {
+        let sequence = self.find_sequence(low_pc)?;
+        sequence.rows.iter()
+            .find(|row| row.address > low_pc && row.is_stmt && row.line != 0)
+            .map(|row| row.address)
+            .filter(|addr| *addr < sequence.end_address)
+    }
+}
+
+// 壊れたプログラムでアドレスが桁あふれしたらエラーにする
+fn advance_address(address: u64, operation_advance: u64, minimum_instruction_length: u64) -> Result<u64> {
+    operation_advance.checked_mul(minimum_instruction_length)
+        .and_then(|delta| address.checked_add(delta))
+        .context("address overflow in line program")
+}
+
+fn map_file_idx(file_idx_map: &[usize], file: u64) -> usize {
+    file_idx_map.get(file as usize).cloned().unwrap_or(0)
+}
+
+// DWARF5のdirectory/file_name_entry_formatを読む. 戻り値は (path, directory_index) のリスト
+fn parse_entry_formats(reader: &mut DwarfReader, is_dwarf64: bool, debug_str: &[u8], debug_line_str: &[u8]) -> Result<Vec<(String, u64)>> {
+    let format_count = reader.read_u8()?;
+    let mut formats: Vec<(u64, u64)> = vec![];
+    for _ in 0..format_count {
+        formats.push((reader.read_uleb128()?, reader.read_uleb128()?));
+    }
+    let count = reader.read_uleb128()?;
+    let mut entries: Vec<(String, u64)> = vec![];
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut dir_idx: u64 = 0;
+        for (content_type, form) in formats.iter() {
+            let mut value_str: Option<String> = None;
+            let mut value_num: u64 = 0;
+            match *form {
+                DW_FORM_STRING => value_str = Some(reader.read_cstr()?),
+                DW_FORM_STRP => value_str = read_cstr_at(debug_str, reader.read_offset(is_dwarf64)? as usize),
+                DW_FORM_LINE_STRP => value_str = read_cstr_at(debug_line_str, reader.read_offset(is_dwarf64)? as usize),
+                DW_FORM_UDATA => value_num = reader.read_uleb128()?,
+                DW_FORM_DATA1 => value_num = reader.read_u8()? as u64,
+                DW_FORM_DATA2 => value_num = reader.read_u16()? as u64,
+                DW_FORM_DATA4 => value_num = reader.read_u32()? as u64,
+                DW_FORM_DATA8 => value_num = reader.read_u64()?,
+                DW_FORM_DATA16 => reader.skip(16)?,
+                DW_FORM_BLOCK => {
+                    let len = reader.read_uleb128()? as usize;
+                    reader.skip(len)?;
+                },
+                _ => bail!("unsupported form in line header: 0x{:x}", form),
+            }
+            match *content_type {
+                DW_LNCT_PATH => path = value_str.unwrap_or_default(),
+                DW_LNCT_DIRECTORY_INDEX => dir_idx = value_num,
+                _ => {},
+            }
+        }
+        entries.push((path, dir_idx));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+    // unit_lengthとheader_lengthを埋めて1ユニットにする. address_headerはDWARF5のaddress_sizeとsegment_selector_size
+    fn line_unit(version: u16, address_header: &[u8], header: &[u8], program: &[u8]) -> Vec<u8> {
+        let mut body = version.to_le_bytes().to_vec();
+        body.extend_from_slice(address_header);
+        body.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        body.extend_from_slice(header);
+        body.extend_from_slice(program);
+        let mut unit = (body.len() as u32).to_le_bytes().to_vec();
+        unit.extend(body);
+        unit
+    }
+
+    // minimum_instruction_length=1, maximum_operations_per_instruction=1, default_is_stmt=1, line_base=-5, line_range=14, opcode_base=13
+    fn common_header() -> Vec<u8> {
+        let mut header = vec![1, 1, 1, (-5i8) as u8, 14, 13];
+        header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+        header
+    }
+
+    fn set_address(address: u64) -> Vec<u8> {
+        let mut op = vec![0x00, 9, DW_LNE_SET_ADDRESS];
+        op.extend_from_slice(&address.to_le_bytes());
+        op
+    }
+
+    // 特殊オペコード: アドレスをaddress_advance, 行をline_advanceだけ進めて行を追加する
+    fn special(address_advance: u8, line_advance: i8) -> u8 {
+        ((line_advance + 5) as u8) + 14 * address_advance + 13
+    }
+
+    fn v4_unit() -> Vec<u8> {
+        let mut header = common_header();
+        header.extend_from_slice(b"src\0\0");
+        header.extend_from_slice(b"main.c\0\x01\x00\x00");
+        header.extend_from_slice(b"util.h\0\x00\x00\x00");
+        header.push(0);
+
+        let mut program = set_address(0x401000);
+        program.extend_from_slice(&[DW_LNS_ADVANCE_LINE, 9, DW_LNS_COPY]);
+        program.push(special(4, 1));
+        program.extend_from_slice(&[DW_LNS_SET_FILE, 2, DW_LNS_ADVANCE_LINE, 0x78, DW_LNS_ADVANCE_PC, 6, DW_LNS_NEGATE_STMT, DW_LNS_COPY]);
+        program.extend_from_slice(&[DW_LNS_CONST_ADD_PC, DW_LNS_NEGATE_STMT, DW_LNS_SET_FILE, 1]);
+        program.push(special(0, 2));
+        program.extend_from_slice(&[DW_LNS_FIXED_ADVANCE_PC, 5, 0]);
+        program.extend_from_slice(&[0x00, 1, DW_LNE_END_SEQUENCE]);
+        line_unit(4, &[], &header, &program)
+    }
+
+    #[test]
+    fn parse_v4() {
+        let line_table = LineTable::parse(&v4_unit(), &[], &[]).unwrap();
+        assert_eq!(line_table.find_location(0x401000), Some(("src/main.c", 10)));
+        assert_eq!(line_table.find_location(0x401003), Some(("src/main.c", 10)));
+        assert_eq!(line_table.find_location(0x401004), Some(("src/main.c", 11)));
+        assert_eq!(line_table.find_location(0x40100a), Some(("util.h", 3)));
+        // DW_LNS_const_add_pcで (255 - 13) / 14 = 17 進む
+        assert_eq!(line_table.find_location(0x40101b), Some(("src/main.c", 5)));
+        assert_eq!(line_table.find_location(0x40101f), Some(("src/main.c", 5)));
+        // end_sequenceのアドレスは含まない
+        assert_eq!(line_table.find_location(0x401020), None);
+        assert_eq!(line_table.find_location(0x400fff), None);
+
+        assert!(line_table.is_statement_start(0x401004));
+        assert!(!line_table.is_statement_start(0x40100a));
+        assert_eq!(line_table.find_line_address(Some("main.c"), 4), Some((0x40101b, 5)));
+        assert_eq!(line_table.find_line_address(Some("util.h"), 1), None);
+        assert_eq!(line_table.find_prologue_end(0x401000), Some(0x401004));
+    }
+
+    #[test]
+    fn parse_v5() {
+        let debug_line_str = b"/work\0lib\0";
+        let mut header = common_header();
+        // directory_entry_format: (DW_LNCT_path, DW_FORM_line_strp)
+        header.extend_from_slice(&[1, DW_LNCT_PATH as u8, DW_FORM_LINE_STRP as u8]);
+        header.push(2);
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&6u32.to_le_bytes());
+        // file_name_entry_format: (DW_LNCT_path, DW_FORM_string), (DW_LNCT_directory_index, DW_FORM_udata), (DW_LNCT_MD5, DW_FORM_data16)
+        header.extend_from_slice(&[3, DW_LNCT_PATH as u8, DW_FORM_STRING as u8, DW_LNCT_DIRECTORY_INDEX as u8, DW_FORM_UDATA as u8, 0x05, DW_FORM_DATA16 as u8]);
+        header.push(2);
+        header.extend_from_slice(b"a.c\0\x00");
+        header.extend_from_slice(&[0xaa; 16]);
+        header.extend_from_slice(b"b.c\0\x01");
+        header.extend_from_slice(&[0xbb; 16]);
+
+        let mut program = set_address(0x2000);
+        program.push(DW_LNS_COPY);
+        program.push(special(2, 1));
+        program.extend_from_slice(&[DW_LNS_SET_FILE, 1, DW_LNS_ADVANCE_LINE, 5]);
+        program.push(special(3, 0));
+        program.extend_from_slice(&[DW_LNS_ADVANCE_PC, 3]);
+        program.extend_from_slice(&[0x00, 1, DW_LNE_END_SEQUENCE]);
+        let debug_line = line_unit(5, &[8, 0], &header, &program);
+
+        let line_table = LineTable::parse(&debug_line, &[], debug_line_str).unwrap();
+        // DWARF5のファイル番号は0始まり
+        assert_eq!(line_table.find_location(0x2000), Some(("/work/a.c", 1)));
+        assert_eq!(line_table.find_location(0x2002), Some(("/work/a.c", 2)));
+        assert_eq!(line_table.find_location(0x2005), Some(("lib/b.c", 7)));
+        assert_eq!(line_table.find_location(0x2008), None);
+    }
+
+    #[test]
+    fn overflowing_lengths() {
+        // DWARF64の巨大なunit_length
+        let mut debug_line = vec![0xff, 0xff, 0xff, 0xff];
+        debug_line.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(LineTable::parse(&debug_line, &[], &[]).unwrap().find_location(0).is_none());
+
+        // アドレスが桁あふれするプログラムはそのユニットだけ捨てる
+        let mut header = common_header();
+        header.extend_from_slice(b"\0a.c\0\x00\x00\x00\0");
+        let mut program = set_address(u64::MAX - 1);
+        program.push(DW_LNS_COPY);
+        program.extend_from_slice(&[DW_LNS_ADVANCE_PC, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]);
+        program.extend_from_slice(&[0x00, 1, DW_LNE_END_SEQUENCE]);
+        let mut debug_line = line_unit(4, &[], &header, &program);
+        debug_line.extend(v4_unit());
+        let line_table = LineTable::parse(&debug_line, &[], &[]).unwrap();
+        assert_eq!(line_table.find_location(0x401004), Some(("src/main.c", 11)));
+    }
+
+    #[test]
+    fn skip_broken_unit() {
+        let mut header = common_header();
+        header.extend_from_slice(&[0, 0]);
+        let mut debug_line = line_unit(9, &[], &header, &[]);
+        debug_line.extend(v4_unit());
+        let line_table = LineTable::parse(&debug_line, &[], &[]).unwrap();
+        assert_eq!(line_table.find_location(0x401004), Some(("src/main.c", 11)));
+    }
+}
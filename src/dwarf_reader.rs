@@ -0,0 +1,235 @@
+use anyhow::{Result, bail};
+
+// .debug_line / .debug_info / .eh_frame などを読むためのカーソル
+#[derive(Debug, Clone)]
+pub struct DwarfReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> DwarfReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        DwarfReader { data, offset: 0 }
+    }
+
+    pub fn at(data: &'a [u8], offset: usize) -> Self {
+        DwarfReader { data, offset }
+    }
+
+    pub fn get_offset(&self) -> usize { self.offset }
+
+    pub fn set_offset(&mut self, offset: usize) { self.offset = offset; }
+
+    pub fn is_empty(&self) -> bool { self.offset >= self.data.len() }
+
+    // 今の位置からlenバイト先のオフセット. 壊れた長さでオーバーフローするならエラー
+    pub fn offset_after(&self, len: u64) -> Result<usize> {
+        match self.offset.checked_add(len as usize) {
+            Some(end) if len <= usize::MAX as u64 => Ok(end),
+            _ => bail!("DwarfReader::offset_after overflow. [offset: 0x{:x}, len: {}]", self.offset, len),
+        }
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        let end = match self.offset.checked_add(len) {
+            Some(end) if end <= self.data.len() => end,
+            _ => bail!("DwarfReader::skip out of range. [offset: 0x{:x}, len: {}]", self.offset, len),
+        };
+        self.offset = end;
+        Ok(())
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = match self.offset.checked_add(len) {
+            Some(end) if end <= self.data.len() => end,
+            _ => bail!("DwarfReader::read_bytes out of range. [offset: 0x{:x}, len: {}]", self.offset, len),
+        };
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8> {
+        Ok(self.read_u8()? as i8)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16> {
+        Ok(self.read_u16()? as i16)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32> {
+        Ok(self.read_u32()? as i32)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let bytes = self.read_bytes(8)?;
+        let mut array = [0u8; 8];
+        array.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(array))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64> {
+        Ok(self.read_u64()? as i64)
+    }
+
+    pub fn read_uleb128(&mut self) -> Result<u64> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift < 64 { result |= ((byte & 0x7f) as u64) << shift; }
+            shift += 7;
+            if byte & 0x80 == 0 { break; }
+        }
+        Ok(result)
+    }
+
+    pub fn read_sleb128(&mut self) -> Result<i64> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        let mut byte;
+        loop {
+            byte = self.read_u8()?;
+            if shift < 64 { result |= ((byte & 0x7f) as i64) << shift; }
+            shift += 7;
+            if byte & 0x80 == 0 { break; }
+        }
+        if shift < 64 && (byte & 0x40) != 0 {
+            result |= -1i64 << shift;
+        }
+        Ok(result)
+    }
+
+    pub fn read_cstr(&mut self) -> Result<String> {
+        let start = self.offset;
+        while self.offset < self.data.len() && self.data[self.offset] != 0 {
+            self.offset += 1;
+        }
+        if self.offset >= self.data.len() { bail!("DwarfReader::read_cstr is not terminated. [offset: 0x{:x}]", start); }
+        let s = String::from_utf8_lossy(&self.data[start..self.offset]).into_owned();
+        self.offset += 1;
+        Ok(s)
+    }
+
+    // 戻り値は (unit_length, is_dwarf64)
+    pub fn read_initial_length(&mut self) -> Result<(u64, bool)> {
+        let length = self.read_u32()?;
+        if length == 0xffff_ffff {
+            Ok((self.read_u64()?, true))
+        } else {
+            Ok((length as u64, false))
+        }
+    }
+
+    pub fn read_offset(&mut self, is_dwarf64: bool) -> Result<u64> {
+        if is_dwarf64 { self.read_u64() } else { Ok(self.read_u32()? as u64) }
+    }
+
+    pub fn read_address(&mut self, address_size: u8) -> Result<u64> {
+        match address_size {
+            1 => Ok(self.read_u8()? as u64),
+            2 => Ok(self.read_u16()? as u64),
+            4 => Ok(self.read_u32()? as u64),
+            8 => self.read_u64(),
+            _ => bail!("unsupported address size: {}", address_size),
+        }
+    }
+}
+
+pub fn read_cstr_at(data: &[u8], offset: usize) -> Option<String> {
+    if offset >= data.len() { return None; }
+    DwarfReader::at(data, offset).read_cstr().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uleb(bytes: &[u8]) -> Result<u64> {
+        DwarfReader::new(bytes).read_uleb128()
+    }
+
+    fn sleb(bytes: &[u8]) -> Result<i64> {
+        DwarfReader::new(bytes).read_sleb128()
+    }
+
+    #[test]
+    fn read_uleb128() {
+        assert_eq!(uleb(&[0x00]).unwrap(), 0);
+        assert_eq!(uleb(&[0x7f]).unwrap(), 127);
+        assert_eq!(uleb(&[0x80, 0x01]).unwrap(), 128);
+        assert_eq!(uleb(&[0xe5, 0x8e, 0x26]).unwrap(), 624485);
+        // 冗長な0x80の続き
+        assert_eq!(uleb(&[0x80, 0x80, 0x00]).unwrap(), 0);
+        assert_eq!(uleb(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).unwrap(), u64::MAX);
+        // 64ビットを超える分は捨てる
+        assert_eq!(uleb(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]).unwrap(), 0);
+        assert!(uleb(&[0x80]).is_err());
+        assert!(uleb(&[]).is_err());
+    }
+
+    #[test]
+    fn read_sleb128() {
+        assert_eq!(sleb(&[0x02]).unwrap(), 2);
+        assert_eq!(sleb(&[0x7e]).unwrap(), -2);
+        assert_eq!(sleb(&[0xff, 0x00]).unwrap(), 127);
+        assert_eq!(sleb(&[0x81, 0x7f]).unwrap(), -127);
+        assert_eq!(sleb(&[0x80, 0x01]).unwrap(), 128);
+        assert_eq!(sleb(&[0x80, 0x7f]).unwrap(), -128);
+        assert_eq!(sleb(&[0xc0, 0xbb, 0x78]).unwrap(), -123456);
+        assert_eq!(sleb(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]).unwrap(), i64::MAX);
+        assert_eq!(sleb(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]).unwrap(), i64::MIN);
+        assert!(sleb(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn read_leb128_advances_offset() {
+        let mut reader = DwarfReader::new(&[0xe5, 0x8e, 0x26, 0x7e]);
+        assert_eq!(reader.read_uleb128().unwrap(), 624485);
+        assert_eq!(reader.get_offset(), 3);
+        assert_eq!(reader.read_sleb128().unwrap(), -2);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let data = [1, 2, 3, 4];
+        let mut reader = DwarfReader::at(&data, 2);
+        assert!(reader.skip(usize::MAX).is_err());
+        assert!(reader.read_bytes(usize::MAX).is_err());
+        assert!(reader.read_u32().is_err());
+        assert_eq!(reader.get_offset(), 2);
+        assert_eq!(reader.read_bytes(2).unwrap(), &[3, 4]);
+        assert!(reader.read_cstr().is_err());
+    }
+
+    #[test]
+    fn offset_after() {
+        let reader = DwarfReader::at(&[0; 4], 2);
+        assert_eq!(reader.offset_after(10).unwrap(), 12);
+        assert!(reader.offset_after(u64::MAX).is_err());
+        assert!(reader.offset_after(usize::MAX as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn read_initial_length() {
+        let mut reader = DwarfReader::new(&[0x10, 0x00, 0x00, 0x00]);
+        assert_eq!(reader.read_initial_length().unwrap(), (0x10, false));
+        let mut reader = DwarfReader::new(&[0xff, 0xff, 0xff, 0xff, 0x20, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(reader.read_initial_length().unwrap(), (0x20, true));
+    }
+}
@@ -0,0 +1,541 @@
+use anyhow::{Result, bail, Context};
+
+use crate::dwarf_info::{DebugInfo, Type};
+use crate::value::{self, Scalar, Value, ValueType};
+
+// print などで式を評価する時の変数・レジスタ・メモリの取得元
+pub trait EvalContext {
+    fn get_debug_info(&self) -> Option<&DebugInfo>;
+    fn lookup_variable(&self, name: &str) -> Result<Option<Value>>;
+    fn read_register(&self, name: &str) -> Result<Option<Value>>;
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>>;
+    fn write_memory(&self, addr: u64, bytes: &[u8]) -> Result<()>;
+    // $reg = value. レジスタでなければfalse
+    fn write_register(&self, name: &str, value: &Value) -> Result<bool>;
+    // {type}addr の型名を探す
+    fn lookup_type(&self, name: &str) -> Option<ValueType>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(u64),
+    Float(f64),
+    Char(u32),
+    Ident(String),
+    Dollar(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 30] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~", "(", ")", "[", "]", "{", "}", ".", ",", "=",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens: Vec<Token> = vec![];
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        if c.is_whitespace() {
+            idx += 1;
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '.' || chars[idx] == '_') {
+                idx += 1;
+            }
+            let literal: String = chars[start..idx].iter().filter(|c| **c != '_').collect();
+            tokens.push(parse_number(&literal)?);
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = idx;
+            idx += 1;
+            // a::b::c のようなパスもひとつの識別子として扱う
+            while idx < chars.len() {
+                if chars[idx].is_alphanumeric() || chars[idx] == '_' {
+                    idx += 1;
+                } else if chars[idx] == ':' && idx + 2 < chars.len() && chars[idx + 1] == ':' && (chars[idx + 2].is_alphabetic() || chars[idx + 2] == '_') {
+                    idx += 2;
+                } else {
+                    break;
+                }
+            }
+            let word: String = chars[start..idx].iter().collect();
+            if let Some(name) = word.strip_prefix('$') {
+                tokens.push(Token::Dollar(name.to_owned()));
+            } else {
+                tokens.push(Token::Ident(word));
+            }
+            continue;
+        }
+        if c == '\'' {
+            if idx + 2 < chars.len() && chars[idx + 2] == '\'' {
+                tokens.push(Token::Char(chars[idx + 1] as u32));
+                idx += 3;
+                continue;
+            }
+            bail!("Unmatched single quote.");
+        }
+        let rest: String = chars[idx..chars.len().min(idx + 2)].iter().collect();
+        match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => {
+                tokens.push(Token::Op(op));
+                idx += op.len();
+            },
+            None => bail!("Invalid character '{}' in expression.", c),
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(literal: &str) -> Result<Token> {
+    let lower = literal.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return Ok(Token::Int(u64::from_str_radix(hex, 16).with_context(|| format!("Invalid number \"{}\".", literal))?));
+    }
+    if let Some(bin) = lower.strip_prefix("0b") {
+        return Ok(Token::Int(u64::from_str_radix(bin, 2).with_context(|| format!("Invalid number \"{}\".", literal))?));
+    }
+    if lower.contains('.') || lower.contains('e') {
+        return Ok(Token::Float(lower.parse::<f64>().with_context(|| format!("Invalid number \"{}\".", literal))?));
+    }
+    Ok(Token::Int(lower.parse::<u64>().with_context(|| format!("Invalid number \"{}\".", literal))?))
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Int(u64),
+    Float(f64),
+    Char(u32),
+    Variable(String),
+    Dollar(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    // {type}addr
+    Memory(String, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+// 優先度の低い順
+const BINARY_PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos) }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            other => bail!("A syntax error in expression, expected '{}' but {:?}.", op, other),
+        }
+    }
+
+    // 代入は右結合で一番優先度が低い
+    fn parse_assign(&mut self) -> Result<Expr> {
+        let lhs = self.parse_binary(0)?;
+        if self.peek() == Some(&Token::Op("=")) {
+            self.pos += 1;
+            let rhs = self.parse_assign()?;
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        if level >= BINARY_PRECEDENCE.len() { return self.parse_unary(); }
+        let mut lhs = self.parse_binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(op)) if BINARY_PRECEDENCE[level].contains(op) => *op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if ["-", "!", "~", "*", "&", "+"].contains(&op) {
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                return Ok(if op == "+" { operand } else { Expr::Unary(op, Box::new(operand)) });
+            }
+            if op == "{" {
+                self.pos += 1;
+                let mut words: Vec<String> = vec![];
+                let mut pointers = 0;
+                loop {
+                    match self.next() {
+                        Some(Token::Ident(word)) if pointers == 0 => words.push(word),
+                        Some(Token::Op("*")) => pointers += 1,
+                        Some(Token::Op("}")) if !words.is_empty() => break,
+                        other => bail!("A syntax error in expression, near {:?}.", other),
+                    }
+                }
+                let type_name = format!("{}{}", words.join(" "), "*".repeat(pointers));
+                let operand = self.parse_unary()?;
+                return Ok(Expr::Memory(type_name, Box::new(operand)));
+            }
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Op(".")) | Some(Token::Op("->")) => {
+                    let is_arrow = self.peek() == Some(&Token::Op("->"));
+                    self.pos += 1;
+                    let field = match self.next() {
+                        Some(Token::Ident(field)) => field,
+                        // タプルのメンバー (t.0)
+                        Some(Token::Int(idx)) => format!("__{}", idx),
+                        other => bail!("A syntax error in expression, expected field name but {:?}.", other),
+                    };
+                    if is_arrow { expr = Expr::Unary("*", Box::new(expr)); }
+                    expr = Expr::Member(Box::new(expr), field);
+                },
+                Some(Token::Op("[")) => {
+                    self.pos += 1;
+                    let index = self.parse_binary(0)?;
+                    self.expect("]")?;
+                    expr = Expr::Index(Box::new(expr), Box::new(index));
+                },
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Int(value)) => Ok(Expr::Int(value)),
+            Some(Token::Float(value)) => Ok(Expr::Float(value)),
+            Some(Token::Char(value)) => Ok(Expr::Char(value)),
+            Some(Token::Ident(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Dollar(name)) => Ok(Expr::Dollar(name)),
+            Some(Token::Op("(")) => {
+                let expr = self.parse_assign()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            other => bail!("A syntax error in expression, near {:?}.", other),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() { bail!("Argument required (expression to compute)."); }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_assign()?;
+    if parser.pos < parser.tokens.len() {
+        bail!("A syntax error in expression, near {:?}.", parser.tokens[parser.pos]);
+    }
+    Ok(expr)
+}
+
+pub fn evaluate_str(ctx: &dyn EvalContext, input: &str) -> Result<Value> {
+    evaluate(ctx, &parse(input)?)
+}
+
+pub fn evaluate(ctx: &dyn EvalContext, expr: &Expr) -> Result<Value> {
+    let debug_info = ctx.get_debug_info();
+    match expr {
+        Expr::Int(value) => {
+            if *value > i64::MAX as u64 { Ok(Value::from_u64(*value)) } else { Ok(Value::from_i64(*value as i64)) }
+        },
+        Expr::Float(value) => Ok(Value::from_f64(*value)),
+        Expr::Char(value) => Ok(Value::new(ValueType::Char, value.to_le_bytes().to_vec(), None)),
+        Expr::Variable(name) => match ctx.lookup_variable(name)? {
+            Some(value) => Ok(value),
+            None => match name.as_str() {
+                "true" => Ok(Value::from_bool(true)),
+                "false" => Ok(Value::from_bool(false)),
+                _ => bail!("No symbol \"{}\" in current context.", name),
+            },
+        },
+        Expr::Dollar(name) => match ctx.read_register(name)? {
+            Some(value) => Ok(value),
+            None => bail!("Unknown register or value history \"${}\".", name),
+        },
+        Expr::Unary(op, operand) => {
+            let value = evaluate(ctx, operand)?;
+            match *op {
+                "*" => dereference(ctx, &value),
+                "&" => match value.get_address() {
+                    Some(addr) => Ok(Value::pointer(value.get_type().clone(), addr)),
+                    None => bail!("Attempt to take address of value not located in memory."),
+                },
+                "-" => match value::to_scalar(debug_info, &value)? {
+                    Scalar::Int(value) => Ok(Value::from_i64(value.wrapping_neg())),
+                    Scalar::Float(value) => Ok(Value::from_f64(-value)),
+                },
+                "!" => Ok(Value::from_bool(!is_true(debug_info, &value)?)),
+                _ => match value::to_scalar(debug_info, &value)? {
+                    Scalar::Int(value) => Ok(Value::from_i64(!value)),
+                    Scalar::Float(_) => bail!("Argument to complement operation not an integer."),
+                },
+            }
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = evaluate(ctx, lhs)?;
+            // && と || は短絡評価する
+            if *op == "&&" || *op == "||" {
+                let lhs_true = is_true(debug_info, &lhs)?;
+                if (*op == "&&" && !lhs_true) || (*op == "||" && lhs_true) {
+                    return Ok(Value::from_bool(lhs_true));
+                }
+                let rhs = evaluate(ctx, rhs)?;
+                return Ok(Value::from_bool(is_true(debug_info, &rhs)?));
+            }
+            let rhs = evaluate(ctx, rhs)?;
+            binary_operation(ctx, op, &lhs, &rhs)
+        },
+        Expr::Member(operand, field) => {
+            let mut value = evaluate(ctx, operand)?;
+            // Rustのように参照越しのメンバーアクセスも許す
+            for _ in 0..8 {
+                if !is_pointer(debug_info, value.get_type()) { break; }
+                value = dereference(ctx, &value)?;
+            }
+            get_member(debug_info, &value, field)
+        },
+        Expr::Memory(type_name, operand) => {
+            let ty = lookup_type(ctx, type_name)?;
+            let addr = evaluate(ctx, operand)?.raw_u64();
+            read_value(ctx, ty, addr)
+        },
+        Expr::Assign(lhs, rhs) => {
+            if let Expr::Dollar(name) = lhs.as_ref() {
+                let value = evaluate(ctx, rhs)?;
+                if ctx.write_register(name, &value)? {
+                    return evaluate(ctx, lhs);
+                }
+                bail!("Left operand of assignment is not a modifiable lvalue.");
+            }
+            let target = evaluate(ctx, lhs)?;
+            let addr = target.get_address().context("Left operand of assignment is not an lvalue.")?;
+            let value = evaluate(ctx, rhs)?;
+            let bytes = value::convert_to_bytes(debug_info, &value, target.get_type())?;
+            ctx.write_memory(addr, &bytes)?;
+            Ok(Value::new(target.get_type().clone(), bytes, Some(addr)))
+        },
+        Expr::Index(operand, index) => {
+            let value = evaluate(ctx, operand)?;
+            let index = match value::to_scalar(debug_info, &evaluate(ctx, index)?)? {
+                Scalar::Int(index) => index,
+                Scalar::Float(_) => bail!("Array index must be an integer."),
+            };
+            if let ValueType::Vector(element, count) = value.get_type() {
+                if index < 0 || index as usize >= *count {
+                    bail!("no such vector element");
+                }
+                let element_size = value::type_size(debug_info, element);
+                let offset = (index as u64).checked_mul(element_size).context("no such vector element")?;
+                return Ok(value::element_value(&value, offset, element_size, (**element).clone()));
+            }
+            match value::resolve_type(debug_info, value.get_type()) {
+                Some(Type::Array { target, count }) => {
+                    let target = target.context("Array element type is unknown.")?;
+                    if index < 0 || count.is_some_and(|count| index as u64 >= count) {
+                        bail!("no such vector element");
+                    }
+                    let element_size = value::type_size(debug_info, &ValueType::Dwarf(target));
+                    // 要素数が分からない配列や大きい要素では, 大きい添字でオーバーフローする
+                    let offset = (index as u64).checked_mul(element_size).context("no such vector element")?;
+                    let end = offset.checked_add(element_size).context("no such vector element")?;
+                    if end <= value.get_bytes().len() as u64 {
+                        return Ok(value::member_value(debug_info, &value, offset, Some(target)));
+                    }
+                    let addr = value.get_address().context("Array is not located in memory.")?.checked_add(offset).context("no such vector element")?;
+                    read_value(ctx, ValueType::Dwarf(target), addr)
+                },
+                _ => {
+                    let pointer = binary_operation(ctx, "+", &value, &Value::from_i64(index))?;
+                    dereference(ctx, &pointer)
+                },
+            }
+        },
+    }
+}
+
+// 型名の後ろの*はポインタにする
+fn lookup_type(ctx: &dyn EvalContext, name: &str) -> Result<ValueType> {
+    if let Some(target) = name.strip_suffix('*') {
+        return Ok(ValueType::Pointer(Box::new(lookup_type(ctx, target)?)));
+    }
+    if let Some(ty) = ctx.lookup_type(name) {
+        return Ok(ty);
+    }
+    let ty = match name {
+        "i8" | "signed char" => ValueType::Int { size: 1, signed: true },
+        "u8" | "char" | "unsigned char" => ValueType::Int { size: 1, signed: false },
+        "i16" | "short" => ValueType::Int { size: 2, signed: true },
+        "u16" | "unsigned short" => ValueType::Int { size: 2, signed: false },
+        "i32" | "int" => ValueType::Int { size: 4, signed: true },
+        "u32" | "unsigned" | "unsigned int" => ValueType::Int { size: 4, signed: false },
+        "i64" | "isize" | "long" | "long long" => ValueType::Int { size: 8, signed: true },
+        "u64" | "usize" | "unsigned long" | "unsigned long long" => ValueType::Int { size: 8, signed: false },
+        "f32" | "float" => ValueType::Float { size: 4 },
+        "f64" | "double" => ValueType::Float { size: 8 },
+        "bool" => ValueType::Bool,
+        _ => bail!("No symbol \"{}\" in current context.", name),
+    };
+    Ok(ty)
+}
+
+pub fn is_true(debug_info: Option<&DebugInfo>, value: &Value) -> Result<bool> {
+    match value::to_scalar(debug_info, value)? {
+        Scalar::Int(value) => Ok(value != 0),
+        Scalar::Float(value) => Ok(value != 0.0),
+    }
+}
+
+fn is_pointer(debug_info: Option<&DebugInfo>, ty: &ValueType) -> bool {
+    match ty {
+        ValueType::Pointer(_) => true,
+        ValueType::Dwarf(_) => matches!(value::resolve_type(debug_info, ty), Some(Type::Pointer { .. })),
+        _ => false,
+    }
+}
+
+fn pointer_target(debug_info: Option<&DebugInfo>, ty: &ValueType) -> Option<ValueType> {
+    match ty {
+        ValueType::Pointer(target) => Some((**target).clone()),
+        ValueType::Dwarf(_) => match value::resolve_type(debug_info, ty) {
+            Some(Type::Pointer { target: Some(target), .. }) => Some(ValueType::Dwarf(target)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn read_value(ctx: &dyn EvalContext, ty: ValueType, addr: u64) -> Result<Value> {
+    let size = value::type_size(ctx.get_debug_info(), &ty) as usize;
+    let bytes = ctx.read_memory(addr, size).with_context(|| format!("Cannot access memory at address 0x{:x}", addr))?;
+    Ok(Value::new(ty, bytes, Some(addr)))
+}
+
+fn dereference(ctx: &dyn EvalContext, value: &Value) -> Result<Value> {
+    let debug_info = ctx.get_debug_info();
+    if !is_pointer(debug_info, value.get_type()) {
+        bail!("Attempt to take contents of a non-pointer value.");
+    }
+    let target = pointer_target(debug_info, value.get_type()).context("Attempt to take contents of a non-pointer value.")?;
+    read_value(ctx, target, value.raw_u64())
+}
+
+fn get_member(debug_info: Option<&DebugInfo>, value: &Value, field: &str) -> Result<Value> {
+    if let ValueType::Union(_, members) = value.get_type() {
+        let (_, ty) = members.iter().find(|(name, _)| name == field)
+            .with_context(|| format!("There is no member named {}.", field))?;
+        return Ok(value::element_value(value, 0, value::type_size(debug_info, ty), ty.clone()));
+    }
+    match value::resolve_type(debug_info, value.get_type()) {
+        Some(Type::Struct { members, .. }) => {
+            let member = members.iter().find(|member| member.get_name() == field)
+                .with_context(|| format!("There is no member named {}.", field.trim_start_matches("__")))?;
+            Ok(value::member_value(debug_info, value, member.get_offset(), member.get_type_offset()))
+        },
+        _ => bail!("Attempt to extract a component of a value that is not a structure."),
+    }
+}
+
+fn binary_operation(ctx: &dyn EvalContext, op: &str, lhs: &Value, rhs: &Value) -> Result<Value> {
+    let debug_info = ctx.get_debug_info();
+    // ポインタ ± 整数 は要素サイズ単位で進める
+    if (op == "+" || op == "-") && is_pointer(debug_info, lhs.get_type()) && !is_pointer(debug_info, rhs.get_type()) {
+        let target = pointer_target(debug_info, lhs.get_type());
+        let element_size = target.as_ref().map(|target| value::type_size(debug_info, target)).unwrap_or(1).max(1);
+        let offset = match value::to_scalar(debug_info, rhs)? {
+            Scalar::Int(offset) => offset,
+            Scalar::Float(_) => bail!("Argument to arithmetic operation not a number or boolean."),
+        };
+        let delta = offset.wrapping_mul(element_size as i64) as u64;
+        let addr = if op == "+" { lhs.raw_u64().wrapping_add(delta) } else { lhs.raw_u64().wrapping_sub(delta) };
+        return Ok(Value::new(lhs.get_type().clone(), addr.to_le_bytes().to_vec(), None));
+    }
+
+    let lhs_scalar = value::to_scalar(debug_info, lhs)?;
+    let rhs_scalar = value::to_scalar(debug_info, rhs)?;
+    match (lhs_scalar, rhs_scalar) {
+        (Scalar::Int(a), Scalar::Int(b)) => {
+            let value = match op {
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" => {
+                    if b == 0 { bail!("Division by zero"); }
+                    a.wrapping_div(b)
+                },
+                "%" => {
+                    if b == 0 { bail!("Division by zero"); }
+                    a.wrapping_rem(b)
+                },
+                "<<" => a.checked_shl(b as u32).unwrap_or(0),
+                ">>" => a.checked_shr(b as u32).unwrap_or(0),
+                "&" => a & b,
+                "|" => a | b,
+                "^" => a ^ b,
+                "==" => return Ok(Value::from_bool(a == b)),
+                "!=" => return Ok(Value::from_bool(a != b)),
+                "<" => return Ok(Value::from_bool(a < b)),
+                "<=" => return Ok(Value::from_bool(a <= b)),
+                ">" => return Ok(Value::from_bool(a > b)),
+                ">=" => return Ok(Value::from_bool(a >= b)),
+                _ => bail!("Unsupported operator: {}", op),
+            };
+            Ok(Value::from_i64(value))
+        },
+        (a, b) => {
+            let a = match a { Scalar::Int(a) => a as f64, Scalar::Float(a) => a };
+            let b = match b { Scalar::Int(b) => b as f64, Scalar::Float(b) => b };
+            let value = match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                "==" => return Ok(Value::from_bool(a == b)),
+                "!=" => return Ok(Value::from_bool(a != b)),
+                "<" => return Ok(Value::from_bool(a < b)),
+                "<=" => return Ok(Value::from_bool(a <= b)),
+                ">" => return Ok(Value::from_bool(a > b)),
+                ">=" => return Ok(Value::from_bool(a >= b)),
+                _ => bail!("Integer only operation {}.", op),
+            };
+            Ok(Value::from_f64(value))
+        },
+    }
+}
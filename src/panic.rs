@@ -0,0 +1,51 @@
+use anyhow::Result;
+use std::convert::TryInto;
+
+// PanicInfoを受け取るパニックハンドラ. 新しいstdでは begin_panic_handler が rust_begin_unwind の名前になっている
+pub const HANDLER_FUNCTIONS: [&str; 2] = ["rust_begin_unwind", "std::panicking::begin_panic_handler"];
+// フックを呼んだ後, 巻き戻しを始める直前に呼ばれる関数. 引数は &mut dyn PanicPayload
+pub const RAISE_FUNCTIONS: [&str; 2] = ["rust_panic", "__rust_start_panic"];
+
+// 文字列として読む長さの上限
+const MAX_STRING_LENGTH: u64 = 0x10000;
+
+fn read_u64(read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, addr: u64) -> Option<u64> {
+    let bytes = read_memory(addr, 8).ok()?;
+    Some(u64::from_le_bytes(bytes.as_slice().try_into().ok()?))
+}
+
+// ptrからlenバイトをUTF-8の文字列として読む. 読めなければNone
+fn read_str(read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, ptr: u64, len: u64) -> Option<String> {
+    if ptr == 0 || len == 0 || len > MAX_STRING_LENGTH { return None; }
+    let bytes = read_memory(ptr, len as usize).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+// Location { file: &str, line: u32, col: u32 } を "src/main.rs:2:18" の形で読む
+fn read_location_at(read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, addr: u64) -> Option<String> {
+    let bytes = read_memory(addr, 24).ok()?;
+    let word = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    let half = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let (line, col) = (half(16), half(20));
+    if line == 0 { return None; }
+    let file = read_str(read_memory, word(0), word(8))?;
+    Some(format!("{}:{}:{}", file, line, col))
+}
+
+// パニックハンドラの引数の &PanicInfo から場所を読む. PanicInfoのフィールドの並びは決まっていないので,
+// 先頭の2つのポインタのうちLocationとして読める方を使う
+pub fn read_location(read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, info: u64) -> Option<String> {
+    (0..2).find_map(|idx| {
+        let location = read_u64(read_memory, info + idx * 8)?;
+        read_location_at(read_memory, location)
+    })
+}
+
+// &mut dyn PanicPayload のデータとvtableからメッセージを読む. フックを呼んだ後なのでformat!のメッセージも組み立て済み.
+// ペイロードの型はstdの中のものでDWARFが無いので, (ポインタ, 長さ) と読める連続した2語を探す
+pub fn read_message(read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, data: u64, vtable: u64) -> Option<String> {
+    // vtableは drop_in_place, サイズ, アラインメント, メソッド... の順
+    let size = read_u64(read_memory, vtable + 8)?.min(64);
+    let words: Vec<u64> = (0..size / 8).map_while(|idx| read_u64(read_memory, data + idx * 8)).collect();
+    words.windows(2).find_map(|pair| read_str(read_memory, pair[0], pair[1]))
+}
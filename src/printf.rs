@@ -0,0 +1,455 @@
+use anyhow::{Result, bail};
+
+use crate::dwarf_info::DebugInfo;
+use crate::value::{self, Scalar, Value};
+
+// %の後ろの指定. 例えば %-08.3lx なら flags="-0", width=8, precision=3, length="l", conversion='x'
+// 幅と精度の上限. これより大きいと巨大な文字列を作ることになる
+const MAX_FIELD_WIDTH: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    flags: String,
+    width: Option<usize>,
+    precision: Option<usize>,
+    length: String,
+    conversion: char,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Conversion(Spec),
+}
+
+// gdbのprintfと同じ "書式", 式, ... を書式と式に分ける
+pub fn split_arguments(input: &str) -> Result<(String, Vec<String>)> {
+    let input = input.trim();
+    let mut chars = match input.strip_prefix('"') {
+        Some(rest) => rest.chars(),
+        None => bail!("Bad format string"),
+    };
+    let mut format = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => format.push('\n'),
+                Some('t') => format.push('\t'),
+                Some('r') => format.push('\r'),
+                Some('a') => format.push('\x07'),
+                Some('e') => format.push('\x1b'),
+                Some('\\') => format.push('\\'),
+                Some('"') => format.push('"'),
+                Some(c) => bail!("Unrecognized escape character \\{} in format string.", c),
+                None => bail!("Bad format string, non-terminated '\"'"),
+            },
+            Some(c) => format.push(c),
+            None => bail!("Bad format string, non-terminated '\"'"),
+        }
+    }
+    let rest = chars.as_str().trim();
+    if rest.is_empty() { return Ok((format, vec![])); }
+    let rest = match rest.strip_prefix(',') {
+        Some(rest) => rest,
+        None => bail!("Invalid argument syntax"),
+    };
+    Ok((format, split_top_level(rest)?))
+}
+
+// 括弧や文字列の中にないカンマで分ける
+fn split_top_level(input: &str) -> Result<Vec<String>> {
+    let mut args = vec![];
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut current = String::new();
+    for c in input.chars() {
+        match quote {
+            Some(q) => {
+                if escaped { escaped = false; } else if c == '\\' { escaped = true; } else if c == q { quote = None; }
+            },
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    args.push(current.trim().to_owned());
+                    current.clear();
+                    continue;
+                },
+                _ => {},
+            },
+        }
+        current.push(c);
+    }
+    args.push(current.trim().to_owned());
+    if args.iter().any(|arg| arg.is_empty()) { bail!("Invalid argument syntax"); }
+    Ok(args)
+}
+
+fn parse_format(format: &str) -> Result<Vec<Piece>> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            text.push('%');
+            continue;
+        }
+        let mut spec = Spec { flags: String::new(), width: None, precision: None, length: String::new(), conversion: ' ' };
+        while let Some(flag) = chars.next_if(|c| "-+ #0".contains(*c)) {
+            spec.flags.push(flag);
+        }
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(digit);
+        }
+        if !digits.is_empty() { spec.width = Some(parse_field_width(&digits)?); }
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                digits.push(digit);
+            }
+            spec.precision = Some(if digits.is_empty() { 0 } else { parse_field_width(&digits)? });
+        }
+        while let Some(length) = chars.next_if(|c| "hlLqjzt".contains(*c)) {
+            spec.length.push(length);
+        }
+        spec.conversion = match chars.next() {
+            Some(conversion) if "diuxXocsfFeEgGp".contains(conversion) => conversion,
+            Some('*') => bail!("`*' not supported for precision or width in printf"),
+            Some(conversion) => bail!("Unrecognized format specifier '{}' in printf", conversion.escape_default()),
+            None => bail!("Incomplete format specifier at end of format string"),
+        };
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(Piece::Conversion(spec));
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn parse_field_width(digits: &str) -> Result<usize> {
+    match digits.parse::<usize>() {
+        Ok(width) if width <= MAX_FIELD_WIDTH => Ok(width),
+        _ => bail!("Field width or precision too large in printf"),
+    }
+}
+
+// 式の個数が書式と合うか先に確かめる
+pub fn check(format: &str, args: &[String]) -> Result<()> {
+    let count = parse_format(format)?.iter().filter(|piece| matches!(piece, Piece::Conversion(_))).count();
+    if count != args.len() { bail!("Wrong number of arguments for specified format-string"); }
+    Ok(())
+}
+
+pub fn format(debug_info: Option<&DebugInfo>, format: &str, values: &[Value], read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Result<String> {
+    let pieces = parse_format(format)?;
+    let mut values = values.iter();
+    let mut output = String::new();
+    for piece in pieces.iter() {
+        let spec = match piece {
+            Piece::Text(text) => {
+                output.push_str(text);
+                continue;
+            },
+            Piece::Conversion(spec) => spec,
+        };
+        let value = match values.next() {
+            Some(value) => value,
+            None => bail!("Wrong number of arguments for specified format-string"),
+        };
+        output.push_str(&format_conversion(debug_info, spec, value, read_memory)?);
+    }
+    if values.next().is_some() { bail!("Wrong number of arguments for specified format-string"); }
+    Ok(output)
+}
+
+fn format_conversion(debug_info: Option<&DebugInfo>, spec: &Spec, value: &Value, read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Result<String> {
+    let (prefix, body) = match spec.conversion {
+        's' => {
+            let mut s = match value::string_contents(debug_info, value, read_memory) {
+                Some(s) => s,
+                None => bail!("Value can't be converted to a string."),
+            };
+            if let Some(precision) = spec.precision {
+                s = s.chars().take(precision).collect();
+            }
+            return Ok(pad(spec, "", &s, false));
+        },
+        'c' => {
+            let c = char::from_u32(as_int(debug_info, value)? as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+            return Ok(pad(spec, "", &c.to_string(), false));
+        },
+        'p' => ("0x".to_owned(), format!("{:x}", value.raw_u64())),
+        'd' | 'i' => {
+            let number = sign_extend(as_int(debug_info, value)?, bits(spec));
+            (sign_prefix(spec, number < 0), apply_precision(spec, number.unsigned_abs().to_string()))
+        },
+        'u' | 'x' | 'X' | 'o' => {
+            let number = truncate(as_int(debug_info, value)?, bits(spec));
+            let digits = match spec.conversion {
+                'u' => number.to_string(),
+                'x' => format!("{:x}", number),
+                'X' => format!("{:X}", number),
+                _ => format!("{:o}", number),
+            };
+            let digits = apply_precision(spec, digits);
+            // %#o は先頭が0になるようにする. 精度0で0を表示する時も "0" になる
+            let prefix = match spec.conversion {
+                'x' if spec.flags.contains('#') && number != 0 => "0x",
+                'X' if spec.flags.contains('#') && number != 0 => "0X",
+                'o' if spec.flags.contains('#') && !digits.starts_with('0') => "0",
+                _ => "",
+            };
+            (prefix.to_owned(), digits)
+        },
+        _ => {
+            let number = match value::to_scalar(debug_info, value)? {
+                Scalar::Float(number) => number,
+                Scalar::Int(number) => number as f64,
+            };
+            let body = format_float(spec, number.abs());
+            let prefix = sign_prefix(spec, number.is_sign_negative() && !number.is_nan());
+            // infやnanは0で埋めない
+            if !number.is_finite() { return Ok(pad(spec, &prefix, &body, false)); }
+            (prefix, body)
+        },
+    };
+    let zero = spec.flags.contains('0') && !spec.flags.contains('-') && (spec.precision.is_none() || "fFeEgG".contains(spec.conversion));
+    Ok(pad(spec, &prefix, &body, zero))
+}
+
+fn as_int(debug_info: Option<&DebugInfo>, value: &Value) -> Result<i64> {
+    match value::to_scalar(debug_info, value)? {
+        Scalar::Int(number) => Ok(number),
+        Scalar::Float(number) => Ok(number as i64),
+    }
+}
+
+// 長さの指定が無ければCと同じくintとして扱う
+fn bits(spec: &Spec) -> u32 {
+    match spec.length.as_str() {
+        "" => 32,
+        "h" => 16,
+        "hh" => 8,
+        _ => 64,
+    }
+}
+
+fn truncate(number: i64, bits: u32) -> u64 {
+    if bits >= 64 { number as u64 } else { number as u64 & ((1 << bits) - 1) }
+}
+
+fn sign_extend(number: i64, bits: u32) -> i64 {
+    if bits >= 64 { number } else { ((number as u64) << (64 - bits)) as i64 >> (64 - bits) }
+}
+
+fn sign_prefix(spec: &Spec, negative: bool) -> String {
+    if negative { "-".to_owned() }
+    else if spec.flags.contains('+') { "+".to_owned() }
+    else if spec.flags.contains(' ') { " ".to_owned() }
+    else { "".to_owned() }
+}
+
+// 整数の精度は最低限の桁数
+fn apply_precision(spec: &Spec, digits: String) -> String {
+    match spec.precision {
+        Some(0) if digits == "0" => "".to_owned(),
+        Some(precision) if digits.len() < precision => format!("{}{}", "0".repeat(precision - digits.len()), digits),
+        _ => digits,
+    }
+}
+
+fn format_float(spec: &Spec, number: f64) -> String {
+    if number.is_nan() { return if spec.conversion.is_uppercase() { "NAN".to_owned() } else { "nan".to_owned() }; }
+    if number.is_infinite() { return if spec.conversion.is_uppercase() { "INF".to_owned() } else { "inf".to_owned() }; }
+    let precision = spec.precision.unwrap_or(6);
+    let body = match spec.conversion {
+        'f' | 'F' => format!("{:.*}", precision, number),
+        'e' | 'E' => format_exponent(number, precision),
+        _ => {
+            // %g は指数が -4 以上で精度より小さければ %f, それ以外は %e. 末尾の0は消す
+            let precision = precision.max(1);
+            let exponent = if number == 0.0 { 0 } else { format!("{:.*e}", precision - 1, number).split_once('e').map(|(_, exponent)| exponent.parse::<i32>().unwrap_or(0)).unwrap_or(0) };
+            let body = if exponent < -4 || exponent >= precision as i32 {
+                format_exponent(number, precision - 1)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent).max(0) as usize, number)
+            };
+            if spec.flags.contains('#') { body } else { strip_trailing_zeros(&body) }
+        },
+    };
+    // %# は小数部が無くても小数点を残す
+    let body = if spec.flags.contains('#') && !body.contains('.') {
+        match body.find('e') {
+            Some(idx) => format!("{}.{}", &body[..idx], &body[idx..]),
+            None => format!("{}.", body),
+        }
+    } else {
+        body
+    };
+    if spec.conversion.is_uppercase() { body.to_uppercase() } else { body }
+}
+
+// Rustの {:e} は 1.5e2 になるので, Cと同じ 1.500000e+02 の形にする
+fn format_exponent(number: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, number);
+    match s.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent: i32 = exponent.parse().unwrap_or(0);
+            format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+        },
+        None => s,
+    }
+}
+
+fn strip_trailing_zeros(body: &str) -> String {
+    let (mantissa, exponent) = match body.find('e') {
+        Some(idx) => body.split_at(idx),
+        None => (body, ""),
+    };
+    let mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa };
+    format!("{}{}", mantissa, exponent)
+}
+
+// 幅に足りない分を埋める. 0埋めは符号や0xの後ろに入れる
+fn pad(spec: &Spec, prefix: &str, body: &str, zero: bool) -> String {
+    let len = prefix.chars().count() + body.chars().count();
+    let width = spec.width.unwrap_or(0);
+    if len >= width { return format!("{}{}", prefix, body); }
+    let fill = width - len;
+    if spec.flags.contains('-') {
+        format!("{}{}{}", prefix, body, " ".repeat(fill))
+    } else if zero {
+        format!("{}{}{}", prefix, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), prefix, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(format: &str) -> Spec {
+        match parse_format(format).unwrap().pop() {
+            Some(Piece::Conversion(spec)) => spec,
+            piece => panic!("{:?}", piece),
+        }
+    }
+
+    fn printf(format_str: &str, values: &[Value]) -> String {
+        format(None, format_str, values, &|_, _| bail!("no memory")).unwrap()
+    }
+
+    #[test]
+    fn parse_spec() {
+        let pieces = parse_format("x=%-08.3lx%%y\n").unwrap();
+        assert_eq!(pieces, vec![
+            Piece::Text("x=".to_owned()),
+            Piece::Conversion(Spec { flags: "-0".to_owned(), width: Some(8), precision: Some(3), length: "l".to_owned(), conversion: 'x' }),
+            Piece::Text("%y\n".to_owned()),
+        ]);
+        assert_eq!(spec("%.f").precision, Some(0));
+        assert_eq!(spec("%# +5hhd").flags, "# +");
+        assert!(parse_format("%*d").is_err());
+        assert!(parse_format("%k").is_err());
+        assert!(parse_format("abc%").is_err());
+    }
+
+    #[test]
+    fn huge_width_and_precision() {
+        assert!(parse_format("%999999999999d").is_err());
+        assert!(parse_format("%.999999999999f").is_err());
+        assert!(parse_format("%99999999999999999999999d").is_err());
+        assert_eq!(spec("%1000.1000f").width, Some(1000));
+    }
+
+    #[test]
+    fn split_arguments_at_top_level() {
+        assert_eq!(split_top_level("a, f(b, c), \"x,y\", ',', s[1,2]").unwrap(), vec!["a", "f(b, c)", "\"x,y\"", "','", "s[1,2]"]);
+        assert_eq!(split_top_level("\"a\\\",b\", c").unwrap(), vec!["\"a\\\",b\"", "c"]);
+        assert!(split_top_level("a,,b").is_err());
+        assert!(split_top_level("a,").is_err());
+        assert_eq!(split_arguments("\"%d\\t%s\\n\", x, y").unwrap(), ("%d\t%s\n".to_owned(), vec!["x".to_owned(), "y".to_owned()]));
+        assert!(split_arguments("\"%d\" x").is_err());
+    }
+
+    // 期待値はglibcのprintfの出力
+    #[test]
+    fn float_conversions() {
+        let cases: &[(&str, f64, &str)] = &[
+            ("%g", 0.0001, "0.0001"),
+            ("%g", 100000.0, "100000"),
+            ("%g", 1000000.0, "1e+06"),
+            ("%g", 1.5, "1.5"),
+            ("%g", 0.00001234, "1.234e-05"),
+            ("%#g", 1.5, "1.50000"),
+            ("%.3g", 1.23456, "1.23"),
+            ("%g", 0.0, "0"),
+            ("%G", 1e-10, "1E-10"),
+            ("%.0g", 123.0, "1e+02"),
+            ("%#.3g", 100.0, "100."),
+            ("%e", 1234.5, "1.234500e+03"),
+            ("%.0e", 12345.0, "1e+04"),
+            ("%#.0e", 12345.0, "1.e+04"),
+            ("%E", 0.5, "5.000000E-01"),
+            ("%f", 3.0, "3.000000"),
+            ("%.0f", 2.5, "2"),
+            ("%.0f", 3.5, "4"),
+            ("%#.0f", 2.0, "2."),
+        ];
+        for (format_str, number, expected) in cases {
+            assert_eq!(format_float(&spec(format_str), *number), *expected, "{} {}", format_str, number);
+        }
+    }
+
+    #[test]
+    fn integer_prefixes() {
+        assert_eq!(printf("%#x", &[Value::from_i64(255)]), "0xff");
+        assert_eq!(printf("%#X", &[Value::from_i64(255)]), "0XFF");
+        assert_eq!(printf("%#o", &[Value::from_i64(8)]), "010");
+        assert_eq!(printf("%#x", &[Value::from_i64(0)]), "0");
+        assert_eq!(printf("%#o", &[Value::from_i64(0)]), "0");
+        assert_eq!(printf("%#.0o", &[Value::from_i64(0)]), "0");
+        assert_eq!(printf("%#.3o", &[Value::from_i64(8)]), "010");
+        assert_eq!(printf("%.0d", &[Value::from_i64(0)]), "");
+        assert_eq!(printf("%x", &[Value::from_i64(-1)]), "ffffffff");
+        assert_eq!(printf("%lx", &[Value::from_i64(-1)]), "ffffffffffffffff");
+        assert_eq!(printf("%hhd", &[Value::from_i64(255)]), "-1");
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(printf("%08.3f", &[Value::from_f64(-1.23456)]), "-001.235");
+        assert_eq!(printf("%+06d", &[Value::from_i64(42)]), "+00042");
+        assert_eq!(printf("%#010x", &[Value::from_i64(255)]), "0x000000ff");
+        assert_eq!(printf("%-6d|", &[Value::from_i64(-5)]), "-5    |");
+        assert_eq!(printf("% 05d", &[Value::from_i64(7)]), " 0007");
+        assert_eq!(printf("%08.3d", &[Value::from_i64(5)]), "     005");
+        assert_eq!(printf("%-3c|", &[Value::from_i64('x' as i64)]), "x  |");
+        assert_eq!(printf("%05f", &[Value::from_f64(f64::INFINITY)]), "  inf");
+        assert_eq!(printf("%-8.2e|", &[Value::from_f64(1234.5)]), "1.23e+03|");
+        assert_eq!(printf("%+.1f", &[Value::from_f64(2.25)]), "+2.2");
+        assert_eq!(printf("% g", &[Value::from_f64(2.0)]), " 2");
+        assert_eq!(printf("%+e", &[Value::from_f64(-1.0)]), "-1.000000e+00");
+        assert_eq!(printf("%010.2g", &[Value::from_f64(-0.000123)]), "-000.00012");
+        assert_eq!(printf("%.2f", &[Value::from_f64(-0.004)]), "-0.00");
+
+        assert_eq!(pad(&spec("%5s"), "", "ab", false), "   ab");
+        assert_eq!(pad(&spec("%-5s"), "", "ab", false), "ab   ");
+        assert_eq!(pad(&spec("%06x"), "0x", "ff", true), "0x00ff");
+        assert_eq!(pad(&spec("%6x"), "0x", "ff", false), "  0xff");
+        assert_eq!(pad(&spec("%2x"), "0x", "ff", true), "0xff");
+    }
+}
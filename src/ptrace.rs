@@ -1,27 +1,159 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use nix::sys::{ptrace, signal};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::Pid;
+use nix::unistd::{Pid, ForkResult, fork, execvp, setpgid};
 use nix::libc::{self, user_regs_struct};
 use nix::errno::Errno;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicI32, Ordering};
 
+use crate::thread;
+
+const ADDR_NO_RANDOMIZE: libc::c_ulong = 0x0040000;
+const PTRACE_GET_SYSCALL_INFO: libc::c_uint = 0x420e;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+
+// Ctrl-Cで止めるスレッド. 0なら何もしない
+static INTERRUPT_TARGET: AtomicI32 = AtomicI32::new(0);
+// Ctrl-CでPTRACE_INTERRUPTを送ったスレッド. その停止をまだ受け取っていなければ0以外
+static INTERRUPTED: AtomicI32 = AtomicI32::new(0);
+
+// PTRACE_ATTACHと違ってSIGSTOPを送らないので, トレースされている側からは見えない.
+// オプションも同時に設定し, PTRACE_INTERRUPTで止める. 止まったことはwaitで受け取る
 pub fn attach(pid: Pid) -> Result<()> {
-    ptrace::attach(pid)?;
+    seize(pid)?;
+    interrupt(pid)
+}
+
+// 新しいスレッドやforkした子プロセスも自動的にトレースし, execしたこととスレッドが終わる直前も受け取る.
+// PTRACE_SYSCALLで再開した時のシステムコールの停止はSIGTRAPと区別する
+pub fn seize(pid: Pid) -> Result<()> {
+    ptrace::seize(pid, ptrace::Options::PTRACE_O_TRACECLONE | ptrace::Options::PTRACE_O_TRACEFORK | ptrace::Options::PTRACE_O_TRACEVFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORKDONE | ptrace::Options::PTRACE_O_TRACEEXEC | ptrace::Options::PTRACE_O_TRACEEXIT
+        | ptrace::Options::PTRACE_O_TRACESYSGOOD)?;
+    Ok(())
+}
+
+// 動いているスレッドをPTRACE_EVENT_STOPで止める. SEIZEしたスレッドにだけ使える
+pub fn interrupt(tid: Pid) -> Result<()> {
+    Errno::result(unsafe { libc::ptrace(libc::PTRACE_INTERRUPT, libc::pid_t::from(tid), 0, 0) })?;
+    Ok(())
+}
+
+// グループストップしているスレッドを, 止まったまま再開する. SIGCONTで動き出す
+pub fn listen(tid: Pid) -> Result<()> {
+    Errno::result(unsafe { libc::ptrace(libc::PTRACE_LISTEN, libc::pid_t::from(tid), 0, 0) })?;
     Ok(())
 }
 
-pub fn traceme() -> Result<()> {
-    ptrace::traceme()?;
+extern "C" fn handle_sigint(_: libc::c_int) {
+    let tid = INTERRUPT_TARGET.load(Ordering::SeqCst);
+    if tid != 0 {
+        unsafe { libc::ptrace(libc::PTRACE_INTERRUPT, tid, 0, 0); }
+        INTERRUPTED.store(tid, Ordering::SeqCst);
+    }
+}
+
+// Ctrl-Cでデバッガが終わらないようにし, 代わりにset_interrupt_targetのスレッドを止める
+pub fn install_interrupt_handler() -> Result<()> {
+    let action = signal::SigAction::new(signal::SigHandler::Handler(handle_sigint), signal::SaFlags::SA_RESTART, signal::SigSet::empty());
+    unsafe { signal::sigaction(signal::SIGINT, &action)?; }
     Ok(())
 }
 
+pub fn set_interrupt_target(tid: Option<Pid>) {
+    INTERRUPT_TARGET.store(tid.map(|tid| tid.as_raw()).unwrap_or(0), Ordering::SeqCst);
+}
+
+// tidの停止がCtrl-Cで止めたものならtrue
+pub fn take_interrupted(tid: Pid) -> bool {
+    INTERRUPTED.compare_exchange(tid.as_raw(), 0, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+}
+
+// Ctrl-Cで止めたのにまだ受け取っていないスレッド
+pub fn take_any_interrupted() -> Option<Pid> {
+    match INTERRUPTED.swap(0, Ordering::SeqCst) {
+        0 => None,
+        tid => Some(Pid::from_raw(tid)),
+    }
+}
+
+// programを子プロセスで起動して, execしたところで止まるまで待つ.
+// gdbと同じくASLRを切るので, 起動し直してもブレークポイントのアドレスは変わらない
+pub fn spawn(program: &[String]) -> Result<Pid> {
+    let path = CString::new(program[0].as_str())?;
+    let args: Vec<CString> = program.iter().map(|arg| CString::new(arg.as_str())).collect::<Result<_, _>>()?;
+    match unsafe { fork()? } {
+        ForkResult::Child => {
+            unsafe { libc::personality(ADDR_NO_RANDOMIZE); }
+            // 端末からのCtrl-Cはデバッガだけが受け取り, PTRACE_INTERRUPTで止める
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+            // デバッガがSEIZEするまで止まって待つ
+            if signal::raise(signal::SIGSTOP).is_ok() {
+                let _ = execvp(&path, &args);
+            }
+            eprintln!("Cannot exec {}.", program[0]);
+            unsafe { libc::_exit(127) }
+        },
+        ForkResult::Parent { child } => {
+            match waitpid(child, Some(WaitPidFlag::WUNTRACED))? {
+                WaitStatus::Stopped(_, signal::SIGSTOP) => {},
+                _ => bail!("During startup program exited."),
+            }
+            seize(child)?;
+            signal::kill(child, signal::SIGCONT)?;
+            // グループストップとSIGCONTを受け取ってから, execで止まる
+            loop {
+                match wait_pid(child)? {
+                    WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => return Ok(child),
+                    WaitStatus::PtraceEvent(..) | WaitStatus::Stopped(..) => cont(child, None)?,
+                    _ => bail!("During startup program exited."),
+                }
+            }
+        },
+    }
+}
+
+// 起動したプロセスを終わらせる. ptraceで止まっていてもSIGKILLなら終わる.
+// 他のインフェリアのイベントを受け取ってしまわないように, このプロセスのスレッドだけを待つ
+pub fn kill(pid: Pid) -> Result<()> {
+    signal::kill(pid, signal::SIGKILL)?;
+    // 終わる直前 (PTRACE_EVENT_EXIT) で止まっているスレッドはSIGKILLでは動かないので, このプロセスのスレッドを再開する.
+    // 動いているスレッドには失敗するだけ
+    for tid in thread::list_tasks(pid).unwrap_or_default().into_iter() {
+        let _ = cont(tid, None);
+    }
+    // 待てなくなったスレッド. 既に受け取ったものか, 他のスレッドが受け取ったもの
+    let mut gone: Vec<Pid> = vec![];
+    loop {
+        // リーダーの終了は, 他のスレッドの終了を全て受け取るまで届かない
+        let tids = thread::list_tasks(pid).unwrap_or_default();
+        let tid = tids.into_iter().find(|tid| *tid != pid && !gone.contains(tid)).unwrap_or(pid);
+        match waitpid(tid, Some(WaitPidFlag::__WALL)) {
+            Ok(WaitStatus::Exited(exited, _)) | Ok(WaitStatus::Signaled(exited, _, _)) if exited == pid => return Ok(()),
+            // 終わる直前で止まったこのプロセスのスレッドは, 再開すると終わる. 他のプロセスのスレッドは再開しない
+            Ok(WaitStatus::PtraceEvent(stopped, _, libc::PTRACE_EVENT_EXIT)) if stopped == tid => { let _ = cont(tid, None); },
+            Ok(_) => {},
+            Err(nix::Error::Sys(Errno::ECHILD)) if tid == pid => return Ok(()),
+            Err(nix::Error::Sys(Errno::ECHILD)) => gone.push(tid),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 pub fn detach(pid: Pid) -> Result<()> {
     ptrace::detach(pid, None)?;
     Ok(())
 }
 
-pub fn cont(pid: Pid) -> Result<()> {
-    ptrace::cont(pid, None)?;
+// sigは止まった時に受け取ったシグナル. Noneなら何も渡さずに再開する
+pub fn cont(pid: Pid, sig: Option<signal::Signal>) -> Result<()> {
+    ptrace::cont(pid, sig)?;
+    Ok(())
+}
+
+pub fn single_step(pid: Pid, sig: Option<signal::Signal>) -> Result<()> {
+    ptrace::step(pid, sig)?;
     Ok(())
 }
 
@@ -36,7 +168,7 @@ pub fn set_tracesysgood(pid: Pid) -> Result<()>  {
             panic!("Not Stopped Process...{:?}", pid);
         }
     }
-    syscall(pid);
+    syscall(pid, None)?;
     Ok(())
 }
 
@@ -51,7 +183,7 @@ pub fn set_emulate_option(pid: Pid) -> Result<()> {
             panic!("Not Stopped Process...{:?}, Status: {:?}", pid, status);
         }
     }
-    syscall(pid);
+    syscall(pid, None)?;
     Ok(())
 }
 
@@ -61,7 +193,7 @@ pub fn set_emulate_option_simple(pid: Pid) -> Result<()> {
 }
 
 pub fn set_option_simple(pid: Pid, options: ptrace::Options) -> Result<()> {
-    ptrace::setoptions(pid, options).unwrap();
+    ptrace::setoptions(pid, options)?;
     Ok(())
 }
 
@@ -70,14 +202,42 @@ pub fn wait_pid(pid: Pid) -> Result<WaitStatus> {
     Ok(status)
 }
 
+// スレッドを待つには__WALLが要る
+pub fn wait_thread(tid: Pid) -> Result<WaitStatus> {
+    let status = waitpid(tid, Some(WaitPidFlag::__WALL))?;
+    Ok(status)
+}
+
+// 止まったスレッドが無ければStillAliveを返す
+pub fn wait_all_nohang() -> Result<WaitStatus> {
+    let status = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::__WALL | WaitPidFlag::WNOHANG))?;
+    Ok(status)
+}
+
 pub fn wait_all() -> Result<WaitStatus> {
     // let status = waitpid(Pid::from_raw(-1), Some)?;
     let status = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::__WALL))?;
     Ok(status)
 }
 
-pub fn syscall(pid: Pid) {
-    ptrace::syscall(pid, None);
+// 次のシステムコールの入り口か出口で止まるように再開する
+pub fn syscall(pid: Pid, sig: Option<signal::Signal>) -> Result<()> {
+    ptrace::syscall(pid, sig)?;
+    Ok(())
+}
+
+// システムコールの停止が入り口ならtrue, 出口ならfalse. PTRACE_GET_SYSCALL_INFOの先頭のopだけを見る.
+// PTRACE_GET_SYSCALL_INFOの無いLinux 5.3より前のカーネルではNone
+pub fn is_syscall_entry(pid: Pid) -> Result<Option<bool>> {
+    let mut info = [0u8; 88];
+    let result = Errno::result(unsafe {
+        libc::ptrace(PTRACE_GET_SYSCALL_INFO, libc::pid_t::from(pid), info.len(), info.as_mut_ptr() as *mut libc::c_void)
+    });
+    match result {
+        Ok(_) => Ok(Some(info[0] == PTRACE_SYSCALL_INFO_ENTRY)),
+        Err(nix::Error::Sys(Errno::EIO)) | Err(nix::Error::Sys(Errno::EINVAL)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
 
 pub fn syscall_step(pid: Pid) {
@@ -92,18 +252,109 @@ pub fn setregs(pid: Pid, urs: user_regs_struct) -> Result<()> {
     Ok(ptrace::setregs(pid, urs)?)
 }
 
+pub fn getfpregs(pid: Pid) -> Result<libc::user_fpregs_struct> {
+    let mut fpregs = std::mem::MaybeUninit::<libc::user_fpregs_struct>::uninit();
+    // PTRACE_GETFPREGS
+    Errno::result(
+        unsafe { libc::ptrace(14 as libc::c_uint, libc::pid_t::from(pid), std::ptr::null_mut::<libc::c_void>(), fpregs.as_mut_ptr()) }
+    )?;
+    Ok(unsafe { fpregs.assume_init() })
+}
+
+// NT_X86_XSTATE で取れるXSAVE領域. AVX-512まででも3KB弱なので4KBあれば足りる (AMXの分は切り捨てられる)
+pub fn getregset_xstate(pid: Pid) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; 4096];
+    let mut iov = libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() };
+    // PTRACE_GETREGSET, NT_X86_XSTATE
+    Errno::result(
+        unsafe { libc::ptrace(0x4204 as libc::c_uint, libc::pid_t::from(pid), 0x202 as *mut libc::c_void, &mut iov as *mut libc::iovec) }
+    )?;
+    buffer.truncate(iov.iov_len);
+    Ok(buffer)
+}
+
 pub fn read_memory(pid: Pid, addr: u64) -> Result<i64> {
     let res = ptrace::read(pid, addr as *mut std::ffi::c_void)?;
     Ok(res as i64)
 }
 
+// 読めるところまで読む. 途中に読めないページがあればそこまでの長さになる
+pub fn read_bytes_partial(pid: Pid, addr: u64, len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0; len];
+    let mut done: usize = 0;
+    // process_vm_readvなら1回のシステムコールでまとめて読める
+    while done < len {
+        let local = libc::iovec { iov_base: bytes[done..].as_mut_ptr() as *mut libc::c_void, iov_len: len - done };
+        let remote = libc::iovec { iov_base: (addr + done as u64) as *mut libc::c_void, iov_len: len - done };
+        let res = unsafe { libc::process_vm_readv(libc::pid_t::from(pid), &local, 1, &remote, 1, 0) };
+        if res <= 0 { break; }
+        done += res as usize;
+    }
+    // 使えない時 (ENOSYSや読み取り不可の.textなど) はPEEKDATAで1wordずつ読む
+    while done < len {
+        let cur = addr + done as u64;
+        let word_addr = cur & !7;
+        let word = match read_memory(pid, word_addr) {
+            Ok(word) => word.to_le_bytes(),
+            Err(_) => break,
+        };
+        let skip = (cur - word_addr) as usize;
+        let n = (8 - skip).min(len - done);
+        bytes[done..done + n].copy_from_slice(&word[skip..skip + n]);
+        done += n;
+    }
+    bytes.truncate(done);
+    bytes
+}
+
+pub fn read_bytes(pid: Pid, addr: u64, len: usize) -> Result<Vec<u8>> {
+    let bytes = read_bytes_partial(pid, addr, len);
+    if bytes.len() < len {
+        bail!("Cannot access memory at address 0x{:x}", addr + bytes.len() as u64);
+    }
+    Ok(bytes)
+}
+
 // pub fn write_data(pid: Pid, addr: u64, data: u64) -> Result<()> {
 //     ptrace::write(pid, addr as *mut std::ffi::c_void, data as *mut libc::c_void).unwrap();
 //     Ok(())
 // }
 pub fn write_data(pid: Pid, addr: u64, data: u64) -> Result<()> {
     unsafe {
-        ptrace::write(pid, addr as *mut std::ffi::c_void, data as *mut libc::c_void).unwrap();
+        ptrace::write(pid, addr as *mut std::ffi::c_void, data as *mut libc::c_void)?;
+    }
+    Ok(())
+}
+
+// 任意のアドレスに任意の長さを書く
+pub fn write_bytes(pid: Pid, addr: u64, data: &[u8]) -> Result<()> {
+    let mut done: usize = 0;
+    while done < data.len() {
+        let local = libc::iovec { iov_base: data[done..].as_ptr() as *mut libc::c_void, iov_len: data.len() - done };
+        let remote = libc::iovec { iov_base: (addr + done as u64) as *mut libc::c_void, iov_len: data.len() - done };
+        let res = unsafe { libc::process_vm_writev(libc::pid_t::from(pid), &local, 1, &remote, 1, 0) };
+        if res <= 0 { break; }
+        done += res as usize;
+    }
+    // 書き込み禁止のページ (.textなど) はPOKEDATAなら書ける. 端のwordは読んでから一部だけ書き換える
+    while done < data.len() {
+        let cur = addr + done as u64;
+        let word_addr = cur & !7;
+        let skip = (cur - word_addr) as usize;
+        let n = (8 - skip).min(data.len() - done);
+        let mut word = if skip == 0 && n == 8 {
+            [0u8; 8]
+        } else {
+            match read_memory(pid, word_addr) {
+                Ok(word) => word.to_le_bytes(),
+                Err(_) => bail!("Cannot access memory at address 0x{:x}", cur),
+            }
+        };
+        word[skip..skip + n].copy_from_slice(&data[done..done + n]);
+        if write_data(pid, word_addr, u64::from_le_bytes(word)).is_err() {
+            bail!("Cannot access memory at address 0x{:x}", cur);
+        }
+        done += n;
     }
     Ok(())
 }
@@ -147,11 +398,20 @@ pub fn poke_text(pid: Pid, vir_addr: u64, data: u64) -> Result<i64> {
 pub fn poke_user(pid: Pid, addr: u64, data: u64) -> Result<()> {
     Errno::result(
         unsafe { libc::ptrace(6 as libc::c_uint, libc::pid_t::from(pid), addr as *mut libc::c_void, data as *mut libc::c_void) }
-    )
-        .map(drop);
+    )?;
     Ok(())
 }
 
+// PTRACE_PEEKUSER. 値が-1の時とエラーを区別するためにerrnoを見る
+pub fn peek_user(pid: Pid, addr: u64) -> Result<u64> {
+    Errno::clear();
+    let res = unsafe { libc::ptrace(3 as libc::c_uint, libc::pid_t::from(pid), addr as *mut libc::c_void, std::ptr::null_mut::<libc::c_void>()) };
+    if res == -1 && Errno::last() != Errno::UnknownErrno {
+        bail!("Cannot read user area at offset {}: {}", addr, Errno::last());
+    }
+    Ok(res as u64)
+}
+
 pub fn get_event(pid: Pid) -> Result<i64> {
     let u_long: i64 = ptrace::getevent(pid)?;
     Ok(u_long)
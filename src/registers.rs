@@ -0,0 +1,392 @@
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+
+use crate::value::{Value, ValueType};
+
+// info registers で表示する順番 (gdbと同じ)
+pub const GENERAL_REGISTERS: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+    "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+pub const BASE_REGISTERS: [&str; 2] = ["fs_base", "gs_base"];
+
+// EFLAGSのビット位置と名前
+const EFLAGS_BITS: [(u32, &str); 16] = [
+    (0, "CF"), (2, "PF"), (4, "AF"), (6, "ZF"), (7, "SF"), (8, "TF"), (9, "IF"), (10, "DF"),
+    (11, "OF"), (14, "NT"), (16, "RF"), (17, "VM"), (18, "AC"), (19, "VIF"), (20, "VIP"), (21, "ID"),
+];
+
+// MXCSRのビット位置と名前
+const MXCSR_BITS: [(u32, &str); 14] = [
+    (0, "IE"), (1, "DE"), (2, "ZE"), (3, "OE"), (4, "UE"), (5, "PE"), (6, "DAZ"),
+    (7, "IM"), (8, "DM"), (9, "ZM"), (10, "OM"), (11, "UM"), (12, "PM"), (15, "FZ"),
+];
+
+pub const X87_REGISTERS: [&str; 16] = [
+    "st0", "st1", "st2", "st3", "st4", "st5", "st6", "st7",
+    "fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop",
+];
+
+// XSAVE領域のレイアウト. 先頭512バイトはFXSAVEと同じ
+const FXSAVE_MXCSR: usize = 24;
+const FXSAVE_ST: usize = 32;
+const FXSAVE_XMM: usize = 160;
+// カーネルはソフトウェア用の領域にXCR0 (有効な機能のビット) を入れてくれる
+const XSAVE_XCR0: usize = 464;
+const XSAVE_XSTATE_BV: usize = 512;
+// ptraceで返ってくるのは標準形式なので, 各コンポーネントの位置は固定
+const XSAVE_YMM_HI128: usize = 576;
+const XSAVE_OPMASK: usize = 1088;
+const XSAVE_ZMM_HI256: usize = 1152;
+const XSAVE_HI16_ZMM: usize = 1664;
+
+const XFEATURE_AVX: u32 = 2;
+const XFEATURE_OPMASK: u32 = 5;
+const XFEATURE_ZMM_HI256: u32 = 6;
+const XFEATURE_HI16_ZMM: u32 = 7;
+// x87とSSEだけ
+const XFEATURE_LEGACY: u64 = 0x3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FpName {
+    St(usize),
+    // FXSAVE領域の中のオフセットとバイト数
+    X87Control(usize, usize),
+    Ftag,
+    Mxcsr,
+    Mask(usize),
+    // 番号とバイト数 (xmm: 16, ymm: 32, zmm: 64)
+    Vector(usize, usize),
+}
+
+fn parse_fp_name(name: &str) -> Option<FpName> {
+    let numbered = |prefix: &str, count: usize| {
+        name.strip_prefix(prefix).and_then(|idx| idx.parse::<usize>().ok()).filter(|idx| *idx < count)
+    };
+    let fp_name = match name {
+        "fctrl" => FpName::X87Control(0, 2),
+        "fstat" => FpName::X87Control(2, 2),
+        "ftag" => FpName::Ftag,
+        "fop" => FpName::X87Control(6, 2),
+        "fioff" => FpName::X87Control(8, 4),
+        "fiseg" => FpName::X87Control(12, 2),
+        "fooff" => FpName::X87Control(16, 4),
+        "foseg" => FpName::X87Control(20, 2),
+        "mxcsr" => FpName::Mxcsr,
+        _ => {
+            if let Some(idx) = numbered("st", 8) { return Some(FpName::St(idx)); }
+            if let Some(idx) = numbered("k", 8) { return Some(FpName::Mask(idx)); }
+            if let Some(idx) = numbered("xmm", 32) { return Some(FpName::Vector(idx, 16)); }
+            if let Some(idx) = numbered("ymm", 32) { return Some(FpName::Vector(idx, 32)); }
+            if let Some(idx) = numbered("zmm", 32) { return Some(FpName::Vector(idx, 64)); }
+            return None;
+        },
+    };
+    Some(fp_name)
+}
+
+pub fn is_fp_register(name: &str) -> bool {
+    parse_fp_name(name).is_some()
+}
+
+// info registers でどう表示するか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FpKind {
+    X87,
+    Control,
+    Mxcsr,
+    Vector,
+}
+
+// PTRACE_GETREGSET (NT_X86_XSTATE) か PTRACE_GETFPREGS で読んだ浮動小数点・ベクトルレジスタ
+#[derive(Debug, Clone)]
+pub struct FpRegisters {
+    area: Vec<u8>,
+    xcr0: u64,
+}
+
+impl FpRegisters {
+    pub fn from_xstate(area: Vec<u8>) -> Self {
+        let xcr0 = if area.len() >= XSAVE_XSTATE_BV { read_le(&area, XSAVE_XCR0, 8) } else { XFEATURE_LEGACY };
+        FpRegisters { area, xcr0 }
+    }
+
+    // XSAVEが使えないときはFXSAVEの範囲 (x87, MXCSR, XMM0-15) だけになる
+    pub fn from_fpregs(fpregs: &user_fpregs_struct) -> Self {
+        let area = unsafe {
+            std::slice::from_raw_parts(fpregs as *const user_fpregs_struct as *const u8, std::mem::size_of::<user_fpregs_struct>())
+        };
+        FpRegisters { area: area.to_vec(), xcr0: XFEATURE_LEGACY }
+    }
+
+    fn has_feature(&self, feature: u32) -> bool {
+        self.xcr0 & (1 << feature) != 0
+    }
+
+    pub fn has_avx(&self) -> bool {
+        self.has_feature(XFEATURE_AVX)
+    }
+
+    pub fn has_avx512(&self) -> bool {
+        self.has_feature(XFEATURE_OPMASK) && self.has_feature(XFEATURE_ZMM_HI256) && self.has_feature(XFEATURE_HI16_ZMM)
+    }
+
+    // XSTATE_BVのビットが立っていないコンポーネントは初期状態なので全て0
+    fn component(&self, feature: u32, offset: usize, len: usize) -> Vec<u8> {
+        let xstate_bv = if self.area.len() >= XSAVE_XSTATE_BV + 8 { read_le(&self.area, XSAVE_XSTATE_BV, 8) } else { 0 };
+        if xstate_bv & (1 << feature) == 0 || self.area.len() < offset + len {
+            return vec![0; len];
+        }
+        self.area[offset..offset + len].to_vec()
+    }
+
+    fn vector(&self, idx: usize, size: usize) -> Option<Vec<u8>> {
+        if idx >= 16 {
+            // xmm16-31 はAVX-512でしか使えない. 64バイト全部がHi16_ZMMにある
+            if !self.has_avx512() { return None; }
+            let mut bytes = self.component(XFEATURE_HI16_ZMM, XSAVE_HI16_ZMM + (idx - 16) * 64, 64);
+            bytes.truncate(size);
+            return Some(bytes);
+        }
+        let mut bytes = self.area.get(FXSAVE_XMM + idx * 16..FXSAVE_XMM + (idx + 1) * 16)?.to_vec();
+        if size >= 32 {
+            if !self.has_avx() { return None; }
+            bytes.extend(self.component(XFEATURE_AVX, XSAVE_YMM_HI128 + idx * 16, 16));
+        }
+        if size >= 64 {
+            if !self.has_avx512() { return None; }
+            bytes.extend(self.component(XFEATURE_ZMM_HI256, XSAVE_ZMM_HI256 + idx * 32, 32));
+        }
+        Some(bytes)
+    }
+
+    // FXSAVEは有効かどうかの1bitずつしか持っていないので, gdbと同じく2bitずつのタグに戻す
+    fn full_tag_word(&self) -> u64 {
+        let top = (read_le(&self.area, 2, 2) >> 11) & 7;
+        let abridged = self.area[4];
+        let mut tag = 0;
+        for physical in 0..8u64 {
+            let value = if abridged & (1 << physical) == 0 {
+                3
+            } else {
+                let st = ((physical + 8 - top) % 8) as usize;
+                let exponent = read_le(&self.area, FXSAVE_ST + st * 16 + 8, 2) & 0x7fff;
+                let mantissa = read_le(&self.area, FXSAVE_ST + st * 16, 8);
+                match exponent {
+                    0x7fff => 2,
+                    0 if mantissa == 0 => 1,
+                    0 => 2,
+                    _ if mantissa >> 63 == 0 => 2,
+                    _ => 0,
+                }
+            };
+            tag |= value << (physical * 2);
+        }
+        tag
+    }
+
+    // 戻り値は (表示の仕方, レジスタの生のバイト列)
+    pub fn read(&self, name: &str) -> Option<(FpKind, Vec<u8>)> {
+        let read = match parse_fp_name(name)? {
+            FpName::St(idx) => (FpKind::X87, self.area.get(FXSAVE_ST + idx * 16..FXSAVE_ST + idx * 16 + 10)?.to_vec()),
+            FpName::X87Control(offset, len) => (FpKind::Control, read_le(&self.area, offset, len).to_le_bytes()[..4].to_vec()),
+            FpName::Ftag => (FpKind::Control, self.full_tag_word().to_le_bytes()[..4].to_vec()),
+            FpName::Mxcsr => (FpKind::Mxcsr, self.area.get(FXSAVE_MXCSR..FXSAVE_MXCSR + 4)?.to_vec()),
+            FpName::Mask(idx) => {
+                if !self.has_avx512() { return None; }
+                (FpKind::Control, self.component(XFEATURE_OPMASK, XSAVE_OPMASK + idx * 8, 8))
+            },
+            FpName::Vector(idx, size) => (FpKind::Vector, self.vector(idx, size)?),
+        };
+        Some(read)
+    }
+
+    pub fn read_value(&self, name: &str) -> Option<Value> {
+        let (kind, bytes) = self.read(name)?;
+        let value = match kind {
+            FpKind::X87 => Value::from_f64(extended_to_f64(&bytes)),
+            FpKind::Control | FpKind::Mxcsr => {
+                let size = bytes.len() as u8;
+                Value::new(ValueType::Int { size, signed: false }, bytes, None)
+            },
+            FpKind::Vector => Value::new(vector_type(bytes.len()), bytes, None),
+        };
+        Some(value)
+    }
+
+    // info all-registers で表示するレジスタ. ベクトルレジスタは一番広いものだけ
+    pub fn get_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = X87_REGISTERS.iter().map(|name| name.to_string()).collect();
+        names.push("mxcsr".to_owned());
+        if self.has_avx512() {
+            names.extend((0..8).map(|idx| format!("k{}", idx)));
+            names.extend((0..32).map(|idx| format!("zmm{}", idx)));
+        } else if self.has_avx() {
+            names.extend((0..16).map(|idx| format!("ymm{}", idx)));
+        } else {
+            names.extend((0..16).map(|idx| format!("xmm{}", idx)));
+        }
+        names
+    }
+
+    // info registers vector
+    pub fn get_vector_names(&self) -> Vec<String> {
+        let (prefix, count) = if self.has_avx512() { ("zmm", 32) } else if self.has_avx() { ("ymm", 16) } else { ("xmm", 16) };
+        let mut names: Vec<String> = (0..count).map(|idx| format!("{}{}", prefix, idx)).collect();
+        names.push("mxcsr".to_owned());
+        if self.has_avx512() {
+            names.extend((0..8).map(|idx| format!("k{}", idx)));
+        }
+        names
+    }
+}
+
+// gdbの vec128 などと同じく, 同じバイト列を色々な幅の要素の並びとして見られるようにする
+fn vector_type(size: usize) -> ValueType {
+    let lanes = |ty: ValueType, width: usize| ValueType::Vector(Box::new(ty), size / width);
+    let int = |width: u8| ValueType::Int { size: width, signed: true };
+    let mut members = vec![
+        (format!("v{}_float", size / 4), lanes(ValueType::Float { size: 4 }, 4)),
+        (format!("v{}_double", size / 8), lanes(ValueType::Float { size: 8 }, 8)),
+        (format!("v{}_int8", size), lanes(int(1), 1)),
+        (format!("v{}_int16", size / 2), lanes(int(2), 2)),
+        (format!("v{}_int32", size / 4), lanes(int(4), 4)),
+        (format!("v{}_int64", size / 8), lanes(int(8), 8)),
+    ];
+    if size == 16 {
+        members.push(("uint128".to_owned(), ValueType::Int { size: 16, signed: false }));
+    } else {
+        members.push((format!("v{}_int128", size / 16), lanes(int(16), 16)));
+    }
+    ValueType::Union(format!("vec{}", size * 8), members)
+}
+
+// x87の80bit拡張倍精度をf64にする. 範囲外は無限大, 精度は落ちる
+pub fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let mantissa = read_le(bytes, 0, 8);
+    let sign_exponent = read_le(bytes, 8, 2);
+    let sign = if sign_exponent & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    let value = match exponent {
+        0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+        0x7fff => f64::NAN,
+        // 非正規化数の指数は1と同じ
+        0 => mantissa as f64 / 2f64.powi(63) * 2f64.powi(1 - 16383),
+        _ => mantissa as f64 / 2f64.powi(63) * 2f64.powi(exponent - 16383),
+    };
+    sign * value
+}
+
+fn read_le(bytes: &[u8], offset: usize, len: usize) -> u64 {
+    let mut array = [0u8; 8];
+    if let Some(slice) = bytes.get(offset..offset + len) {
+        array[..len].copy_from_slice(slice);
+    }
+    u64::from_le_bytes(array)
+}
+
+const LOW32: [&str; 16] = ["eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+const LOW16: [&str; 16] = ["ax", "bx", "cx", "dx", "si", "di", "bp", "sp", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+const LOW8: [&str; 16] = ["al", "bl", "cl", "dl", "sil", "dil", "bpl", "spl", "r8l", "r9l", "r10l", "r11l", "r12l", "r13l", "r14l", "r15l"];
+const HIGH8: [&str; 4] = ["ah", "bh", "ch", "dh"];
+
+fn field<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    let field = match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        "orig_rax" => &mut regs.orig_rax,
+        _ => return None,
+    };
+    Some(field)
+}
+
+// eaxやalのような一部分のレジスタを (64bitのレジスタ名, 下から何bit目か, バイト数) にする
+fn resolve(name: &str) -> Option<(&'static str, u32, u8)> {
+    // $pc, $sp, $fp はgdbと同じ別名
+    let name = match name {
+        "pc" => "rip",
+        "sp" => "rsp",
+        "fp" => "rbp",
+        name => name,
+    };
+    let full = |idx: usize| GENERAL_REGISTERS[idx];
+    if let Some(reg) = GENERAL_REGISTERS.iter().chain(BASE_REGISTERS.iter()).find(|reg| **reg == name) {
+        return Some((reg, 0, 8));
+    }
+    if name == "orig_rax" { return Some(("orig_rax", 0, 8)); }
+    if let Some(idx) = LOW32.iter().position(|reg| *reg == name) { return Some((full(idx), 0, 4)); }
+    if let Some(idx) = LOW16.iter().position(|reg| *reg == name) { return Some((full(idx), 0, 2)); }
+    if let Some(idx) = LOW8.iter().position(|reg| *reg == name) { return Some((full(idx), 0, 1)); }
+    if let Some(idx) = HIGH8.iter().position(|reg| *reg == name) { return Some((full(idx), 8, 1)); }
+    None
+}
+
+// 戻り値は (値, バイト数)
+pub fn read(regs: &user_regs_struct, name: &str) -> Option<(u64, u8)> {
+    let (base, shift, size) = resolve(name)?;
+    let mut regs = *regs;
+    let value = *field(&mut regs, base)?;
+    Some(((value >> shift) & mask(size), size))
+}
+
+// 一部分のレジスタなら, 残りのビットはそのままにする. 32bitのレジスタもgdbと同じく上位は保つ
+pub fn write(regs: &mut user_regs_struct, name: &str, value: u64) -> bool {
+    let (base, shift, size) = match resolve(name) {
+        Some(resolved) => resolved,
+        None => return false,
+    };
+    match field(regs, base) {
+        Some(field) => {
+            let mask = mask(size) << shift;
+            *field = (*field & !mask) | ((value << shift) & mask);
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn is_register(name: &str) -> bool {
+    resolve(name).is_some()
+}
+
+fn mask(size: u8) -> u64 {
+    if size >= 8 { !0 } else { (1u64 << (size as u32 * 8)) - 1 }
+}
+
+// "[ ZF PF IF ]" の形
+pub fn format_eflags(value: u64) -> String {
+    format_flags(value, &EFLAGS_BITS)
+}
+
+pub fn format_mxcsr(value: u64) -> String {
+    format_flags(value, &MXCSR_BITS)
+}
+
+fn format_flags(value: u64, bits: &[(u32, &str)]) -> String {
+    let flags: Vec<&str> = bits.iter().filter(|(bit, _)| value & (1 << bit) != 0).map(|(_, name)| *name).collect();
+    format!("[ {} ]", flags.join(" "))
+}
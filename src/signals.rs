@@ -0,0 +1,181 @@
+use anyhow::{Result, bail};
+use nix::sys::signal::Signal;
+use nix::libc;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+const SI_USER: i32 = 0;
+const SI_KERNEL: i32 = 0x80;
+const SI_QUEUE: i32 = -1;
+const SI_TIMER: i32 = -2;
+const SI_MESGQ: i32 = -3;
+const SI_ASYNCIO: i32 = -4;
+const SI_SIGIO: i32 = -5;
+const SI_TKILL: i32 = -6;
+
+// シグナルを受け取った時にどうするか. handle コマンドで変える
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalPolicy {
+    pub stop: bool,
+    pub print: bool,
+    pub pass: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignalTable {
+    policies: Vec<(Signal, SignalPolicy)>,
+}
+
+impl Default for SignalTable {
+    // gdbと同じく, よく使われる通知のシグナルは止めずに渡す. SIGINTとSIGTRAPはデバッガが使うので渡さない
+    fn default() -> Self {
+        let policies = Signal::iterator().map(|sig| {
+            let policy = match sig {
+                Signal::SIGALRM | Signal::SIGURG | Signal::SIGCHLD | Signal::SIGIO | Signal::SIGVTALRM
+                    | Signal::SIGPROF | Signal::SIGWINCH => SignalPolicy { stop: false, print: false, pass: true },
+                Signal::SIGINT | Signal::SIGTRAP => SignalPolicy { stop: true, print: true, pass: false },
+                _ => SignalPolicy { stop: true, print: true, pass: true },
+            };
+            (sig, policy)
+        }).collect();
+        SignalTable { policies }
+    }
+}
+
+impl SignalTable {
+    pub fn get_policy(&self, sig: Signal) -> SignalPolicy {
+        self.policies.iter().find(|(s, _)| *s == sig).map(|(_, policy)| *policy)
+            .unwrap_or(SignalPolicy { stop: true, print: true, pass: true })
+    }
+
+    pub fn get_policies(&self) -> &Vec<(Signal, SignalPolicy)> { &self.policies }
+
+    // stop は print も, noprint は nostop も含む
+    pub fn apply(&mut self, sig: Signal, actions: &[String]) -> Result<()> {
+        let policy = match self.policies.iter_mut().find(|(s, _)| *s == sig) {
+            Some((_, policy)) => policy,
+            None => return Ok(()),
+        };
+        for action in actions.iter() {
+            match action.as_str() {
+                "stop" => { policy.stop = true; policy.print = true; },
+                "nostop" => policy.stop = false,
+                "print" => policy.print = true,
+                "noprint" => { policy.print = false; policy.stop = false; },
+                "pass" | "noignore" => policy.pass = true,
+                "nopass" | "ignore" => policy.pass = false,
+                _ => bail!("Unrecognized or ambiguous flag word: \"{}\".", action),
+            }
+        }
+        Ok(())
+    }
+}
+
+// SIGUSR1 のような名前か, gdbと同じく1から15までの番号
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(num) = name.parse::<i32>() {
+        return if (1..=15).contains(&num) { Signal::try_from(num).ok() } else { None };
+    }
+    Signal::from_str(name).ok()
+}
+
+pub fn description(sig: Signal) -> String {
+    let description = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(sig as libc::c_int)) };
+    description.to_string_lossy().into_owned()
+}
+
+// info signals の1行. 見出しと列を揃える
+pub fn format_policy(sig: Signal, policy: &SignalPolicy) -> String {
+    let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+    format!("{:<14}{}\t{}\t{}\t\t{}", sig.as_str(), yes_no(policy.stop), yes_no(policy.print), yes_no(policy.pass), description(sig))
+}
+
+pub const POLICY_HEADER: &str = "Signal        Stop\tPrint\tPass to program\tDescription";
+
+// si_codeの名前と意味. 戻り値は (名前, 意味)
+fn si_code_name(sig: Signal, si_code: i32) -> Option<(&'static str, &'static str)> {
+    let name = match si_code {
+        SI_USER => ("SI_USER", "Sent by kill"),
+        SI_KERNEL => ("SI_KERNEL", "Sent by the kernel"),
+        SI_QUEUE => ("SI_QUEUE", "Sent by sigqueue"),
+        SI_TIMER => ("SI_TIMER", "POSIX timer expired"),
+        SI_MESGQ => ("SI_MESGQ", "POSIX message queue state changed"),
+        SI_ASYNCIO => ("SI_ASYNCIO", "AIO completed"),
+        SI_SIGIO => ("SI_SIGIO", "Queued SIGIO"),
+        SI_TKILL => ("SI_TKILL", "Sent by tkill"),
+        _ => match (sig, si_code) {
+            (Signal::SIGSEGV, 1) => ("SEGV_MAPERR", "Address not mapped to object"),
+            (Signal::SIGSEGV, 2) => ("SEGV_ACCERR", "Invalid permissions for mapped object"),
+            (Signal::SIGSEGV, 3) => ("SEGV_BNDERR", "Failed address bound checks"),
+            (Signal::SIGSEGV, 4) => ("SEGV_PKUERR", "Access was denied by memory protection keys"),
+            (Signal::SIGBUS, 1) => ("BUS_ADRALN", "Invalid address alignment"),
+            (Signal::SIGBUS, 2) => ("BUS_ADRERR", "Nonexistent physical address"),
+            (Signal::SIGBUS, 3) => ("BUS_OBJERR", "Object-specific hardware error"),
+            (Signal::SIGBUS, 4) => ("BUS_MCEERR_AR", "Hardware memory error consumed on a machine check"),
+            (Signal::SIGBUS, 5) => ("BUS_MCEERR_AO", "Hardware memory error detected in process but not consumed"),
+            (Signal::SIGFPE, 1) => ("FPE_INTDIV", "Integer divide by zero"),
+            (Signal::SIGFPE, 2) => ("FPE_INTOVF", "Integer overflow"),
+            (Signal::SIGFPE, 3) => ("FPE_FLTDIV", "Floating-point divide by zero"),
+            (Signal::SIGFPE, 4) => ("FPE_FLTOVF", "Floating-point overflow"),
+            (Signal::SIGFPE, 5) => ("FPE_FLTUND", "Floating-point underflow"),
+            (Signal::SIGFPE, 6) => ("FPE_FLTRES", "Floating-point inexact result"),
+            (Signal::SIGFPE, 7) => ("FPE_FLTINV", "Floating-point invalid operation"),
+            (Signal::SIGFPE, 8) => ("FPE_FLTSUB", "Subscript out of range"),
+            (Signal::SIGILL, 1) => ("ILL_ILLOPC", "Illegal opcode"),
+            (Signal::SIGILL, 2) => ("ILL_ILLOPN", "Illegal operand"),
+            (Signal::SIGILL, 3) => ("ILL_ILLADR", "Illegal addressing mode"),
+            (Signal::SIGILL, 4) => ("ILL_ILLTRP", "Illegal trap"),
+            (Signal::SIGILL, 5) => ("ILL_PRVOPC", "Privileged opcode"),
+            (Signal::SIGILL, 6) => ("ILL_PRVREG", "Privileged register"),
+            (Signal::SIGILL, 7) => ("ILL_COPROC", "Coprocessor error"),
+            (Signal::SIGILL, 8) => ("ILL_BADSTK", "Internal stack error"),
+            (Signal::SIGTRAP, 1) => ("TRAP_BRKPT", "Process breakpoint"),
+            (Signal::SIGTRAP, 2) => ("TRAP_TRACE", "Process trace trap"),
+            (Signal::SIGTRAP, 3) => ("TRAP_BRANCH", "Process taken branch trap"),
+            (Signal::SIGTRAP, 4) => ("TRAP_HWBKPT", "Hardware breakpoint/watchpoint"),
+            (Signal::SIGCHLD, 1) => ("CLD_EXITED", "Child has exited"),
+            (Signal::SIGCHLD, 2) => ("CLD_KILLED", "Child was killed"),
+            (Signal::SIGCHLD, 3) => ("CLD_DUMPED", "Child terminated abnormally"),
+            (Signal::SIGCHLD, 4) => ("CLD_TRAPPED", "Traced child has trapped"),
+            (Signal::SIGCHLD, 5) => ("CLD_STOPPED", "Child has stopped"),
+            (Signal::SIGCHLD, 6) => ("CLD_CONTINUED", "Stopped child has continued"),
+            _ => return None,
+        },
+    };
+    Some(name)
+}
+
+// 止まった時に表示するsiginfoの詳しい内容. 例えば "SEGV_MAPERR (Address not mapped to object), fault address 0x0"
+pub fn describe_siginfo(sig: Signal, siginfo: &libc::siginfo_t) -> String {
+    let si_code = siginfo.si_code;
+    let mut description = match si_code_name(sig, si_code) {
+        Some((name, meaning)) => format!("{} ({})", name, meaning),
+        None => format!("si_code {}", si_code),
+    };
+    // 送ったプロセスが分かるのはユーザーが送ったシグナルだけ
+    if si_code == SI_USER || si_code == SI_QUEUE || si_code == SI_TKILL {
+        let (pid, uid) = unsafe { (siginfo.si_pid(), siginfo.si_uid()) };
+        description.push_str(&format!(", sent by process {} (uid {})", pid, uid));
+    } else if is_fault(sig) {
+        description.push_str(&format!(", fault address 0x{:x}", fault_address(siginfo)));
+    } else if sig == Signal::SIGCHLD {
+        let (pid, status) = unsafe { (siginfo.si_pid(), siginfo.si_status()) };
+        description.push_str(&format!(", child process {} status {}", pid, status));
+    }
+    description
+}
+
+// 命令の実行で起きたシグナル. si_addrに原因のアドレスが入る
+pub fn is_fault(sig: Signal) -> bool {
+    matches!(sig, Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGFPE | Signal::SIGILL)
+}
+
+// 命令の実行で起きたシグナルの原因のアドレス
+pub fn fault_address(siginfo: &libc::siginfo_t) -> u64 {
+    unsafe { siginfo.si_addr() as u64 }
+}
+
+// プログラムが落ちたことを表すシグナル. 止まった時にクラッシュレポートを表示する
+pub fn is_crash(sig: Signal) -> bool {
+    is_fault(sig) || sig == Signal::SIGABRT
+}
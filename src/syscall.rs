@@ -0,0 +1,155 @@
+use anyhow::{Result, bail};
+
+// x86-64のシステムコールの番号, 名前, 引数の数.
+// 0から順の (名前, 引数の数)
+const SYSCALLS: [(&str, usize); 335] = [
+    ("read", 3), ("write", 3), ("open", 3), ("close", 1), ("stat", 2), // 0
+    ("fstat", 2), ("lstat", 2), ("poll", 3), ("lseek", 3), ("mmap", 6), // 5
+    ("mprotect", 3), ("munmap", 2), ("brk", 1), ("rt_sigaction", 4), ("rt_sigprocmask", 4), // 10
+    ("rt_sigreturn", 0), ("ioctl", 3), ("pread64", 4), ("pwrite64", 4), ("readv", 3), // 15
+    ("writev", 3), ("access", 2), ("pipe", 1), ("select", 5), ("sched_yield", 0), // 20
+    ("mremap", 5), ("msync", 3), ("mincore", 3), ("madvise", 3), ("shmget", 3), // 25
+    ("shmat", 3), ("shmctl", 3), ("dup", 1), ("dup2", 2), ("pause", 0), // 30
+    ("nanosleep", 2), ("getitimer", 2), ("alarm", 1), ("setitimer", 3), ("getpid", 0), // 35
+    ("sendfile", 4), ("socket", 3), ("connect", 3), ("accept", 3), ("sendto", 6), // 40
+    ("recvfrom", 6), ("sendmsg", 3), ("recvmsg", 3), ("shutdown", 2), ("bind", 3), // 45
+    ("listen", 2), ("getsockname", 3), ("getpeername", 3), ("socketpair", 4), ("setsockopt", 5), // 50
+    ("getsockopt", 5), ("clone", 5), ("fork", 0), ("vfork", 0), ("execve", 3), // 55
+    ("exit", 1), ("wait4", 4), ("kill", 2), ("uname", 1), ("semget", 3), // 60
+    ("semop", 3), ("semctl", 4), ("shmdt", 1), ("msgget", 2), ("msgsnd", 4), // 65
+    ("msgrcv", 5), ("msgctl", 3), ("fcntl", 3), ("flock", 2), ("fsync", 1), // 70
+    ("fdatasync", 1), ("truncate", 2), ("ftruncate", 2), ("getdents", 3), ("getcwd", 2), // 75
+    ("chdir", 1), ("fchdir", 1), ("rename", 2), ("mkdir", 2), ("rmdir", 1), // 80
+    ("creat", 2), ("link", 2), ("unlink", 1), ("symlink", 2), ("readlink", 3), // 85
+    ("chmod", 2), ("fchmod", 2), ("chown", 3), ("fchown", 3), ("lchown", 3), // 90
+    ("umask", 1), ("gettimeofday", 2), ("getrlimit", 2), ("getrusage", 2), ("sysinfo", 1), // 95
+    ("times", 1), ("ptrace", 4), ("getuid", 0), ("syslog", 3), ("getgid", 0), // 100
+    ("setuid", 1), ("setgid", 1), ("geteuid", 0), ("getegid", 0), ("setpgid", 2), // 105
+    ("getppid", 0), ("getpgrp", 0), ("setsid", 0), ("setreuid", 2), ("setregid", 2), // 110
+    ("getgroups", 2), ("setgroups", 2), ("setresuid", 3), ("getresuid", 3), ("setresgid", 3), // 115
+    ("getresgid", 3), ("getpgid", 1), ("setfsuid", 1), ("setfsgid", 1), ("getsid", 1), // 120
+    ("capget", 2), ("capset", 2), ("rt_sigpending", 2), ("rt_sigtimedwait", 4), ("rt_sigqueueinfo", 3), // 125
+    ("rt_sigsuspend", 2), ("sigaltstack", 2), ("utime", 2), ("mknod", 3), ("uselib", 1), // 130
+    ("personality", 1), ("ustat", 2), ("statfs", 2), ("fstatfs", 2), ("sysfs", 3), // 135
+    ("getpriority", 2), ("setpriority", 3), ("sched_setparam", 2), ("sched_getparam", 2), ("sched_setscheduler", 3), // 140
+    ("sched_getscheduler", 1), ("sched_get_priority_max", 1), ("sched_get_priority_min", 1), ("sched_rr_get_interval", 2), ("mlock", 2), // 145
+    ("munlock", 2), ("mlockall", 1), ("munlockall", 0), ("vhangup", 0), ("modify_ldt", 3), // 150
+    ("pivot_root", 2), ("_sysctl", 1), ("prctl", 5), ("arch_prctl", 2), ("adjtimex", 1), // 155
+    ("setrlimit", 2), ("chroot", 1), ("sync", 0), ("acct", 1), ("settimeofday", 2), // 160
+    ("mount", 5), ("umount2", 2), ("swapon", 2), ("swapoff", 1), ("reboot", 4), // 165
+    ("sethostname", 2), ("setdomainname", 2), ("iopl", 1), ("ioperm", 3), ("create_module", 2), // 170
+    ("init_module", 3), ("delete_module", 2), ("get_kernel_syms", 1), ("query_module", 5), ("quotactl", 4), // 175
+    ("nfsservctl", 3), ("getpmsg", 5), ("putpmsg", 5), ("afs_syscall", 5), ("tuxcall", 3), // 180
+    ("security", 3), ("gettid", 0), ("readahead", 3), ("setxattr", 5), ("lsetxattr", 5), // 185
+    ("fsetxattr", 5), ("getxattr", 4), ("lgetxattr", 4), ("fgetxattr", 4), ("listxattr", 3), // 190
+    ("llistxattr", 3), ("flistxattr", 3), ("removexattr", 2), ("lremovexattr", 2), ("fremovexattr", 2), // 195
+    ("tkill", 2), ("time", 1), ("futex", 6), ("sched_setaffinity", 3), ("sched_getaffinity", 3), // 200
+    ("set_thread_area", 1), ("io_setup", 2), ("io_destroy", 1), ("io_getevents", 5), ("io_submit", 3), // 205
+    ("io_cancel", 3), ("get_thread_area", 1), ("lookup_dcookie", 3), ("epoll_create", 1), ("epoll_ctl_old", 4), // 210
+    ("epoll_wait_old", 4), ("remap_file_pages", 5), ("getdents64", 3), ("set_tid_address", 1), ("restart_syscall", 0), // 215
+    ("semtimedop", 4), ("fadvise64", 4), ("timer_create", 3), ("timer_settime", 4), ("timer_gettime", 2), // 220
+    ("timer_getoverrun", 1), ("timer_delete", 1), ("clock_settime", 2), ("clock_gettime", 2), ("clock_getres", 2), // 225
+    ("clock_nanosleep", 4), ("exit_group", 1), ("epoll_wait", 4), ("epoll_ctl", 4), ("tgkill", 3), // 230
+    ("utimes", 2), ("vserver", 5), ("mbind", 6), ("set_mempolicy", 3), ("get_mempolicy", 5), // 235
+    ("mq_open", 4), ("mq_unlink", 1), ("mq_timedsend", 5), ("mq_timedreceive", 5), ("mq_notify", 2), // 240
+    ("mq_getsetattr", 3), ("kexec_load", 4), ("waitid", 5), ("add_key", 5), ("request_key", 4), // 245
+    ("keyctl", 5), ("ioprio_set", 3), ("ioprio_get", 2), ("inotify_init", 0), ("inotify_add_watch", 3), // 250
+    ("inotify_rm_watch", 2), ("migrate_pages", 4), ("openat", 4), ("mkdirat", 3), ("mknodat", 4), // 255
+    ("fchownat", 5), ("futimesat", 3), ("newfstatat", 4), ("unlinkat", 3), ("renameat", 4), // 260
+    ("linkat", 5), ("symlinkat", 3), ("readlinkat", 4), ("fchmodat", 3), ("faccessat", 3), // 265
+    ("pselect6", 6), ("ppoll", 5), ("unshare", 1), ("set_robust_list", 2), ("get_robust_list", 3), // 270
+    ("splice", 6), ("tee", 4), ("sync_file_range", 4), ("vmsplice", 4), ("move_pages", 6), // 275
+    ("utimensat", 4), ("epoll_pwait", 6), ("signalfd", 3), ("timerfd_create", 2), ("eventfd", 1), // 280
+    ("fallocate", 4), ("timerfd_settime", 4), ("timerfd_gettime", 2), ("accept4", 4), ("signalfd4", 4), // 285
+    ("eventfd2", 2), ("epoll_create1", 1), ("dup3", 3), ("pipe2", 2), ("inotify_init1", 1), // 290
+    ("preadv", 5), ("pwritev", 5), ("rt_tgsigqueueinfo", 4), ("perf_event_open", 5), ("recvmmsg", 5), // 295
+    ("fanotify_init", 2), ("fanotify_mark", 5), ("prlimit64", 4), ("name_to_handle_at", 5), ("open_by_handle_at", 3), // 300
+    ("clock_adjtime", 2), ("syncfs", 1), ("sendmmsg", 4), ("setns", 2), ("getcpu", 3), // 305
+    ("process_vm_readv", 6), ("process_vm_writev", 6), ("kcmp", 5), ("finit_module", 3), ("sched_setattr", 3), // 310
+    ("sched_getattr", 4), ("renameat2", 5), ("seccomp", 3), ("getrandom", 3), ("memfd_create", 2), // 315
+    ("kexec_file_load", 5), ("bpf", 3), ("execveat", 5), ("userfaultfd", 1), ("membarrier", 3), // 320
+    ("mlock2", 3), ("copy_file_range", 6), ("preadv2", 6), ("pwritev2", 6), ("pkey_mprotect", 4), // 325
+    ("pkey_alloc", 2), ("pkey_free", 1), ("statx", 5), ("io_pgetevents", 6), ("rseq", 4), // 330
+];
+
+// 424から順の新しいシステムコール. 間の番号は使われていない
+const NEW_SYSCALLS_START: u64 = 424;
+const NEW_SYSCALLS: [(&str, usize); 39] = [
+    ("pidfd_send_signal", 4), ("io_uring_setup", 2), ("io_uring_enter", 6), ("io_uring_register", 4), ("open_tree", 3), // 424
+    ("move_mount", 5), ("fsopen", 2), ("fsconfig", 5), ("fsmount", 3), ("fspick", 3), // 429
+    ("pidfd_open", 2), ("clone3", 2), ("close_range", 3), ("openat2", 4), ("pidfd_getfd", 3), // 434
+    ("faccessat2", 4), ("process_madvise", 5), ("epoll_pwait2", 6), ("mount_setattr", 5), ("quotactl_fd", 4), // 439
+    ("landlock_create_ruleset", 3), ("landlock_add_rule", 4), ("landlock_restrict_self", 2), ("memfd_secret", 1), ("process_mrelease", 2), // 444
+    ("futex_waitv", 5), ("set_mempolicy_home_node", 4), ("cachestat", 4), ("fchmodat2", 4), ("map_shadow_stack", 3), // 449
+    ("futex_wake", 4), ("futex_wait", 6), ("futex_requeue", 4), ("statmount", 4), ("listmount", 4), // 454
+    ("lsm_get_self_attr", 4), ("lsm_set_self_attr", 4), ("lsm_list_modules", 3), ("mseal", 3), // 459
+];
+
+// gdbの catch syscall group:名前 のグループ
+const GROUPS: [(&str, &[&str]); 7] = [
+    ("process", &["clone", "fork", "vfork", "execve", "exit", "wait4", "kill", "exit_group", "tkill", "tgkill", "waitid", "unshare", "arch_prctl", "set_tid_address", "execveat", "clone3", "pidfd_open", "pidfd_send_signal", "pidfd_getfd", "process_mrelease"]),
+    ("file", &["open", "stat", "lstat", "access", "execve", "truncate", "getcwd", "chdir", "rename", "mkdir", "rmdir", "creat", "link", "unlink", "symlink", "readlink", "chmod", "chown", "lchown", "utime", "mknod", "uselib", "statfs", "pivot_root", "chroot", "acct", "mount", "umount2", "swapon", "swapoff", "quotactl", "setxattr", "lsetxattr", "getxattr", "lgetxattr", "listxattr", "llistxattr", "removexattr", "lremovexattr", "utimes", "inotify_add_watch", "openat", "mkdirat", "mknodat", "fchownat", "futimesat", "newfstatat", "unlinkat", "renameat", "linkat", "symlinkat", "readlinkat", "fchmodat", "faccessat", "utimensat", "fanotify_mark", "name_to_handle_at", "renameat2", "execveat", "statx", "openat2", "faccessat2", "fchmodat2"]),
+    ("network", &["socket", "connect", "accept", "sendto", "recvfrom", "sendmsg", "recvmsg", "shutdown", "bind", "listen", "getsockname", "getpeername", "socketpair", "setsockopt", "getsockopt", "accept4", "recvmmsg", "sendmmsg"]),
+    ("signal", &["rt_sigaction", "rt_sigprocmask", "rt_sigreturn", "pause", "kill", "rt_sigpending", "rt_sigtimedwait", "rt_sigqueueinfo", "rt_sigsuspend", "sigaltstack", "tkill", "tgkill", "signalfd", "signalfd4", "rt_tgsigqueueinfo", "pidfd_send_signal"]),
+    ("ipc", &["shmget", "shmat", "shmctl", "semget", "semop", "semctl", "shmdt", "msgget", "msgsnd", "msgrcv", "msgctl", "semtimedop", "mq_open", "mq_unlink", "mq_timedsend", "mq_timedreceive", "mq_notify", "mq_getsetattr"]),
+    ("memory", &["mmap", "mprotect", "munmap", "brk", "mremap", "msync", "mincore", "madvise", "shmat", "shmdt", "mlock", "munlock", "mlockall", "munlockall", "remap_file_pages", "mbind", "set_mempolicy", "get_mempolicy", "migrate_pages", "move_pages", "mlock2", "pkey_mprotect", "process_madvise", "memfd_secret", "set_mempolicy_home_node", "map_shadow_stack", "mseal"]),
+    ("descriptor", &["read", "write", "open", "close", "fstat", "poll", "lseek", "mmap", "ioctl", "pread64", "pwrite64", "readv", "writev", "pipe", "select", "dup", "dup2", "sendfile", "socket", "connect", "accept", "sendto", "recvfrom", "sendmsg", "recvmsg", "shutdown", "bind", "listen", "getsockname", "getpeername", "socketpair", "setsockopt", "getsockopt", "fcntl", "flock", "fsync", "fdatasync", "ftruncate", "getdents", "fchdir", "creat", "fchmod", "fchown", "fstatfs", "readahead", "fsetxattr", "fgetxattr", "flistxattr", "fremovexattr", "epoll_create", "getdents64", "fadvise64", "epoll_wait", "epoll_ctl", "inotify_init", "inotify_add_watch", "inotify_rm_watch", "openat", "mkdirat", "mknodat", "fchownat", "futimesat", "newfstatat", "unlinkat", "renameat", "linkat", "symlinkat", "readlinkat", "fchmodat", "faccessat", "pselect6", "ppoll", "splice", "tee", "sync_file_range", "vmsplice", "utimensat", "epoll_pwait", "signalfd", "timerfd_create", "eventfd", "fallocate", "timerfd_settime", "timerfd_gettime", "accept4", "signalfd4", "eventfd2", "epoll_create1", "dup3", "pipe2", "inotify_init1", "preadv", "pwritev", "perf_event_open", "recvmmsg", "fanotify_init", "fanotify_mark", "name_to_handle_at", "open_by_handle_at", "syncfs", "sendmmsg", "setns", "finit_module", "renameat2", "memfd_create", "execveat", "userfaultfd", "copy_file_range", "preadv2", "pwritev2", "statx", "pidfd_open", "close_range", "openat2", "pidfd_getfd", "faccessat2", "epoll_pwait2", "fchmodat2"]),
+];
+
+fn entry(nr: u64) -> Option<(&'static str, usize)> {
+    if nr < SYSCALLS.len() as u64 {
+        return Some(SYSCALLS[nr as usize]);
+    }
+    nr.checked_sub(NEW_SYSCALLS_START).and_then(|idx| NEW_SYSCALLS.get(idx as usize)).copied()
+}
+
+pub fn name(nr: u64) -> Option<&'static str> {
+    entry(nr).map(|(name, _)| name)
+}
+
+// 分からないシステムコールなら6つ全て
+pub fn argument_count(nr: u64) -> usize {
+    entry(nr).map(|(_, count)| count).unwrap_or(6)
+}
+
+pub fn lookup(name: &str) -> Option<u64> {
+    if let Some(nr) = SYSCALLS.iter().position(|(syscall, _)| *syscall == name) {
+        return Some(nr as u64);
+    }
+    NEW_SYSCALLS.iter().position(|(syscall, _)| *syscall == name).map(|idx| NEW_SYSCALLS_START + idx as u64)
+}
+
+// グループに入っているシステムコールの番号
+pub fn lookup_group(group: &str) -> Option<Vec<u64>> {
+    let (_, names) = GROUPS.iter().find(|(name, _)| *name == group)?;
+    Some(names.iter().filter_map(|name| lookup(name)).collect())
+}
+
+// "write" か, 表に無ければ番号
+pub fn display_name(nr: u64) -> String {
+    name(nr).map(|name| name.to_owned()).unwrap_or_else(|| nr.to_string())
+}
+
+// catch syscall の引数. 名前, 番号, group:グループ (g:グループ) を番号にする. 重複は除く
+pub fn parse(args: &[String]) -> Result<Vec<u64>> {
+    let mut nrs = vec![];
+    for arg in args.iter() {
+        let found = if let Some(group) = arg.strip_prefix("group:").or_else(|| arg.strip_prefix("g:")) {
+            match lookup_group(group) {
+                Some(found) => found,
+                None => bail!("Unknown syscall group '{}'.", group),
+            }
+        } else if let Ok(nr) = arg.parse::<u64>() {
+            vec![nr]
+        } else {
+            match lookup(arg) {
+                Some(nr) => vec![nr],
+                None => bail!("Unknown syscall name '{}'.", arg),
+            }
+        };
+        for nr in found.into_iter() {
+            if !nrs.contains(&nr) { nrs.push(nr); }
+        }
+    }
+    Ok(nrs)
+}
@@ -0,0 +1,249 @@
+use anyhow::{Result, Context};
+use nix::unistd::Pid;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::check_fn_viradd::{self, SymMap};
+use crate::dwarf_info::{DebugInfo, DebugSections, Function};
+use crate::dwarf_line::LineTable;
+use crate::unwind::UnwindTable;
+
+// トレース対象のプロセスにマップされているELF(実行ファイル本体や共有ライブラリ)
+#[derive(Debug)]
+pub struct Module {
+    path: String,
+    start: u64,
+    end: u64,
+    base: u64,
+    sym_map_list: Vec<SymMap>,
+    unwind_table: UnwindTable,
+    line_table: LineTable,
+    debug_info: Option<DebugInfo>,
+}
+
+impl Module {
+    fn load(path: &str, start: u64, end: u64, first_mapping: u64) -> Result<Self> {
+        let load_vir_addr = check_fn_viradd::get_load_vir_addr(path).map_err(anyhow::Error::msg)?;
+        let base = first_mapping.wrapping_sub(load_vir_addr);
+        let sym_map_list = check_fn_viradd::get_fn_sym_maps(path).map_err(anyhow::Error::msg)?;
+        let sections = check_fn_viradd::get_sections(path, &[
+            ".eh_frame", ".eh_frame_hdr", ".debug_frame", ".debug_line", ".debug_str", ".debug_line_str",
+            ".debug_info", ".debug_abbrev", ".debug_str_offsets", ".debug_addr", ".debug_ranges", ".debug_rnglists", ".debug_loc", ".debug_loclists",
+        ]).map_err(anyhow::Error::msg)?;
+        let find_section = |name: &str| sections.iter().find(|section| section.get_name() == name);
+        let section_data = |name: &str| find_section(name).map(|section| section.get_data()).unwrap_or(&[]);
+
+        let unwind_table = UnwindTable::parse(
+            find_section(".eh_frame").map(|section| (section.get_data().to_vec(), section.get_vir_addr())),
+            find_section(".eh_frame_hdr").map(|section| (section.get_data().to_vec(), section.get_vir_addr())),
+            find_section(".debug_frame").map(|section| section.get_data().to_vec()),
+        );
+        let line_table = match find_section(".debug_line") {
+            Some(debug_line) => {
+                let debug_str = find_section(".debug_str").map(|section| section.get_data()).unwrap_or(&[]);
+                let debug_line_str = find_section(".debug_line_str").map(|section| section.get_data()).unwrap_or(&[]);
+                LineTable::parse(debug_line.get_data(), debug_str, debug_line_str).unwrap_or_default()
+            },
+            None => LineTable::default(),
+        };
+        let debug_info = match find_section(".debug_info") {
+            Some(debug_info) => {
+                let debug_sections = DebugSections {
+                    debug_info: debug_info.get_data(),
+                    debug_abbrev: section_data(".debug_abbrev"),
+                    debug_str: section_data(".debug_str"),
+                    debug_line_str: section_data(".debug_line_str"),
+                    debug_str_offsets: section_data(".debug_str_offsets"),
+                    debug_addr: section_data(".debug_addr"),
+                    debug_ranges: section_data(".debug_ranges"),
+                    debug_rnglists: section_data(".debug_rnglists"),
+                    debug_loc: section_data(".debug_loc"),
+                    debug_loclists: section_data(".debug_loclists"),
+                };
+                DebugInfo::parse(&debug_sections).ok()
+            },
+            None => None,
+        };
+        Ok(Module { path: path.to_owned(), start, end, base, sym_map_list, unwind_table, line_table, debug_info })
+    }
+
+    pub fn get_path(&self) -> &String { &self.path }
+    pub fn get_base(&self) -> u64 { self.base }
+    pub fn get_unwind_table(&self) -> &UnwindTable { &self.unwind_table }
+    pub fn get_debug_info(&self) -> Option<&DebugInfo> { self.debug_info.as_ref() }
+
+    pub fn contains(&self, vir_addr: u64) -> bool {
+        self.start <= vir_addr && vir_addr < self.end
+    }
+}
+
+#[derive(Debug)]
+pub struct Target {
+    pid: Pid,
+    modules: Vec<Module>,
+}
+
+impl Target {
+    pub fn load(pid: Pid) -> Result<Self> {
+        let mut target = Target { pid, modules: vec![] };
+        target.reload()?;
+        Ok(target)
+    }
+
+    // プロセスがまだ無いインフェリアのもの. 起動するかアタッチするまでシンボルは無い
+    pub fn empty() -> Self {
+        Target { pid: Pid::from_raw(0), modules: vec![] }
+    }
+
+    // /proc/<pid>/maps を読み直してモジュール一覧を作り直す
+    pub fn reload(&mut self) -> Result<()> {
+        let path = format!("/proc/{}/maps", self.pid);
+        let maps = read_to_string(&path).with_context(|| format!("Error in reading {}", path))?;
+        // (path, start, end, offset 0 のマッピングの開始アドレス)
+        let mut mappings: Vec<(String, u64, u64, Option<u64>)> = vec![];
+        for line in maps.lines() {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 6 || !columns[5].starts_with('/') { continue; }
+            let mut range = columns[0].split('-');
+            let start = u64::from_str_radix(range.next().unwrap_or(""), 16).unwrap_or(0);
+            let end = u64::from_str_radix(range.next().unwrap_or(""), 16).unwrap_or(0);
+            let offset = u64::from_str_radix(columns[2], 16).unwrap_or(0);
+            let path = columns[5..].join(" ");
+            match mappings.iter_mut().find(|(mapped_path, ..)| mapped_path == &path) {
+                Some(mapping) => {
+                    mapping.1 = mapping.1.min(start);
+                    mapping.2 = mapping.2.max(end);
+                    if offset == 0 && mapping.3.is_none() { mapping.3 = Some(start); }
+                },
+                None => mappings.push((path, start, end, if offset == 0 { Some(start) } else { None })),
+            }
+        }
+
+        let mut modules: Vec<Module> = vec![];
+        for (path, start, end, first_mapping) in mappings.into_iter() {
+            let first_mapping = first_mapping.unwrap_or(start);
+            // 既に読み込んだモジュールは使い回す
+            if let Some(idx) = self.modules.iter().position(|module| module.path == path && module.start == start) {
+                let mut module = self.modules.remove(idx);
+                module.end = end;
+                modules.push(module);
+                continue;
+            }
+            if let Ok(module) = Module::load(&path, start, end, first_mapping) {
+                modules.push(module);
+            }
+        }
+        self.modules = modules;
+        Ok(())
+    }
+
+    // /proc/<pid>/maps のうちvir_addrを含むマッピング. 戻り値は (開始, 終わり, 権限, パス). 無名のマッピングならパスは空
+    pub fn find_mapping(&self, vir_addr: u64) -> Option<(u64, u64, String, String)> {
+        let maps = read_to_string(format!("/proc/{}/maps", self.pid)).ok()?;
+        maps.lines().find_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let (start, end) = columns.first()?.split_once('-')?;
+            let start = u64::from_str_radix(start, 16).ok()?;
+            let end = u64::from_str_radix(end, 16).ok()?;
+            if vir_addr < start || end <= vir_addr { return None; }
+            let path = if columns.len() > 5 { columns[5..].join(" ") } else { String::new() };
+            Some((start, end, columns.get(1)?.to_string(), path))
+        })
+    }
+
+    pub fn find_module(&self, vir_addr: u64) -> Option<&Module> {
+        self.modules.iter().find(|module| module.contains(vir_addr))
+    }
+
+    pub fn get_modules(&self) -> &Vec<Module> { &self.modules }
+
+    // vir_addrを含むDWARF上の関数
+    pub fn find_function(&self, vir_addr: u64) -> Option<(&Module, &Function)> {
+        let module = self.find_module(vir_addr)?;
+        let function = module.debug_info.as_ref()?.find_function(vir_addr.wrapping_sub(module.base))?;
+        Some((module, function))
+    }
+
+    // 戻り値は (関数名, 関数先頭からのオフセット)
+    pub fn symbolize(&self, vir_addr: u64) -> Option<(String, u64)> {
+        let module = self.find_module(vir_addr)?;
+        let relative_addr = vir_addr.wrapping_sub(module.base);
+        let sym_map = check_fn_viradd::lookup_sym_by_addr(&module.sym_map_list, relative_addr)?;
+        Some((sym_map.get_fn_name().clone(), relative_addr - sym_map.get_vir_addr()))
+    }
+
+    // vir_addrを含む関数のシンボル. 戻り値は (関数名, 先頭アドレス, 終わりのアドレス)
+    pub fn find_symbol_range(&self, vir_addr: u64) -> Option<(String, u64, u64)> {
+        let module = self.find_module(vir_addr)?;
+        let sym_map = check_fn_viradd::lookup_sym_by_addr(&module.sym_map_list, vir_addr.wrapping_sub(module.base))?;
+        let start = module.base + sym_map.get_vir_addr();
+        Some((sym_map.get_fn_name().clone(), start, start + sym_map.get_size()))
+    }
+
+    // 戻り値は (ファイル名, 行番号). 行番号0はどの行でもない命令なので, 見つからなかったことにする
+    pub fn find_source_line(&self, vir_addr: u64) -> Option<(String, u64)> {
+        let module = self.find_module(vir_addr)?;
+        let (file_name, line) = module.line_table.find_location(vir_addr.wrapping_sub(module.base))?;
+        if line == 0 { return None; }
+        Some((file_name.to_owned(), line))
+    }
+
+    // 全てのモジュールから file:line のアドレスを探す. 戻り値は (アドレス, 実際の行番号)
+    pub fn find_line_address(&self, file: Option<&str>, line: u64) -> Option<(u64, u64)> {
+        self.modules.iter().find_map(|module| {
+            module.line_table.find_line_address(file, line).map(|(addr, line)| (module.base + addr, line))
+        })
+    }
+
+    // DWARFの関数名, 無ければシンボルテーブルから関数の先頭アドレスを探す. nameは main2 のように最後の要素だけでもよい
+    pub fn find_function_by_name(&self, name: &str) -> Option<u64> {
+        for module in self.modules.iter() {
+            if let Some(function) = module.debug_info.as_ref().and_then(|debug_info| debug_info.find_function_by_name(name)) {
+                return Some(module.base + function.get_low_pc());
+            }
+        }
+        let suffix = format!("::{}", name);
+        for module in self.modules.iter() {
+            let sym_map = module.sym_map_list.iter().find(|sym_map| sym_map.get_fn_name() == name)
+                .or_else(|| module.sym_map_list.iter().find(|sym_map| sym_map.get_fn_name().ends_with(&suffix)));
+            if let Some(sym_map) = sym_map {
+                return Some(module.base + sym_map.get_vir_addr());
+            }
+        }
+        None
+    }
+
+    // プログラムのmainの先頭アドレス. Rustのシンボルの main はCランタイムから呼ばれてlang_startに渡すだけなので,
+    // 実行ファイルの <クレート名>::main を探す. 無ければCのプログラムとみなして main にする
+    pub fn find_main(&self) -> Option<u64> {
+        let module = self.modules.first()?;
+        let crate_name = Path::new(&module.path).file_name()
+            .map(|file_name| file_name.to_string_lossy().replace('-', "_"))
+            .unwrap_or_default();
+        let candidates: Vec<&SymMap> = module.sym_map_list.iter()
+            .filter(|sym_map| match sym_map.get_fn_name().split_once("::") {
+                Some((prefix, "main")) => !prefix.is_empty() && !prefix.contains(['<', ' ']),
+                _ => false,
+            })
+            .collect();
+        let rust_main = candidates.iter().find(|sym_map| sym_map.get_fn_name() == &format!("{}::main", crate_name))
+            .or_else(|| candidates.first());
+        match rust_main {
+            Some(sym_map) => Some(module.base + sym_map.get_vir_addr()),
+            None => self.find_function_by_name("main"),
+        }
+    }
+
+    pub fn is_statement_start(&self, vir_addr: u64) -> bool {
+        match self.find_module(vir_addr) {
+            Some(module) => module.line_table.is_statement_start(vir_addr.wrapping_sub(module.base)),
+            None => false,
+        }
+    }
+
+    pub fn find_prologue_end(&self, vir_addr: u64) -> Option<u64> {
+        let module = self.find_module(vir_addr)?;
+        let addr = module.line_table.find_prologue_end(vir_addr.wrapping_sub(module.base))?;
+        Some(module.base + addr)
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Result, Context};
+use nix::unistd::Pid;
+use nix::sys::signal::Signal;
+use std::fs::{read_dir, read_to_string};
+
+// トレースしているスレッド (LWP)
+#[derive(Debug, Clone)]
+pub struct Thread {
+    // gdbと同じく1から順に振る番号. スレッドが終わっても使い回さない
+    num: usize,
+    tid: Pid,
+    // 再開してからまだ止まったのを受け取っていない
+    running: bool,
+    // 止めるために送ったPTRACE_INTERRUPTの停止をまだ受け取っていない. 受け取ったら捨てる
+    pending_stop: bool,
+    // ジョブ制御のシグナルでグループストップしている. 再開してもSIGCONTが来るまでは止めておく
+    group_stop: bool,
+    // 次に再開する時にこのスレッドに渡すシグナル
+    pending_signal: Option<Signal>,
+    // 最後に報告したブレークポイントのアドレス. 再開する時はここから1命令進めてからint3を書く
+    reported_breakpoint: Option<u64>,
+    // 最後に報告した止まった理由. デバッガが止めただけなら空
+    stop_reason: String,
+    // pthread_t (スレッドのfs_base). 終わった後も表示できるように覚えておく
+    pthread: u64,
+    // catch panic: パニックハンドラで読んだパニックの場所. 巻き戻しを始める所で止まった時に表示する
+    panic_location: Option<String>,
+    // catch syscall: 最後のシステムコールの停止が入り口だった. PTRACE_SYSCALL以外で再開したら戻す
+    in_syscall: bool,
+}
+
+impl Thread {
+    pub fn new(num: usize, tid: Pid) -> Self {
+        Thread { num, tid, running: false, pending_stop: false, group_stop: false, pending_signal: None, reported_breakpoint: None, stop_reason: String::new(), pthread: 0, panic_location: None, in_syscall: false }
+    }
+
+    pub fn get_num(&self) -> usize { self.num }
+    pub fn get_tid(&self) -> Pid { self.tid }
+    pub fn is_running(&self) -> bool { self.running }
+    pub fn has_pending_stop(&self) -> bool { self.pending_stop }
+    pub fn get_reported_breakpoint(&self) -> Option<u64> { self.reported_breakpoint }
+    pub fn get_stop_reason(&self) -> &String { &self.stop_reason }
+    pub fn get_pthread(&self) -> u64 { self.pthread }
+    pub fn is_in_syscall(&self) -> bool { self.in_syscall }
+
+    pub fn set_running(&mut self, running: bool) {
+        self.running = running;
+        if running {
+            self.reported_breakpoint = None;
+            self.stop_reason.clear();
+        }
+    }
+
+    pub fn set_pending_stop(&mut self, pending: bool) {
+        self.pending_stop = pending;
+    }
+
+    pub fn set_group_stop(&mut self, group_stop: bool) {
+        self.group_stop = group_stop;
+    }
+
+    pub fn take_group_stop(&mut self) -> bool {
+        std::mem::take(&mut self.group_stop)
+    }
+
+    pub fn set_pending_signal(&mut self, sig: Option<Signal>) {
+        self.pending_signal = sig;
+    }
+
+    pub fn take_pending_signal(&mut self) -> Option<Signal> {
+        self.pending_signal.take()
+    }
+
+    pub fn set_reported_breakpoint(&mut self, addr: Option<u64>) {
+        self.reported_breakpoint = addr;
+    }
+
+    pub fn set_stop_reason(&mut self, reason: String) {
+        self.stop_reason = reason;
+    }
+
+    pub fn set_pthread(&mut self, pthread: u64) {
+        self.pthread = pthread;
+    }
+
+    pub fn set_in_syscall(&mut self, in_syscall: bool) {
+        self.in_syscall = in_syscall;
+    }
+
+    pub fn set_panic_location(&mut self, location: Option<String>) {
+        self.panic_location = location;
+    }
+
+    pub fn take_panic_location(&mut self) -> Option<String> {
+        self.panic_location.take()
+    }
+}
+
+// /proc/<pid>/task にあるスレッドのid
+pub fn list_tasks(pid: Pid) -> Result<Vec<Pid>> {
+    let path = format!("/proc/{}/task", pid);
+    let mut tids: Vec<Pid> = read_dir(&path).with_context(|| format!("Error in reading {}", path))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()))
+        .map(Pid::from_raw)
+        .collect();
+    tids.sort_by_key(|tid| tid.as_raw());
+    Ok(tids)
+}
+
+// /proc/<tid>/status の (Tgid, PPid). まだ知らないスレッドやプロセスがどのプロセスから作られたかを調べる
+pub fn task_owner(tid: Pid) -> Option<(Pid, Pid)> {
+    let status = read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    let field = |name: &str| status.lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.trim().parse::<i32>().ok())
+        .map(Pid::from_raw);
+    Some((field("Tgid:")?, field("PPid:")?))
+}
+
+// pthread_setname_np などで付けたスレッドの名前
+pub fn thread_name(pid: Pid, tid: Pid) -> Option<String> {
+    read_to_string(format!("/proc/{}/task/{}/comm", pid, tid)).ok().map(|name| name.trim_end().to_owned())
+}
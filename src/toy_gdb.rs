@@ -10,18 +10,44 @@ use std::collections::HashMap;
 use std::fs::{read, read_to_string, read_dir};
 
 mod ptrace;
+mod registers;
+mod breakpoint;
 mod check_fn_viradd;
+mod command;
+mod debugger;
+mod disasm;
+mod dwarf_reader;
+mod dwarf_expr;
+mod dwarf_info;
+mod dwarf_line;
+mod expression;
+mod panic;
+mod printf;
+mod signals;
+mod syscall;
+mod target;
+mod thread;
+mod unwind;
+mod value;
+mod watchpoint;
+
+use debugger::Debugger;
+use target::Target;
 
 fn main() -> Result<()> {
     let commands: Vec<String> = std::env::args().collect();
     if commands.len() < 2 {
-        println!("You should input pid.");
+        println!("You should input pid or program.");
         println!("ex) cargo run --bin check-state <pid> <function_filter>");
+        println!("ex) cargo run --bin toy-gdb <program> [args...]");
         std::process::exit(0);
     }
+    // Ctrl-Cではデバッガを終わらせずに, 動いているプログラムを止める
+    ptrace::install_interrupt_handler().with_context(|| "Error in installing the SIGINT handler")?;
     let pid_str = &commands[1];
     let pid_num = pid_str.parse::<i64>().unwrap_or(-1);
-    if pid_num == -1 { panic!("invalid pid."); }
+    // 数字でなければプログラムとみなして起動する
+    if pid_num == -1 { return launch(&commands[1..]); }
     let pid = Pid::from_raw(pid_num as libc::pid_t);
     println!("pid: {:?}", pid);
     let filter: Option<&str> = if (&commands).len() > 2 { Some(&commands[2]) } else { None };
@@ -34,35 +60,25 @@ fn main() -> Result<()> {
     let debug_point = decide_debug_point(&pid, filter, &sym_map_list).with_context(|| "Error in decide_debug_point")?;
 
     ptrace::attach(pid).unwrap();
+    let target = Target::load(pid).with_context(|| "Error in Target::load")?;
+    let mut debugger = Debugger::new(pid, target);
+
+    let status: WaitStatus = ptrace::wait_pid(pid).unwrap();
+    println!("status: {:?}", status);
+    let regs: user_regs_struct = ptrace::getregs(pid).unwrap();
+    print_regs(&pid, &regs);
+    debugger.stopped(regs);
+    debugger.attach_threads().with_context(|| "Error in attaching threads")?;
+
+    debugger.add_breakpoint(debug_point, false);
+    debugger.run();
+    Ok(())
+}
 
-    loop {
-        let status: WaitStatus = ptrace::wait_pid(pid).unwrap();
-        let pid = status.pid().unwrap();
-        println!("status: {:?}", status);
-        let mut regs: user_regs_struct = ptrace::getregs(pid).unwrap();
-        print_regs(&pid, &regs);
-
-        let instruction_res = ptrace::peek_text(pid, debug_point);
-        println!("instruction_res: {:?}", instruction_res);
-        let instruction = if instruction_res.is_ok() { instruction_res.unwrap() } else { panic!(format!("ptrace::peek_text error.[instruction_res: {:?}, pid: {:?}, debug_point: {:x}]", instruction_res, pid, debug_point)) };
-
-        ptrace::poke_text(pid, debug_point, (instruction & !0xff | 0xcc) as u64);
-
-        ptrace::cont(pid)?;
-
-        let status: WaitStatus = ptrace::wait_pid(pid).unwrap();
-        println!("status: {:?}" , status);
-        let mut regs: user_regs_struct = ptrace::getregs(pid).unwrap();
-        print_regs(&pid, &regs);
-
-        // wait
-        wait_until_enter();
-
-        regs.rip = debug_point;
-        ptrace::setregs(pid, regs);
-        ptrace::poke_text(pid, debug_point, instruction as u64);
-        ptrace::syscall_step(pid);
-    }
+// execした所で止めてコマンドを受け付ける. start でmainまで進められる
+fn launch(program: &[String]) -> Result<()> {
+    let mut debugger = Debugger::launch(program.to_vec()).with_context(|| format!("Error in launching {}", program[0]))?;
+    debugger.prompt();
     Ok(())
 }
 
@@ -125,10 +141,3 @@ fn fetch_text_base_vir_address(pid: &str) -> Result<u64> {
     println!("text_start_at: {:?}", text_start_at);
     u64::from_str_radix(text_start_at, 16).with_context(|| "u64::from_str_radix error in fetch_text_base_vir_address method.")
 }
-
-
-fn wait_until_enter() {
-    println!("Wait!!");
-    let mut s = String::new();
-    std::io::stdin().read_line(&mut s).unwrap();
-}
\ No newline at end of file
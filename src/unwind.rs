@@ -0,0 +1,798 @@
+use anyhow::{Result, bail, Context};
+use nix::unistd::Pid;
+use nix::libc::user_regs_struct;
+use std::collections::HashMap;
+
+use crate::dwarf_reader::DwarfReader;
+use crate::dwarf_expr::{self, ExprContext, ExprResult};
+use crate::ptrace;
+use crate::target::Target;
+
+// x86-64のDWARFレジスタ番号. 16はリターンアドレス(rip)
+const DWARF_REG_RSP: u16 = 7;
+const DWARF_REG_NUM: usize = 17;
+
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+const MAX_BACKTRACE_DEPTH: usize = 1024;
+
+pub fn get_dwarf_reg(regs: &user_regs_struct, dwarf_reg: u16) -> Option<u64> {
+    let value = match dwarf_reg {
+        0 => regs.rax,
+        1 => regs.rdx,
+        2 => regs.rcx,
+        3 => regs.rbx,
+        4 => regs.rsi,
+        5 => regs.rdi,
+        6 => regs.rbp,
+        7 => regs.rsp,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        15 => regs.r15,
+        16 => regs.rip,
+        49 => regs.eflags,
+        50 => regs.es,
+        51 => regs.cs,
+        52 => regs.ss,
+        53 => regs.ds,
+        54 => regs.fs,
+        55 => regs.gs,
+        58 => regs.fs_base,
+        59 => regs.gs_base,
+        _ => return None,
+    };
+    Some(value)
+}
+
+pub fn set_dwarf_reg(regs: &mut user_regs_struct, dwarf_reg: u16, value: u64) {
+    match dwarf_reg {
+        0 => regs.rax = value,
+        1 => regs.rdx = value,
+        2 => regs.rcx = value,
+        3 => regs.rbx = value,
+        4 => regs.rsi = value,
+        5 => regs.rdi = value,
+        6 => regs.rbp = value,
+        7 => regs.rsp = value,
+        8 => regs.r8 = value,
+        9 => regs.r9 = value,
+        10 => regs.r10 = value,
+        11 => regs.r11 = value,
+        12 => regs.r12 = value,
+        13 => regs.r13 = value,
+        14 => regs.r14 = value,
+        15 => regs.r15 = value,
+        16 => regs.rip = value,
+        _ => {},
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CfaRule {
+    RegOffset(u16, i64),
+    Expression(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum RegRule {
+    Undefined,
+    SameValue,
+    Offset(i64),
+    ValOffset(i64),
+    Register(u16),
+    Expression(Vec<u8>),
+    ValExpression(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct RowState {
+    cfa: CfaRule,
+    regs: HashMap<u16, RegRule>,
+}
+
+#[derive(Debug, Clone)]
+struct Cie {
+    code_alignment_factor: u64,
+    data_alignment_factor: i64,
+    return_address_register: u16,
+    fde_pointer_encoding: u8,
+    has_augmentation_data: bool,
+    is_signal_frame: bool,
+    address_size: u8,
+    instructions: (usize, usize),
+}
+
+#[derive(Debug, Clone)]
+struct Fde {
+    pc_begin: u64,
+    pc_end: u64,
+    cie: Cie,
+    instructions: (usize, usize),
+}
+
+// .eh_frame / .debug_frame のどちらか. fde_indexは (pc_begin, FDEのオフセット) をpc順に並べたもの
+#[derive(Debug)]
+struct CfiSection {
+    data: Vec<u8>,
+    vir_addr: u64,
+    is_eh_frame: bool,
+    fde_index: Vec<(u64, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct UnwindTable {
+    eh_frame: Option<CfiSection>,
+    debug_frame: Option<CfiSection>,
+}
+
+impl UnwindTable {
+    pub fn parse(eh_frame: Option<(Vec<u8>, u64)>, eh_frame_hdr: Option<(Vec<u8>, u64)>, debug_frame: Option<Vec<u8>>) -> Self {
+        let eh_frame = eh_frame.map(|(data, vir_addr)| {
+            let mut section = CfiSection { data, vir_addr, is_eh_frame: true, fde_index: vec![] };
+            // .eh_frame_hdrの二分探索テーブルがあればそれを使い, なければ.eh_frameを全部なめる
+            let fde_index = eh_frame_hdr.as_ref()
+                .and_then(|(hdr, hdr_vir_addr)| parse_eh_frame_hdr(hdr, *hdr_vir_addr, section.vir_addr).ok())
+                .filter(|fde_index| !fde_index.is_empty());
+            section.fde_index = match fde_index {
+                Some(fde_index) => fde_index,
+                None => section.build_fde_index(),
+            };
+            section
+        });
+        let debug_frame = debug_frame.map(|data| {
+            let mut section = CfiSection { data, vir_addr: 0, is_eh_frame: false, fde_index: vec![] };
+            section.fde_index = section.build_fde_index();
+            section
+        });
+        UnwindTable { eh_frame, debug_frame }
+    }
+
+    fn find_fde(&self, pc: u64) -> Option<(&CfiSection, Fde)> {
+        for section in [self.eh_frame.as_ref(), self.debug_frame.as_ref()].iter().flatten() {
+            if let Some(fde) = section.find_fde(pc) {
+                return Some((section, fde));
+            }
+        }
+        None
+    }
+}
+
+impl CfiSection {
+    fn build_fde_index(&self) -> Vec<(u64, usize)> {
+        let mut fde_index: Vec<(u64, usize)> = vec![];
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let mut reader = DwarfReader::at(&self.data, offset);
+            let (length, is_dwarf64) = match reader.read_initial_length() {
+                Ok(res) => res,
+                Err(_) => break,
+            };
+            if length == 0 {
+                // .eh_frameの終端
+                if self.is_eh_frame { break; }
+                offset = reader.get_offset();
+                continue;
+            }
+            let next = match reader.offset_after(length) {
+                Ok(next) => next,
+                Err(_) => break,
+            };
+            if !self.is_cie(&mut reader, is_dwarf64) {
+                if let Ok(fde) = self.parse_fde(offset) {
+                    if fde.pc_begin != 0 { fde_index.push((fde.pc_begin, offset)); }
+                }
+            }
+            offset = next;
+        }
+        fde_index.sort();
+        fde_index
+    }
+
+    fn is_cie(&self, reader: &mut DwarfReader, is_dwarf64: bool) -> bool {
+        let id = match reader.read_offset(is_dwarf64) {
+            Ok(id) => id,
+            Err(_) => return true,
+        };
+        if self.is_eh_frame { id == 0 } else if is_dwarf64 { id == 0xffff_ffff_ffff_ffff } else { id == 0xffff_ffff }
+    }
+
+    fn find_fde(&self, pc: u64) -> Option<Fde> {
+        let idx = match self.fde_index.binary_search_by(|(pc_begin, _)| pc_begin.cmp(&pc)) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let fde = self.parse_fde(self.fde_index[idx].1).ok()?;
+        if fde.pc_begin <= pc && pc < fde.pc_end { Some(fde) } else { None }
+    }
+
+    fn parse_cie(&self, offset: usize) -> Result<Cie> {
+        let mut reader = DwarfReader::at(&self.data, offset);
+        let (length, is_dwarf64) = reader.read_initial_length()?;
+        let end = reader.offset_after(length)?;
+        if !self.is_cie(&mut reader, is_dwarf64) { bail!("0x{:x} is not CIE.", offset); }
+        let version = reader.read_u8()?;
+        let augmentation = reader.read_cstr()?;
+        let mut address_size = 8;
+        if version >= 4 {
+            address_size = reader.read_u8()?;
+            let _segment_size = reader.read_u8()?;
+        }
+        let code_alignment_factor = reader.read_uleb128()?;
+        let data_alignment_factor = reader.read_sleb128()?;
+        let return_address_register = if version == 1 { reader.read_u8()? as u16 } else { reader.read_uleb128()? as u16 };
+        let mut fde_pointer_encoding = 0;
+        let mut is_signal_frame = false;
+        let has_augmentation_data = augmentation.starts_with('z');
+        if has_augmentation_data {
+            let augmentation_length = reader.read_uleb128()?;
+            let augmentation_end = reader.offset_after(augmentation_length)?;
+            for c in augmentation.chars().skip(1) {
+                match c {
+                    'L' => { reader.read_u8()?; },
+                    'P' => {
+                        let encoding = reader.read_u8()?;
+                        self.read_encoded_pointer(&mut reader, encoding)?;
+                    },
+                    'R' => fde_pointer_encoding = reader.read_u8()?,
+                    'S' => is_signal_frame = true,
+                    _ => break,
+                }
+            }
+            reader.set_offset(augmentation_end);
+        } else if augmentation == "eh" {
+            // 古いGCCの形式. eh_ptrを読み飛ばす
+            reader.skip(8)?;
+        }
+        Ok(Cie {
+            code_alignment_factor,
+            data_alignment_factor,
+            return_address_register,
+            fde_pointer_encoding,
+            has_augmentation_data,
+            is_signal_frame,
+            address_size,
+            instructions: (reader.get_offset(), end),
+        })
+    }
+
+    fn parse_fde(&self, offset: usize) -> Result<Fde> {
+        let mut reader = DwarfReader::at(&self.data, offset);
+        let (length, is_dwarf64) = reader.read_initial_length()?;
+        let end = reader.offset_after(length)?;
+        let cie_pointer_offset = reader.get_offset();
+        let cie_pointer = reader.read_offset(is_dwarf64)?;
+        // .eh_frameのCIEポインタは自分の位置からの相対値, .debug_frameはセクション先頭からのオフセット
+        let cie_offset = if self.is_eh_frame { cie_pointer_offset - cie_pointer as usize } else { cie_pointer as usize };
+        let cie = self.parse_cie(cie_offset)?;
+        let (pc_begin, pc_range) = if self.is_eh_frame {
+            let pc_begin = self.read_encoded_pointer(&mut reader, cie.fde_pointer_encoding)?;
+            let pc_range = self.read_encoded_pointer(&mut reader, cie.fde_pointer_encoding & 0x0f)?;
+            (pc_begin, pc_range)
+        } else {
+            (reader.read_address(cie.address_size)?, reader.read_address(cie.address_size)?)
+        };
+        if cie.has_augmentation_data {
+            let augmentation_length = reader.read_uleb128()? as usize;
+            reader.skip(augmentation_length)?;
+        }
+        Ok(Fde {
+            pc_begin,
+            pc_end: pc_begin.wrapping_add(pc_range),
+            cie,
+            instructions: (reader.get_offset(), end),
+        })
+    }
+
+    fn read_encoded_pointer(&self, reader: &mut DwarfReader, encoding: u8) -> Result<u64> {
+        read_encoded_pointer(reader, encoding, self.vir_addr, 0)
+    }
+
+    // pcの位置でのCFAとレジスタの復元ルールを求める
+    fn find_row(&self, fde: &Fde, pc: u64) -> Result<RowState> {
+        let initial = RowState { cfa: CfaRule::RegOffset(DWARF_REG_RSP, 8), regs: HashMap::new() };
+        let cie_row = self.execute_instructions(&fde.cie, fde.cie.instructions, initial.clone(), &initial, fde.pc_begin, u64::MAX)?;
+        self.execute_instructions(&fde.cie, fde.instructions, cie_row.clone(), &cie_row, fde.pc_begin, pc)
+    }
+
+    fn execute_instructions(&self, cie: &Cie, range: (usize, usize), mut row: RowState, initial: &RowState, pc_begin: u64, target_pc: u64) -> Result<RowState> {
+        let data = &self.data[..range.1.min(self.data.len())];
+        let mut reader = DwarfReader::at(data, range.0);
+        let mut loc = pc_begin;
+        let mut stack: Vec<RowState> = vec![];
+        while !reader.is_empty() {
+            let opcode = reader.read_u8()?;
+            let high = opcode & 0xc0;
+            let low = (opcode & 0x3f) as u16;
+            let mut advance: Option<u64> = None;
+            match high {
+                // DW_CFA_advance_loc
+                0x40 => advance = Some(low as u64 * cie.code_alignment_factor),
+                // DW_CFA_offset
+                0x80 => {
+                    let offset = reader.read_uleb128()? as i64 * cie.data_alignment_factor;
+                    row.regs.insert(low, RegRule::Offset(offset));
+                },
+                // DW_CFA_restore
+                0xc0 => restore_rule(&mut row, initial, low),
+                _ => match opcode {
+                    // DW_CFA_nop
+                    0x00 => {},
+                    // DW_CFA_set_loc
+                    0x01 => {
+                        let new_loc = if self.is_eh_frame {
+                            self.read_encoded_pointer(&mut reader, cie.fde_pointer_encoding)?
+                        } else {
+                            reader.read_address(cie.address_size)?
+                        };
+                        if new_loc > target_pc { return Ok(row); }
+                        loc = new_loc;
+                    },
+                    // DW_CFA_advance_loc1/2/4
+                    0x02 => advance = Some(reader.read_u8()? as u64 * cie.code_alignment_factor),
+                    0x03 => advance = Some(reader.read_u16()? as u64 * cie.code_alignment_factor),
+                    0x04 => advance = Some(reader.read_u32()? as u64 * cie.code_alignment_factor),
+                    // DW_CFA_offset_extended
+                    0x05 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_uleb128()? as i64 * cie.data_alignment_factor;
+                        row.regs.insert(reg, RegRule::Offset(offset));
+                    },
+                    // DW_CFA_restore_extended
+                    0x06 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        restore_rule(&mut row, initial, reg);
+                    },
+                    // DW_CFA_undefined
+                    0x07 => { row.regs.insert(reader.read_uleb128()? as u16, RegRule::Undefined); },
+                    // DW_CFA_same_value
+                    0x08 => { row.regs.insert(reader.read_uleb128()? as u16, RegRule::SameValue); },
+                    // DW_CFA_register
+                    0x09 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let other = reader.read_uleb128()? as u16;
+                        row.regs.insert(reg, RegRule::Register(other));
+                    },
+                    // DW_CFA_remember_state
+                    0x0a => stack.push(row.clone()),
+                    // DW_CFA_restore_state: CFAもremember_stateの時のものに戻す
+                    0x0b => row = stack.pop().context("DW_CFA_restore_state with empty stack.")?,
+                    // DW_CFA_def_cfa
+                    0x0c => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_uleb128()? as i64;
+                        row.cfa = CfaRule::RegOffset(reg, offset);
+                    },
+                    // DW_CFA_def_cfa_register
+                    0x0d => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = match row.cfa { CfaRule::RegOffset(_, offset) => offset, _ => 0 };
+                        row.cfa = CfaRule::RegOffset(reg, offset);
+                    },
+                    // DW_CFA_def_cfa_offset
+                    0x0e => {
+                        let offset = reader.read_uleb128()? as i64;
+                        if let CfaRule::RegOffset(reg, _) = row.cfa { row.cfa = CfaRule::RegOffset(reg, offset); }
+                    },
+                    // DW_CFA_def_cfa_expression
+                    0x0f => {
+                        let len = reader.read_uleb128()? as usize;
+                        row.cfa = CfaRule::Expression(reader.read_bytes(len)?.to_vec());
+                    },
+                    // DW_CFA_expression
+                    0x10 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let len = reader.read_uleb128()? as usize;
+                        row.regs.insert(reg, RegRule::Expression(reader.read_bytes(len)?.to_vec()));
+                    },
+                    // DW_CFA_offset_extended_sf
+                    0x11 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_sleb128()? * cie.data_alignment_factor;
+                        row.regs.insert(reg, RegRule::Offset(offset));
+                    },
+                    // DW_CFA_def_cfa_sf
+                    0x12 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_sleb128()? * cie.data_alignment_factor;
+                        row.cfa = CfaRule::RegOffset(reg, offset);
+                    },
+                    // DW_CFA_def_cfa_offset_sf
+                    0x13 => {
+                        let offset = reader.read_sleb128()? * cie.data_alignment_factor;
+                        if let CfaRule::RegOffset(reg, _) = row.cfa { row.cfa = CfaRule::RegOffset(reg, offset); }
+                    },
+                    // DW_CFA_val_offset
+                    0x14 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_uleb128()? as i64 * cie.data_alignment_factor;
+                        row.regs.insert(reg, RegRule::ValOffset(offset));
+                    },
+                    // DW_CFA_val_offset_sf
+                    0x15 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = reader.read_sleb128()? * cie.data_alignment_factor;
+                        row.regs.insert(reg, RegRule::ValOffset(offset));
+                    },
+                    // DW_CFA_val_expression
+                    0x16 => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let len = reader.read_uleb128()? as usize;
+                        row.regs.insert(reg, RegRule::ValExpression(reader.read_bytes(len)?.to_vec()));
+                    },
+                    // DW_CFA_GNU_args_size
+                    0x2e => { reader.read_uleb128()?; },
+                    // DW_CFA_GNU_negative_offset_extended
+                    0x2f => {
+                        let reg = reader.read_uleb128()? as u16;
+                        let offset = -(reader.read_uleb128()? as i64) * cie.data_alignment_factor;
+                        row.regs.insert(reg, RegRule::Offset(offset));
+                    },
+                    _ => bail!("unsupported CFA instruction: 0x{:x}", opcode),
+                },
+            }
+            if let Some(advance) = advance {
+                if loc + advance > target_pc { return Ok(row); }
+                loc += advance;
+            }
+        }
+        Ok(row)
+    }
+}
+
+fn restore_rule(row: &mut RowState, initial: &RowState, reg: u16) {
+    match initial.regs.get(&reg) {
+        Some(rule) => { row.regs.insert(reg, rule.clone()); },
+        None => { row.regs.remove(&reg); },
+    }
+}
+
+pub fn read_encoded_pointer(reader: &mut DwarfReader, encoding: u8, section_vir_addr: u64, data_rel_base: u64) -> Result<u64> {
+    if encoding == DW_EH_PE_OMIT { return Ok(0); }
+    let field_vir_addr = section_vir_addr.wrapping_add(reader.get_offset() as u64);
+    let value = match encoding & 0x0f {
+        0x00 => reader.read_u64()?,
+        0x01 => reader.read_uleb128()?,
+        0x02 => reader.read_u16()? as u64,
+        0x03 => reader.read_u32()? as u64,
+        0x04 => reader.read_u64()?,
+        0x09 => reader.read_sleb128()? as u64,
+        0x0a => reader.read_i16()? as i64 as u64,
+        0x0b => reader.read_i32()? as i64 as u64,
+        0x0c => reader.read_i64()? as u64,
+        _ => bail!("unsupported pointer encoding: 0x{:x}", encoding),
+    };
+    match encoding & 0x70 {
+        0x00 => Ok(value),
+        DW_EH_PE_PCREL => Ok(field_vir_addr.wrapping_add(value)),
+        DW_EH_PE_DATAREL => Ok(data_rel_base.wrapping_add(value)),
+        _ => bail!("unsupported pointer application: 0x{:x}", encoding),
+    }
+}
+
+fn parse_eh_frame_hdr(hdr: &[u8], hdr_vir_addr: u64, eh_frame_vir_addr: u64) -> Result<Vec<(u64, usize)>> {
+    let mut reader = DwarfReader::new(hdr);
+    let version = reader.read_u8()?;
+    if version != 1 { bail!("unsupported .eh_frame_hdr version: {}", version); }
+    let eh_frame_ptr_enc = reader.read_u8()?;
+    let fde_count_enc = reader.read_u8()?;
+    let table_enc = reader.read_u8()?;
+    read_encoded_pointer(&mut reader, eh_frame_ptr_enc, hdr_vir_addr, hdr_vir_addr)?;
+    if fde_count_enc == DW_EH_PE_OMIT || table_enc == DW_EH_PE_OMIT { return Ok(vec![]); }
+    let fde_count = read_encoded_pointer(&mut reader, fde_count_enc, hdr_vir_addr, hdr_vir_addr)?;
+    let mut fde_index: Vec<(u64, usize)> = vec![];
+    for _ in 0..fde_count {
+        let initial_loc = read_encoded_pointer(&mut reader, table_enc, hdr_vir_addr, hdr_vir_addr)?;
+        let fde_vir_addr = read_encoded_pointer(&mut reader, table_enc, hdr_vir_addr, hdr_vir_addr)?;
+        fde_index.push((initial_loc, fde_vir_addr.wrapping_sub(eh_frame_vir_addr) as usize));
+    }
+    fde_index.sort();
+    Ok(fde_index)
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    level: usize,
+    pc: u64,
+    cfa: Option<u64>,
+    regs: user_regs_struct,
+    // シグナルで割り込まれたフレーム. pcはリターンアドレスではなく割り込まれた命令そのもの
+    is_interrupted: bool,
+}
+
+impl Frame {
+    pub fn get_pc(&self) -> u64 { self.pc }
+    pub fn get_cfa(&self) -> Option<u64> { self.cfa }
+    pub fn get_regs(&self) -> &user_regs_struct { &self.regs }
+
+    // 呼び出し元のフレームはリターンアドレスを指しているので, 1つ前のアドレスでシンボルや行を引く
+    pub fn get_lookup_pc(&self) -> u64 {
+        if self.level == 0 || self.is_interrupted { self.pc } else { self.pc.wrapping_sub(1) }
+    }
+}
+
+struct UnwindExprContext<'a> {
+    pid: Pid,
+    regs: &'a user_regs_struct,
+    cfa: u64,
+}
+
+impl<'a> ExprContext for UnwindExprContext<'a> {
+    fn read_register(&self, dwarf_reg: u16) -> Result<u64> {
+        get_dwarf_reg(self.regs, dwarf_reg).with_context(|| format!("unknown DWARF register: {}", dwarf_reg))
+    }
+
+    fn read_memory(&self, addr: u64, size: u8) -> Result<u64> {
+        read_sized(self.pid, addr, size)
+    }
+
+    fn get_call_frame_cfa(&self) -> Result<u64> {
+        Ok(self.cfa)
+    }
+}
+
+fn read_sized(pid: Pid, addr: u64, size: u8) -> Result<u64> {
+    let bytes = ptrace::read_bytes(pid, addr, (size as usize).min(8))?;
+    let mut array = [0u8; 8];
+    array[..bytes.len()].copy_from_slice(&bytes);
+    Ok(u64::from_le_bytes(array))
+}
+
+// 1フレーム分巻き戻す. 巻き戻せなければNone
+fn unwind_step(target: &Target, pid: Pid, frame: &Frame) -> Option<Frame> {
+    let regs = &frame.regs;
+    let lookup_pc = frame.get_lookup_pc();
+    let mut caller_regs = *regs;
+    let mut cfa: Option<u64> = None;
+    let mut is_interrupted = false;
+
+    let cfi = target.find_module(lookup_pc).and_then(|module| {
+        let relative_pc = lookup_pc.wrapping_sub(module.get_base());
+        module.get_unwind_table().find_fde(relative_pc).map(|(section, fde)| (section, fde, relative_pc))
+    });
+    match cfi {
+        Some((section, fde, relative_pc)) => {
+            let row = section.find_row(&fde, relative_pc).ok()?;
+            let cfa_value = match &row.cfa {
+                CfaRule::RegOffset(reg, offset) => get_dwarf_reg(regs, *reg)?.wrapping_add(*offset as u64),
+                CfaRule::Expression(expr) => {
+                    let ctx = UnwindExprContext { pid, regs, cfa: 0 };
+                    match dwarf_expr::evaluate(expr, &ctx, &[]).ok()? {
+                        ExprResult::Address(addr) | ExprResult::Value(addr) => addr,
+                        _ => return None,
+                    }
+                },
+            };
+            let ctx = UnwindExprContext { pid, regs, cfa: cfa_value };
+            let mut return_address: Option<u64> = None;
+            for dwarf_reg in 0..DWARF_REG_NUM as u16 {
+                let rule = match row.regs.get(&dwarf_reg) {
+                    Some(rule) => rule,
+                    None => continue,
+                };
+                let value = match rule {
+                    RegRule::Undefined => None,
+                    RegRule::SameValue => get_dwarf_reg(regs, dwarf_reg),
+                    RegRule::Offset(offset) => read_sized(pid, cfa_value.wrapping_add(*offset as u64), 8).ok(),
+                    RegRule::ValOffset(offset) => Some(cfa_value.wrapping_add(*offset as u64)),
+                    RegRule::Register(other) => get_dwarf_reg(regs, *other),
+                    RegRule::Expression(expr) => match dwarf_expr::evaluate(expr, &ctx, &[cfa_value]) {
+                        Ok(ExprResult::Address(addr)) => read_sized(pid, addr, 8).ok(),
+                        _ => None,
+                    },
+                    RegRule::ValExpression(expr) => match dwarf_expr::evaluate(expr, &ctx, &[cfa_value]) {
+                        Ok(ExprResult::Address(value)) | Ok(ExprResult::Value(value)) => Some(value),
+                        _ => None,
+                    },
+                };
+                if dwarf_reg == fde.cie.return_address_register {
+                    // リターンアドレスがUndefinedならスタックの底
+                    return_address = Some(value?);
+                } else if let Some(value) = value {
+                    set_dwarf_reg(&mut caller_regs, dwarf_reg, value);
+                }
+            }
+            caller_regs.rip = return_address?;
+            caller_regs.rsp = cfa_value;
+            cfa = Some(cfa_value);
+            is_interrupted = fde.cie.is_signal_frame;
+        },
+        None => {
+            // CFIがなければフレームポインタを辿る
+            let rbp = regs.rbp;
+            if rbp == 0 || rbp < regs.rsp { return None; }
+            caller_regs.rip = read_sized(pid, rbp.wrapping_add(8), 8).ok()?;
+            caller_regs.rbp = read_sized(pid, rbp, 8).ok()?;
+            caller_regs.rsp = rbp.wrapping_add(16);
+        },
+    }
+
+    if caller_regs.rip == 0 { return None; }
+    // スタックは呼び出し元ほどアドレスが大きいので, 進まなければ壊れている
+    if frame.level > 0 && caller_regs.rsp <= regs.rsp { return None; }
+    Some(Frame { level: frame.level + 1, pc: caller_regs.rip, cfa, regs: caller_regs, is_interrupted })
+}
+
+pub fn backtrace(target: &Target, pid: Pid, regs: &user_regs_struct, limit: Option<usize>) -> Vec<Frame> {
+    let limit = limit.unwrap_or(MAX_BACKTRACE_DEPTH).min(MAX_BACKTRACE_DEPTH);
+    let mut frames: Vec<Frame> = vec![];
+    if limit == 0 { return frames; }
+    let mut frame = Frame { level: 0, pc: regs.rip, cfa: None, regs: *regs, is_interrupted: false };
+    loop {
+        let caller = unwind_step(target, pid, &frame);
+        if let Some(caller) = &caller {
+            frame.cfa = caller.cfa;
+        }
+        frames.push(frame);
+        if frames.len() >= limit { break; }
+        match caller {
+            Some(caller) => frame = caller,
+            None => break,
+        }
+    }
+    frames
+}
+
+pub fn print_backtrace(target: &Target, frames: &[Frame], has_more: bool) {
+    for frame in frames.iter() {
+        println!("{}", format_frame(target, frame));
+    }
+    if has_more {
+        println!("(More stack frames follow...)");
+    }
+}
+
+pub fn format_frame(target: &Target, frame: &Frame) -> String {
+    let lookup_pc = frame.get_lookup_pc();
+    let symbol = match target.symbolize(lookup_pc) {
+        Some((fn_name, offset)) => format!("{}+{}", fn_name, offset + (frame.pc - lookup_pc)),
+        None => "??".to_owned(),
+    };
+    let source = match target.find_source_line(lookup_pc) {
+        Some((file_name, line)) => format!(" at {}:{}", file_name, line),
+        None => match target.find_module(frame.pc) {
+            Some(module) => format!(" from {}", module.get_path()),
+            None => "".to_owned(),
+        },
+    };
+    format!("#{:<3} 0x{:016x} in {}{}", frame.level, frame.pc, symbol, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EH_FRAME_VIR_ADDR: u64 = 0x3000;
+
+    fn with_length(body: &[u8]) -> Vec<u8> {
+        let mut entry = (body.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(body);
+        entry
+    }
+
+    // GCCが出す形の.eh_frame. CIEは "zR" でFDEのポインタはpcrel|sdata4
+    fn eh_frame() -> Vec<u8> {
+        let mut cie = 0u32.to_le_bytes().to_vec();
+        cie.extend_from_slice(&[1, b'z', b'R', 0]);
+        // code_alignment_factor=1, data_alignment_factor=-8, return_address_register=16, augmentation_data=[0x1b]
+        cie.extend_from_slice(&[1, 0x78, 16, 1, 0x1b]);
+        // DW_CFA_def_cfa: rsp+8, DW_CFA_offset: rip at cfa-8
+        cie.extend_from_slice(&[0x0c, 7, 8, 0x90, 1]);
+        let mut data = with_length(&cie);
+
+        let fde_offset = data.len();
+        let mut fde = ((fde_offset + 4) as u32).to_le_bytes().to_vec();
+        let pc_begin_vir_addr = EH_FRAME_VIR_ADDR + fde_offset as u64 + 8;
+        fde.extend_from_slice(&(0x1000u64.wrapping_sub(pc_begin_vir_addr) as i32).to_le_bytes());
+        fde.extend_from_slice(&0x20i32.to_le_bytes());
+        fde.push(0);
+        fde.extend_from_slice(&[
+            // DW_CFA_advance_loc: 1, DW_CFA_def_cfa_offset: 16, DW_CFA_offset: rbp at cfa-16
+            0x41, 0x0e, 16, 0x86, 2,
+            // DW_CFA_advance_loc: 3, DW_CFA_def_cfa_register: rbp
+            0x43, 0x0d, 6,
+            // DW_CFA_advance_loc1: 16, DW_CFA_remember_state, DW_CFA_def_cfa: rsp+8, DW_CFA_restore: rbp
+            0x02, 16, 0x0a, 0x0c, 7, 8, 0xc6,
+            // DW_CFA_advance_loc: 1, DW_CFA_restore_state
+            0x41, 0x0b,
+        ]);
+        data.extend(with_length(&fde));
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data
+    }
+
+    fn row_at(table: &UnwindTable, pc: u64) -> Option<RowState> {
+        let (section, fde) = table.find_fde(pc)?;
+        section.find_row(&fde, pc).ok()
+    }
+
+    fn assert_cfa(row: &RowState, reg: u16, offset: i64) {
+        assert!(matches!(row.cfa, CfaRule::RegOffset(r, o) if r == reg && o == offset), "{:?}", row.cfa);
+    }
+
+    fn assert_offset(row: &RowState, reg: u16, offset: Option<i64>) {
+        match offset {
+            Some(offset) => assert!(matches!(row.regs.get(&reg), Some(RegRule::Offset(o)) if *o == offset), "{:?}", row.regs),
+            None => assert!(!row.regs.contains_key(&reg), "{:?}", row.regs),
+        }
+    }
+
+    #[test]
+    fn eh_frame_rows() {
+        let table = UnwindTable::parse(Some((eh_frame(), EH_FRAME_VIR_ADDR)), None, None);
+        let (_, fde) = table.find_fde(0x1000).unwrap();
+        assert_eq!((fde.pc_begin, fde.pc_end), (0x1000, 0x1020));
+        assert_eq!(fde.cie.return_address_register, 16);
+
+        let row = row_at(&table, 0x1000).unwrap();
+        assert_cfa(&row, 7, 8);
+        assert_offset(&row, 16, Some(-8));
+        assert_offset(&row, 6, None);
+
+        let row = row_at(&table, 0x1003).unwrap();
+        assert_cfa(&row, 7, 16);
+        assert_offset(&row, 6, Some(-16));
+
+        let row = row_at(&table, 0x1004).unwrap();
+        assert_cfa(&row, 6, 16);
+        let row = row_at(&table, 0x1013).unwrap();
+        assert_cfa(&row, 6, 16);
+
+        let row = row_at(&table, 0x1014).unwrap();
+        assert_cfa(&row, 7, 8);
+        assert_offset(&row, 6, None);
+        assert_offset(&row, 16, Some(-8));
+
+        // ret の後ろはremember_stateした時のCFAに戻る
+        let row = row_at(&table, 0x1015).unwrap();
+        assert_cfa(&row, 6, 16);
+        assert_offset(&row, 6, Some(-16));
+
+        assert!(table.find_fde(0x0fff).is_none());
+        assert!(table.find_fde(0x1020).is_none());
+    }
+
+    #[test]
+    fn overflowing_length() {
+        let mut data = eh_frame();
+        let terminator = data.len() - 4;
+        data.truncate(terminator);
+        // DWARF64の巨大な長さのエントリが続いても, それまでのFDEは使える
+        data.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        data.extend_from_slice(&(u64::MAX - 4).to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        let table = UnwindTable::parse(Some((data, EH_FRAME_VIR_ADDR)), None, None);
+        assert_cfa(&row_at(&table, 0x1004).unwrap(), 6, 16);
+    }
+
+    #[test]
+    fn debug_frame_rows() {
+        let mut cie = 0xffff_ffffu32.to_le_bytes().to_vec();
+        // version 1, augmentation "", code_alignment_factor=1, data_alignment_factor=-8, return_address_register=16
+        cie.extend_from_slice(&[1, 0, 1, 0x78, 16]);
+        cie.extend_from_slice(&[0x0c, 7, 8, 0x90, 1]);
+        let mut data = with_length(&cie);
+        let mut fde = 0u32.to_le_bytes().to_vec();
+        fde.extend_from_slice(&0x5000u64.to_le_bytes());
+        fde.extend_from_slice(&0x10u64.to_le_bytes());
+        // DW_CFA_advance_loc: 2, DW_CFA_def_cfa_sf: rsp+24, DW_CFA_val_offset: rbx = cfa-16, DW_CFA_undefined: r12
+        fde.extend_from_slice(&[0x42, 0x12, 7, 0x7d, 0x14, 3, 2, 0x07, 12]);
+        data.extend(with_length(&fde));
+
+        let table = UnwindTable::parse(None, None, Some(data));
+        let row = row_at(&table, 0x5001).unwrap();
+        assert_cfa(&row, 7, 8);
+        let row = row_at(&table, 0x5002).unwrap();
+        assert_cfa(&row, 7, 24);
+        assert!(matches!(row.regs.get(&3), Some(RegRule::ValOffset(-16))));
+        assert!(matches!(row.regs.get(&12), Some(RegRule::Undefined)));
+        assert!(table.find_fde(0x5010).is_none());
+    }
+}
@@ -0,0 +1,446 @@
+use anyhow::{Result, bail};
+
+use crate::dwarf_info::{self, DebugInfo, Type};
+
+const MAX_FORMAT_DEPTH: usize = 4;
+const MAX_ARRAY_ELEMENTS: u64 = 200;
+pub const MAX_STRING_LENGTH: u64 = 200;
+
+// 式の評価結果の型. DWARFの型はDIEのオフセットで指す
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueType {
+    Int { size: u8, signed: bool },
+    Float { size: u8 },
+    Bool,
+    Char,
+    Dwarf(usize),
+    Pointer(Box<ValueType>),
+    // $xmm0 などのベクトルレジスタ用. 要素の型と個数
+    Vector(Box<ValueType>, usize),
+    // レジスタの見方をまとめたもの. 型名と (メンバー名, 型) の一覧で, 全てオフセット0にある
+    Union(String, Vec<(String, ValueType)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Value {
+    ty: ValueType,
+    bytes: Vec<u8>,
+    // メモリ上にある値ならそのアドレス
+    address: Option<u64>,
+}
+
+impl Value {
+    pub fn new(ty: ValueType, bytes: Vec<u8>, address: Option<u64>) -> Self {
+        Value { ty, bytes, address }
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        Value { ty: ValueType::Int { size: 8, signed: true }, bytes: value.to_le_bytes().to_vec(), address: None }
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        Value { ty: ValueType::Int { size: 8, signed: false }, bytes: value.to_le_bytes().to_vec(), address: None }
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        Value { ty: ValueType::Float { size: 8 }, bytes: value.to_le_bytes().to_vec(), address: None }
+    }
+
+    pub fn from_bool(value: bool) -> Self {
+        Value { ty: ValueType::Bool, bytes: vec![value as u8], address: None }
+    }
+
+    pub fn pointer(target: ValueType, addr: u64) -> Self {
+        Value { ty: ValueType::Pointer(Box::new(target)), bytes: addr.to_le_bytes().to_vec(), address: None }
+    }
+
+    pub fn get_type(&self) -> &ValueType { &self.ty }
+    pub fn get_bytes(&self) -> &Vec<u8> { &self.bytes }
+    pub fn get_address(&self) -> Option<u64> { self.address }
+
+    pub fn raw_u64(&self) -> u64 {
+        let mut array = [0u8; 8];
+        let len = self.bytes.len().min(8);
+        array[..len].copy_from_slice(&self.bytes[..len]);
+        u64::from_le_bytes(array)
+    }
+}
+
+// typedefやconstを剥がした型
+pub fn resolve_type(debug_info: Option<&DebugInfo>, ty: &ValueType) -> Option<Type> {
+    let debug_info = debug_info?;
+    let mut type_offset = match ty {
+        ValueType::Dwarf(type_offset) => *type_offset,
+        _ => return None,
+    };
+    for _ in 0..16 {
+        match debug_info.get_type(type_offset)? {
+            Type::Typedef { target: Some(target), .. } | Type::Modifier { target: Some(target) } => type_offset = *target,
+            other => return Some(other.clone()),
+        }
+    }
+    None
+}
+
+pub fn type_size(debug_info: Option<&DebugInfo>, ty: &ValueType) -> u64 {
+    match ty {
+        ValueType::Int { size, .. } | ValueType::Float { size } => *size as u64,
+        ValueType::Bool => 1,
+        ValueType::Char => 4,
+        ValueType::Pointer(_) => 8,
+        ValueType::Vector(element, count) => type_size(debug_info, element) * *count as u64,
+        ValueType::Union(_, members) => members.iter().map(|(_, ty)| type_size(debug_info, ty)).max().unwrap_or(0),
+        ValueType::Dwarf(_) => match resolve_type(debug_info, ty) {
+            Some(Type::Base { size, .. }) | Some(Type::Struct { size, .. }) | Some(Type::Enumeration { size, .. }) | Some(Type::Other { size, .. }) => size,
+            Some(Type::Pointer { .. }) => 8,
+            Some(Type::Array { target, count }) => {
+                let element = target.map(|target| type_size(debug_info, &ValueType::Dwarf(target))).unwrap_or(0);
+                element * count.unwrap_or(0)
+            },
+            _ => 0,
+        },
+    }
+}
+
+pub fn type_name(debug_info: Option<&DebugInfo>, ty: &ValueType) -> String {
+    match ty {
+        ValueType::Int { size, signed } => format!("{}{}", if *signed { "i" } else { "u" }, *size as u32 * 8),
+        ValueType::Float { size } => format!("f{}", *size as u32 * 8),
+        ValueType::Bool => "bool".to_owned(),
+        ValueType::Char => "char".to_owned(),
+        ValueType::Pointer(target) => format!("*mut {}", type_name(debug_info, target)),
+        ValueType::Vector(element, count) => format!("[{}; {}]", type_name(debug_info, element), count),
+        ValueType::Union(name, _) => name.clone(),
+        ValueType::Dwarf(type_offset) => {
+            let debug_info = match debug_info {
+                Some(debug_info) => debug_info,
+                None => return "?".to_owned(),
+            };
+            match debug_info.get_type(*type_offset) {
+                Some(Type::Base { name, .. }) | Some(Type::Struct { name, .. }) | Some(Type::Enumeration { name, .. })
+                | Some(Type::Typedef { name, .. }) | Some(Type::Other { name, .. }) => name.clone(),
+                Some(Type::Pointer { name, target }) => {
+                    if !name.is_empty() { return name.clone(); }
+                    match target {
+                        Some(target) => format!("*mut {}", type_name(Some(debug_info), &ValueType::Dwarf(*target))),
+                        None => "*mut ()".to_owned(),
+                    }
+                },
+                Some(Type::Array { target, count }) => {
+                    let element = target.map(|target| type_name(Some(debug_info), &ValueType::Dwarf(target))).unwrap_or_else(|| "?".to_owned());
+                    format!("[{}; {}]", element, count.unwrap_or(0))
+                },
+                Some(Type::Modifier { target }) => match target {
+                    Some(target) => type_name(Some(debug_info), &ValueType::Dwarf(*target)),
+                    None => "()".to_owned(),
+                },
+                None => "?".to_owned(),
+            }
+        },
+    }
+}
+
+// System V ABIで返り値の8バイトを運ぶレジスタの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReturnClass {
+    Integer,
+    Sse,
+}
+
+// 返り値の8バイトごとの分類. メモリ経由で返る型や分類できない型はNone
+pub fn classify_return(debug_info: Option<&DebugInfo>, ty: &ValueType) -> Option<Vec<ReturnClass>> {
+    let size = type_size(debug_info, ty);
+    if size == 0 || size > 16 { return None; }
+    let mut classes = vec![None; size.div_ceil(8) as usize];
+    classify_fields(debug_info, ty, 0, &mut classes)?;
+    // パディングしかない8バイトがあるのはメンバーが読めていない型
+    classes.into_iter().collect()
+}
+
+fn classify_fields(debug_info: Option<&DebugInfo>, ty: &ValueType, offset: u64, classes: &mut Vec<Option<ReturnClass>>) -> Option<()> {
+    let (class, size) = match resolve_type(debug_info, ty)? {
+        Type::Base { size, encoding, .. } if encoding == dwarf_info::DW_ATE_FLOAT => {
+            // long doubleはx87レジスタで返る
+            if size > 8 { return None; }
+            (ReturnClass::Sse, size)
+        },
+        Type::Base { size, .. } | Type::Enumeration { size, .. } => (ReturnClass::Integer, size),
+        Type::Pointer { .. } => (ReturnClass::Integer, 8),
+        Type::Struct { members, .. } => {
+            for member in &members {
+                let member_ty = ValueType::Dwarf(member.get_type_offset()?);
+                classify_fields(debug_info, &member_ty, offset + member.get_offset(), classes)?;
+            }
+            return Some(());
+        },
+        Type::Array { target, count } => {
+            let element_ty = ValueType::Dwarf(target?);
+            let element_size = type_size(debug_info, &element_ty);
+            for i in 0..count? {
+                classify_fields(debug_info, &element_ty, offset + i * element_size, classes)?;
+            }
+            return Some(());
+        },
+        _ => return None,
+    };
+    if size == 0 { return Some(()); }
+    // 境界に揃っていないメンバーがあればメモリ経由
+    if !offset.is_multiple_of(size) { return None; }
+    for index in offset / 8..(offset + size).div_ceil(8) {
+        let slot = classes.get_mut(index as usize)?;
+        // 同じ8バイトにINTEGERとSSEが混ざればINTEGER
+        *slot = Some(if *slot == Some(ReturnClass::Integer) { ReturnClass::Integer } else { class });
+    }
+    Some(())
+}
+
+// 算術演算に使うためのスカラー値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scalar {
+    Int(i64),
+    Float(f64),
+}
+
+pub fn to_scalar(debug_info: Option<&DebugInfo>, value: &Value) -> Result<Scalar> {
+    let raw = value.raw_u64();
+    let scalar = match &value.ty {
+        ValueType::Int { size, signed } => Scalar::Int(extend(raw, *size as u64, *signed)),
+        ValueType::Float { size } => Scalar::Float(to_float(raw, *size as u64)),
+        ValueType::Bool | ValueType::Char | ValueType::Pointer(_) => Scalar::Int(raw as i64),
+        ValueType::Vector(..) | ValueType::Union(..) => bail!("Value of type {} can not be used as a number.", type_name(debug_info, &value.ty)),
+        ValueType::Dwarf(_) => match resolve_type(debug_info, &value.ty) {
+            Some(Type::Base { size, encoding, .. }) => {
+                if encoding == dwarf_info::DW_ATE_FLOAT {
+                    Scalar::Float(to_float(raw, size))
+                } else {
+                    let signed = encoding == dwarf_info::DW_ATE_SIGNED || encoding == dwarf_info::DW_ATE_SIGNED_CHAR;
+                    Scalar::Int(extend(raw, size, signed))
+                }
+            },
+            Some(Type::Pointer { .. }) => Scalar::Int(raw as i64),
+            Some(Type::Enumeration { size, .. }) => Scalar::Int(extend(raw, size, false)),
+            _ => bail!("Value of type {} can not be used as a number.", type_name(debug_info, &value.ty)),
+        },
+    };
+    Ok(scalar)
+}
+
+// 代入のためにvalueをtyの型のバイト列にする
+pub fn convert_to_bytes(debug_info: Option<&DebugInfo>, value: &Value, ty: &ValueType) -> Result<Vec<u8>> {
+    let size = type_size(debug_info, ty) as usize;
+    let is_float = match ty {
+        ValueType::Float { .. } => true,
+        ValueType::Int { .. } | ValueType::Bool | ValueType::Char | ValueType::Pointer(_) => false,
+        ValueType::Vector(..) | ValueType::Union(..) if value.ty == *ty && value.bytes.len() == size => return Ok(value.bytes.clone()),
+        ValueType::Vector(..) | ValueType::Union(..) => bail!("Invalid cast."),
+        ValueType::Dwarf(_) => match resolve_type(debug_info, ty) {
+            Some(Type::Base { encoding, .. }) => encoding == dwarf_info::DW_ATE_FLOAT,
+            Some(Type::Pointer { .. }) | Some(Type::Enumeration { .. }) => false,
+            // 構造体などは同じ型の値ならそのままコピーする
+            _ if value.ty == *ty && value.bytes.len() == size => return Ok(value.bytes.clone()),
+            _ => bail!("Invalid cast."),
+        },
+    };
+    let scalar = to_scalar(debug_info, value)?;
+    let bytes = match (is_float, scalar) {
+        (true, scalar) => {
+            let float = match scalar { Scalar::Int(value) => value as f64, Scalar::Float(value) => value };
+            if size == 4 { (float as f32).to_le_bytes().to_vec() } else { float.to_le_bytes().to_vec() }
+        },
+        (false, Scalar::Int(value)) => value.to_le_bytes()[..size.min(8)].to_vec(),
+        (false, Scalar::Float(value)) => (value as i64).to_le_bytes()[..size.min(8)].to_vec(),
+    };
+    Ok(bytes)
+}
+
+fn extend(raw: u64, size: u64, signed: bool) -> i64 {
+    if size == 0 || size >= 8 { return raw as i64; }
+    let bits = size * 8;
+    let masked = raw & ((1u64 << bits) - 1);
+    if signed && (masked >> (bits - 1)) & 1 == 1 {
+        (masked | (!0u64 << bits)) as i64
+    } else {
+        masked as i64
+    }
+}
+
+fn to_float(raw: u64, size: u64) -> f64 {
+    if size == 4 { f32::from_bits(raw as u32) as f64 } else { f64::from_bits(raw) }
+}
+
+// 文字列や配列の中身を読むためにメモリを読む関数を受け取る
+pub fn format_value(debug_info: Option<&DebugInfo>, value: &Value, read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> String {
+    format_value_depth(debug_info, value, read_memory, 0)
+}
+
+fn format_value_depth(debug_info: Option<&DebugInfo>, value: &Value, read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>, depth: usize) -> String {
+    let raw = value.raw_u64();
+    match &value.ty {
+        ValueType::Int { size: 16, signed } => format_int128(&value.bytes, *signed),
+        ValueType::Int { size, signed } => extend(raw, *size as u64, *signed).to_string(),
+        ValueType::Float { size } => format_float(to_float(raw, *size as u64), *size as u64),
+        ValueType::Bool => (raw & 0xff != 0).to_string(),
+        ValueType::Char => format_char(raw as u32),
+        ValueType::Pointer(_) => format!("0x{:x}", raw),
+        // ベクトルレジスタはgdbと同じ {a, b, ...} の形にする
+        ValueType::Vector(element, count) => {
+            let element_size = type_size(debug_info, element);
+            let elements: Vec<String> = (0..*count as u64).map(|idx| {
+                let element = element_value(value, idx * element_size, element_size, (**element).clone());
+                format_value_depth(debug_info, &element, read_memory, depth + 1)
+            }).collect();
+            format!("{{{}}}", elements.join(", "))
+        },
+        ValueType::Union(_, members) => {
+            let fields: Vec<String> = members.iter().map(|(name, ty)| {
+                let field = element_value(value, 0, type_size(debug_info, ty), ty.clone());
+                format!("{} = {}", name, format_value_depth(debug_info, &field, read_memory, depth + 1))
+            }).collect();
+            format!("{{{}}}", fields.join(", "))
+        },
+        ValueType::Dwarf(_) => {
+            let resolved = match resolve_type(debug_info, &value.ty) {
+                Some(resolved) => resolved,
+                None => return format!("<unknown type: {:?}>", value.bytes),
+            };
+            match resolved {
+                Type::Base { size, encoding, .. } => match encoding {
+                    dwarf_info::DW_ATE_FLOAT => format_float(to_float(raw, size), size),
+                    dwarf_info::DW_ATE_BOOLEAN => (raw & 0xff != 0).to_string(),
+                    dwarf_info::DW_ATE_UTF => format_char(raw as u32),
+                    dwarf_info::DW_ATE_SIGNED | dwarf_info::DW_ATE_SIGNED_CHAR => extend(raw, size, true).to_string(),
+                    dwarf_info::DW_ATE_UNSIGNED_CHAR if size == 1 => format!("{} {}", raw & 0xff, format_char((raw & 0xff) as u32)),
+                    _ => extend(raw, size, false).to_string(),
+                },
+                Type::Pointer { .. } => format!("0x{:x}", raw),
+                Type::Enumeration { size, enumerators, .. } => {
+                    let number = extend(raw, size, false);
+                    match enumerators.iter().find(|(_, value)| *value == number) {
+                        Some((name, _)) => name.clone(),
+                        None => number.to_string(),
+                    }
+                },
+                Type::Struct { name, members, .. } => {
+                    if name == "&str" || name == "&mut str" {
+                        if let Some(s) = format_str_slice(debug_info, value, &members, read_memory) {
+                            return s;
+                        }
+                    }
+                    if depth >= MAX_FORMAT_DEPTH { return format!("{} {{...}}", name); }
+                    let fields: Vec<String> = members.iter().map(|member| {
+                        let field = member_value(debug_info, value, member.get_offset(), member.get_type_offset());
+                        format!("{}: {}", member.get_name(), format_value_depth(debug_info, &field, read_memory, depth + 1))
+                    }).collect();
+                    format!("{} {{{}}}", name, fields.join(", "))
+                },
+                Type::Array { target, count } => {
+                    let target = match target {
+                        Some(target) => target,
+                        None => return "[...]".to_owned(),
+                    };
+                    if depth >= MAX_FORMAT_DEPTH { return "[...]".to_owned(); }
+                    let element_size = type_size(debug_info, &ValueType::Dwarf(target));
+                    let count = count.unwrap_or(0);
+                    let mut elements: Vec<String> = vec![];
+                    for idx in 0..count.min(MAX_ARRAY_ELEMENTS) {
+                        let element = member_value(debug_info, value, idx * element_size, Some(target));
+                        elements.push(format_value_depth(debug_info, &element, read_memory, depth + 1));
+                    }
+                    if count > MAX_ARRAY_ELEMENTS { elements.push("...".to_owned()); }
+                    format!("[{}]", elements.join(", "))
+                },
+                Type::Other { name, .. } => format!("<{}>", name),
+                Type::Typedef { .. } | Type::Modifier { .. } => "<unknown>".to_owned(),
+            }
+        },
+    }
+}
+
+// 構造体の中のoffsetの位置にあるメンバーを取り出す
+pub fn member_value(debug_info: Option<&DebugInfo>, value: &Value, offset: u64, type_offset: Option<usize>) -> Value {
+    let ty = match type_offset {
+        Some(type_offset) => ValueType::Dwarf(type_offset),
+        None => ValueType::Int { size: 1, signed: false },
+    };
+    let size = type_size(debug_info, &ty) as usize;
+    let start = (offset as usize).min(value.bytes.len());
+    let end = (start + size).min(value.bytes.len());
+    Value { ty, bytes: value.bytes[start..end].to_vec(), address: value.address.map(|addr| addr + offset) }
+}
+
+// DWARFの型を持たないベクトルの要素などを取り出す
+pub fn element_value(value: &Value, offset: u64, size: u64, ty: ValueType) -> Value {
+    let start = (offset as usize).min(value.bytes.len());
+    let end = (start + size as usize).min(value.bytes.len());
+    Value { ty, bytes: value.bytes[start..end].to_vec(), address: value.address.map(|addr| addr + offset) }
+}
+
+fn format_int128(bytes: &[u8], signed: bool) -> String {
+    let mut array = [0u8; 16];
+    let len = bytes.len().min(16);
+    array[..len].copy_from_slice(&bytes[..len]);
+    if signed { i128::from_le_bytes(array).to_string() } else { u128::from_le_bytes(array).to_string() }
+}
+
+fn format_str_slice(debug_info: Option<&DebugInfo>, value: &Value, members: &[dwarf_info::Member], read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Option<String> {
+    let (s, truncated) = read_str_slice(debug_info, value, members, read_memory)?;
+    Some(format!("{:?}{}", s, if truncated { "..." } else { "" }))
+}
+
+// &str の中身と, 長すぎて途中で切ったかどうか
+fn read_str_slice(debug_info: Option<&DebugInfo>, value: &Value, members: &[dwarf_info::Member], read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Option<(String, bool)> {
+    let data_ptr = members.iter().find(|member| member.get_name() == "data_ptr")?;
+    let length = members.iter().find(|member| member.get_name() == "length")?;
+    let ptr = member_value(debug_info, value, data_ptr.get_offset(), data_ptr.get_type_offset()).raw_u64();
+    let len = member_value(debug_info, value, length.get_offset(), length.get_type_offset()).raw_u64();
+    let bytes = read_memory(ptr, len.min(MAX_STRING_LENGTH) as usize).ok()?;
+    Some((String::from_utf8_lossy(&bytes).into_owned(), len > MAX_STRING_LENGTH))
+}
+
+// printfの%sで表示する文字列. &str はそのまま, ポインタはNUL終端の文字列, 配列は中身をNULまで読む
+pub fn string_contents(debug_info: Option<&DebugInfo>, value: &Value, read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Option<String> {
+    let bytes = match resolve_type(debug_info, &value.ty) {
+        Some(Type::Struct { name, members, .. }) if name == "&str" || name == "&mut str" => {
+            return read_str_slice(debug_info, value, &members, read_memory).map(|(s, _)| s);
+        },
+        Some(Type::Array { .. }) => value.bytes.clone(),
+        Some(Type::Pointer { .. }) => read_c_string(value.raw_u64(), read_memory)?,
+        _ => match value.ty {
+            ValueType::Pointer(_) | ValueType::Int { .. } => read_c_string(value.raw_u64(), read_memory)?,
+            _ => return None,
+        },
+    };
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+// ページの境目をまたいで読めないことがあるので1バイトずつ読む
+fn read_c_string(addr: u64, read_memory: &dyn Fn(u64, usize) -> Result<Vec<u8>>) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    for offset in 0..MAX_STRING_LENGTH {
+        let byte = *read_memory(addr + offset, 1).ok()?.first()?;
+        if byte == 0 { break; }
+        bytes.push(byte);
+    }
+    Some(bytes)
+}
+
+// f32はf32のまま表示しないと余計な桁が出る. 極端に大きい・小さい値は指数表記にする
+fn format_float(value: f64, size: u64) -> String {
+    if value.fract() == 0.0 && value.is_finite() && value.abs() < 1e16 { return format!("{:.0}", value); }
+    let magnitude = value.abs();
+    let exponential = value.is_finite() && !(1e-5..1e16).contains(&magnitude);
+    match (size, exponential) {
+        (4, true) => format!("{:e}", value as f32),
+        (4, false) => (value as f32).to_string(),
+        (_, true) => format!("{:e}", value),
+        (_, false) => value.to_string(),
+    }
+}
+
+fn format_char(code: u32) -> String {
+    match std::char::from_u32(code) {
+        Some(c) => format!("{:?}", c),
+        None => format!("'\\u{{{:x}}}'", code),
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Result, bail};
+use nix::unistd::Pid;
+
+use crate::ptrace;
+use crate::value::{Value, ValueType};
+
+// struct user の u_debugreg までのオフセット
+const DEBUGREG_OFFSET: u64 = 848;
+pub const DEBUG_REGISTER_COUNT: usize = 4;
+const DR6: usize = 6;
+const DR7: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    // watch: 書き込みで値が変わった時
+    Write,
+    // rwatch: 読み込み. x86には読み込みだけの設定が無いので, 値が変わっていない時に読んだとみなす
+    Read,
+    // awatch: 読み書きの両方
+    Access,
+}
+
+impl WatchKind {
+    // DR7のR/Wビット
+    fn rw_bits(&self) -> u64 {
+        match self {
+            WatchKind::Write => 0b01,
+            WatchKind::Read | WatchKind::Access => 0b11,
+        }
+    }
+}
+
+// 設定した時に式を評価したアドレスを見張り続ける (gdbの watch -location と同じ)
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    id: usize,
+    kind: WatchKind,
+    // falseならソフトウェアウォッチポイント. 1命令ずつ進めて値を比べる
+    hardware: bool,
+    expr: String,
+    addr: u64,
+    ty: ValueType,
+    // 型を調べるDWARFを持っているモジュールの中のアドレス
+    scope_pc: u64,
+    // 最後に見た時の値
+    value: Vec<u8>,
+}
+
+impl Watchpoint {
+    pub fn new(id: usize, kind: WatchKind, hardware: bool, expr: String, addr: u64, scope_pc: u64, value: &Value) -> Self {
+        Watchpoint { id, kind, hardware, expr, addr, ty: value.get_type().clone(), scope_pc, value: value.get_bytes().clone() }
+    }
+
+    pub fn get_id(&self) -> usize { self.id }
+    pub fn get_kind(&self) -> WatchKind { self.kind }
+    pub fn is_hardware(&self) -> bool { self.hardware }
+    pub fn get_expr(&self) -> &String { &self.expr }
+    pub fn get_addr(&self) -> u64 { self.addr }
+    pub fn get_len(&self) -> usize { self.value.len() }
+    pub fn get_type(&self) -> &ValueType { &self.ty }
+    pub fn get_scope_pc(&self) -> u64 { self.scope_pc }
+    pub fn get_value(&self) -> &Vec<u8> { &self.value }
+
+    pub fn set_value(&mut self, value: Vec<u8>) {
+        self.value = value;
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match (self.hardware, self.kind) {
+            (false, _) => "Watchpoint",
+            (true, WatchKind::Write) => "Hardware watchpoint",
+            (true, WatchKind::Read) => "Hardware read watchpoint",
+            (true, WatchKind::Access) => "Hardware access (read/write) watchpoint",
+        }
+    }
+
+    // info breakpoints のType欄
+    pub fn type_name(&self) -> &'static str {
+        match (self.hardware, self.kind) {
+            (false, _) => "watchpoint",
+            (true, WatchKind::Write) => "hw watchpoint",
+            (true, WatchKind::Read) => "read watchpoint",
+            (true, WatchKind::Access) => "acc watchpoint",
+        }
+    }
+}
+
+// デバッグレジスタで見張れる大きさか. アドレスは大きさに揃っていないといけない
+pub fn fits_debug_register(addr: u64, len: usize) -> bool {
+    matches!(len, 1 | 2 | 4 | 8) && addr.is_multiple_of(len as u64)
+}
+
+// DR0-3 に入れたウォッチポイントのid. DR7と合わせて, 変わった時だけ書き込む.
+// デバッグレジスタはスレッドごとにあるので, 書き込んだスレッドも覚えておく
+#[derive(Debug, Default)]
+pub struct DebugRegisters {
+    slots: [Option<(usize, u64, usize, WatchKind)>; DEBUG_REGISTER_COUNT],
+    synced: Vec<Pid>,
+}
+
+impl DebugRegisters {
+    // watchpointsのうちハードウェアのものを先頭から順に, 全てのスレッドの DR0-3 に入れる
+    pub fn sync(&mut self, tids: &[Pid], watchpoints: &[Watchpoint]) -> Result<()> {
+        let hardware: Vec<&Watchpoint> = watchpoints.iter().filter(|watchpoint| watchpoint.is_hardware()).collect();
+        if hardware.len() > DEBUG_REGISTER_COUNT {
+            bail!("Hardware watchpoints used exceeds limit.");
+        }
+        let mut slots = [None; DEBUG_REGISTER_COUNT];
+        for (slot, watchpoint) in slots.iter_mut().zip(hardware.iter()) {
+            *slot = Some((watchpoint.get_id(), watchpoint.get_addr(), watchpoint.get_len(), watchpoint.get_kind()));
+        }
+        if slots != self.slots {
+            self.synced.clear();
+        }
+        // 新しいスレッドのデバッグレジスタは空なので, まだ書いていないスレッドには書く
+        for tid in tids.iter().filter(|tid| !self.synced.contains(tid)) {
+            write_slots(*tid, &slots)?;
+        }
+        self.slots = slots;
+        self.synced = tids.to_vec();
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(|slot| slot.is_none())
+    }
+
+    // DR6を見て, 引っかかったウォッチポイントのidを返す. 次に備えてDR6は消しておく
+    pub fn take_hits(&self, pid: Pid) -> Result<Vec<usize>> {
+        if self.is_empty() { return Ok(vec![]); }
+        let dr6 = ptrace::peek_user(pid, debugreg_offset(DR6))?;
+        if dr6 & 0xf == 0 { return Ok(vec![]); }
+        ptrace::poke_user(pid, debugreg_offset(DR6), 0)?;
+        let hits = self.slots.iter().enumerate()
+            .filter(|(idx, _)| dr6 & (1 << idx) != 0)
+            .filter_map(|(_, slot)| slot.map(|(id, ..)| id))
+            .collect();
+        Ok(hits)
+    }
+}
+
+fn write_slots(tid: Pid, slots: &[Option<(usize, u64, usize, WatchKind)>; DEBUG_REGISTER_COUNT]) -> Result<()> {
+    // カーネルはDR7を書いた時に設定を確かめるので, 先に全部無効にしてからアドレスを書く
+    ptrace::poke_user(tid, debugreg_offset(DR7), 0)?;
+    let mut dr7 = 0;
+    for (idx, slot) in slots.iter().enumerate() {
+        if let Some((_, addr, len, kind)) = slot {
+            ptrace::poke_user(tid, debugreg_offset(idx), *addr)?;
+            dr7 |= 1 << (idx * 2);
+            dr7 |= kind.rw_bits() << (16 + idx * 4);
+            dr7 |= len_bits(*len) << (18 + idx * 4);
+        }
+    }
+    if dr7 != 0 {
+        ptrace::poke_user(tid, debugreg_offset(DR7), dr7)?;
+    }
+    Ok(())
+}
+
+fn debugreg_offset(idx: usize) -> u64 {
+    DEBUGREG_OFFSET + idx as u64 * 8
+}
+
+// DR7のLENビット. 8バイトは 0b10
+fn len_bits(len: usize) -> u64 {
+    match len {
+        1 => 0b00,
+        2 => 0b01,
+        8 => 0b10,
+        _ => 0b11,
+    }
+}